@@ -0,0 +1,131 @@
+// Compares the in-memory user repository against the single-lock, scanning design it
+// replaced. Seed size defaults to 100k users, set USER_REPO_BENCH_USERS for more, e.g.
+// USER_REPO_BENCH_USERS=1000000 cargo bench -p auth
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::{Arc, RwLock};
+
+use auth::{InMemoryUserRepository, User, UserRepositoryTrait};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tokio::runtime::Runtime;
+
+const TASKS: usize = 8;
+const CREATES_PER_TASK: usize = 1_000;
+
+// The previous design, one lock around every user and a scan to find an email
+#[derive(Default)]
+struct LinearScan {
+    users: RwLock<HashMap<String, User>>,
+}
+
+impl LinearScan {
+    fn create_user(&self, user: User) {
+        self.users.write().unwrap().insert(user.id.clone(), user);
+    }
+
+    fn find_by_email(&self, email: &str) -> Option<User> {
+        let users = self.users.read().unwrap();
+        users.values().find(|u| u.email == email).cloned()
+    }
+}
+
+fn seed_size() -> usize {
+    std::env::var("USER_REPO_BENCH_USERS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(100_000)
+}
+
+fn user(i: usize) -> User {
+    User::new(
+        format!("user{i}@example.com"),
+        String::new(),
+        format!("User {i}"),
+    )
+}
+
+fn find_by_email(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let seeded = seed_size();
+
+    let repo = InMemoryUserRepository::with_capacity(seeded);
+    let baseline = LinearScan::default();
+    runtime.block_on(async {
+        for i in 0..seeded {
+            repo.create_user(user(i)).await.unwrap();
+            baseline.create_user(user(i));
+        }
+    });
+
+    let mut group = c.benchmark_group("find_by_email");
+    let mut i = 0;
+    group.bench_function(BenchmarkId::new("indexed", seeded), |b| {
+        b.to_async(&runtime).iter(|| {
+            i = (i + 7919) % seeded;
+            let email = format!("user{i}@example.com");
+            let repo = &repo;
+            async move { black_box(repo.find_by_email(&email).await.unwrap()) }
+        })
+    });
+    group.bench_function(BenchmarkId::new("linear_scan", seeded), |b| {
+        b.iter(|| {
+            i = (i + 7919) % seeded;
+            black_box(baseline.find_by_email(&format!("user{i}@example.com")))
+        })
+    });
+    group.finish();
+}
+
+// Users created from several tasks at once, as concurrent signups would
+fn concurrent_create(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("concurrent_create");
+    group.throughput(Throughput::Elements((TASKS * CREATES_PER_TASK) as u64));
+    group.bench_function("indexed", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let repo = Arc::new(InMemoryUserRepository::new());
+            let tasks: Vec<_> = (0..TASKS)
+                .map(|t| {
+                    let repo = repo.clone();
+                    tokio::spawn(async move {
+                        for i in 0..CREATES_PER_TASK {
+                            repo.create_user(user(t * CREATES_PER_TASK + i))
+                                .await
+                                .unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        })
+    });
+    group.bench_function("single_lock", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let repo = Arc::new(LinearScan::default());
+            let tasks: Vec<_> = (0..TASKS)
+                .map(|t| {
+                    let repo = repo.clone();
+                    tokio::spawn(async move {
+                        for i in 0..CREATES_PER_TASK {
+                            // emails were kept unique by looking them up before creating
+                            let user = user(t * CREATES_PER_TASK + i);
+                            if repo.find_by_email(&user.email).is_none() {
+                                repo.create_user(user);
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, find_by_email, concurrent_create);
+criterion_main!(benches);
@@ -0,0 +1,80 @@
+use crate::utils::random_string;
+
+pub const API_KEY_PREFIX: &str = "sk";
+
+const PREFIX_LEN: usize = 8;
+const SECRET_LEN: usize = 32;
+const KEY_DELIMITER: char = '_';
+
+pub struct GeneratedKey {
+    pub prefix: String,
+    pub secret: String,
+}
+
+impl GeneratedKey {
+    // full key handed to the user, e.g. sk_AbCd1234_<secret>
+    pub fn plain_text(&self) -> String {
+        format!(
+            "{API_KEY_PREFIX}{KEY_DELIMITER}{}{KEY_DELIMITER}{}",
+            self.prefix, self.secret
+        )
+    }
+}
+
+pub fn generate_api_key() -> GeneratedKey {
+    GeneratedKey {
+        prefix: random_string(PREFIX_LEN),
+        secret: random_string(SECRET_LEN),
+    }
+}
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(&format!("{API_KEY_PREFIX}{KEY_DELIMITER}"))
+}
+
+pub fn parse_api_key(key: &str) -> Option<GeneratedKey> {
+    let mut parts = key.splitn(3, KEY_DELIMITER);
+
+    if parts.next()? != API_KEY_PREFIX {
+        return None;
+    }
+
+    let prefix = parts.next()?;
+    let secret = parts.next()?;
+
+    if prefix.len() != PREFIX_LEN || secret.len() != SECRET_LEN {
+        return None;
+    }
+
+    Some(GeneratedKey {
+        prefix: prefix.to_string(),
+        secret: secret.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_and_parse_api_key() {
+        let key = generate_api_key();
+        let plain_text = key.plain_text();
+
+        assert!(
+            is_api_key(&plain_text),
+            "Key should carry the api key prefix"
+        );
+
+        let parsed = parse_api_key(&plain_text).unwrap();
+        assert_eq!(parsed.prefix, key.prefix);
+        assert_eq!(parsed.secret, key.secret);
+    }
+
+    #[test]
+    fn test_parse_invalid_api_key() {
+        assert!(parse_api_key("invalid.jwt.token").is_none());
+        assert!(parse_api_key("sk_short_secret").is_none());
+        assert!(parse_api_key("pk_AbCd1234_0123456789abcdef0123456789abcdef").is_none());
+    }
+}
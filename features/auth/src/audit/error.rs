@@ -0,0 +1,33 @@
+pub type Result<T> = std::result::Result<T, AuditError>;
+
+#[derive(Debug)]
+pub enum AuditError {
+    Open(String),
+    Write(String),
+    Read(String),
+}
+
+impl std::fmt::Display for AuditError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            AuditError::Open(e) => write!(fmt, "Can't open audit log: {e}"),
+            AuditError::Write(e) => write!(fmt, "Can't write audit event: {e}"),
+            AuditError::Read(e) => write!(fmt, "Can't read audit log: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for AuditError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_error_rendering() {
+        assert_eq!(
+            "Can't write audit event: disk full",
+            AuditError::Write("disk full".to_string()).to_string()
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use async_trait::async_trait;
+use std::sync::{Arc, RwLock};
+
+use super::error::{AuditError, Result};
+use super::{AuditEvent, AuditFilter, AuditSink};
+
+pub struct InMemoryAuditSink {
+    entries: Arc<RwLock<Vec<AuditEvent>>>,
+}
+
+impl Default for InMemoryAuditSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryAuditSink {
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for InMemoryAuditSink {
+    async fn record(&self, entry: AuditEvent) -> Result<()> {
+        let mut entries = self
+            .entries
+            .write()
+            .map_err(|e| AuditError::Write(e.to_string()))?;
+
+        entries.push(entry);
+        Ok(())
+    }
+
+    async fn record_all(&self, new_entries: Vec<AuditEvent>) -> Result<()> {
+        let mut entries = self
+            .entries
+            .write()
+            .map_err(|e| AuditError::Write(e.to_string()))?;
+
+        entries.extend(new_entries);
+        Ok(())
+    }
+
+    async fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>> {
+        let entries = self
+            .entries
+            .read()
+            .map_err(|e| AuditError::Read(e.to_string()))?;
+
+        Ok(entries
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry))
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,122 @@
+use async_trait::async_trait;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use super::error::{AuditError, Result};
+use super::{AuditEvent, AuditFilter, AuditSink};
+
+// One JSON object per line, the file is only ever appended to
+pub struct JsonlAuditSink {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl JsonlAuditSink {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| AuditError::Open(e.to_string()))?;
+
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for JsonlAuditSink {
+    async fn record(&self, entry: AuditEvent) -> Result<()> {
+        self.record_all(vec![entry]).await
+    }
+
+    async fn record_all(&self, entries: Vec<AuditEvent>) -> Result<()> {
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(
+                &serde_json::to_string(entry).map_err(|e| AuditError::Write(e.to_string()))?,
+            );
+            lines.push('\n');
+        }
+
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut file = file.lock().map_err(|e| AuditError::Write(e.to_string()))?;
+            // a single write so concurrent entries don't interleave
+            file.write_all(lines.as_bytes())
+                .and_then(|_| file.sync_data())
+                .map_err(|e| AuditError::Write(e.to_string()))
+        })
+        .await
+        .map_err(|e| AuditError::Write(e.to_string()))?
+    }
+
+    async fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>> {
+        let path = self.path.clone();
+        let filter = filter.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let file = File::open(&path).map_err(|e| AuditError::Read(e.to_string()))?;
+
+            let mut entries = Vec::new();
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|e| AuditError::Read(e.to_string()))?;
+                // a torn last line from a crash is skipped rather than failing the query
+                if let Ok(entry) = serde_json::from_str::<AuditEvent>(&line)
+                    && filter.matches(&entry)
+                {
+                    entries.push(entry);
+                }
+            }
+
+            entries.reverse();
+            entries.truncate(filter.limit.unwrap_or(usize::MAX));
+            Ok(entries)
+        })
+        .await
+        .map_err(|e| AuditError::Read(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuthEvent;
+
+    #[tokio::test]
+    async fn test_jsonl_sink_appends() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid::Uuid::new_v4()));
+
+        let sink = JsonlAuditSink::open(&path).unwrap();
+        sink.record(AuditEvent::new(AuthEvent::SignedOut).with_user("u1"))
+            .await
+            .unwrap();
+        drop(sink);
+
+        // reopening keeps what was written
+        let sink = JsonlAuditSink::open(&path).unwrap();
+        sink.record(AuditEvent::new(AuthEvent::PasswordChanged).with_user("u2"))
+            .await
+            .unwrap();
+
+        let entries = sink.query(&AuditFilter::default()).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].event, AuthEvent::PasswordChanged);
+
+        let filter = AuditFilter {
+            user_id: Some("u1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(sink.query(&filter).await.unwrap().len(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::models::ClientInfo;
+
+pub mod error;
+pub mod in_mem_sink;
+pub mod jsonl_sink;
+pub mod sqlite_sink;
+
+use error::Result;
+
+// Security relevant things that happen to accounts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthEvent {
+    Registered {
+        email: String,
+    },
+    // `method` is password, oauth:<provider> or saml:<org>
+    SigninSucceeded {
+        method: String,
+    },
+    SigninFailed {
+        method: String,
+        email: Option<String>,
+        reason: String,
+    },
+    SignedOut,
+    SessionRevoked {
+        session_id: String,
+    },
+    OtherSessionsRevoked {
+        count: usize,
+    },
+    PasswordChanged,
+    ApiKeyCreated {
+        key_id: String,
+        name: String,
+    },
+    ApiKeyRevoked {
+        key_id: String,
+    },
+    ClientAppRegistered {
+        client_id: String,
+    },
+    AuthorizationGranted {
+        client_id: String,
+        scopes: String,
+    },
+    TokenRevoked {
+        client_id: String,
+    },
+    RoleGranted {
+        role: String,
+    },
+    RoleRevoked {
+        role: String,
+    },
+    AccountDisabled {
+        reason: Option<String>,
+    },
+    // the account can be used again at `until`
+    AccountSuspended {
+        until: i64,
+        reason: Option<String>,
+    },
+    // the account can't be used until the email address is verified
+    VerificationRequired {
+        reason: Option<String>,
+    },
+    AccountEnabled,
+    // every session and token of the user was revoked, `count` is how many stored sessions
+    AllSessionsRevoked {
+        count: usize,
+    },
+    EmailVerified,
+    // the password was made unusable and a reset link sent
+    PasswordResetRequired,
+    PasswordReset,
+    // an admin (the actor) started or stopped acting as the user
+    ImpersonationStarted,
+    ImpersonationStopped,
+    // the account is purged at `purge_at`
+    ErasureRequested {
+        purge_at: i64,
+    },
+    AccountDeleted,
+    ProfileUpdated,
+    // `format` is json or zip
+    DataExported {
+        format: String,
+    },
+    EmailChangeRequested {
+        new_email: String,
+    },
+    EmailChanged {
+        old_email: String,
+        new_email: String,
+    },
+    EmailChangeCancelled {
+        new_email: String,
+    },
+    UserProvisioned {
+        org: String,
+    },
+    UserDeprovisioned {
+        org: String,
+    },
+    ScimTokenCreated {
+        org: String,
+    },
+    SsoConnectionImported {
+        org: String,
+    },
+}
+
+impl AuthEvent {
+    pub const KINDS: [&str; 35] = [
+        "registered",
+        "signin_succeeded",
+        "signin_failed",
+        "signed_out",
+        "session_revoked",
+        "other_sessions_revoked",
+        "password_changed",
+        "api_key_created",
+        "api_key_revoked",
+        "client_app_registered",
+        "authorization_granted",
+        "token_revoked",
+        "role_granted",
+        "role_revoked",
+        "account_disabled",
+        "account_suspended",
+        "verification_required",
+        "account_enabled",
+        "all_sessions_revoked",
+        "email_verified",
+        "password_reset_required",
+        "password_reset",
+        "impersonation_started",
+        "impersonation_stopped",
+        "erasure_requested",
+        "account_deleted",
+        "profile_updated",
+        "data_exported",
+        "email_change_requested",
+        "email_changed",
+        "email_change_cancelled",
+        "user_provisioned",
+        "user_deprovisioned",
+        "scim_token_created",
+        "sso_connection_imported",
+    ];
+
+    // the serialized `type` of the event
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuthEvent::Registered { .. } => "registered",
+            AuthEvent::SigninSucceeded { .. } => "signin_succeeded",
+            AuthEvent::SigninFailed { .. } => "signin_failed",
+            AuthEvent::SignedOut => "signed_out",
+            AuthEvent::SessionRevoked { .. } => "session_revoked",
+            AuthEvent::OtherSessionsRevoked { .. } => "other_sessions_revoked",
+            AuthEvent::PasswordChanged => "password_changed",
+            AuthEvent::ApiKeyCreated { .. } => "api_key_created",
+            AuthEvent::ApiKeyRevoked { .. } => "api_key_revoked",
+            AuthEvent::ClientAppRegistered { .. } => "client_app_registered",
+            AuthEvent::AuthorizationGranted { .. } => "authorization_granted",
+            AuthEvent::TokenRevoked { .. } => "token_revoked",
+            AuthEvent::RoleGranted { .. } => "role_granted",
+            AuthEvent::RoleRevoked { .. } => "role_revoked",
+            AuthEvent::AccountDisabled { .. } => "account_disabled",
+            AuthEvent::AccountSuspended { .. } => "account_suspended",
+            AuthEvent::VerificationRequired { .. } => "verification_required",
+            AuthEvent::AccountEnabled => "account_enabled",
+            AuthEvent::AllSessionsRevoked { .. } => "all_sessions_revoked",
+            AuthEvent::EmailVerified => "email_verified",
+            AuthEvent::PasswordResetRequired => "password_reset_required",
+            AuthEvent::PasswordReset => "password_reset",
+            AuthEvent::ImpersonationStarted => "impersonation_started",
+            AuthEvent::ImpersonationStopped => "impersonation_stopped",
+            AuthEvent::ErasureRequested { .. } => "erasure_requested",
+            AuthEvent::AccountDeleted => "account_deleted",
+            AuthEvent::ProfileUpdated => "profile_updated",
+            AuthEvent::DataExported { .. } => "data_exported",
+            AuthEvent::EmailChangeRequested { .. } => "email_change_requested",
+            AuthEvent::EmailChanged { .. } => "email_changed",
+            AuthEvent::EmailChangeCancelled { .. } => "email_change_cancelled",
+            AuthEvent::UserProvisioned { .. } => "user_provisioned",
+            AuthEvent::UserDeprovisioned { .. } => "user_deprovisioned",
+            AuthEvent::ScimTokenCreated { .. } => "scim_token_created",
+            AuthEvent::SsoConnectionImported { .. } => "sso_connection_imported",
+        }
+    }
+
+    // the event specific fields, e.g. {"email":"..."}
+    pub fn details(&self) -> serde_json::Map<String, serde_json::Value> {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(mut fields)) => {
+                fields.remove("type");
+                fields
+            }
+            _ => serde_json::Map::new(),
+        }
+    }
+}
+
+// An entry of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: String,
+    pub timestamp: i64,
+    // who did it, a user id or a system principal such as scim:<org>
+    pub actor: Option<String>,
+    // the user it happened to
+    pub subject: Option<String>,
+    pub ip: Option<String>,
+    #[serde(flatten)]
+    pub event: AuthEvent,
+}
+
+impl AuditEvent {
+    pub fn new(event: AuthEvent) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+            actor: None,
+            subject: None,
+            ip: None,
+            event,
+        }
+    }
+
+    pub fn with_actor(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_string());
+        self
+    }
+
+    pub fn with_subject(mut self, subject: &str) -> Self {
+        self.subject = Some(subject.to_string());
+        self
+    }
+
+    // most events are done by the user to their own account
+    pub fn with_user(self, user_id: &str) -> Self {
+        self.with_actor(user_id).with_subject(user_id)
+    }
+
+    pub fn with_client(mut self, client: &ClientInfo) -> Self {
+        self.ip = client.ip.clone();
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    // matches the actor or the subject
+    pub user_id: Option<String>,
+    pub kind: Option<String>,
+    // unix timestamps, inclusive
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEvent) -> bool {
+        let user_matches = self.user_id.as_ref().is_none_or(|user_id| {
+            entry.actor.as_ref() == Some(user_id) || entry.subject.as_ref() == Some(user_id)
+        });
+
+        user_matches
+            && self
+                .kind
+                .as_ref()
+                .is_none_or(|kind| entry.event.kind() == kind)
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp <= until)
+    }
+}
+
+// Where audit events go, entries are never updated or deleted
+#[async_trait]
+pub trait AuditSink: Send + Sync + 'static {
+    async fn record(&self, entry: AuditEvent) -> Result<()>;
+    // the events of a unit of work, sinks that can record them all or none should
+    async fn record_all(&self, entries: Vec<AuditEvent>) -> Result<()> {
+        for entry in entries {
+            self.record(entry).await?;
+        }
+        Ok(())
+    }
+    // newest first
+    async fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() {
+        let entry = AuditEvent::new(AuthEvent::SigninFailed {
+            method: "password".to_string(),
+            email: Some("jane@example.com".to_string()),
+            reason: "Invalid credentials".to_string(),
+        })
+        .with_client(&ClientInfo {
+            ip: Some("203.0.113.7".to_string()),
+            user_agent: None,
+        });
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["type"], "signin_failed");
+        assert_eq!(json["email"], "jane@example.com");
+        assert_eq!(json["ip"], "203.0.113.7");
+        assert_eq!(serde_json::from_value::<AuditEvent>(json).unwrap(), entry);
+
+        assert!(AuthEvent::KINDS.contains(&entry.event.kind()));
+        assert_eq!(entry.event.details().len(), 3);
+
+        // entries written before disabling took a reason still read
+        let event: AuthEvent = serde_json::from_str(r#"{"type":"account_disabled"}"#).unwrap();
+        assert_eq!(event, AuthEvent::AccountDisabled { reason: None });
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut entry = AuditEvent::new(AuthEvent::SignedOut).with_user("u1");
+        entry.timestamp = 100;
+
+        let filter = |user_id: Option<&str>, kind: Option<&str>, since, until| AuditFilter {
+            user_id: user_id.map(str::to_string),
+            kind: kind.map(str::to_string),
+            since,
+            until,
+            limit: None,
+        };
+
+        assert!(filter(None, None, None, None).matches(&entry));
+        assert!(filter(Some("u1"), Some("signed_out"), Some(100), Some(100)).matches(&entry));
+        assert!(!filter(Some("u2"), None, None, None).matches(&entry));
+        assert!(!filter(None, Some("registered"), None, None).matches(&entry));
+        assert!(!filter(None, None, Some(101), None).matches(&entry));
+        assert!(!filter(None, None, None, Some(99)).matches(&entry));
+    }
+}
@@ -0,0 +1,192 @@
+use async_trait::async_trait;
+use rusqlite::{Connection, params_from_iter, types::Value};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::error::{AuditError, Result};
+use super::{AuditEvent, AuditFilter, AuditSink};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS audit_events (
+        id TEXT PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        actor TEXT,
+        subject TEXT,
+        ip TEXT,
+        entry TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS audit_events_timestamp ON audit_events (timestamp);
+    CREATE INDEX IF NOT EXISTS audit_events_actor ON audit_events (actor);
+    CREATE INDEX IF NOT EXISTS audit_events_subject ON audit_events (subject);
+    -- the log is append only
+    CREATE TRIGGER IF NOT EXISTS audit_events_no_update BEFORE UPDATE ON audit_events
+    BEGIN SELECT RAISE(ABORT, 'audit events are append only'); END;
+    CREATE TRIGGER IF NOT EXISTS audit_events_no_delete BEFORE DELETE ON audit_events
+    BEGIN SELECT RAISE(ABORT, 'audit events are append only'); END;
+";
+
+pub struct SqliteAuditSink {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteAuditSink {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(Connection::open(path).map_err(|e| AuditError::Open(e.to_string()))?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory().map_err(|e| AuditError::Open(e.to_string()))?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| AuditError::Open(e.to_string()))?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+}
+
+#[async_trait]
+impl AuditSink for SqliteAuditSink {
+    async fn record(&self, entry: AuditEvent) -> Result<()> {
+        self.record_all(vec![entry]).await
+    }
+
+    // in one transaction, either every entry is recorded or none is
+    async fn record_all(&self, entries: Vec<AuditEvent>) -> Result<()> {
+        let mut rows = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let json =
+                serde_json::to_string(&entry).map_err(|e| AuditError::Write(e.to_string()))?;
+            rows.push((entry, json));
+        }
+
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|e| AuditError::Write(e.to_string()))?;
+            let write_error = |e: rusqlite::Error| AuditError::Write(e.to_string());
+
+            let tx = conn.transaction().map_err(write_error)?;
+            for (entry, json) in rows {
+                tx.execute(
+                    "INSERT INTO audit_events (id, timestamp, kind, actor, subject, ip, entry)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    rusqlite::params![
+                        entry.id,
+                        entry.timestamp,
+                        entry.event.kind(),
+                        entry.actor,
+                        entry.subject,
+                        entry.ip,
+                        json,
+                    ],
+                )
+                .map_err(write_error)?;
+            }
+            tx.commit().map_err(write_error)
+        })
+        .await
+        .map_err(|e| AuditError::Write(e.to_string()))?
+    }
+
+    async fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<Value> = Vec::new();
+        if let Some(user_id) = &filter.user_id {
+            values.push(Value::Text(user_id.clone()));
+            conditions.push(format!("(actor = ?{0} OR subject = ?{0})", values.len()));
+        }
+        if let Some(kind) = &filter.kind {
+            values.push(Value::Text(kind.clone()));
+            conditions.push(format!("kind = ?{}", values.len()));
+        }
+        if let Some(since) = filter.since {
+            values.push(Value::Integer(since));
+            conditions.push(format!("timestamp >= ?{}", values.len()));
+        }
+        if let Some(until) = filter.until {
+            values.push(Value::Integer(until));
+            conditions.push(format!("timestamp <= ?{}", values.len()));
+        }
+
+        let mut sql = "SELECT entry FROM audit_events".to_string();
+        if !conditions.is_empty() {
+            sql.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        // rowid keeps insertion order for entries recorded in the same second
+        sql.push_str(" ORDER BY timestamp DESC, rowid DESC");
+        if let Some(limit) = filter.limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock().map_err(|e| AuditError::Read(e.to_string()))?;
+            let read_error = |e: rusqlite::Error| AuditError::Read(e.to_string());
+
+            let mut statement = conn.prepare(&sql).map_err(read_error)?;
+            let rows = statement
+                .query_map(params_from_iter(values), |row| row.get::<_, String>(0))
+                .map_err(read_error)?;
+
+            rows.map(|json| {
+                let json = json.map_err(read_error)?;
+                serde_json::from_str(&json).map_err(|e| AuditError::Read(e.to_string()))
+            })
+            .collect()
+        })
+        .await
+        .map_err(|e| AuditError::Read(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuthEvent;
+
+    #[tokio::test]
+    async fn test_sqlite_sink() {
+        let sink = SqliteAuditSink::open_in_memory().unwrap();
+
+        let mut registered = AuditEvent::new(AuthEvent::Registered {
+            email: "jane@example.com".to_string(),
+        })
+        .with_user("u1");
+        registered.timestamp = 100;
+        sink.record(registered.clone()).await.unwrap();
+        let mut role = AuditEvent::new(AuthEvent::RoleGranted {
+            role: "admin".to_string(),
+        })
+        .with_actor("u2")
+        .with_subject("u1");
+        role.timestamp = 200;
+        sink.record(role.clone()).await.unwrap();
+
+        let all = sink.query(&AuditFilter::default()).await.unwrap();
+        assert_eq!(all, vec![role.clone(), registered.clone()]);
+
+        let filter = AuditFilter {
+            user_id: Some("u2".to_string()),
+            kind: Some("role_granted".to_string()),
+            since: Some(150),
+            until: Some(250),
+            limit: Some(10),
+        };
+        assert_eq!(sink.query(&filter).await.unwrap(), vec![role]);
+
+        // a batch with a clash records nothing
+        let signed_out = AuditEvent::new(AuthEvent::SignedOut).with_user("u1");
+        assert!(sink.record_all(vec![signed_out, registered]).await.is_err());
+        assert_eq!(sink.query(&AuditFilter::default()).await.unwrap().len(), 2);
+
+        // entries can't be tampered with
+        let conn = sink.conn.lock().unwrap();
+        assert!(conn.execute("DELETE FROM audit_events", []).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+pub type Result<T> = std::result::Result<T, AuthzError>;
+
+// Error responses defined by RFC 6749
+#[derive(Debug)]
+pub enum AuthzError {
+    InvalidRequest(String),
+    InvalidClient,
+    InvalidGrant,
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    InvalidScope,
+    AccessDenied,
+}
+
+impl AuthzError {
+    // value of the `error` field in responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthzError::InvalidRequest(_) => "invalid_request",
+            AuthzError::InvalidClient => "invalid_client",
+            AuthzError::InvalidGrant => "invalid_grant",
+            AuthzError::UnauthorizedClient => "unauthorized_client",
+            AuthzError::UnsupportedGrantType => "unsupported_grant_type",
+            AuthzError::UnsupportedResponseType => "unsupported_response_type",
+            AuthzError::InvalidScope => "invalid_scope",
+            AuthzError::AccessDenied => "access_denied",
+        }
+    }
+}
+
+impl std::fmt::Display for AuthzError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            AuthzError::InvalidRequest(e) => write!(fmt, "{}: {e}", self.code()),
+            _ => write!(fmt, "{}", self.code()),
+        }
+    }
+}
+
+impl std::error::Error for AuthzError {}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_authz_error_rendering() {
+        assert_eq!("invalid_grant", AuthzError::InvalidGrant.to_string());
+        assert_eq!(
+            "invalid_request: missing code",
+            AuthzError::InvalidRequest("missing code".to_string()).to_string()
+        );
+    }
+}
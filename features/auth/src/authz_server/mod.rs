@@ -0,0 +1,861 @@
+pub mod error;
+pub mod oidc;
+
+use std::sync::Arc;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use url::Url;
+use uuid::Uuid;
+
+use crate::error::{AuthError, Result};
+use crate::jwt::{JwtClaims, JwtService, TokenUse};
+use crate::models::{AuthorizationCode, ClientApp, NewClientApp, RefreshToken, Scope, User};
+use crate::oauth::pkce;
+use crate::password::{self, hash_password, verify_password};
+use crate::repository::in_mem_client_app_repo::InMemoryClientAppRepository;
+use crate::repository::in_mem_grant_repo::InMemoryGrantRepository;
+use crate::repository::{ClientAppRepositoryTrait, GrantRepositoryTrait};
+use crate::utils::random_string;
+
+use error::AuthzError;
+use oidc::{IdTokenClaims, Jwks, ProviderMetadata, SigningKey, UserInfo};
+
+const CODE_TTL_SECONDS: i64 = 60;
+const ACCESS_TOKEN_MINUTES: i64 = 60;
+const REFRESH_TOKEN_DAYS: i64 = 30;
+const ID_TOKEN_MINUTES: i64 = 60;
+const CLIENT_SECRET_LEN: usize = 48;
+const CODE_LEN: usize = 32;
+
+// Query parameters of the authorization endpoint
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuthorizeParams {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub nonce: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+impl ClientCredentials {
+    // client_secret_basic, `Authorization: Basic base64(client_id:client_secret)`
+    pub fn from_basic_auth(header: &str) -> Option<Self> {
+        let encoded = header.strip_prefix("Basic ")?;
+        let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+        let (client_id, client_secret) = decoded.split_once(':')?;
+
+        Some(Self {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    // client_secret_post, used when no basic auth header is sent
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: i64,
+    pub refresh_token: String,
+    pub scope: String,
+    // only when the openid scope was granted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+// Tokens along with who they were issued to, needed to add an id token for openid grants
+#[derive(Debug, Clone)]
+pub struct IssuedTokens {
+    pub response: TokenResponse,
+    pub client_id: String,
+    pub user_id: String,
+    pub scopes: Vec<Scope>,
+    pub nonce: Option<String>,
+}
+
+// RFC 7662 response, only `active` is set for inactive tokens
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Introspection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+}
+
+// What a third party client was granted, attached to requests made with its access token
+#[derive(Debug, Clone)]
+pub struct AccessGrant {
+    pub client_id: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl AccessGrant {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+pub struct AuthorizationServer {
+    client_repo: Arc<dyn ClientAppRepositoryTrait>,
+    grant_repo: Arc<dyn GrantRepositoryTrait>,
+    jwt_service: Arc<JwtService>,
+    issuer: String,
+    signing_key: Arc<SigningKey>,
+}
+
+impl AuthorizationServer {
+    pub fn new(jwt_service: Arc<JwtService>) -> Self {
+        Self {
+            client_repo: Arc::new(InMemoryClientAppRepository::new()),
+            grant_repo: Arc::new(InMemoryGrantRepository::new()),
+            jwt_service,
+            issuer: "http://localhost:3000".to_string(),
+            signing_key: Arc::new(SigningKey::generate()),
+        }
+    }
+
+    // public base url of the app, id tokens are issued under it
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.issuer = issuer.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_signing_key(mut self, signing_key: Arc<SigningKey>) -> Self {
+        self.signing_key = signing_key;
+        self
+    }
+
+    pub fn with_client_repo(mut self, client_repo: Arc<dyn ClientAppRepositoryTrait>) -> Self {
+        self.client_repo = client_repo;
+        self
+    }
+
+    pub fn with_grant_repo(mut self, grant_repo: Arc<dyn GrantRepositoryTrait>) -> Self {
+        self.grant_repo = grant_repo;
+        self
+    }
+
+    // returns the stored client along with the plain text secret, which is only shown once
+    pub async fn register_client(
+        &self,
+        owner_id: &str,
+        new_client: NewClientApp,
+    ) -> Result<(ClientApp, String)> {
+        if new_client.name.trim().is_empty() {
+            return Err(invalid_request("name is required"));
+        }
+        if new_client.redirect_uris.is_empty() || new_client.scopes.is_empty() {
+            return Err(invalid_request("redirect uri and scope are required"));
+        }
+        for redirect_uri in &new_client.redirect_uris {
+            match Url::parse(redirect_uri) {
+                Ok(url) if url.fragment().is_none() => {}
+                _ => return Err(invalid_request("invalid redirect uri")),
+            }
+        }
+
+        let secret = random_string(CLIENT_SECRET_LEN);
+        let secret_hash = hash_password(&password::ContentToHash {
+            content: secret.clone(),
+            salt: Uuid::new_v4(),
+        })?;
+
+        let client_app = ClientApp {
+            id: Uuid::new_v4().to_string(),
+            owner_id: owner_id.to_string(),
+            name: new_client.name,
+            secret_hash,
+            redirect_uris: new_client.redirect_uris,
+            scopes: new_client.scopes,
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+        let client_app = self.client_repo.create_client_app(client_app).await?;
+
+        Ok((client_app, secret))
+    }
+
+    pub async fn list_clients(&self, owner_id: &str) -> Result<Vec<ClientApp>> {
+        Ok(self.client_repo.list_by_owner(owner_id).await?)
+    }
+
+    // Tokens already issued to the clients stay valid until they expire or are revoked
+    pub async fn delete_clients(&self, owner_id: &str) -> Result<usize> {
+        Ok(self.client_repo.delete_by_owner(owner_id).await?)
+    }
+
+    // Checks an authorization request before showing the consent page
+    pub async fn validate_authorize(
+        &self,
+        params: &AuthorizeParams,
+    ) -> Result<(ClientApp, Vec<Scope>)> {
+        let client_app = self
+            .client_repo
+            .find_client_app(&params.client_id)
+            .await?
+            .ok_or(AuthError::Authz(AuthzError::InvalidClient))?;
+
+        // never redirect to an uri that wasn't registered
+        if !client_app.redirect_uris.contains(&params.redirect_uri) {
+            return Err(invalid_request("redirect_uri"));
+        }
+        if params.response_type != "code" {
+            return Err(AuthError::Authz(AuthzError::UnsupportedResponseType));
+        }
+
+        // PKCE is required for every client
+        match (&params.code_challenge, &params.code_challenge_method) {
+            (Some(challenge), Some(method))
+                if !challenge.is_empty() && method == pkce::CHALLENGE_METHOD => {}
+            _ => return Err(invalid_request("code_challenge")),
+        }
+
+        // identity scopes only reveal the user's own profile, any client may ask for them
+        let allowed: Vec<Scope> = client_app
+            .scopes
+            .iter()
+            .chain(Scope::OIDC.iter())
+            .copied()
+            .collect();
+        let scopes = requested_scopes(params.scope.as_deref(), &client_app.scopes, &allowed)?;
+
+        Ok((client_app, scopes))
+    }
+
+    // The user approved the request, returns where to send them back to
+    pub async fn approve(&self, user_id: &str, params: &AuthorizeParams) -> Result<String> {
+        let (client_app, scopes) = self.validate_authorize(params).await?;
+
+        let code = self
+            .grant_repo
+            .create_code(AuthorizationCode {
+                code: random_string(CODE_LEN),
+                client_id: client_app.id,
+                user_id: user_id.to_string(),
+                redirect_uri: params.redirect_uri.clone(),
+                scopes,
+                code_challenge: params.code_challenge.clone().unwrap_or_default(),
+                nonce: params.nonce.clone(),
+                expires_at: OffsetDateTime::now_utc().unix_timestamp() + CODE_TTL_SECONDS,
+            })
+            .await?;
+
+        redirect_with(
+            &params.redirect_uri,
+            &[("code", Some(&code.code)), ("state", params.state.as_ref())],
+        )
+    }
+
+    pub async fn deny(&self, params: &AuthorizeParams) -> Result<String> {
+        self.validate_authorize(params).await?;
+
+        redirect_with(
+            &params.redirect_uri,
+            &[
+                ("error", Some(&AuthzError::AccessDenied.code().to_string())),
+                ("state", params.state.as_ref()),
+            ],
+        )
+    }
+
+    pub async fn token(
+        &self,
+        creds: &ClientCredentials,
+        request: TokenRequest,
+    ) -> Result<IssuedTokens> {
+        let client_app = self.authenticate_client(creds).await?;
+
+        match request.grant_type.as_str() {
+            "authorization_code" => self.authorization_code_grant(&client_app, request).await,
+            "refresh_token" => self.refresh_token_grant(&client_app, request).await,
+            _ => Err(AuthError::Authz(AuthzError::UnsupportedGrantType)),
+        }
+    }
+
+    async fn authorization_code_grant(
+        &self,
+        client_app: &ClientApp,
+        request: TokenRequest,
+    ) -> Result<IssuedTokens> {
+        let code = request.code.ok_or_else(|| invalid_request("code"))?;
+        let code_verifier = request
+            .code_verifier
+            .ok_or_else(|| invalid_request("code_verifier"))?;
+
+        let grant = self
+            .grant_repo
+            .take_code(&code)
+            .await?
+            .ok_or(AuthError::Authz(AuthzError::InvalidGrant))?;
+
+        if grant.client_id != client_app.id
+            || grant.expires_at <= OffsetDateTime::now_utc().unix_timestamp()
+            || request.redirect_uri.as_deref() != Some(grant.redirect_uri.as_str())
+            || pkce::challenge(&code_verifier) != grant.code_challenge
+        {
+            return Err(AuthError::Authz(AuthzError::InvalidGrant));
+        }
+
+        self.issue_tokens(&client_app.id, &grant.user_id, &grant.scopes, grant.nonce)
+            .await
+    }
+
+    async fn refresh_token_grant(
+        &self,
+        client_app: &ClientApp,
+        request: TokenRequest,
+    ) -> Result<IssuedTokens> {
+        let refresh_token = request
+            .refresh_token
+            .ok_or_else(|| invalid_request("refresh_token"))?;
+
+        let claims = self
+            .jwt_service
+            .validate_token(&refresh_token)
+            .map_err(|_| AuthError::Authz(AuthzError::InvalidGrant))?;
+
+        if claims.token_use != Some(TokenUse::Refresh)
+            || claims.client_id.as_deref() != Some(client_app.id.as_str())
+        {
+            return Err(AuthError::Authz(AuthzError::InvalidGrant));
+        }
+
+        let mut stored = match self.grant_repo.find_refresh_token(&claims.jti).await? {
+            Some(stored) if stored.revoked_at.is_none() => stored,
+            _ => return Err(AuthError::Authz(AuthzError::InvalidGrant)),
+        };
+
+        // a refresh can narrow the scope but never widen it
+        let scopes = requested_scopes(request.scope.as_deref(), &stored.scopes, &stored.scopes)?;
+
+        // refresh tokens are rotated on use
+        stored.revoked_at = Some(OffsetDateTime::now_utc().unix_timestamp());
+        self.grant_repo.update_refresh_token(&stored).await?;
+
+        self.issue_tokens(&client_app.id, &stored.user_id, &scopes, None)
+            .await
+    }
+
+    async fn issue_tokens(
+        &self,
+        client_id: &str,
+        user_id: &str,
+        scopes: &[Scope],
+        nonce: Option<String>,
+    ) -> Result<IssuedTokens> {
+        let scope = Scope::join(scopes);
+        let access_duration = Duration::minutes(ACCESS_TOKEN_MINUTES);
+
+        let (access_token, _) = self.jwt_service.generate_client_token(
+            user_id,
+            client_id,
+            &scope,
+            TokenUse::Access,
+            access_duration,
+        )?;
+
+        let (refresh_token, refresh_claims) = self.jwt_service.generate_client_token(
+            user_id,
+            client_id,
+            &scope,
+            TokenUse::Refresh,
+            Duration::days(REFRESH_TOKEN_DAYS),
+        )?;
+
+        self.grant_repo
+            .create_refresh_token(RefreshToken {
+                jti: refresh_claims.jti,
+                client_id: client_id.to_string(),
+                user_id: user_id.to_string(),
+                scopes: scopes.to_vec(),
+                expires_at: refresh_claims.exp,
+                revoked_at: None,
+            })
+            .await?;
+
+        Ok(IssuedTokens {
+            response: TokenResponse {
+                access_token,
+                token_type: "Bearer",
+                expires_in: access_duration.whole_seconds(),
+                refresh_token,
+                scope,
+                id_token: None,
+            },
+            client_id: client_id.to_string(),
+            user_id: user_id.to_string(),
+            scopes: scopes.to_vec(),
+            nonce,
+        })
+    }
+
+    // OpenID Connect id token, `user` is who the tokens were issued for
+    pub fn id_token(&self, user: &User, issued: &IssuedTokens) -> Result<String> {
+        let now = OffsetDateTime::now_utc();
+
+        self.signing_key.sign(&IdTokenClaims {
+            iss: self.issuer.clone(),
+            aud: issued.client_id.clone(),
+            exp: (now + Duration::minutes(ID_TOKEN_MINUTES)).unix_timestamp(),
+            iat: now.unix_timestamp(),
+            nonce: issued.nonce.clone(),
+            user_info: UserInfo::from_user(user, &issued.scopes),
+        })
+    }
+
+    pub fn metadata(&self) -> ProviderMetadata {
+        ProviderMetadata::new(&self.issuer)
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        self.signing_key.jwks()
+    }
+
+    pub async fn introspect(
+        &self,
+        creds: &ClientCredentials,
+        token: &str,
+    ) -> Result<Introspection> {
+        let client_app = self.authenticate_client(creds).await?;
+
+        let claims = match self.active_claims(token).await? {
+            // clients can only introspect their own tokens
+            Some(claims) if claims.client_id.as_deref() == Some(client_app.id.as_str()) => claims,
+            _ => return Ok(Introspection::default()),
+        };
+
+        Ok(Introspection {
+            active: true,
+            scope: claims.scope,
+            client_id: claims.client_id,
+            sub: Some(claims.sub),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            token_type: match claims.token_use {
+                Some(TokenUse::Access) => Some("Bearer".to_string()),
+                _ => Some("refresh_token".to_string()),
+            },
+        })
+    }
+
+    // RFC 7009, revoking an unknown or invalid token is not an error
+    pub async fn revoke(&self, creds: &ClientCredentials, token: &str) -> Result<()> {
+        let client_app = self.authenticate_client(creds).await?;
+
+        let claims = match self.jwt_service.validate_token(token) {
+            Ok(claims) => claims,
+            Err(_) => return Ok(()),
+        };
+
+        if claims.client_id.as_deref() != Some(client_app.id.as_str()) {
+            return Err(AuthError::Authz(AuthzError::UnauthorizedClient));
+        }
+
+        match claims.token_use {
+            Some(TokenUse::Access) => {
+                self.grant_repo
+                    .revoke_access_token(&claims.jti, claims.exp)
+                    .await?;
+            }
+            Some(TokenUse::Refresh) => {
+                if let Some(mut stored) = self.grant_repo.find_refresh_token(&claims.jti).await?
+                    && stored.revoked_at.is_none()
+                {
+                    stored.revoked_at = Some(OffsetDateTime::now_utc().unix_timestamp());
+                    self.grant_repo.update_refresh_token(&stored).await?;
+                }
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    // Revokes every refresh token issued for the user, access tokens are short lived
+    // and checked against the user by the caller
+    pub async fn revoke_user_tokens(&self, user_id: &str) -> Result<()> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        self.grant_repo
+            .revoke_refresh_tokens_by_user(user_id, now)
+            .await?;
+        Ok(())
+    }
+
+    // Validates an access token sent by a third party client to our api
+    pub async fn validate_access_token(&self, token: &str) -> Result<(JwtClaims, AccessGrant)> {
+        let claims = self.jwt_service.validate_token(token)?;
+
+        if claims.token_use != Some(TokenUse::Access) {
+            return Err(AuthError::Unauthorized);
+        }
+        if self.grant_repo.is_access_token_revoked(&claims.jti).await? {
+            return Err(AuthError::Unauthorized);
+        }
+
+        let grant = AccessGrant {
+            client_id: claims.client_id.clone().ok_or(AuthError::Unauthorized)?,
+            scopes: Scope::parse_list(claims.scope.as_deref().unwrap_or_default())?,
+        };
+
+        Ok((claims, grant))
+    }
+
+    async fn active_claims(&self, token: &str) -> Result<Option<JwtClaims>> {
+        let claims = match self.jwt_service.validate_token(token) {
+            Ok(claims) => claims,
+            Err(_) => return Ok(None),
+        };
+
+        let active = match claims.token_use {
+            Some(TokenUse::Access) => !self.grant_repo.is_access_token_revoked(&claims.jti).await?,
+            Some(TokenUse::Refresh) => self
+                .grant_repo
+                .find_refresh_token(&claims.jti)
+                .await?
+                .is_some_and(|stored| stored.revoked_at.is_none()),
+            None => false,
+        };
+
+        Ok(active.then_some(claims))
+    }
+
+    async fn authenticate_client(&self, creds: &ClientCredentials) -> Result<ClientApp> {
+        let client_app = self
+            .client_repo
+            .find_client_app(&creds.client_id)
+            .await?
+            .ok_or(AuthError::Authz(AuthzError::InvalidClient))?;
+
+        verify_password(&creds.client_secret, &client_app.secret_hash)
+            .map_err(|_| AuthError::Authz(AuthzError::InvalidClient))?;
+
+        Ok(client_app)
+    }
+}
+
+// An empty request gets the default, everything the client (or the original grant) allows
+fn requested_scopes(
+    requested: Option<&str>,
+    default: &[Scope],
+    allowed: &[Scope],
+) -> Result<Vec<Scope>> {
+    let scopes = Scope::parse_list(requested.unwrap_or_default())
+        .map_err(|_| AuthError::Authz(AuthzError::InvalidScope))?;
+
+    if scopes.is_empty() {
+        return Ok(default.to_vec());
+    }
+    if scopes.iter().any(|s| !allowed.contains(s)) {
+        return Err(AuthError::Authz(AuthzError::InvalidScope));
+    }
+
+    Ok(scopes)
+}
+
+fn redirect_with(redirect_uri: &str, params: &[(&str, Option<&String>)]) -> Result<String> {
+    let mut url = Url::parse(redirect_uri).map_err(|_| invalid_request("redirect_uri"))?;
+    for (name, value) in params {
+        if let Some(value) = value {
+            url.query_pairs_mut().append_pair(name, value);
+        }
+    }
+
+    Ok(url.to_string())
+}
+
+fn invalid_request(reason: &str) -> AuthError {
+    AuthError::Authz(AuthzError::InvalidRequest(reason.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REDIRECT_URI: &str = "https://partner.example.com/callback";
+    const OWNER_ID: &str = "owner-123";
+    const USER_ID: &str = "user-123";
+
+    async fn setup() -> (AuthorizationServer, ClientCredentials) {
+        let server = AuthorizationServer::new(Arc::new(JwtService::new(b"test_secret", 24)));
+        let (client_app, secret) = server
+            .register_client(
+                OWNER_ID,
+                NewClientApp {
+                    name: "Partner".to_string(),
+                    redirect_uris: vec![REDIRECT_URI.to_string()],
+                    scopes: vec![Scope::Read, Scope::Write],
+                },
+            )
+            .await
+            .unwrap();
+
+        let creds = ClientCredentials {
+            client_id: client_app.id,
+            client_secret: secret,
+        };
+        (server, creds)
+    }
+
+    fn authorize_params(client_id: &str, verifier: &str, scope: &str) -> AuthorizeParams {
+        AuthorizeParams {
+            response_type: "code".to_string(),
+            client_id: client_id.to_string(),
+            redirect_uri: REDIRECT_URI.to_string(),
+            scope: Some(scope.to_string()),
+            state: Some("xyz".to_string()),
+            code_challenge: Some(pkce::challenge(verifier)),
+            code_challenge_method: Some("S256".to_string()),
+            nonce: None,
+        }
+    }
+
+    fn code_from_redirect(redirect: &str) -> String {
+        let url = Url::parse(redirect).unwrap();
+        assert_eq!(
+            url.query_pairs().find(|(k, _)| k == "state").unwrap().1,
+            "xyz"
+        );
+        url.query_pairs()
+            .find(|(k, _)| k == "code")
+            .unwrap()
+            .1
+            .to_string()
+    }
+
+    fn code_request(code: &str, verifier: &str) -> TokenRequest {
+        TokenRequest {
+            grant_type: "authorization_code".to_string(),
+            code: Some(code.to_string()),
+            redirect_uri: Some(REDIRECT_URI.to_string()),
+            code_verifier: Some(verifier.to_string()),
+            refresh_token: None,
+            scope: None,
+            client_id: None,
+            client_secret: None,
+        }
+    }
+
+    fn refresh_request(refresh_token: &str, scope: Option<&str>) -> TokenRequest {
+        TokenRequest {
+            grant_type: "refresh_token".to_string(),
+            code: None,
+            redirect_uri: None,
+            code_verifier: None,
+            refresh_token: Some(refresh_token.to_string()),
+            scope: scope.map(str::to_string),
+            client_id: None,
+            client_secret: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_flow() {
+        let (server, creds) = setup().await;
+        let verifier = pkce::generate_verifier();
+
+        let params = authorize_params(&creds.client_id, &verifier, "read");
+        let redirect = server.approve(USER_ID, &params).await.unwrap();
+        let code = code_from_redirect(&redirect);
+
+        let tokens = server
+            .token(&creds, code_request(&code, &verifier))
+            .await
+            .unwrap()
+            .response;
+        assert_eq!(tokens.scope, "read");
+        assert_eq!(tokens.token_type, "Bearer");
+
+        let (claims, grant) = server
+            .validate_access_token(&tokens.access_token)
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, USER_ID);
+        assert!(grant.has_scope(Scope::Read));
+        assert!(!grant.has_scope(Scope::Write));
+
+        // codes are single use
+        let replay = server.token(&creds, code_request(&code, &verifier)).await;
+        assert!(matches!(
+            replay,
+            Err(AuthError::Authz(AuthzError::InvalidGrant))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pkce_and_client_checks() {
+        let (server, creds) = setup().await;
+        let verifier = pkce::generate_verifier();
+
+        let params = authorize_params(&creds.client_id, &verifier, "read");
+        let code = code_from_redirect(&server.approve(USER_ID, &params).await.unwrap());
+
+        let wrong_secret = ClientCredentials {
+            client_id: creds.client_id.clone(),
+            client_secret: "wrong".to_string(),
+        };
+        let result = server
+            .token(&wrong_secret, code_request(&code, &verifier))
+            .await;
+        assert!(matches!(
+            result,
+            Err(AuthError::Authz(AuthzError::InvalidClient))
+        ));
+
+        let result = server
+            .token(&creds, code_request(&code, "wrong-verifier"))
+            .await;
+        assert!(matches!(
+            result,
+            Err(AuthError::Authz(AuthzError::InvalidGrant))
+        ));
+
+        // unregistered redirect uri and unknown scope are rejected up front
+        let mut params = authorize_params(&creds.client_id, &verifier, "read");
+        params.redirect_uri = "https://evil.example.com/callback".to_string();
+        assert!(server.validate_authorize(&params).await.is_err());
+
+        let params = authorize_params(&creds.client_id, &verifier, "admin");
+        assert!(matches!(
+            server.validate_authorize(&params).await,
+            Err(AuthError::Authz(AuthzError::InvalidScope))
+        ));
+
+        let mut params = authorize_params(&creds.client_id, &verifier, "read");
+        params.code_challenge = None;
+        assert!(server.validate_authorize(&params).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotation_and_scope_narrowing() {
+        let (server, creds) = setup().await;
+        let verifier = pkce::generate_verifier();
+
+        let params = authorize_params(&creds.client_id, &verifier, "read write");
+        let code = code_from_redirect(&server.approve(USER_ID, &params).await.unwrap());
+        let tokens = server
+            .token(&creds, code_request(&code, &verifier))
+            .await
+            .unwrap()
+            .response;
+
+        let refreshed = server
+            .token(&creds, refresh_request(&tokens.refresh_token, Some("read")))
+            .await
+            .unwrap()
+            .response;
+        assert_eq!(refreshed.scope, "read");
+
+        // the old refresh token was rotated out
+        let reuse = server
+            .token(&creds, refresh_request(&tokens.refresh_token, None))
+            .await;
+        assert!(matches!(
+            reuse,
+            Err(AuthError::Authz(AuthzError::InvalidGrant))
+        ));
+
+        // can't widen back to write
+        let widen = server
+            .token(
+                &creds,
+                refresh_request(&refreshed.refresh_token, Some("read write")),
+            )
+            .await;
+        assert!(matches!(
+            widen,
+            Err(AuthError::Authz(AuthzError::InvalidScope))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_introspection_and_revocation() {
+        let (server, creds) = setup().await;
+        let verifier = pkce::generate_verifier();
+
+        let params = authorize_params(&creds.client_id, &verifier, "read");
+        let code = code_from_redirect(&server.approve(USER_ID, &params).await.unwrap());
+        let tokens = server
+            .token(&creds, code_request(&code, &verifier))
+            .await
+            .unwrap()
+            .response;
+
+        let introspection = server
+            .introspect(&creds, &tokens.access_token)
+            .await
+            .unwrap();
+        assert!(introspection.active);
+        assert_eq!(introspection.sub.as_deref(), Some(USER_ID));
+        assert_eq!(introspection.scope.as_deref(), Some("read"));
+
+        server.revoke(&creds, &tokens.access_token).await.unwrap();
+        assert!(
+            !server
+                .introspect(&creds, &tokens.access_token)
+                .await
+                .unwrap()
+                .active
+        );
+        assert!(
+            server
+                .validate_access_token(&tokens.access_token)
+                .await
+                .is_err()
+        );
+
+        server.revoke(&creds, &tokens.refresh_token).await.unwrap();
+        assert!(
+            !server
+                .introspect(&creds, &tokens.refresh_token)
+                .await
+                .unwrap()
+                .active
+        );
+
+        // garbage tokens are inactive, revoking them is a no-op
+        assert!(!server.introspect(&creds, "garbage").await.unwrap().active);
+        assert!(server.revoke(&creds, "garbage").await.is_ok());
+    }
+
+    #[test]
+    fn test_basic_auth_credentials() {
+        // base64("client:secret")
+        let creds = ClientCredentials::from_basic_auth("Basic Y2xpZW50OnNlY3JldA==").unwrap();
+        assert_eq!(creds.client_id, "client");
+        assert_eq!(creds.client_secret, "secret");
+
+        assert!(ClientCredentials::from_basic_auth("Bearer abc").is_none());
+    }
+}
@@ -0,0 +1,249 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+use ring::rand::SystemRandom;
+use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{AuthError, Result};
+use crate::models::{Scope, User};
+
+pub const SIGNING_ALG: Algorithm = Algorithm::ES256;
+
+// Paths the webapp serves the endpoints on, relative to the issuer
+const AUTHORIZATION_PATH: &str = "/oauth/authorize";
+const TOKEN_PATH: &str = "/oauth/token";
+const INTROSPECTION_PATH: &str = "/oauth/introspect";
+const REVOCATION_PATH: &str = "/oauth/revoke";
+const USERINFO_PATH: &str = "/userinfo";
+const JWKS_PATH: &str = "/.well-known/jwks.json";
+
+// P-256 key id tokens are signed with, clients fetch the public half from the jwks endpoint
+pub struct SigningKey {
+    encoding_key: EncodingKey,
+    jwk: Jwk,
+}
+
+impl SigningKey {
+    // A generated key doesn't survive a restart, load a stored one with `from_pkcs8`
+    pub fn generate() -> Self {
+        let pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+                .unwrap_or_else(|e| panic!("FATAL - WHILE GENERATING SIGNING KEY - Cause: {e:?}"));
+
+        Self::from_pkcs8(pkcs8.as_ref())
+            .unwrap_or_else(|e| panic!("FATAL - WHILE LOADING SIGNING KEY - Cause: {e:?}"))
+    }
+
+    pub fn from_pkcs8(der: &[u8]) -> Result<Self> {
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der, &SystemRandom::new())
+                .map_err(|_| AuthError::JwtError("invalid signing key".to_string()))?;
+
+        // uncompressed point, 0x04 || x || y
+        let point = key_pair.public_key().as_ref();
+        let x = URL_SAFE_NO_PAD.encode(&point[1..33]);
+        let y = URL_SAFE_NO_PAD.encode(&point[33..65]);
+
+        Ok(Self {
+            encoding_key: EncodingKey::from_ec_der(der),
+            jwk: Jwk {
+                kty: "EC",
+                crv: "P-256",
+                alg: "ES256",
+                key_use: "sig",
+                kid: URL_SAFE_NO_PAD.encode(&Sha256::digest(point)[..12]),
+                x,
+                y,
+            },
+        })
+    }
+
+    pub fn jwks(&self) -> Jwks {
+        Jwks {
+            keys: vec![self.jwk.clone()],
+        }
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let mut header = Header::new(SIGNING_ALG);
+        header.kid = Some(self.jwk.kid.clone());
+
+        encode(&header, claims, &self.encoding_key).map_err(|e| AuthError::JwtError(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub crv: &'static str,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    pub kid: String,
+    pub x: String,
+    pub y: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+// Standard claims about the user, what is released depends on the granted scopes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl UserInfo {
+    pub fn from_user(user: &User, scopes: &[Scope]) -> Self {
+        let email = scopes.contains(&Scope::Email);
+        let profile = scopes.contains(&Scope::Profile);
+
+        Self {
+            sub: user.id.clone(),
+            email: email.then(|| user.email.clone()),
+            email_verified: email.then_some(user.email_verified),
+            name: profile.then(|| user.name.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    // echoed back from the authorization request so the client can detect replays
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user_info: UserInfo,
+}
+
+// `/.well-known/openid-configuration`
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub introspection_endpoint: String,
+    pub revocation_endpoint: String,
+    pub scopes_supported: Vec<&'static str>,
+    pub response_types_supported: Vec<&'static str>,
+    pub grant_types_supported: Vec<&'static str>,
+    pub subject_types_supported: Vec<&'static str>,
+    pub id_token_signing_alg_values_supported: Vec<&'static str>,
+    pub token_endpoint_auth_methods_supported: Vec<&'static str>,
+    pub code_challenge_methods_supported: Vec<&'static str>,
+    pub claims_supported: Vec<&'static str>,
+}
+
+impl ProviderMetadata {
+    pub fn new(issuer: &str) -> Self {
+        let issuer = issuer.trim_end_matches('/');
+
+        Self {
+            issuer: issuer.to_string(),
+            authorization_endpoint: format!("{issuer}{AUTHORIZATION_PATH}"),
+            token_endpoint: format!("{issuer}{TOKEN_PATH}"),
+            userinfo_endpoint: format!("{issuer}{USERINFO_PATH}"),
+            jwks_uri: format!("{issuer}{JWKS_PATH}"),
+            introspection_endpoint: format!("{issuer}{INTROSPECTION_PATH}"),
+            revocation_endpoint: format!("{issuer}{REVOCATION_PATH}"),
+            scopes_supported: [Scope::Read, Scope::Write]
+                .iter()
+                .chain(Scope::OIDC.iter())
+                .map(|s| s.as_str())
+                .collect(),
+            response_types_supported: vec!["code"],
+            grant_types_supported: vec!["authorization_code", "refresh_token"],
+            subject_types_supported: vec!["public"],
+            id_token_signing_alg_values_supported: vec!["ES256"],
+            token_endpoint_auth_methods_supported: vec![
+                "client_secret_basic",
+                "client_secret_post",
+            ],
+            code_challenge_methods_supported: vec!["S256"],
+            claims_supported: vec![
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "nonce",
+                "email",
+                "email_verified",
+                "name",
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{DecodingKey, Validation, decode, decode_header};
+
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify_with_jwk() {
+        let key = SigningKey::generate();
+        let user = User::new(
+            "oidc@example.com".to_string(),
+            "hash".to_string(),
+            "Oidc User".to_string(),
+        );
+
+        let claims = IdTokenClaims {
+            iss: "https://app.example.com".to_string(),
+            aud: "client-123".to_string(),
+            exp: time::OffsetDateTime::now_utc().unix_timestamp() + 60,
+            iat: time::OffsetDateTime::now_utc().unix_timestamp(),
+            nonce: Some("n-0S6_WzA2Mj".to_string()),
+            user_info: UserInfo::from_user(&user, &[Scope::OpenId, Scope::Email]),
+        };
+        let token = key.sign(&claims).unwrap();
+
+        let jwks = key.jwks();
+        let header = decode_header(&token).unwrap();
+        assert_eq!(header.alg, SIGNING_ALG);
+        assert_eq!(header.kid.as_ref(), Some(&jwks.keys[0].kid));
+
+        // clients only have the published x and y coordinates
+        let public_key = DecodingKey::from_ec_components(&jwks.keys[0].x, &jwks.keys[0].y).unwrap();
+        let mut validation = Validation::new(SIGNING_ALG);
+        validation.set_audience(&["client-123"]);
+        let decoded = decode::<IdTokenClaims>(&token, &public_key, &validation)
+            .unwrap()
+            .claims;
+
+        assert_eq!(decoded.user_info.sub, user.id);
+        assert_eq!(decoded.user_info.email.as_deref(), Some("oidc@example.com"));
+        assert_eq!(decoded.user_info.email_verified, Some(false));
+        // no profile scope, no name
+        assert!(decoded.user_info.name.is_none());
+        assert_eq!(decoded.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+    }
+
+    #[test]
+    fn test_provider_metadata() {
+        let metadata = ProviderMetadata::new("https://app.example.com/");
+        assert_eq!(metadata.issuer, "https://app.example.com");
+        assert_eq!(
+            metadata.jwks_uri,
+            "https://app.example.com/.well-known/jwks.json"
+        );
+        assert!(metadata.scopes_supported.contains(&"openid"));
+    }
+}
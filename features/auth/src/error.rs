@@ -1,4 +1,9 @@
-use crate::{pwd_scheme::error::SchemeError, repository::error::RepoError};
+use crate::{
+    audit::error::AuditError, authz_server::error::AuthzError, gdpr::error::GdprError,
+    hooks::error::HookError, mailer::error::MailError, oauth::error::OAuthError,
+    outbox::error::OutboxError, pwd_scheme::error::SchemeError, repository::error::RepoError,
+    saml::error::SamlError, scim::error::ScimError,
+};
 
 pub type Result<T> = std::result::Result<T, AuthError>;
 
@@ -9,17 +14,111 @@ pub enum AuthError {
 
     EmailValidation,
     PasswordValidation(String),
+    ProfileValidation(String),
     UserExists,
     InvalidCredentials,
     UserNotFound,
+    AccountDisabled,
+    // until the timestamp
+    AccountSuspended(i64),
+    AccountPendingVerification,
+    AccountDeleted,
+    InvalidStatus(String),
+    // the user was changed at the same time and the change couldn't be applied on top
+    Conflict,
+    // a change failed with the first error and what it had written couldn't all be taken
+    // back, see the second
+    RollbackFailed(Box<AuthError>, Box<AuthError>),
+    SessionStoreRequired,
+    SessionNotFound,
+    EmailChangeNotFound,
+    PasswordResetNotFound,
+    ImpersonationNotAllowed,
+    Impersonating,
+    NotImpersonating,
+
+    InvalidApiKey,
+    ApiKeyExpired,
+    ApiKeyRevoked,
+    ApiKeyNotFound,
+    InvalidScope(String),
+    InsufficientScope,
+    InvalidRole(String),
+    InvalidSort(String),
 
     Scheme(SchemeError),
     Repository(RepoError),
+    OAuth(OAuthError),
+    Authz(AuthzError),
+    Saml(SamlError),
+    Scim(ScimError),
+    Audit(AuditError),
+    Hook(HookError),
+    Mail(MailError),
+    Gdpr(GdprError),
+    Outbox(OutboxError),
 }
 
 impl From<RepoError> for AuthError {
     fn from(value: RepoError) -> Self {
-        Self::Repository(value)
+        match value {
+            RepoError::DuplicateEmail => Self::UserExists,
+            value => Self::Repository(value),
+        }
+    }
+}
+
+impl From<OAuthError> for AuthError {
+    fn from(value: OAuthError) -> Self {
+        Self::OAuth(value)
+    }
+}
+
+impl From<AuthzError> for AuthError {
+    fn from(value: AuthzError) -> Self {
+        Self::Authz(value)
+    }
+}
+
+impl From<SamlError> for AuthError {
+    fn from(value: SamlError) -> Self {
+        Self::Saml(value)
+    }
+}
+
+impl From<ScimError> for AuthError {
+    fn from(value: ScimError) -> Self {
+        Self::Scim(value)
+    }
+}
+
+impl From<AuditError> for AuthError {
+    fn from(value: AuditError) -> Self {
+        Self::Audit(value)
+    }
+}
+
+impl From<OutboxError> for AuthError {
+    fn from(value: OutboxError) -> Self {
+        Self::Outbox(value)
+    }
+}
+
+impl From<HookError> for AuthError {
+    fn from(value: HookError) -> Self {
+        Self::Hook(value)
+    }
+}
+
+impl From<MailError> for AuthError {
+    fn from(value: MailError) -> Self {
+        Self::Mail(value)
+    }
+}
+
+impl From<GdprError> for AuthError {
+    fn from(value: GdprError) -> Self {
+        Self::Gdpr(value)
     }
 }
 
@@ -37,10 +136,48 @@ impl std::fmt::Display for AuthError {
             AuthError::EmailValidation => write!(fmt, "Invalid email"),
             AuthError::Scheme(e) => write!(fmt, "Scheme error: {e}"),
             AuthError::PasswordValidation(e) => write!(fmt, "Password validation: {e}"),
+            AuthError::ProfileValidation(e) => write!(fmt, "Profile validation: {e}"),
             AuthError::UserExists => write!(fmt, "User already exists"),
             AuthError::Repository(e) => write!(fmt, "Repository error: {e}"),
+            AuthError::OAuth(e) => write!(fmt, "OAuth error: {e}"),
+            AuthError::Authz(e) => write!(fmt, "Authorization server error: {e}"),
+            AuthError::Saml(e) => write!(fmt, "SAML error: {e}"),
+            AuthError::Scim(e) => write!(fmt, "SCIM error: {e}"),
+            AuthError::Audit(e) => write!(fmt, "Audit error: {e}"),
+            AuthError::Hook(e) => write!(fmt, "Rejected: {e}"),
+            AuthError::Mail(e) => write!(fmt, "Mail error: {e}"),
+            AuthError::Gdpr(e) => write!(fmt, "Data export error: {e}"),
+            AuthError::Outbox(e) => write!(fmt, "Outbox error: {e}"),
             AuthError::InvalidCredentials => write!(fmt, "Invalid credentials"),
             AuthError::UserNotFound => write!(fmt, "User not found"),
+            AuthError::AccountDisabled => write!(fmt, "Account disabled"),
+            AuthError::AccountSuspended(until) => write!(fmt, "Account suspended until {until}"),
+            AuthError::AccountPendingVerification => {
+                write!(fmt, "Account pending email verification")
+            }
+            AuthError::InvalidStatus(e) => write!(fmt, "Invalid account status: {e}"),
+            AuthError::Conflict => write!(fmt, "The account was changed at the same time"),
+            AuthError::RollbackFailed(e, rollback) => {
+                write!(fmt, "Can't roll back ({rollback}) after: {e}")
+            }
+            AuthError::AccountDeleted => write!(fmt, "Account scheduled for deletion"),
+            AuthError::SessionStoreRequired => write!(fmt, "Sessions are not stored server side"),
+            AuthError::SessionNotFound => write!(fmt, "Session not found"),
+            AuthError::EmailChangeNotFound => write!(fmt, "Email change not found or expired"),
+            AuthError::PasswordResetNotFound => {
+                write!(fmt, "Password reset not found or expired")
+            }
+            AuthError::ImpersonationNotAllowed => write!(fmt, "Impersonation not allowed"),
+            AuthError::Impersonating => write!(fmt, "Not allowed while impersonating a user"),
+            AuthError::NotImpersonating => write!(fmt, "Not impersonating a user"),
+            AuthError::InvalidApiKey => write!(fmt, "Invalid API key"),
+            AuthError::ApiKeyExpired => write!(fmt, "API key expired"),
+            AuthError::ApiKeyRevoked => write!(fmt, "API key revoked"),
+            AuthError::ApiKeyNotFound => write!(fmt, "API key not found"),
+            AuthError::InvalidScope(e) => write!(fmt, "Invalid scope: {e}"),
+            AuthError::InsufficientScope => write!(fmt, "Insufficient scope"),
+            AuthError::InvalidRole(e) => write!(fmt, "Invalid role: {e}"),
+            AuthError::InvalidSort(e) => write!(fmt, "Invalid sort: {e}"),
         }
     }
 }
@@ -63,5 +200,56 @@ mod tests {
             AuthError::JwtError("Error Message".to_string()).to_string()
         );
         assert_eq!("Unauthorized access", AuthError::Unauthorized.to_string());
+        assert_eq!(
+            "SAML error: Replayed",
+            AuthError::Saml(SamlError::Replayed).to_string()
+        );
+        assert_eq!(
+            "SCIM error: Uniqueness",
+            AuthError::Scim(ScimError::Uniqueness).to_string()
+        );
+        assert_eq!("Account disabled", AuthError::AccountDisabled.to_string());
+        assert_eq!(
+            "Account suspended until 1700000000",
+            AuthError::AccountSuspended(1700000000).to_string()
+        );
+        assert_eq!(
+            "Not allowed while impersonating a user",
+            AuthError::Impersonating.to_string()
+        );
+        assert_eq!(
+            "Sessions are not stored server side",
+            AuthError::SessionStoreRequired.to_string()
+        );
+        assert_eq!("Session not found", AuthError::SessionNotFound.to_string());
+        assert_eq!(
+            "Audit error: Can't write audit event: disk full",
+            AuthError::Audit(AuditError::Write("disk full".to_string())).to_string()
+        );
+        assert_eq!(
+            "Rejected: Signups are closed",
+            AuthError::Hook(HookError::Rejected("Signups are closed".to_string())).to_string()
+        );
+        assert_eq!(
+            "Email change not found or expired",
+            AuthError::EmailChangeNotFound.to_string()
+        );
+        assert_eq!(
+            "Outbox error: Dead letter not found: m1",
+            AuthError::Outbox(OutboxError::NotFound("m1".to_string())).to_string()
+        );
+        assert_eq!(
+            "Can't roll back (The account was changed at the same time) after: Audit error: \
+             Can't write audit event: disk full",
+            AuthError::RollbackFailed(
+                Box::new(AuthError::Audit(AuditError::Write("disk full".to_string()))),
+                Box::new(AuthError::Conflict)
+            )
+            .to_string()
+        );
+        assert_eq!(
+            "Invalid role: root",
+            AuthError::InvalidRole("root".to_string()).to_string()
+        );
     }
 }
@@ -0,0 +1,192 @@
+use std::io::Write;
+
+use flate2::{Compression, Crc, write::DeflateEncoder};
+use time::OffsetDateTime;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0605_4b50;
+// 2.0, the first version with deflate
+const VERSION: u16 = 20;
+// file names are UTF-8
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_DEFLATE: u16 = 8;
+
+struct Entry {
+    name: String,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+// A ZIP archive of deflated files, without ZIP64 so every file and the archive must stay
+// under 4 GiB which exports do by far
+pub fn zip(files: &[(String, Vec<u8>)], modified: OffsetDateTime) -> std::io::Result<Vec<u8>> {
+    let (time, date) = dos_date_time(modified);
+    let mut out = Vec::new();
+    let mut entries = Vec::with_capacity(files.len());
+
+    for (name, content) in files {
+        let mut crc = Crc::new();
+        crc.update(content);
+
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content)?;
+        let compressed = encoder.finish()?;
+
+        let entry = Entry {
+            name: name.clone(),
+            crc: crc.sum(),
+            compressed_size: to_u32(compressed.len())?,
+            size: to_u32(content.len())?,
+            offset: to_u32(out.len())?,
+        };
+
+        put_u32(&mut out, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut out, VERSION);
+        put_entry_fields(&mut out, &entry, time, date)?;
+        // no extra field
+        put_u16(&mut out, 0);
+        out.extend_from_slice(entry.name.as_bytes());
+        out.extend_from_slice(&compressed);
+
+        entries.push(entry);
+    }
+
+    let central_dir_offset = to_u32(out.len())?;
+    for entry in &entries {
+        put_u32(&mut out, CENTRAL_HEADER_SIGNATURE);
+        // made by
+        put_u16(&mut out, VERSION);
+        // needed to extract
+        put_u16(&mut out, VERSION);
+        put_entry_fields(&mut out, entry, time, date)?;
+        // extra field, comment, disk number, internal and external attributes
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u32(&mut out, 0);
+        put_u32(&mut out, entry.offset);
+        out.extend_from_slice(entry.name.as_bytes());
+    }
+    let central_dir_size = to_u32(out.len())? - central_dir_offset;
+
+    let count = u16::try_from(entries.len()).map_err(|_| too_large())?;
+    put_u32(&mut out, END_OF_CENTRAL_DIR_SIGNATURE);
+    // this disk and the one the central directory starts on
+    put_u16(&mut out, 0);
+    put_u16(&mut out, 0);
+    put_u16(&mut out, count);
+    put_u16(&mut out, count);
+    put_u32(&mut out, central_dir_size);
+    put_u32(&mut out, central_dir_offset);
+    // no comment
+    put_u16(&mut out, 0);
+
+    Ok(out)
+}
+
+// Flags through name length, shared by local and central headers
+fn put_entry_fields(out: &mut Vec<u8>, entry: &Entry, time: u16, date: u16) -> std::io::Result<()> {
+    put_u16(out, FLAG_UTF8);
+    put_u16(out, METHOD_DEFLATE);
+    put_u16(out, time);
+    put_u16(out, date);
+    put_u32(out, entry.crc);
+    put_u32(out, entry.compressed_size);
+    put_u32(out, entry.size);
+    put_u16(
+        out,
+        u16::try_from(entry.name.len()).map_err(|_| too_large())?,
+    );
+    Ok(())
+}
+
+// MS-DOS time and date, two second precision and no years before 1980
+fn dos_date_time(at: OffsetDateTime) -> (u16, u16) {
+    let year = (at.year() - 1980).clamp(0, 127) as u16;
+    let time = ((at.hour() as u16) << 11) | ((at.minute() as u16) << 5) | (at.second() as u16 / 2);
+    let date = (year << 9) | ((at.month() as u16) << 5) | at.day() as u16;
+    (time, date)
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn to_u32(value: usize) -> std::io::Result<u32> {
+    u32::try_from(value).map_err(|_| too_large())
+}
+
+fn too_large() -> std::io::Error {
+    std::io::Error::other("archive too large")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::DeflateDecoder;
+
+    use super::*;
+
+    fn u16_at(bytes: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_zip() {
+        let files = vec![
+            ("profile.json".to_string(), br#"{"name":"Jane"}"#.repeat(50)),
+            ("sessions.json".to_string(), b"[]".to_vec()),
+        ];
+        // 2024-03-05 10:20:30 UTC
+        let modified = OffsetDateTime::from_unix_timestamp(1709634030).unwrap();
+        let archive = zip(&files, modified).unwrap();
+
+        // The end of central directory record points back at every entry
+        let eocd = archive.len() - 22;
+        assert_eq!(u32_at(&archive, eocd), END_OF_CENTRAL_DIR_SIGNATURE);
+        assert_eq!(u16_at(&archive, eocd + 10), 2);
+        let mut central = u32_at(&archive, eocd + 16) as usize;
+
+        for (name, content) in &files {
+            assert_eq!(u32_at(&archive, central), CENTRAL_HEADER_SIGNATURE);
+            let name_len = u16_at(&archive, central + 28) as usize;
+            assert_eq!(
+                &archive[central + 46..central + 46 + name_len],
+                name.as_bytes()
+            );
+            let local = u32_at(&archive, central + 42) as usize;
+            central += 46 + name_len;
+
+            assert_eq!(u32_at(&archive, local), LOCAL_HEADER_SIGNATURE);
+            assert_eq!(u16_at(&archive, local + 8), METHOD_DEFLATE);
+            assert_eq!(u16_at(&archive, local + 12), (44 << 9) | (3 << 5) | 5);
+            let crc = u32_at(&archive, local + 14);
+            let compressed_size = u32_at(&archive, local + 18) as usize;
+            assert_eq!(u32_at(&archive, local + 22) as usize, content.len());
+
+            let data = local + 30 + name_len;
+            let mut inflated = Vec::new();
+            DeflateDecoder::new(&archive[data..data + compressed_size])
+                .read_to_end(&mut inflated)
+                .unwrap();
+            assert_eq!(&inflated, content);
+
+            let mut expected = Crc::new();
+            expected.update(content);
+            assert_eq!(crc, expected.sum());
+        }
+    }
+}
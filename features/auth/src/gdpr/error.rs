@@ -0,0 +1,31 @@
+pub type Result<T> = std::result::Result<T, GdprError>;
+
+#[derive(Debug)]
+pub enum GdprError {
+    UnsupportedFormat(String),
+    Encode(String),
+}
+
+impl std::fmt::Display for GdprError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            GdprError::UnsupportedFormat(format) => write!(fmt, "Unsupported format: {format}"),
+            GdprError::Encode(e) => write!(fmt, "Can't encode export: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for GdprError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gdpr_error_rendering() {
+        assert_eq!(
+            "Unsupported format: xml",
+            GdprError::UnsupportedFormat("xml".to_string()).to_string()
+        );
+    }
+}
@@ -0,0 +1,290 @@
+pub mod archive;
+pub mod error;
+
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::Value;
+use time::OffsetDateTime;
+
+use crate::audit::AuditEvent;
+use crate::models::{
+    AccountStatus, ApiKey, ClientApp, EmailChange, Group, Identity, ProvisionedUser, Session, User,
+};
+use crate::user_agent::describe_user_agent;
+
+use error::{GdprError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    // a single JSON document
+    Json,
+    // one JSON file per section
+    Zip,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Zip => "zip",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Zip => "application/zip",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = GdprError;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "json" => Ok(ExportFormat::Json),
+            "zip" => Ok(ExportFormat::Zip),
+            _ => Err(GdprError::UnsupportedFormat(format.to_string())),
+        }
+    }
+}
+
+// Everything we hold about a user, for right of access requests. Password, key and token
+// hashes are left out, they're of no use to the user and shouldn't leave the system.
+#[derive(Debug, Clone, Serialize)]
+pub struct DataExport {
+    pub generated_at: i64,
+    pub profile: Profile,
+    pub identities: Vec<LinkedIdentity>,
+    pub api_keys: Vec<ApiKeyRecord>,
+    pub client_apps: Vec<ClientAppRecord>,
+    pub sessions: Vec<SessionRecord>,
+    pub memberships: Vec<Membership>,
+    pub pending_email_change: Option<PendingEmailChange>,
+    pub audit_events: Vec<AuditEvent>,
+}
+
+impl DataExport {
+    pub fn encode(&self, format: ExportFormat) -> Result<Vec<u8>> {
+        match format {
+            ExportFormat::Json => {
+                serde_json::to_vec_pretty(self).map_err(|e| GdprError::Encode(e.to_string()))
+            }
+            ExportFormat::Zip => {
+                let sections = match serde_json::to_value(self) {
+                    Ok(Value::Object(sections)) => sections,
+                    Ok(_) => return Err(GdprError::Encode("not an object".to_string())),
+                    Err(e) => return Err(GdprError::Encode(e.to_string())),
+                };
+
+                // the entries' modification time says when the export was generated
+                let mut files = Vec::with_capacity(sections.len());
+                for (name, section) in sections {
+                    if name == "generated_at" {
+                        continue;
+                    }
+                    let content = serde_json::to_vec_pretty(&section)
+                        .map_err(|e| GdprError::Encode(e.to_string()))?;
+                    files.push((format!("{name}.json"), content));
+                }
+
+                let generated_at = OffsetDateTime::from_unix_timestamp(self.generated_at)
+                    .map_err(|e| GdprError::Encode(e.to_string()))?;
+                archive::zip(&files, generated_at).map_err(|e| GdprError::Encode(e.to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Profile {
+    pub id: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub name: String,
+    pub status: String,
+    // only set while suspended
+    pub suspended_until: Option<i64>,
+    pub status_reason: Option<String>,
+    pub roles: Vec<String>,
+    pub deleted_at: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl From<&User> for Profile {
+    fn from(user: &User) -> Self {
+        Self {
+            id: user.id.clone(),
+            email: user.email.clone(),
+            email_verified: user.email_verified,
+            name: user.name.clone(),
+            status: user.status.as_str().to_string(),
+            suspended_until: match user.status {
+                AccountStatus::Suspended { until } => Some(until),
+                _ => None,
+            },
+            status_reason: user.status_reason.clone(),
+            roles: user.roles.iter().map(|r| r.as_str().to_string()).collect(),
+            deleted_at: user.deleted_at,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+    pub created_at: i64,
+}
+
+impl From<&Identity> for LinkedIdentity {
+    fn from(identity: &Identity) -> Self {
+        Self {
+            provider: identity.provider.clone(),
+            subject: identity.subject.clone(),
+            email: identity.email.clone(),
+            created_at: identity.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+impl From<&ApiKey> for ApiKeyRecord {
+    fn from(api_key: &ApiKey) -> Self {
+        Self {
+            id: api_key.id.clone(),
+            name: api_key.name.clone(),
+            prefix: api_key.prefix.clone(),
+            scopes: api_key
+                .scopes
+                .iter()
+                .map(|s| s.as_str().to_string())
+                .collect(),
+            created_at: api_key.created_at,
+            expires_at: api_key.expires_at,
+            last_used_at: api_key.last_used_at,
+            revoked_at: api_key.revoked_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientAppRecord {
+    pub id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+}
+
+impl From<&ClientApp> for ClientAppRecord {
+    fn from(client_app: &ClientApp) -> Self {
+        Self {
+            id: client_app.id.clone(),
+            name: client_app.name.clone(),
+            redirect_uris: client_app.redirect_uris.clone(),
+            scopes: client_app
+                .scopes
+                .iter()
+                .map(|s| s.as_str().to_string())
+                .collect(),
+            created_at: client_app.created_at,
+        }
+    }
+}
+
+// Session data is left out, it's the app's bookkeeping rather than the user's
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionRecord {
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    pub expires_at: i64,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub device: String,
+}
+
+impl From<&Session> for SessionRecord {
+    fn from(session: &Session) -> Self {
+        Self {
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            expires_at: session.expires_at,
+            ip: session.ip.clone(),
+            user_agent: session.user_agent.clone(),
+            device: describe_user_agent(session.user_agent.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+// What an organization's directory provisioned for the user
+#[derive(Debug, Clone, Serialize)]
+pub struct Membership {
+    pub org: String,
+    pub user_name: String,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub groups: Vec<String>,
+    pub provisioned_at: i64,
+    pub deprovisioned_at: Option<i64>,
+}
+
+impl Membership {
+    // pairs each organization's record with the user's groups in it
+    pub fn from_records(records: &[ProvisionedUser], groups: &[Group]) -> Vec<Self> {
+        records
+            .iter()
+            .map(|record| Self {
+                org: record.org.clone(),
+                user_name: record.user_name.clone(),
+                external_id: record.external_id.clone(),
+                display_name: record.display_name.clone(),
+                given_name: record.given_name.clone(),
+                family_name: record.family_name.clone(),
+                groups: groups
+                    .iter()
+                    .filter(|g| g.org == record.org)
+                    .map(|g| g.display_name.clone())
+                    .collect(),
+                provisioned_at: record.created_at,
+                deprovisioned_at: record.deprovisioned_at,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingEmailChange {
+    pub new_email: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+impl From<&EmailChange> for PendingEmailChange {
+    fn from(change: &EmailChange) -> Self {
+        Self {
+            new_email: change.new_email.clone(),
+            created_at: change.created_at,
+            expires_at: change.expires_at,
+        }
+    }
+}
@@ -0,0 +1,30 @@
+pub type Result<T> = std::result::Result<T, HookError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookError {
+    // a before hook vetoed the operation, the reason is meant for the user
+    Rejected(String),
+}
+
+impl std::fmt::Display for HookError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            HookError::Rejected(reason) => write!(fmt, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for HookError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_error_rendering() {
+        assert_eq!(
+            "Signups are closed",
+            HookError::Rejected("Signups are closed".to_string()).to_string()
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::models::{ClientInfo, RegisterUser, User};
+
+pub mod error;
+
+use error::Result;
+
+// Lets applications react to account lifecycle events without wrapping AuthService.
+// Before hooks run in registration order and the first rejection cancels the operation,
+// after hooks run once it succeeded and can't undo it.
+#[async_trait]
+pub trait AuthHook: Send + Sync + 'static {
+    async fn before_register(&self, _user_data: &RegisterUser) -> Result<()> {
+        Ok(())
+    }
+
+    async fn after_register(&self, _user: &User) {}
+
+    // runs once the user proved who they are, whatever the signin method
+    async fn before_signin(&self, _user: &User, _client: &ClientInfo) -> Result<()> {
+        Ok(())
+    }
+
+    async fn after_signin(&self, _user: &User, _client: &ClientInfo) {}
+
+    async fn before_password_change(&self, _user: &User) -> Result<()> {
+        Ok(())
+    }
+
+    async fn after_password_change(&self, _user: &User) {}
+
+    // runs when the user asks for erasure, the account is purged after a grace period
+    async fn before_delete(&self, _user: &User) -> Result<()> {
+        Ok(())
+    }
+
+    // runs once the account is purged, the user is already gone from the repository
+    async fn after_delete(&self, _user: &User) {}
+}
+
+#[derive(Clone, Default)]
+pub struct HookRegistry {
+    hooks: Vec<Arc<dyn AuthHook>>,
+}
+
+impl HookRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, hook: Arc<dyn AuthHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub async fn before_register(&self, user_data: &RegisterUser) -> Result<()> {
+        for hook in &self.hooks {
+            hook.before_register(user_data).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after_register(&self, user: &User) {
+        for hook in &self.hooks {
+            hook.after_register(user).await;
+        }
+    }
+
+    pub async fn before_signin(&self, user: &User, client: &ClientInfo) -> Result<()> {
+        for hook in &self.hooks {
+            hook.before_signin(user, client).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after_signin(&self, user: &User, client: &ClientInfo) {
+        for hook in &self.hooks {
+            hook.after_signin(user, client).await;
+        }
+    }
+
+    pub async fn before_password_change(&self, user: &User) -> Result<()> {
+        for hook in &self.hooks {
+            hook.before_password_change(user).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after_password_change(&self, user: &User) {
+        for hook in &self.hooks {
+            hook.after_password_change(user).await;
+        }
+    }
+
+    pub async fn before_delete(&self, user: &User) -> Result<()> {
+        for hook in &self.hooks {
+            hook.before_delete(user).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after_delete(&self, user: &User) {
+        for hook in &self.hooks {
+            hook.after_delete(user).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::hooks::error::HookError;
+
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+        reject: bool,
+    }
+
+    #[async_trait]
+    impl AuthHook for Recorder {
+        async fn before_register(&self, _user_data: &RegisterUser) -> Result<()> {
+            self.calls.lock().unwrap().push(self.name);
+            if self.reject {
+                return Err(HookError::Rejected(format!("{} said no", self.name)));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_first_rejection_stops_the_chain() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = HookRegistry::new();
+        for (name, reject) in [("first", false), ("second", true), ("third", false)] {
+            registry.register(Arc::new(Recorder {
+                name,
+                calls: calls.clone(),
+                reject,
+            }));
+        }
+
+        let user_data = RegisterUser {
+            email: "hooked@example.com".to_string(),
+            password: "Password123!".to_string(),
+            name: "Hooked".to_string(),
+        };
+        assert_eq!(
+            registry.before_register(&user_data).await,
+            Err(HookError::Rejected("second said no".to_string()))
+        );
+        assert_eq!(*calls.lock().unwrap(), vec!["first", "second"]);
+    }
+}
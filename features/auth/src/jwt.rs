@@ -13,6 +13,30 @@ pub struct JwtClaims {
     pub exp: i64,    // expiration time
     pub iat: i64,    // issued at
     pub jti: String, // jwt id
+
+    // only set on tokens issued to third party clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_use: Option<TokenUse>,
+
+    // only set on impersonation tokens, the admin acting as `sub` (RFC 8693)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Actor {
+    pub sub: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenUse {
+    Access,
+    Refresh,
 }
 
 pub struct JwtService {
@@ -39,6 +63,66 @@ impl JwtService {
             exp: expiry.unix_timestamp(),
             iat: now.unix_timestamp(),
             jti: Uuid::new_v4().to_string(),
+            client_id: None,
+            scope: None,
+            token_use: None,
+            act: None,
+        };
+
+        encode(&Header::default(), &claims, &self.encoding_key)
+            .map_err(|e| AuthError::JwtError(e.to_string()))
+    }
+
+    // Token issued to a third party client on behalf of a user
+    pub fn generate_client_token(
+        &self,
+        user_id: &str,
+        client_id: &str,
+        scope: &str,
+        token_use: TokenUse,
+        expiration: Duration,
+    ) -> Result<(String, JwtClaims)> {
+        let now = OffsetDateTime::now_utc();
+        let expiry = now + expiration;
+
+        let claims = JwtClaims {
+            sub: user_id.to_string(),
+            exp: expiry.unix_timestamp(),
+            iat: now.unix_timestamp(),
+            jti: Uuid::new_v4().to_string(),
+            client_id: Some(client_id.to_string()),
+            scope: Some(scope.to_string()),
+            token_use: Some(token_use),
+            act: None,
+        };
+
+        let token = encode(&Header::default(), &claims, &self.encoding_key)
+            .map_err(|e| AuthError::JwtError(e.to_string()))?;
+
+        Ok((token, claims))
+    }
+
+    // Session token for `user_id` carrying the admin acting as them
+    pub fn generate_impersonation_token(
+        &self,
+        user_id: &str,
+        actor_id: &str,
+        expiration: Duration,
+    ) -> Result<String> {
+        let now = OffsetDateTime::now_utc();
+        let expiry = now + expiration;
+
+        let claims = JwtClaims {
+            sub: user_id.to_string(),
+            exp: expiry.unix_timestamp(),
+            iat: now.unix_timestamp(),
+            jti: Uuid::new_v4().to_string(),
+            client_id: None,
+            scope: None,
+            token_use: None,
+            act: Some(Actor {
+                sub: actor_id.to_string(),
+            }),
         };
 
         encode(&Header::default(), &claims, &self.encoding_key)
@@ -62,8 +146,9 @@ impl JwtService {
 mod tests {
     use crate::{config::auth_config, error::AuthError};
 
-    use super::JwtService;
+    use super::{JwtService, TokenUse};
     use std::time::SystemTime;
+    use time::Duration;
 
     const TEST_SECRET: &[u8] = b"test_jwt_secret";
     const TEST_USER_ID: &str = "user-123";
@@ -153,6 +238,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_generate_client_token() {
+        let jwt_service = create_test_jwt_service();
+        let (token, issued) = jwt_service
+            .generate_client_token(
+                TEST_USER_ID,
+                "client-123",
+                "read",
+                TokenUse::Access,
+                Duration::hours(1),
+            )
+            .unwrap();
+
+        let claims = jwt_service.validate_token(&token).unwrap();
+        assert_eq!(claims.jti, issued.jti);
+        assert_eq!(claims.client_id.as_deref(), Some("client-123"));
+        assert_eq!(claims.scope.as_deref(), Some("read"));
+        assert_eq!(claims.token_use, Some(TokenUse::Access));
+
+        // session tokens don't carry client claims
+        let session_token = jwt_service.generate_token(TEST_USER_ID).unwrap();
+        let session_claims = jwt_service.validate_token(&session_token).unwrap();
+        assert!(session_claims.client_id.is_none());
+        assert!(session_claims.token_use.is_none());
+    }
+
+    #[test]
+    fn test_generate_impersonation_token() {
+        let jwt_service = create_test_jwt_service();
+        let token = jwt_service
+            .generate_impersonation_token(TEST_USER_ID, "admin-123", Duration::hours(1))
+            .unwrap();
+
+        let claims = jwt_service.validate_token(&token).unwrap();
+        assert_eq!(claims.sub, TEST_USER_ID);
+        assert_eq!(claims.act.unwrap().sub, "admin-123");
+        assert!(claims.token_use.is_none());
+
+        let session_token = jwt_service.generate_token(TEST_USER_ID).unwrap();
+        assert!(
+            jwt_service
+                .validate_token(&session_token)
+                .unwrap()
+                .act
+                .is_none()
+        );
+    }
+
     #[test]
     fn test_token_contains_expected_claims() {
         let jwt_service = create_test_jwt_service();
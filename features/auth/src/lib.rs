@@ -1,14 +1,88 @@
+mod api_key;
+mod audit;
+mod authz_server;
 mod config;
 mod error;
+mod gdpr;
+mod hooks;
 mod jwt;
+mod mailer;
+mod migration;
 mod models;
+mod oauth;
+mod outbox;
 mod password;
+mod pii;
 mod pwd_scheme;
 mod repository;
+mod saml;
+mod scim;
 mod service;
+mod unit_of_work;
+mod user_agent;
+mod utils;
 
+pub use api_key::is_api_key;
+pub use audit::{
+    AuditEvent, AuditFilter, AuditSink, AuthEvent, error::AuditError,
+    in_mem_sink::InMemoryAuditSink, jsonl_sink::JsonlAuditSink, sqlite_sink::SqliteAuditSink,
+};
+pub use authz_server::{
+    AccessGrant, AuthorizationServer, AuthorizeParams, ClientCredentials, Introspection,
+    IssuedTokens, TokenRequest, TokenResponse,
+    error::AuthzError,
+    oidc::{Jwks, ProviderMetadata, SigningKey, UserInfo},
+};
 pub use error::AuthError;
+pub use gdpr::{DataExport, ExportFormat, error::GdprError};
+pub use hooks::{AuthHook, error::HookError};
 pub use jwt::JwtService;
-pub use models::{Credentials, RegisterUser, User};
-pub use repository::{UserRepositoryTrait, in_mem_user_repo::InMemoryUserRepository};
+pub use mailer::{Email, InMemoryMailer, LogMailer, Mailer, error::MailError};
+pub use migration::{
+    Migration, MigrationReport, Rejected, UserFormat, UserRecord, error::MigrationError,
+};
+pub use models::{
+    AccountStatus, ActiveSession, ApiKey, ClientApp, ClientInfo, Credentials, EmailChange,
+    ExternalIdentity, Group, Identity, NewApiKey, NewClientApp, OAuthProvider, PasswordReset,
+    ProvisionedUser, RegisterUser, Role, ScimToken, Scope, Session, SessionMode, SsoConnection,
+    User, UserFilter, UserList, UserQuery, UserSort,
+};
+pub use oauth::{
+    AuthorizationRequest, OAuthClient,
+    error::OAuthError,
+    provider::{ClaimNames, OAuthProviderConfig},
+    transport::{HttpTransport, OAuthTransport},
+};
+pub use outbox::{
+    DomainEvent, LogHandler, OutboxHandler, OutboxMessage, OutboxStore,
+    dispatcher::{DispatchReport, OutboxDispatcher},
+    error::OutboxError,
+    in_mem_store::InMemoryOutbox,
+    sqlite_store::SqliteOutbox,
+    webhook::WebhookHandler,
+};
+pub use pii::{PiiKeys, error::PiiError};
+pub use repository::{
+    ApiKeyRepositoryTrait, EmailChangeRepositoryTrait, IdentityRepositoryTrait,
+    PasswordResetRepositoryTrait, ScimRepositoryTrait, SessionStore, SsoConnectionRepositoryTrait,
+    UserOutboxStore, UserRepositoryTrait, error::RepoError,
+    in_mem_api_key_repo::InMemoryApiKeyRepository,
+    in_mem_email_change_repo::InMemoryEmailChangeRepository,
+    in_mem_identity_repo::InMemoryIdentityRepository,
+    in_mem_password_reset_repo::InMemoryPasswordResetRepository,
+    in_mem_scim_repo::InMemoryScimRepository, in_mem_session_store::InMemorySessionStore,
+    in_mem_sso_connection_repo::InMemorySsoConnectionRepository,
+    in_mem_user_repo::InMemoryUserRepository, redb_user_repo::RedbUserRepository,
+    sqlite_session_store::SqliteSessionStore,
+};
+pub use saml::{ServiceProvider, error::SamlError};
+pub use scim::{
+    SCIM_TOKEN_PREFIX, ScimProvisioner,
+    error::ScimError,
+    resources::{
+        ERROR_SCHEMA, ListQuery, ListResponse, Meta, MultiValued, PatchRequest, ScimGroup,
+        ScimName, ScimUser,
+    },
+};
 pub use service::{AuthService, AuthServiceTrait};
+pub use user_agent::describe_user_agent;
@@ -0,0 +1,29 @@
+pub type Result<T> = std::result::Result<T, MailError>;
+
+#[derive(Debug)]
+pub enum MailError {
+    Send(String),
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            MailError::Send(e) => write!(fmt, "Can't send email: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mail_error_rendering() {
+        assert_eq!(
+            "Can't send email: connection refused",
+            MailError::Send("connection refused".to_string()).to_string()
+        );
+    }
+}
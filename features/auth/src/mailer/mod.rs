@@ -0,0 +1,65 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+pub mod error;
+
+use error::{MailError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// How emails leave the app, plug in an SMTP or API based implementation
+#[async_trait]
+pub trait Mailer: Send + Sync + 'static {
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+// Prints emails instead of sending them, for development
+#[derive(Default)]
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        println!(
+            "Email to {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+        Ok(())
+    }
+}
+
+// Keeps sent emails around so tests can read them
+#[derive(Default)]
+pub struct InMemoryMailer {
+    sent: Arc<RwLock<Vec<Email>>>,
+}
+
+impl InMemoryMailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent
+            .read()
+            .map(|sent| sent.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl Mailer for InMemoryMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        self.sent
+            .write()
+            .map_err(|e| MailError::Send(e.to_string()))?
+            .push(email);
+        Ok(())
+    }
+}
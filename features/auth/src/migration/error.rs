@@ -0,0 +1,52 @@
+use crate::repository::error::RepoError;
+
+pub type Result<T> = std::result::Result<T, MigrationError>;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    UnsupportedFormat(String),
+    // the resume point isn't one this kind of run hands out
+    InvalidResume(String),
+    Read(String),
+    Write(String),
+    Repository(RepoError),
+}
+
+impl From<RepoError> for MigrationError {
+    fn from(value: RepoError) -> Self {
+        Self::Repository(value)
+    }
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            MigrationError::UnsupportedFormat(format) => {
+                write!(fmt, "Unsupported format: {format}")
+            }
+            MigrationError::InvalidResume(resume) => write!(fmt, "Can't resume from: {resume}"),
+            MigrationError::Read(e) => write!(fmt, "Can't read users: {e}"),
+            MigrationError::Write(e) => write!(fmt, "Can't write users: {e}"),
+            MigrationError::Repository(e) => write!(fmt, "User repository error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_error_rendering() {
+        assert_eq!(
+            "Unsupported format: xml",
+            MigrationError::UnsupportedFormat("xml".to_string()).to_string()
+        );
+        assert_eq!(
+            "User repository error: DuplicateEmail",
+            MigrationError::Repository(RepoError::DuplicateEmail).to_string()
+        );
+    }
+}
@@ -0,0 +1,684 @@
+pub mod error;
+
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::models::{AccountStatus, Role, User, UserList, UserQuery, UserSort};
+use crate::password::check_password_hash;
+use crate::repository::{UserRepositoryTrait, error::RepoError};
+use crate::service::{MAX_NAME_LEN, unusable_password, validate_email};
+use crate::utils::normalize_email;
+
+use error::{MigrationError, Result};
+
+const DEFAULT_BATCH_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserFormat {
+    // with a header row naming the columns
+    Csv,
+    // one JSON object per line
+    Jsonl,
+}
+
+impl UserFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserFormat::Csv => "csv",
+            UserFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+impl FromStr for UserFormat {
+    type Err = MigrationError;
+
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "csv" => Ok(UserFormat::Csv),
+            "jsonl" => Ok(UserFormat::Jsonl),
+            _ => Err(MigrationError::UnsupportedFormat(format.to_string())),
+        }
+    }
+}
+
+// A user as imported and exported. Passwords are hashes in the `<scheme>#<hash>` form
+// they're stored in, a user without one gets a password no one knows and signs in through
+// a provider or resets it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserRecord {
+    // generated when missing
+    #[serde(default)]
+    pub id: Option<String>,
+    pub email: String,
+    pub name: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    // separated by spaces
+    #[serde(default)]
+    pub roles: String,
+    // the time of the import when missing
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    // active when missing, a suspension also needs `suspended_until`
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub suspended_until: Option<i64>,
+    #[serde(default)]
+    pub status_reason: Option<String>,
+    #[serde(default)]
+    pub status_changed_by: Option<String>,
+    // sessions issued before this stay rejected on the other side
+    #[serde(default)]
+    pub sessions_revoked_at: Option<i64>,
+    // the account is erased once the grace period after it is over
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
+}
+
+impl From<&User> for UserRecord {
+    fn from(user: &User) -> Self {
+        Self {
+            id: Some(user.id.clone()),
+            email: user.email.clone(),
+            name: user.name.clone(),
+            password: Some(user.password.clone()).filter(|p| !p.is_empty()),
+            email_verified: user.email_verified,
+            roles: user
+                .roles
+                .iter()
+                .map(|r| r.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            created_at: Some(user.created_at),
+            status: user.status.as_str().to_string(),
+            suspended_until: match user.status {
+                AccountStatus::Suspended { until } => Some(until),
+                _ => None,
+            },
+            status_reason: user.status_reason.clone(),
+            status_changed_by: user.status_changed_by.clone(),
+            sessions_revoked_at: user.sessions_revoked_at,
+            deleted_at: user.deleted_at,
+        }
+    }
+}
+
+impl UserRecord {
+    // The user the record describes, or why it can't be imported
+    fn into_user(self, now: i64) -> std::result::Result<User, String> {
+        let email = self.email.trim().to_string();
+        if !validate_email(&normalize_email(&email)).unwrap_or(false) {
+            return Err(format!("invalid email: {email}"));
+        }
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err("name is required".to_string());
+        }
+        if name.chars().count() > MAX_NAME_LEN {
+            return Err(format!("name is longer than {MAX_NAME_LEN} characters"));
+        }
+        let password = match self.password.filter(|password| !password.is_empty()) {
+            Some(password) if check_password_hash(&password).is_err() => {
+                return Err("password isn't a hash in a known scheme".to_string());
+            }
+            Some(password) => password,
+            None => unusable_password().map_err(|e| format!("can't set a password: {e}"))?,
+        };
+        let roles = self
+            .roles
+            .split_whitespace()
+            .map(|role| role.parse().map_err(|_| format!("unknown role: {role}")))
+            .collect::<std::result::Result<Vec<Role>, _>>()?;
+        let status = match (self.status.trim(), self.suspended_until) {
+            ("" | "active", _) => AccountStatus::Active,
+            ("disabled", _) => AccountStatus::Disabled,
+            ("suspended", Some(until)) => AccountStatus::Suspended { until },
+            ("suspended", None) => return Err("suspended without suspended_until".to_string()),
+            ("pending_verification", _) => AccountStatus::PendingVerification,
+            (status, _) => return Err(format!("unknown status: {status}")),
+        };
+
+        let created_at = self.created_at.unwrap_or(now);
+        Ok(User {
+            id: self
+                .id
+                .filter(|id| !id.is_empty())
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            email_verified: self.email_verified,
+            status,
+            status_reason: self.status_reason.filter(|reason| !reason.is_empty()),
+            status_changed_by: self.status_changed_by.filter(|by| !by.is_empty()),
+            sessions_revoked_at: self.sessions_revoked_at,
+            roles,
+            deleted_at: self.deleted_at,
+            created_at,
+            updated_at: created_at,
+            ..User::new(email, password, name)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    // records read from the source
+    pub read: usize,
+    // users written to the target, or that would have been on a dry run
+    pub written: usize,
+    // users already in the target, e.g. from an interrupted run
+    pub existing: usize,
+    pub rejected: Vec<Rejected>,
+    // where a run that stopped early picks up again, None once the source was read to
+    // the end
+    pub resume_from: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    // the record's position in the input, or the id of the user being copied
+    pub record: String,
+    pub reason: String,
+}
+
+// Moves users between repositories, files and repositories. Writes are idempotent, a user
+// already in the target is counted and left alone, so a run can be repeated or resumed
+// from the `resume_from` of its report.
+pub struct Migration {
+    dry_run: bool,
+    batch_size: usize,
+    resume_from: Option<String>,
+    report: MigrationReport,
+}
+
+impl Default for Migration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Migration {
+    pub fn new() -> Self {
+        Self {
+            dry_run: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            resume_from: None,
+            report: MigrationReport::default(),
+        }
+    }
+
+    // everything is read and validated, nothing is written
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // how many users are read from a repository at a time
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    // the `resume_from` of an earlier run over the same source
+    pub fn with_resume_from(mut self, resume_from: Option<String>) -> Self {
+        self.resume_from = resume_from;
+        self
+    }
+
+    pub fn report(&self) -> &MigrationReport {
+        &self.report
+    }
+
+    // Copies every user from one repository to another, in creation order
+    pub async fn copy_users(
+        &mut self,
+        from: &dyn UserRepositoryTrait,
+        to: &dyn UserRepositoryTrait,
+    ) -> Result<()> {
+        self.report.resume_from = self.resume_from.clone();
+        let mut query = self.pages();
+        loop {
+            let page = self.page(from, &query).await?;
+            for user in page.users {
+                self.report.read += 1;
+                let record = user.id.clone();
+                self.write(to, user, record, false).await?;
+            }
+
+            self.report.resume_from = page.next_cursor.clone();
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // Creates the users in the input that pass validation, the others are reported
+    pub async fn import_users(
+        &mut self,
+        input: impl BufRead,
+        format: UserFormat,
+        to: &dyn UserRepositoryTrait,
+    ) -> Result<()> {
+        let skip = match &self.resume_from {
+            Some(resume_from) => resume_from
+                .parse()
+                .map_err(|_| MigrationError::InvalidResume(resume_from.clone()))?,
+            None => 0,
+        };
+        self.report.resume_from = Some(skip.to_string());
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        // the target only catches duplicates within the input when it's written to
+        let mut seen = HashSet::new();
+        for (i, record) in read_records(input, format).enumerate().skip(skip) {
+            let position = i + 1;
+            let record_name = format!("record {position}");
+            self.report.read += 1;
+
+            let user = record?.and_then(|record| {
+                let id_given = record.id.as_ref().is_some_and(|id| !id.is_empty());
+                let user = record.into_user(now)?;
+                if !seen.insert(normalize_email(&user.email))
+                    || (id_given && !seen.insert(user.id.clone()))
+                {
+                    return Err("duplicate of an earlier record".to_string());
+                }
+                Ok((user, id_given))
+            });
+            match user {
+                Ok((user, id_given)) => self.write(to, user, record_name, !id_given).await?,
+                Err(reason) => self.reject(record_name, reason),
+            }
+            self.report.resume_from = Some(position.to_string());
+        }
+
+        self.report.resume_from = None;
+        Ok(())
+    }
+
+    // Writes every user in creation order. Users waiting to be erased are left out.
+    pub async fn export_users(
+        &mut self,
+        from: &dyn UserRepositoryTrait,
+        format: UserFormat,
+        output: impl Write,
+    ) -> Result<()> {
+        self.report.resume_from = self.resume_from.clone();
+        // a resumed export appends to what was written already, header included
+        let mut writer = RecordWriter::new(output, format, self.resume_from.is_none());
+        let mut query = self.pages();
+        loop {
+            let page = self.page(from, &query).await?;
+            for user in page.users {
+                self.report.read += 1;
+                if user.deleted_at.is_some() {
+                    continue;
+                }
+                if !self.dry_run {
+                    writer.write(&UserRecord::from(&user))?;
+                }
+                self.report.written += 1;
+            }
+
+            // the resume point only moves past what's actually been written out
+            writer.flush()?;
+            self.report.resume_from = page.next_cursor.clone();
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn pages(&self) -> UserQuery {
+        UserQuery {
+            sort: UserSort::CreatedAt,
+            descending: false,
+            cursor: self.resume_from.clone(),
+            limit: self.batch_size,
+            ..Default::default()
+        }
+    }
+
+    async fn page(&self, from: &dyn UserRepositoryTrait, query: &UserQuery) -> Result<UserList> {
+        from.list_users(query).await.map_err(|e| match e {
+            RepoError::InvalidCursor => {
+                MigrationError::InvalidResume(query.cursor.clone().unwrap_or_default())
+            }
+            e => e.into(),
+        })
+    }
+
+    // Creates the user unless it's in the target already, by id or, for records that
+    // didn't have one, by email
+    async fn write(
+        &mut self,
+        to: &dyn UserRepositoryTrait,
+        user: User,
+        record: String,
+        match_email: bool,
+    ) -> Result<()> {
+        let existing = match to.find_by_id(&user.id).await? {
+            Some(_) => true,
+            None if match_email => to.find_by_email(&user.email).await?.is_some(),
+            None => false,
+        };
+        if existing {
+            self.report.existing += 1;
+            return Ok(());
+        }
+
+        if self.dry_run {
+            match to.find_by_email(&user.email).await? {
+                Some(_) => self.reject(record, "email is already in use".to_string()),
+                None => self.report.written += 1,
+            }
+            return Ok(());
+        }
+        match to.create_user(user).await {
+            Ok(_) => self.report.written += 1,
+            Err(RepoError::DuplicateEmail) => {
+                self.reject(record, "email is already in use".to_string())
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    fn reject(&mut self, record: String, reason: String) {
+        self.report.rejected.push(Rejected { record, reason });
+    }
+}
+
+type ReadRecord = Result<std::result::Result<UserRecord, String>>;
+
+// Records one at a time, ones that don't parse are rejected and reading goes on, failing
+// to read the input at all stops it
+fn read_records<'a>(
+    input: impl BufRead + 'a,
+    format: UserFormat,
+) -> Box<dyn Iterator<Item = ReadRecord> + 'a> {
+    match format {
+        UserFormat::Csv => Box::new(
+            csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(input)
+                .into_deserialize()
+                .map(|record| match record {
+                    Ok(record) => Ok(Ok(record)),
+                    Err(e) if e.is_io_error() => Err(MigrationError::Read(e.to_string())),
+                    Err(e) => Ok(Err(e.to_string())),
+                }),
+        ),
+        UserFormat::Jsonl => Box::new(
+            input
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|line| match line {
+                    Ok(line) => Ok(serde_json::from_str(&line).map_err(|e| e.to_string())),
+                    Err(e) => Err(MigrationError::Read(e.to_string())),
+                }),
+        ),
+    }
+}
+
+enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(W),
+}
+
+impl<W: Write> RecordWriter<W> {
+    fn new(output: W, format: UserFormat, header: bool) -> Self {
+        match format {
+            UserFormat::Csv => RecordWriter::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .has_headers(header)
+                    .from_writer(output),
+            )),
+            UserFormat::Jsonl => RecordWriter::Jsonl(output),
+        }
+    }
+
+    fn write(&mut self, record: &UserRecord) -> Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => writer
+                .serialize(record)
+                .map_err(|e| MigrationError::Write(e.to_string())),
+            RecordWriter::Jsonl(output) => {
+                serde_json::to_writer(&mut *output, record)
+                    .map_err(|e| MigrationError::Write(e.to_string()))?;
+                output
+                    .write_all(b"\n")
+                    .map_err(|e| MigrationError::Write(e.to_string()))
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            RecordWriter::Csv(writer) => writer.flush(),
+            RecordWriter::Jsonl(output) => output.flush(),
+        }
+        .map_err(|e| MigrationError::Write(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::repository::{
+        in_mem_user_repo::InMemoryUserRepository, redb_user_repo::RedbUserRepository,
+    };
+
+    const HASH: &str = "01#$argon2id$v=19$m=19456,t=2,p=1$fNNcBL/FRUi8WfpMK6bpzA$bf4xoz3GFXZC5Hx0I4yQD7xJufC7KaoOFf6ZV6+YA4A";
+
+    async fn seed(repo: &dyn UserRepositoryTrait, count: usize) -> Vec<User> {
+        let mut users = Vec::new();
+        for i in 0..count {
+            let mut user = User::new(
+                format!("user{i}@example.com"),
+                HASH.to_string(),
+                format!("User {i}"),
+            );
+            user.created_at = 1000 + i as i64;
+            users.push(repo.create_user(user).await.unwrap());
+        }
+        users
+    }
+
+    #[tokio::test]
+    async fn test_copy_users() {
+        let from = InMemoryUserRepository::new();
+        let users = seed(&from, 5).await;
+        let to = RedbUserRepository::open_in_memory().unwrap();
+        to.create_user(users[1].clone()).await.unwrap();
+
+        // A dry run finds what would be copied and writes nothing
+        let mut dry_run = Migration::new().with_dry_run(true).with_batch_size(2);
+        dry_run.copy_users(&from, &to).await.unwrap();
+        assert_eq!(dry_run.report().written, 4);
+        assert_eq!(dry_run.report().existing, 1);
+        assert!(to.find_by_id(&users[0].id).await.unwrap().is_none());
+
+        // Stopping after a page and resuming copies everyone once
+        let mut first = Migration::new().with_batch_size(2);
+        let page = from
+            .list_users(&UserQuery {
+                limit: 2,
+                ..first.pages()
+            })
+            .await
+            .unwrap();
+        for user in page.users {
+            first.write(&to, user, String::new(), false).await.unwrap();
+        }
+        let mut resumed = Migration::new()
+            .with_batch_size(2)
+            .with_resume_from(page.next_cursor);
+        resumed.copy_users(&from, &to).await.unwrap();
+        assert_eq!(resumed.report().read, 3);
+        assert_eq!(resumed.report().written, 3);
+        assert!(resumed.report().resume_from.is_none());
+        for user in &users {
+            let copied = to.find_by_id(&user.id).await.unwrap().unwrap();
+            assert_eq!(copied.email, user.email);
+            assert_eq!(copied.password, user.password);
+        }
+
+        let mut stale = Migration::new().with_resume_from(Some("nonsense".to_string()));
+        assert!(matches!(
+            stale.copy_users(&from, &to).await,
+            Err(MigrationError::InvalidResume(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_import_users() {
+        let csv = format!(
+            "email,name,password,roles,email_verified\n\
+             ada@example.com,Ada Lovelace,\"{HASH}\",admin,true\n\
+             not an email,Nobody,,,false\n\
+             grace@example.com,Grace Hopper,plaintext,,false\n\
+             alan@example.com,Alan Turing,,superuser,false\n\
+             ADA@example.com,Ada Again,,,false\n\
+             edsger@example.com,Edsger Dijkstra,,,false\n\
+             nameless@example.com, ,,,false\n"
+        );
+        let user_repo = Arc::new(InMemoryUserRepository::new());
+
+        // A dry run reports every problem and writes nothing
+        let mut dry_run = Migration::new().with_dry_run(true);
+        dry_run
+            .import_users(csv.as_bytes(), UserFormat::Csv, user_repo.as_ref())
+            .await
+            .unwrap();
+        let report = dry_run.report();
+        assert_eq!((report.read, report.written), (7, 2));
+        let rejected: Vec<_> = report.rejected.iter().map(|r| r.record.as_str()).collect();
+        assert_eq!(
+            rejected,
+            ["record 2", "record 3", "record 4", "record 5", "record 7"]
+        );
+        assert_eq!(report.rejected[4].reason, "name is required");
+        assert!(
+            user_repo
+                .find_by_email("ada@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Resuming part way through picks up from there
+        let mut partial = Migration::new().with_resume_from(Some("5".to_string()));
+        partial
+            .import_users(csv.as_bytes(), UserFormat::Csv, user_repo.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(partial.report().written, 1);
+
+        // and running it again only adds what's missing
+        let mut import = Migration::new();
+        import
+            .import_users(csv.as_bytes(), UserFormat::Csv, user_repo.as_ref())
+            .await
+            .unwrap();
+        let report = import.report();
+        assert_eq!((report.written, report.existing), (1, 1));
+        assert_eq!(report.rejected.len(), 5);
+        assert!(report.resume_from.is_none());
+        let ada = user_repo
+            .find_by_email("ada@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ada.password, HASH);
+        assert_eq!(ada.roles, [Role::Admin]);
+        assert!(ada.email_verified);
+        assert_eq!(ada.status, AccountStatus::Active);
+        // a user imported without a password has one no one knows
+        let edsger = user_repo
+            .find_by_email("edsger@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(check_password_hash(&edsger.password).is_ok());
+
+        // A status that can't be told isn't taken to be active
+        let jsonl = "{\"email\":\"x@example.com\",\"name\":\"X\",\"status\":\"suspended\"}\n\
+                     {\"email\":\"y@example.com\",\"name\":\"Y\",\"status\":\"banned\"}\n";
+        let mut import = Migration::new();
+        import
+            .import_users(jsonl.as_bytes(), UserFormat::Jsonl, user_repo.as_ref())
+            .await
+            .unwrap();
+        let reasons: Vec<_> = import
+            .report()
+            .rejected
+            .iter()
+            .map(|r| r.reason.as_str())
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                "suspended without suspended_until",
+                "unknown status: banned"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_users() {
+        let from = InMemoryUserRepository::new();
+        let mut users = seed(&from, 4).await;
+        users[3].deleted_at = Some(2000);
+        from.update_user(&users[3]).await.unwrap();
+        // accounts that can't sign in stay that way
+        users[0].status = AccountStatus::Disabled;
+        users[0].status_reason = Some("chargeback, see ticket 42".to_string());
+        users[0].status_changed_by = Some("admin-1".to_string());
+        users[0].sessions_revoked_at = Some(1500);
+        users[0] = from.update_user(&users[0]).await.unwrap();
+        users[1].status = AccountStatus::Suspended { until: 3000 };
+        users[1] = from.update_user(&users[1]).await.unwrap();
+
+        for format in [UserFormat::Csv, UserFormat::Jsonl] {
+            let mut output = Vec::new();
+            let mut export = Migration::new().with_batch_size(1);
+            export
+                .export_users(&from, format, &mut output)
+                .await
+                .unwrap();
+            assert_eq!(export.report().written, 3);
+
+            // what's exported imports as the same users
+            let to = InMemoryUserRepository::new();
+            let mut import = Migration::new();
+            import
+                .import_users(output.as_slice(), format, &to)
+                .await
+                .unwrap();
+            assert_eq!(import.report().written, 3, "{}", format.as_str());
+            for user in &users[..3] {
+                let imported = to.find_by_id(&user.id).await.unwrap().unwrap();
+                assert_eq!(imported.email, user.email);
+                assert_eq!(imported.password, user.password);
+                assert_eq!(imported.created_at, user.created_at);
+                assert_eq!(imported.status, user.status);
+                assert_eq!(imported.status_reason, user.status_reason);
+                assert_eq!(imported.status_changed_by, user.status_changed_by);
+                assert_eq!(imported.sessions_revoked_at, user.sessions_revoked_at);
+            }
+            assert!(to.find_by_id(&users[3].id).await.unwrap().is_none());
+        }
+    }
+}
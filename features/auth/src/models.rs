@@ -1,15 +1,34 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+use crate::error::{AuthError, Result};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
     pub email: String,
+    // set once we know the user controls the address, e.g. a provider vouched for it
+    pub email_verified: bool,
     pub password: String,
     pub name: String,
+    // only active users can sign in, the tokens of any other are rejected
+    pub status: AccountStatus,
+    // why the status was last changed and who changed it
+    pub status_reason: Option<String>,
+    pub status_changed_by: Option<String>,
+    // sessions issued at or before this are rejected
+    pub sessions_revoked_at: Option<i64>,
+    pub roles: Vec<Role>,
+    // set when the user asks for their account to be erased, it's purged after a grace period
+    pub deleted_at: Option<i64>,
     pub created_at: i64,
     pub updated_at: i64,
+    // bumped on every save, an update only succeeds on the version it was read at
+    pub version: u64,
 }
 
 impl User {
@@ -17,10 +36,88 @@ impl User {
         Self {
             id: Uuid::new_v4().to_string(),
             email,
+            email_verified: false,
             password,
             name,
+            status: AccountStatus::Active,
+            status_reason: None,
+            status_changed_by: None,
+            sessions_revoked_at: None,
+            roles: Vec::new(),
+            deleted_at: None,
             created_at: OffsetDateTime::now_utc().unix_timestamp(),
             updated_at: OffsetDateTime::now_utc().unix_timestamp(),
+            version: 0,
+        }
+    }
+
+    pub fn is_session_revoked(&self, issued_at: i64) -> bool {
+        self.sessions_revoked_at
+            .is_some_and(|revoked_at| issued_at <= revoked_at)
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AccountStatus {
+    Active,
+    // until an admin enables the account again
+    Disabled,
+    // the account is active again once `until` has passed
+    Suspended { until: i64 },
+    // until the user's email address is verified
+    PendingVerification,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Disabled => "disabled",
+            AccountStatus::Suspended { .. } => "suspended",
+            AccountStatus::PendingVerification => "pending_verification",
+        }
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        match self {
+            AccountStatus::Active => true,
+            AccountStatus::Suspended { until } => *until <= now,
+            AccountStatus::Disabled | AccountStatus::PendingVerification => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    // can see the audit log
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = AuthError;
+
+    fn from_str(role: &str) -> Result<Self> {
+        match role {
+            "admin" => Ok(Role::Admin),
+            _ => Err(AuthError::InvalidRole(role.to_string())),
         }
     }
 }
@@ -37,3 +134,410 @@ pub struct RegisterUser {
     pub password: String,
     pub name: String,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Read,
+    Write,
+    // OpenID Connect scopes, they only expose the user's own identity
+    OpenId,
+    Email,
+    Profile,
+}
+
+impl Scope {
+    pub const OIDC: [Scope; 3] = [Scope::OpenId, Scope::Email, Scope::Profile];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::OpenId => "openid",
+            Scope::Email => "email",
+            Scope::Profile => "profile",
+        }
+    }
+
+    // space separated list, as used by oauth
+    pub fn parse_list(scopes: &str) -> Result<Vec<Scope>> {
+        let mut parsed = Vec::new();
+        for scope in scopes.split_whitespace() {
+            let scope = scope.parse()?;
+            if !parsed.contains(&scope) {
+                parsed.push(scope);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl FromStr for Scope {
+    type Err = AuthError;
+
+    fn from_str(scope: &str) -> Result<Self> {
+        match scope {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            "openid" => Ok(Scope::OpenId),
+            "email" => Ok(Scope::Email),
+            "profile" => Ok(Scope::Profile),
+            _ => Err(AuthError::InvalidScope(scope.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+impl ApiKey {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<i64>,
+}
+
+// An external account (e.g. google, github) linked to a user
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub id: String,
+    pub user_id: String,
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+    pub created_at: i64,
+}
+
+// The identity returned by a provider after a successful signin
+#[derive(Debug, Clone)]
+pub struct ExternalIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OAuthProvider {
+    pub name: String,
+    pub display_name: String,
+}
+
+// A third party application allowed to act on behalf of our users
+#[derive(Debug, Clone)]
+pub struct ClientApp {
+    pub id: String,
+    pub owner_id: String,
+    pub name: String,
+    pub secret_hash: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<Scope>,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewClientApp {
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<Scope>,
+}
+
+// A customer organization's SAML identity provider, imported from its metadata
+#[derive(Debug, Clone)]
+pub struct SsoConnection {
+    pub org: String,
+    pub entity_id: String,
+    pub sso_url: String,
+    // DER encoded, more than one while the IdP rolls its key over
+    pub certificates: Vec<Vec<u8>>,
+    // the IdP is only trusted to vouch for emails in these domains
+    pub email_domains: Vec<String>,
+    pub created_at: i64,
+}
+
+// Bearer token an organization's directory uses to call the SCIM api
+#[derive(Debug, Clone)]
+pub struct ScimToken {
+    pub id: String,
+    pub org: String,
+    pub prefix: String,
+    pub token_hash: String,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+// A user managed by an organization's directory, with the SCIM attributes `User` has no room for
+#[derive(Debug, Clone)]
+pub struct ProvisionedUser {
+    pub org: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub external_id: Option<String>,
+    pub display_name: Option<String>,
+    pub formatted_name: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    // kept after deprovisioning so the directory can provision the account again
+    pub deprovisioned_at: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub id: String,
+    pub org: String,
+    pub display_name: String,
+    pub external_id: Option<String>,
+    // user ids
+    pub members: Vec<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+// How signed in users are remembered, a self-contained JWT or an id into a session store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionMode {
+    Jwt,
+    ServerSide,
+}
+
+#[derive(Debug, Clone)]
+pub struct Session {
+    // sha256 of the token in the cookie, the token itself is never stored
+    pub id: String,
+    pub user_id: String,
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    pub expires_at: i64,
+    // where the user signed in from
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    // per-session values set by the application
+    pub data: HashMap<String, String>,
+    // the admin acting as the user, if this is an impersonation session
+    pub impersonator_id: Option<String>,
+}
+
+impl Session {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+// The client a signin came from, as seen by the web layer
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+// A session as shown to its user
+#[derive(Debug, Clone)]
+pub struct ActiveSession {
+    pub id: String,
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    pub ip: Option<String>,
+    // e.g. "Firefox on macOS"
+    pub device: String,
+    // the session the request was made with
+    pub current: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthorizationCode {
+    pub code: String,
+    pub client_id: String,
+    pub user_id: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<Scope>,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    pub jti: String,
+    pub client_id: String,
+    pub user_id: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+// A new login email waiting to be confirmed from the new address, the old address can
+// cancel it. Only the hashes of the emailed tokens are kept.
+#[derive(Debug, Clone)]
+pub struct EmailChange {
+    pub user_id: String,
+    pub new_email: String,
+    pub confirm_token_hash: String,
+    pub cancel_token_hash: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+impl EmailChange {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+// A password reset an admin forced on the user, only the hash of the emailed token is kept
+#[derive(Debug, Clone)]
+pub struct PasswordReset {
+    pub user_id: String,
+    pub token_hash: String,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+impl PasswordReset {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UserSort {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl UserSort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserSort::CreatedAt => "created_at",
+            UserSort::UpdatedAt => "updated_at",
+        }
+    }
+
+    pub fn key(&self, user: &User) -> i64 {
+        match self {
+            UserSort::CreatedAt => user.created_at,
+            UserSort::UpdatedAt => user.updated_at,
+        }
+    }
+}
+
+impl FromStr for UserSort {
+    type Err = AuthError;
+
+    fn from_str(sort: &str) -> Result<Self> {
+        match sort {
+            "created_at" => Ok(UserSort::CreatedAt),
+            "updated_at" => Ok(UserSort::UpdatedAt),
+            _ => Err(AuthError::InvalidSort(sort.to_string())),
+        }
+    }
+}
+
+// Which users to list, unset fields match everyone. Text matches ignore case.
+#[derive(Debug, Clone, Default)]
+pub struct UserFilter {
+    // the email or the name contains it
+    pub search: Option<String>,
+    pub email_prefix: Option<String>,
+    pub name_contains: Option<String>,
+    // compared by kind, a suspension matches whenever it ends
+    pub status: Option<AccountStatus>,
+    // created at or after
+    pub created_from: Option<i64>,
+    // created strictly before
+    pub created_until: Option<i64>,
+}
+
+impl UserFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        let contains =
+            |value: &str, part: &str| value.to_lowercase().contains(&part.to_lowercase());
+
+        self.search
+            .as_ref()
+            .is_none_or(|s| contains(&user.email, s) || contains(&user.name, s))
+            && self
+                .email_prefix
+                .as_ref()
+                .is_none_or(|p| user.email.to_lowercase().starts_with(&p.to_lowercase()))
+            && self
+                .name_contains
+                .as_ref()
+                .is_none_or(|n| contains(&user.name, n))
+            && self
+                .status
+                .is_none_or(|s| s.as_str() == user.status.as_str())
+            && self.created_from.is_none_or(|from| user.created_at >= from)
+            && self
+                .created_until
+                .is_none_or(|until| user.created_at < until)
+    }
+}
+
+// A page of users. Ties on the sort key are broken by id, so paging through with the
+// returned cursors lists every matching user exactly once, as long as their sort key
+// doesn't change in between.
+#[derive(Debug, Clone)]
+pub struct UserQuery {
+    pub filter: UserFilter,
+    pub sort: UserSort,
+    pub descending: bool,
+    // the previous page's `next_cursor`, None for the first page
+    pub cursor: Option<String>,
+    pub limit: usize,
+}
+
+impl Default for UserQuery {
+    fn default() -> Self {
+        Self {
+            filter: UserFilter::default(),
+            sort: UserSort::default(),
+            descending: false,
+            cursor: None,
+            limit: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UserList {
+    pub users: Vec<User>,
+    // None on the last page
+    pub next_cursor: Option<String>,
+}
@@ -0,0 +1,34 @@
+pub type Result<T> = std::result::Result<T, OAuthError>;
+
+#[derive(Debug)]
+pub enum OAuthError {
+    ProviderNotFound(String),
+    InvalidState,
+    NonceMismatch,
+    SubjectMismatch,
+    MissingIdToken,
+    MissingEmail,
+    IdToken(String),
+    TokenExchange(String),
+    UserInfo(String),
+    Transport(String),
+}
+
+impl std::fmt::Display for OAuthError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            OAuthError::ProviderNotFound(e) => write!(fmt, "Provider not found: {e}"),
+            OAuthError::IdToken(e) => write!(fmt, "ID token: {e}"),
+            OAuthError::TokenExchange(e) => write!(fmt, "Token exchange: {e}"),
+            OAuthError::UserInfo(e) => write!(fmt, "User info: {e}"),
+            OAuthError::Transport(e) => write!(fmt, "Transport: {e}"),
+            OAuthError::InvalidState
+            | OAuthError::NonceMismatch
+            | OAuthError::SubjectMismatch
+            | OAuthError::MissingIdToken
+            | OAuthError::MissingEmail => write!(fmt, "{self:?}"),
+        }
+    }
+}
+
+impl std::error::Error for OAuthError {}
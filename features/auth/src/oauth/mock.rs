@@ -0,0 +1,224 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use jsonwebtoken::{EncodingKey, Header, encode};
+use serde::Serialize;
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use url::Url;
+
+use super::error::{OAuthError, Result};
+use super::pkce;
+use super::provider::{ClaimNames, OAuthProviderConfig};
+use super::transport::OAuthTransport;
+use crate::utils::random_string;
+
+// In-process authorization server, stands in for a real provider in tests
+pub struct MockAuthorizationServer {
+    client_id: String,
+    client_secret: String,
+    users: Mutex<HashMap<String, MockUser>>,
+    codes: Mutex<HashMap<String, IssuedCode>>,
+    access_tokens: Mutex<HashMap<String, String>>,
+    nonce_override: Mutex<Option<String>>,
+}
+
+#[derive(Clone)]
+pub struct MockUser {
+    pub sub: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub name: String,
+}
+
+struct IssuedCode {
+    sub: String,
+    redirect_uri: String,
+    code_challenge: String,
+    nonce: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MockIdTokenClaims {
+    iss: String,
+    aud: String,
+    sub: String,
+    exp: i64,
+    iat: i64,
+    nonce: Option<String>,
+    email: String,
+    email_verified: bool,
+    name: String,
+}
+
+impl Default for MockAuthorizationServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockAuthorizationServer {
+    pub const ISSUER: &str = "http://mock.idp";
+    pub const AUTHORIZE_URL: &str = "http://mock.idp/authorize";
+    pub const TOKEN_URL: &str = "http://mock.idp/token";
+    pub const USERINFO_URL: &str = "http://mock.idp/userinfo";
+    pub const REDIRECT_URI: &str = "http://localhost/auth/oauth/mock/callback";
+
+    pub fn new() -> Self {
+        Self {
+            client_id: "mock-client".to_string(),
+            client_secret: "mock-secret".to_string(),
+            users: Mutex::new(HashMap::new()),
+            codes: Mutex::new(HashMap::new()),
+            access_tokens: Mutex::new(HashMap::new()),
+            nonce_override: Mutex::new(None),
+        }
+    }
+
+    pub fn provider_config(&self) -> OAuthProviderConfig {
+        OAuthProviderConfig {
+            name: "mock".to_string(),
+            display_name: "Mock".to_string(),
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+            redirect_uri: Self::REDIRECT_URI.to_string(),
+            authorize_url: Self::AUTHORIZE_URL.to_string(),
+            token_url: Self::TOKEN_URL.to_string(),
+            userinfo_url: Self::USERINFO_URL.to_string(),
+            // id tokens are signed with the client secret
+            jwks_url: None,
+            issuer: Some(Self::ISSUER.to_string()),
+            openid: true,
+            scopes: vec!["openid".into(), "email".into(), "profile".into()],
+            claims: ClaimNames::default(),
+        }
+    }
+
+    pub fn add_user(&self, user: MockUser) {
+        self.users.lock().unwrap().insert(user.sub.clone(), user);
+    }
+
+    // Sign id tokens with this nonce instead of the one requested
+    pub fn set_nonce_override(&self, nonce: Option<String>) {
+        *self.nonce_override.lock().unwrap() = nonce;
+    }
+
+    // Simulates the user approving the request, returns the callback (code, state)
+    pub fn approve(&self, authorize_url: &str, sub: &str) -> Result<(String, String)> {
+        let url = Url::parse(authorize_url).map_err(|e| OAuthError::Transport(e.to_string()))?;
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        let param = |name: &str| {
+            params
+                .get(name)
+                .cloned()
+                .ok_or_else(|| OAuthError::Transport(format!("missing {name}")))
+        };
+
+        if param("client_id")? != self.client_id || param("response_type")? != "code" {
+            return Err(OAuthError::Transport("invalid_request".to_string()));
+        }
+        if param("code_challenge_method")? != pkce::CHALLENGE_METHOD {
+            return Err(OAuthError::Transport("invalid_request".to_string()));
+        }
+        if !self.users.lock().unwrap().contains_key(sub) {
+            return Err(OAuthError::Transport("access_denied".to_string()));
+        }
+
+        let code = random_string(24);
+        self.codes.lock().unwrap().insert(
+            code.clone(),
+            IssuedCode {
+                sub: sub.to_string(),
+                redirect_uri: param("redirect_uri")?,
+                code_challenge: param("code_challenge")?,
+                nonce: params.get("nonce").cloned(),
+            },
+        );
+
+        Ok((code, param("state")?))
+    }
+
+    fn token_endpoint(&self, form: &HashMap<&str, &str>) -> Value {
+        let field = |name: &str| form.get(name).copied().unwrap_or_default();
+
+        if field("grant_type") != "authorization_code"
+            || field("client_id") != self.client_id
+            || field("client_secret") != self.client_secret
+        {
+            return json!({ "error": "invalid_client" });
+        }
+
+        let issued = match self.codes.lock().unwrap().remove(field("code")) {
+            Some(issued) => issued,
+            None => return json!({ "error": "invalid_grant" }),
+        };
+
+        if issued.redirect_uri != field("redirect_uri")
+            || issued.code_challenge != pkce::challenge(field("code_verifier"))
+        {
+            return json!({ "error": "invalid_grant" });
+        }
+
+        let user = self.users.lock().unwrap()[&issued.sub].clone();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let nonce = self.nonce_override.lock().unwrap().clone().or(issued.nonce);
+
+        let id_token = encode(
+            &Header::default(),
+            &MockIdTokenClaims {
+                iss: Self::ISSUER.to_string(),
+                aud: self.client_id.clone(),
+                sub: user.sub.clone(),
+                exp: now + 300,
+                iat: now,
+                nonce,
+                email: user.email,
+                email_verified: user.email_verified,
+                name: user.name,
+            },
+            &EncodingKey::from_secret(self.client_secret.as_bytes()),
+        )
+        .unwrap();
+
+        let access_token = random_string(32);
+        self.access_tokens
+            .lock()
+            .unwrap()
+            .insert(access_token.clone(), user.sub);
+
+        json!({
+            "access_token": access_token,
+            "token_type": "Bearer",
+            "expires_in": 300,
+            "id_token": id_token,
+        })
+    }
+}
+
+#[async_trait]
+impl OAuthTransport for MockAuthorizationServer {
+    async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> Result<Value> {
+        match url {
+            Self::TOKEN_URL => Ok(self.token_endpoint(&form.iter().copied().collect())),
+            _ => Err(OAuthError::Transport(format!("404 {url}"))),
+        }
+    }
+
+    async fn get_json(&self, url: &str, bearer: Option<&str>) -> Result<Value> {
+        if url != Self::USERINFO_URL {
+            return Err(OAuthError::Transport(format!("404 {url}")));
+        }
+
+        let sub = bearer
+            .and_then(|token| self.access_tokens.lock().unwrap().get(token).cloned())
+            .ok_or_else(|| OAuthError::Transport("401 invalid_token".to_string()))?;
+        let user = self.users.lock().unwrap()[&sub].clone();
+
+        Ok(json!({
+            "sub": user.sub,
+            "email": user.email,
+            "email_verified": user.email_verified,
+            "name": user.name,
+        }))
+    }
+}
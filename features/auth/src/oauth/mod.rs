@@ -0,0 +1,377 @@
+pub mod error;
+#[cfg(test)]
+pub mod mock;
+pub(crate) mod pkce;
+pub mod provider;
+pub mod transport;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use serde::Deserialize;
+use serde_json::Value;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::models::ExternalIdentity;
+use crate::utils::random_string;
+
+use error::{OAuthError, Result};
+use provider::OAuthProviderConfig;
+use transport::OAuthTransport;
+
+// How long a user has to complete the flow at the provider
+const PENDING_TTL_SECONDS: i64 = 600;
+const STATE_LEN: usize = 32;
+
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub state: String,
+}
+
+struct PendingAuthorization {
+    provider: String,
+    nonce: String,
+    code_verifier: String,
+    expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    name: Option<String>,
+}
+
+pub struct OAuthClient {
+    providers: Vec<OAuthProviderConfig>,
+    transport: Arc<dyn OAuthTransport>,
+    pending: Mutex<HashMap<String, PendingAuthorization>>,
+}
+
+impl OAuthClient {
+    pub fn new(transport: Arc<dyn OAuthTransport>) -> Self {
+        Self {
+            providers: Vec::new(),
+            transport,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_provider(mut self, provider: OAuthProviderConfig) -> Self {
+        self.providers.push(provider);
+        self
+    }
+
+    pub fn providers(&self) -> &[OAuthProviderConfig] {
+        &self.providers
+    }
+
+    fn provider(&self, name: &str) -> Result<&OAuthProviderConfig> {
+        self.providers
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| OAuthError::ProviderNotFound(name.to_string()))
+    }
+
+    // Authorization code + PKCE, the state is used to look the flow up on callback
+    pub fn authorize(&self, provider_name: &str) -> Result<AuthorizationRequest> {
+        let provider = self.provider(provider_name)?;
+
+        let state = random_string(STATE_LEN);
+        let nonce = random_string(STATE_LEN);
+        let code_verifier = pkce::generate_verifier();
+
+        let mut url = Url::parse(&provider.authorize_url)
+            .map_err(|e| OAuthError::Transport(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &provider.redirect_uri)
+            .append_pair("scope", &provider.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("code_challenge", &pkce::challenge(&code_verifier))
+            .append_pair("code_challenge_method", pkce::CHALLENGE_METHOD);
+        if provider.openid {
+            url.query_pairs_mut().append_pair("nonce", &nonce);
+        }
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut pending = self
+            .pending
+            .lock()
+            .map_err(|_| OAuthError::Transport("pending lock poisoned".to_string()))?;
+        pending.retain(|_, p| p.expires_at > now);
+        pending.insert(
+            state.clone(),
+            PendingAuthorization {
+                provider: provider.name.clone(),
+                nonce,
+                code_verifier,
+                expires_at: now + PENDING_TTL_SECONDS,
+            },
+        );
+
+        Ok(AuthorizationRequest {
+            url: url.to_string(),
+            state,
+        })
+    }
+
+    pub async fn exchange_code(
+        &self,
+        provider_name: &str,
+        code: &str,
+        state: &str,
+    ) -> Result<ExternalIdentity> {
+        let provider = self.provider(provider_name)?;
+
+        // a state can only be used once
+        let pending = self
+            .pending
+            .lock()
+            .map_err(|_| OAuthError::Transport("pending lock poisoned".to_string()))?
+            .remove(state)
+            .ok_or(OAuthError::InvalidState)?;
+
+        if pending.provider != provider.name
+            || pending.expires_at <= OffsetDateTime::now_utc().unix_timestamp()
+        {
+            return Err(OAuthError::InvalidState);
+        }
+
+        let token_response = self
+            .transport
+            .post_form(
+                &provider.token_url,
+                &[
+                    ("grant_type", "authorization_code"),
+                    ("code", code),
+                    ("redirect_uri", &provider.redirect_uri),
+                    ("client_id", &provider.client_id),
+                    ("client_secret", &provider.client_secret),
+                    ("code_verifier", &pending.code_verifier),
+                ],
+            )
+            .await?;
+
+        if let Some(error) = token_response.get("error") {
+            return Err(OAuthError::TokenExchange(error.to_string()));
+        }
+
+        let access_token = token_response
+            .get("access_token")
+            .and_then(Value::as_str)
+            .ok_or_else(|| OAuthError::TokenExchange("missing access_token".to_string()))?;
+
+        let id_claims = if provider.openid {
+            let id_token = token_response
+                .get("id_token")
+                .and_then(Value::as_str)
+                .ok_or(OAuthError::MissingIdToken)?;
+
+            Some(
+                self.verify_id_token(provider, id_token, &pending.nonce)
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        let userinfo = self
+            .transport
+            .get_json(&provider.userinfo_url, Some(access_token))
+            .await?;
+
+        map_identity(provider, &userinfo, id_claims)
+    }
+
+    async fn verify_id_token(
+        &self,
+        provider: &OAuthProviderConfig,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims> {
+        let header = decode_header(id_token).map_err(|e| OAuthError::IdToken(e.to_string()))?;
+
+        // the key type comes from our config, never from the token header
+        let key = match &provider.jwks_url {
+            Some(jwks_url) => {
+                if matches!(
+                    header.alg,
+                    Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+                ) {
+                    return Err(OAuthError::IdToken("unexpected algorithm".to_string()));
+                }
+
+                let jwks: JwkSet =
+                    serde_json::from_value(self.transport.get_json(jwks_url, None).await?)
+                        .map_err(|e| OAuthError::IdToken(e.to_string()))?;
+
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    None => jwks.keys.first(),
+                }
+                .ok_or_else(|| OAuthError::IdToken("unknown key id".to_string()))?;
+
+                DecodingKey::from_jwk(jwk).map_err(|e| OAuthError::IdToken(e.to_string()))?
+            }
+            None => DecodingKey::from_secret(provider.client_secret.as_bytes()),
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&provider.client_id]);
+        if let Some(issuer) = &provider.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| OAuthError::IdToken(e.to_string()))?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OAuthError::NonceMismatch);
+        }
+
+        Ok(claims)
+    }
+}
+
+fn map_identity(
+    provider: &OAuthProviderConfig,
+    userinfo: &Value,
+    id_claims: Option<IdTokenClaims>,
+) -> Result<ExternalIdentity> {
+    let claim = |name: &str| -> Option<String> {
+        match userinfo.get(name)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    };
+
+    let subject = claim(&provider.claims.subject)
+        .ok_or_else(|| OAuthError::UserInfo(format!("missing {}", provider.claims.subject)))?;
+
+    let mut email = claim(&provider.claims.email);
+    let mut email_verified = provider
+        .claims
+        .email_verified
+        .as_ref()
+        .and_then(|name| userinfo.get(name))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let mut name = claim(&provider.claims.name);
+
+    if let Some(id_claims) = id_claims {
+        if id_claims.sub != subject {
+            return Err(OAuthError::SubjectMismatch);
+        }
+
+        if email.is_none() {
+            email = id_claims.email;
+            email_verified = id_claims.email_verified.unwrap_or(false);
+        }
+        name = name.or(id_claims.name);
+    }
+
+    let email = email.ok_or(OAuthError::MissingEmail)?.to_lowercase();
+
+    Ok(ExternalIdentity {
+        provider: provider.name.clone(),
+        subject,
+        name: name.unwrap_or_else(|| email.clone()),
+        email,
+        email_verified,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::{MockAuthorizationServer, MockUser};
+
+    fn setup() -> (Arc<MockAuthorizationServer>, OAuthClient) {
+        let server = Arc::new(MockAuthorizationServer::new());
+        server.add_user(MockUser {
+            sub: "mock-123".to_string(),
+            email: "Mock@Example.com".to_string(),
+            email_verified: true,
+            name: "Mock User".to_string(),
+        });
+
+        let client = OAuthClient::new(server.clone()).with_provider(server.provider_config());
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_flow() {
+        let (server, client) = setup();
+
+        let request = client.authorize("mock").unwrap();
+        let (code, state) = server.approve(&request.url, "mock-123").unwrap();
+        assert_eq!(state, request.state, "State should round trip");
+
+        let identity = client.exchange_code("mock", &code, &state).await.unwrap();
+        assert_eq!(identity.provider, "mock");
+        assert_eq!(identity.subject, "mock-123");
+        assert_eq!(identity.email, "mock@example.com");
+        assert!(identity.email_verified);
+        assert_eq!(identity.name, "Mock User");
+    }
+
+    #[tokio::test]
+    async fn test_state_is_single_use() {
+        let (server, client) = setup();
+
+        let request = client.authorize("mock").unwrap();
+        let (code, state) = server.approve(&request.url, "mock-123").unwrap();
+        client.exchange_code("mock", &code, &state).await.unwrap();
+
+        let replay = client.exchange_code("mock", &code, &state).await;
+        assert!(matches!(replay, Err(OAuthError::InvalidState)));
+
+        let unknown = client.exchange_code("mock", &code, "unknown").await;
+        assert!(matches!(unknown, Err(OAuthError::InvalidState)));
+    }
+
+    #[tokio::test]
+    async fn test_pkce_verifier_must_match() {
+        let (server, client) = setup();
+
+        // the code is bound to the first request's challenge
+        let first = client.authorize("mock").unwrap();
+        let second = client.authorize("mock").unwrap();
+        let (code, _) = server.approve(&first.url, "mock-123").unwrap();
+
+        let result = client.exchange_code("mock", &code, &second.state).await;
+        assert!(matches!(result, Err(OAuthError::TokenExchange(_))));
+    }
+
+    #[tokio::test]
+    async fn test_nonce_mismatch() {
+        let (server, client) = setup();
+        server.set_nonce_override(Some("forged".to_string()));
+
+        let request = client.authorize("mock").unwrap();
+        let (code, state) = server.approve(&request.url, "mock-123").unwrap();
+
+        let result = client.exchange_code("mock", &code, &state).await;
+        assert!(matches!(result, Err(OAuthError::NonceMismatch)));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_provider() {
+        let (_, client) = setup();
+
+        assert!(matches!(
+            client.authorize("unknown"),
+            Err(OAuthError::ProviderNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,32 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
+
+use crate::utils::random_string;
+
+pub const CHALLENGE_METHOD: &str = "S256";
+
+// RFC 7636 allows 43 to 128 characters
+const VERIFIER_LEN: usize = 64;
+
+pub fn generate_verifier() -> String {
+    random_string(VERIFIER_LEN)
+}
+
+pub fn challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_rfc7636_example() {
+        // Appendix B of RFC 7636
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        assert_eq!(
+            challenge(verifier),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
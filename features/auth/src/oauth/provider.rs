@@ -0,0 +1,106 @@
+#[derive(Debug, Clone)]
+pub struct OAuthProviderConfig {
+    pub name: String,
+    pub display_name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: String,
+    // OpenID Connect providers return an id token, plain OAuth2 ones don't
+    pub jwks_url: Option<String>,
+    pub issuer: Option<String>,
+    pub openid: bool,
+    pub scopes: Vec<String>,
+    pub claims: ClaimNames,
+}
+
+// Where to find the identity fields in the userinfo response
+#[derive(Debug, Clone)]
+pub struct ClaimNames {
+    pub subject: String,
+    pub email: String,
+    pub email_verified: Option<String>,
+    pub name: String,
+}
+
+impl Default for ClaimNames {
+    fn default() -> Self {
+        Self {
+            subject: "sub".to_string(),
+            email: "email".to_string(),
+            email_verified: Some("email_verified".to_string()),
+            name: "name".to_string(),
+        }
+    }
+}
+
+impl OAuthProviderConfig {
+    pub fn google(client_id: &str, client_secret: &str, redirect_uri: &str) -> Self {
+        Self {
+            name: "google".to_string(),
+            display_name: "Google".to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            authorize_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            userinfo_url: "https://openidconnect.googleapis.com/v1/userinfo".to_string(),
+            jwks_url: Some("https://www.googleapis.com/oauth2/v3/certs".to_string()),
+            issuer: Some("https://accounts.google.com".to_string()),
+            openid: true,
+            scopes: vec!["openid".into(), "email".into(), "profile".into()],
+            claims: ClaimNames::default(),
+        }
+    }
+
+    pub fn microsoft(client_id: &str, client_secret: &str, redirect_uri: &str) -> Self {
+        Self {
+            name: "microsoft".to_string(),
+            display_name: "Microsoft".to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            authorize_url: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize"
+                .to_string(),
+            token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token".to_string(),
+            userinfo_url: "https://graph.microsoft.com/oidc/userinfo".to_string(),
+            jwks_url: Some(
+                "https://login.microsoftonline.com/common/discovery/v2.0/keys".to_string(),
+            ),
+            // the issuer contains the user's tenant id when using the common endpoint
+            issuer: None,
+            openid: true,
+            scopes: vec!["openid".into(), "email".into(), "profile".into()],
+            claims: ClaimNames {
+                // microsoft doesn't return email_verified
+                email_verified: None,
+                ..ClaimNames::default()
+            },
+        }
+    }
+
+    pub fn github(client_id: &str, client_secret: &str, redirect_uri: &str) -> Self {
+        Self {
+            name: "github".to_string(),
+            display_name: "GitHub".to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            redirect_uri: redirect_uri.to_string(),
+            authorize_url: "https://github.com/login/oauth/authorize".to_string(),
+            token_url: "https://github.com/login/oauth/access_token".to_string(),
+            userinfo_url: "https://api.github.com/user".to_string(),
+            jwks_url: None,
+            issuer: None,
+            openid: false,
+            scopes: vec!["read:user".into(), "user:email".into()],
+            claims: ClaimNames {
+                subject: "id".to_string(),
+                email: "email".to_string(),
+                email_verified: None,
+                name: "name".to_string(),
+            },
+        }
+    }
+}
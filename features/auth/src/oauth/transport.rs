@@ -0,0 +1,68 @@
+use async_trait::async_trait;
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde_json::Value;
+
+use super::error::{OAuthError, Result};
+
+// Http calls made to a provider, swapped out for the mock server in tests
+#[async_trait]
+pub trait OAuthTransport: Send + Sync + 'static {
+    async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> Result<Value>;
+    async fn get_json(&self, url: &str, bearer: Option<&str>) -> Result<Value>;
+}
+
+pub struct HttpTransport {
+    client: reqwest::Client,
+}
+
+impl Default for HttpTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpTransport {
+    pub fn new() -> Self {
+        Self {
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl OAuthTransport for HttpTransport {
+    async fn post_form(&self, url: &str, form: &[(&str, &str)]) -> Result<Value> {
+        self.client
+            .post(url)
+            .header(ACCEPT, "application/json")
+            .form(form)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| OAuthError::Transport(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OAuthError::Transport(e.to_string()))
+    }
+
+    async fn get_json(&self, url: &str, bearer: Option<&str>) -> Result<Value> {
+        let mut req = self
+            .client
+            .get(url)
+            .header(ACCEPT, "application/json")
+            // github rejects requests without a user agent
+            .header(USER_AGENT, "rust-web-app");
+
+        if let Some(token) = bearer {
+            req = req.bearer_auth(token);
+        }
+
+        req.send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| OAuthError::Transport(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| OAuthError::Transport(e.to_string()))
+    }
+}
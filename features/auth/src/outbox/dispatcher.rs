@@ -0,0 +1,217 @@
+use std::sync::Arc;
+use time::Duration;
+
+use super::error::Result;
+use super::{OutboxHandler, OutboxMessage, OutboxStore};
+
+const MAX_ATTEMPTS: u32 = 10;
+const RETRY_DELAY: Duration = Duration::seconds(30);
+const MAX_RETRY_DELAY: Duration = Duration::hours(6);
+const BATCH_SIZE: usize = 100;
+
+// How one pass over the outbox went
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DispatchReport {
+    pub delivered: usize,
+    // failed and tried again later
+    pub retried: usize,
+    // failed once too often, see OutboxStore::dead_letters
+    pub dead: usize,
+}
+
+// Hands the messages in the outbox to every handler. Meant to be run periodically by a
+// single task, concurrent dispatchers would deliver messages twice.
+pub struct OutboxDispatcher {
+    store: Arc<dyn OutboxStore>,
+    handlers: Vec<Arc<dyn OutboxHandler>>,
+    max_attempts: u32,
+    retry_delay: Duration,
+    batch_size: usize,
+}
+
+impl OutboxDispatcher {
+    pub fn new(store: Arc<dyn OutboxStore>) -> Self {
+        Self {
+            store,
+            handlers: Vec::new(),
+            max_attempts: MAX_ATTEMPTS,
+            retry_delay: RETRY_DELAY,
+            batch_size: BATCH_SIZE,
+        }
+    }
+
+    // Handlers are called in the order they're added
+    pub fn with_handler(mut self, handler: Arc<dyn OutboxHandler>) -> Self {
+        self.handlers.push(handler);
+        self
+    }
+
+    // failed deliveries before a message is dead
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    // the wait after the first failure, doubled after each one after it
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    // Delivers the messages due at `now`. A message is only removed once every handler
+    // took it, a failure schedules it again for all of them.
+    pub async fn dispatch(&self, now: i64) -> Result<DispatchReport> {
+        let mut report = DispatchReport::default();
+        for mut message in self.store.due(now, self.batch_size).await? {
+            match self.deliver(&message).await {
+                Ok(()) => {
+                    self.store.delivered(&message.id).await?;
+                    report.delivered += 1;
+                }
+                Err(e) => {
+                    message.attempts += 1;
+                    message.last_error = Some(e.to_string());
+                    message.dead = message.attempts >= self.max_attempts;
+                    message.next_attempt_at = now + self.backoff(message.attempts).whole_seconds();
+                    self.store.failed(&message).await?;
+                    match message.dead {
+                        true => report.dead += 1,
+                        false => report.retried += 1,
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn deliver(&self, message: &OutboxMessage) -> Result<()> {
+        for handler in &self.handlers {
+            handler.handle(message).await?;
+        }
+        Ok(())
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2_i32.saturating_pow(attempts.saturating_sub(1).min(30));
+        self.retry_delay
+            .checked_mul(factor)
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::outbox::error::OutboxError;
+    use crate::outbox::{DomainEvent, in_mem_store::InMemoryOutbox};
+
+    // fails the first `failures` deliveries, remembers the ids it was handed
+    #[derive(Default)]
+    struct FlakyHandler {
+        failures: Mutex<usize>,
+        handled: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl OutboxHandler for FlakyHandler {
+        async fn handle(&self, message: &OutboxMessage) -> Result<()> {
+            self.handled.lock().unwrap().push(message.id.clone());
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(OutboxError::Handler("503 Service Unavailable".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    fn message(user_id: &str) -> OutboxMessage {
+        OutboxMessage {
+            next_attempt_at: 0,
+            ..OutboxMessage::new(DomainEvent::UserRegistered {
+                user_id: user_id.to_string(),
+                email: format!("{user_id}@example.com"),
+                name: user_id.to_string(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let store = Arc::new(InMemoryOutbox::new());
+        let first = Arc::new(FlakyHandler::default());
+        let second = Arc::new(FlakyHandler {
+            failures: Mutex::new(2),
+            ..Default::default()
+        });
+        let dispatcher = OutboxDispatcher::new(store.clone())
+            .with_handler(first.clone())
+            .with_handler(second.clone())
+            .with_max_attempts(3)
+            .with_retry_delay(Duration::seconds(10));
+
+        let jane = message("jane");
+        store.enqueue(vec![jane.clone()]).await.unwrap();
+
+        // A failing handler has the message delivered again, later each time
+        let report = dispatcher.dispatch(100).await.unwrap();
+        assert_eq!(report.retried, 1);
+        assert_eq!(
+            dispatcher.dispatch(109).await.unwrap(),
+            DispatchReport::default()
+        );
+        let retried = dispatcher.dispatch(110).await.unwrap();
+        assert_eq!(retried.retried, 1);
+        let pending = &store.due(i64::MAX, 10).await.unwrap()[0];
+        assert_eq!(pending.attempts, 2);
+        assert_eq!(pending.next_attempt_at, 130);
+        assert_eq!(
+            pending.last_error.as_deref(),
+            Some("Can't deliver event: 503 Service Unavailable")
+        );
+
+        // and is gone once every handler took it, the handlers that took it before see it
+        // again each time
+        let report = dispatcher.dispatch(130).await.unwrap();
+        assert_eq!(report.delivered, 1);
+        assert!(store.due(i64::MAX, 10).await.unwrap().is_empty());
+        assert_eq!(*first.handled.lock().unwrap(), vec![jane.id.clone(); 3]);
+        assert_eq!(*second.handled.lock().unwrap(), vec![jane.id.clone(); 3]);
+
+        // Too many failures make a dead letter, until it's retried
+        *second.failures.lock().unwrap() = 3;
+        let john = message("john");
+        store.enqueue(vec![john.clone()]).await.unwrap();
+        for now in [200, 210, 230] {
+            dispatcher.dispatch(now).await.unwrap();
+        }
+        assert_eq!(
+            dispatcher.dispatch(i64::MAX / 2).await.unwrap().delivered,
+            0
+        );
+        let dead = store.dead_letters().await.unwrap();
+        assert_eq!((dead.len(), dead[0].attempts), (1, 3));
+
+        store.retry(&john.id, 300).await.unwrap();
+        assert_eq!(dispatcher.dispatch(300).await.unwrap().delivered, 1);
+        assert!(store.dead_letters().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let dispatcher = OutboxDispatcher::new(Arc::new(InMemoryOutbox::new()));
+        assert_eq!(dispatcher.backoff(1), RETRY_DELAY);
+        assert_eq!(dispatcher.backoff(3), RETRY_DELAY * 4);
+        assert_eq!(dispatcher.backoff(40), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,43 @@
+pub type Result<T> = std::result::Result<T, OutboxError>;
+
+#[derive(Debug)]
+pub enum OutboxError {
+    Open(String),
+    Write(String),
+    Read(String),
+    // no dead letter with the id
+    NotFound(String),
+    // a handler couldn't take the message, it's tried again later
+    Handler(String),
+}
+
+impl std::fmt::Display for OutboxError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            OutboxError::Open(e) => write!(fmt, "Can't open outbox: {e}"),
+            OutboxError::Write(e) => write!(fmt, "Can't write to outbox: {e}"),
+            OutboxError::Read(e) => write!(fmt, "Can't read outbox: {e}"),
+            OutboxError::NotFound(id) => write!(fmt, "Dead letter not found: {id}"),
+            OutboxError::Handler(e) => write!(fmt, "Can't deliver event: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for OutboxError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outbox_error_rendering() {
+        assert_eq!(
+            "Can't deliver event: 503 Service Unavailable",
+            OutboxError::Handler("503 Service Unavailable".to_string()).to_string()
+        );
+        assert_eq!(
+            "Dead letter not found: m1",
+            OutboxError::NotFound("m1".to_string()).to_string()
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use async_trait::async_trait;
+use scc::HashMap;
+
+use super::error::{OutboxError, Result};
+use super::{OutboxMessage, OutboxStore};
+
+// Messages by id in memory, the outbox InMemoryUserRepository keeps with its users. On
+// its own they're lost on restart, for development and tests. Like the users, the map
+// locks single buckets, so messages only contend with the ones next to them.
+pub struct InMemoryOutbox {
+    messages: HashMap<String, OutboxMessage>,
+}
+
+impl Default for InMemoryOutbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryOutbox {
+    pub fn new() -> Self {
+        Self {
+            messages: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn with_messages(messages: Vec<OutboxMessage>) -> Self {
+        let outbox = Self {
+            messages: HashMap::with_capacity(messages.len()),
+        };
+        for message in messages {
+            let _ = outbox.messages.upsert(message.id.clone(), message);
+        }
+        outbox
+    }
+
+    pub(crate) async fn get(&self, id: &str) -> Option<OutboxMessage> {
+        self.messages
+            .read_async(id, |_, message| message.clone())
+            .await
+    }
+
+    // oldest first
+    pub(crate) async fn all(&self) -> Vec<OutboxMessage> {
+        self.matching(|_| true).await
+    }
+
+    // removes the messages about the user and returns them
+    pub(crate) async fn take_user(&self, user_id: &str) -> Vec<OutboxMessage> {
+        let mut taken = Vec::new();
+        for message in self
+            .matching(|message| message.event.user_id() == user_id)
+            .await
+        {
+            if let Some((_, message)) = self.messages.remove_async(&message.id).await {
+                taken.push(message);
+            }
+        }
+        taken
+    }
+
+    // oldest first, ties by id so the order is the same on every call
+    async fn matching(&self, keep: impl Fn(&OutboxMessage) -> bool) -> Vec<OutboxMessage> {
+        let mut found = Vec::new();
+        self.messages
+            .scan_async(|_, message| {
+                if keep(message) {
+                    found.push(message.clone());
+                }
+            })
+            .await;
+        // a scan racing a resize can visit a message twice
+        found.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        found.dedup_by(|a, b| a.id == b.id);
+        found
+    }
+}
+
+#[async_trait]
+impl OutboxStore for InMemoryOutbox {
+    async fn enqueue(&self, messages: Vec<OutboxMessage>) -> Result<()> {
+        let mut inserted = Vec::with_capacity(messages.len());
+        for message in messages {
+            let id = message.id.clone();
+            if self
+                .messages
+                .insert_async(id.clone(), message)
+                .await
+                .is_err()
+            {
+                // a batch with a clash writes nothing
+                for id in &inserted {
+                    self.messages.remove_async(id).await;
+                }
+                return Err(OutboxError::Write(format!(
+                    "message {id} is already queued"
+                )));
+            }
+            inserted.push(id);
+        }
+        Ok(())
+    }
+
+    async fn release(&self, ids: &[String]) -> Result<()> {
+        for id in ids {
+            self.messages
+                .update_async(id, |_, message| message.held = false)
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn discard(&self, ids: &[String]) -> Result<()> {
+        for id in ids {
+            self.messages.remove_async(id).await;
+        }
+        Ok(())
+    }
+
+    async fn due(&self, now: i64, limit: usize) -> Result<Vec<OutboxMessage>> {
+        let mut due = self
+            .matching(|message| !message.held && !message.dead && message.next_attempt_at <= now)
+            .await;
+        due.truncate(limit);
+        Ok(due)
+    }
+
+    async fn delivered(&self, id: &str) -> Result<()> {
+        self.discard(&[id.to_string()]).await
+    }
+
+    async fn failed(&self, message: &OutboxMessage) -> Result<()> {
+        // delivered or deleted with its user meanwhile
+        self.messages
+            .update_async(&message.id, |_, stored| *stored = message.clone())
+            .await;
+        Ok(())
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<OutboxMessage>> {
+        let mut dead = self.matching(|message| message.dead).await;
+        dead.reverse();
+        Ok(dead)
+    }
+
+    async fn retry(&self, id: &str, now: i64) -> Result<()> {
+        let retried = self
+            .messages
+            .update_async(id, |_, message| {
+                if !message.dead {
+                    return false;
+                }
+                message.dead = false;
+                message.attempts = 0;
+                message.next_attempt_at = now;
+                true
+            })
+            .await;
+
+        match retried {
+            Some(true) => Ok(()),
+            _ => Err(OutboxError::NotFound(id.to_string())),
+        }
+    }
+
+    async fn delete_by_user(&self, user_id: &str) -> Result<usize> {
+        Ok(self.take_user(user_id).await.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbox::DomainEvent;
+
+    fn message(user_id: &str, created_at: i64) -> OutboxMessage {
+        OutboxMessage {
+            created_at,
+            next_attempt_at: created_at,
+            ..OutboxMessage::new(DomainEvent::ErasureRequested {
+                user_id: user_id.to_string(),
+                purge_at: 1000,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_outbox() {
+        let outbox = InMemoryOutbox::new();
+
+        let first = message("u1", 100);
+        let second = message("u2", 200);
+        outbox
+            .enqueue(vec![second.clone(), first.clone()])
+            .await
+            .unwrap();
+        // a batch with a clash writes nothing
+        let third = message("u3", 300);
+        assert!(
+            outbox
+                .enqueue(vec![third.clone(), first.clone()])
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            outbox.due(i64::MAX, 10).await.unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(outbox.due(i64::MAX, 1).await.unwrap(), vec![first.clone()]);
+
+        let held = OutboxMessage {
+            held: true,
+            ..third
+        };
+        outbox.enqueue(vec![held.clone()]).await.unwrap();
+        assert_eq!(outbox.due(i64::MAX, 10).await.unwrap().len(), 2);
+        outbox
+            .release(std::slice::from_ref(&held.id))
+            .await
+            .unwrap();
+        assert_eq!(outbox.due(i64::MAX, 10).await.unwrap().len(), 3);
+
+        let dead = OutboxMessage {
+            attempts: 3,
+            dead: true,
+            ..first.clone()
+        };
+        outbox.failed(&dead).await.unwrap();
+        assert_eq!(outbox.dead_letters().await.unwrap(), vec![dead]);
+        outbox.retry(&first.id, 400).await.unwrap();
+        assert!(matches!(
+            outbox.retry(&first.id, 400).await,
+            Err(OutboxError::NotFound(_))
+        ));
+
+        assert_eq!(outbox.delete_by_user("u1").await.unwrap(), 1);
+        outbox.delivered(&second.id).await.unwrap();
+        assert_eq!(outbox.all().await.len(), 1);
+    }
+}
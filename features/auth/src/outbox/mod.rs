@@ -0,0 +1,186 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub mod dispatcher;
+pub mod error;
+pub mod in_mem_store;
+pub mod sqlite_store;
+pub mod webhook;
+
+use crate::repository::UserOutboxStore;
+use error::Result;
+
+// What other systems are told about, once the change is saved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DomainEvent {
+    UserRegistered {
+        user_id: String,
+        email: String,
+        name: String,
+    },
+    EmailChanged {
+        user_id: String,
+        old_email: String,
+        new_email: String,
+    },
+    // the account is purged at `purge_at`
+    ErasureRequested {
+        user_id: String,
+        purge_at: i64,
+    },
+}
+
+impl DomainEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DomainEvent::UserRegistered { .. } => "user_registered",
+            DomainEvent::EmailChanged { .. } => "email_changed",
+            DomainEvent::ErasureRequested { .. } => "erasure_requested",
+        }
+    }
+
+    // the user the event is about, their events are deleted with them
+    pub fn user_id(&self) -> &str {
+        match self {
+            DomainEvent::UserRegistered { user_id, .. }
+            | DomainEvent::EmailChanged { user_id, .. }
+            | DomainEvent::ErasureRequested { user_id, .. } => user_id,
+        }
+    }
+}
+
+// An event waiting in the outbox until every handler took it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    // handlers can tell a redelivery by it
+    pub id: String,
+    pub event: DomainEvent,
+    pub created_at: i64,
+    // failed deliveries so far
+    pub attempts: u32,
+    pub next_attempt_at: i64,
+    pub last_error: Option<String>,
+    // delivery was given up on, the message waits for an admin to retry it
+    pub dead: bool,
+    // written with its change and not delivered until the unit of work that made the
+    // change is committed
+    #[serde(default)]
+    pub held: bool,
+}
+
+impl OutboxMessage {
+    pub fn new(event: DomainEvent) -> Self {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            event,
+            created_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            dead: false,
+            held: false,
+        }
+    }
+}
+
+// Where events wait to be delivered, deleted once they are. A unit of work writes its
+// messages held and releases them when it commits, see UnitOfWork.
+#[async_trait]
+pub trait OutboxStore: Send + Sync + 'static {
+    // in one write, either every message is written or none is
+    async fn enqueue(&self, messages: Vec<OutboxMessage>) -> Result<()>;
+    // lets the held messages of a committed change be delivered
+    async fn release(&self, ids: &[String]) -> Result<()>;
+    // drops the messages of a change that was rolled back
+    async fn discard(&self, ids: &[String]) -> Result<()>;
+    // live messages due at `now`, oldest first, held ones aren't due
+    async fn due(&self, now: i64, limit: usize) -> Result<Vec<OutboxMessage>>;
+    async fn delivered(&self, id: &str) -> Result<()>;
+    // saves the attempts, next attempt, error and whether it's dead
+    async fn failed(&self, message: &OutboxMessage) -> Result<()>;
+    // newest first
+    async fn dead_letters(&self) -> Result<Vec<OutboxMessage>>;
+    // delivers a dead letter again, with a fresh set of attempts
+    async fn retry(&self, id: &str, now: i64) -> Result<()>;
+    // drops every message about the user, delivered or not, returns how many there were
+    async fn delete_by_user(&self, user_id: &str) -> Result<usize>;
+}
+
+// Where a service's events wait, see AuthService::with_outbox and with_user_outbox
+#[derive(Clone)]
+pub(crate) enum Outbox {
+    // a store of its own, a unit of work's messages are written to it when it commits
+    Separate(Arc<dyn OutboxStore>),
+    // the user repository, messages are written in the same transaction as the user
+    // change they're about
+    WithUsers(Arc<dyn UserOutboxStore>),
+}
+
+impl Outbox {
+    pub(crate) fn store(&self) -> Arc<dyn OutboxStore> {
+        match self {
+            Outbox::Separate(outbox) => outbox.clone(),
+            Outbox::WithUsers(users) => users.clone(),
+        }
+    }
+}
+
+// Something told about the events, e.g. a webhook. A message is delivered at least once:
+// it's handed out again when any handler fails, so handlers should be idempotent.
+#[async_trait]
+pub trait OutboxHandler: Send + Sync + 'static {
+    async fn handle(&self, message: &OutboxMessage) -> Result<()>;
+}
+
+// Prints events instead of delivering them, for development
+#[derive(Default)]
+pub struct LogHandler;
+
+#[async_trait]
+impl OutboxHandler for LogHandler {
+    async fn handle(&self, message: &OutboxMessage) -> Result<()> {
+        println!(
+            "Event {} {}: {}",
+            message.id,
+            message.event.kind(),
+            serde_json::to_string(&message.event).unwrap_or_default()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() {
+        // messages written before they could be held weren't
+        let message = OutboxMessage::new(DomainEvent::UserRegistered {
+            user_id: "u1".to_string(),
+            email: "jane@example.com".to_string(),
+            name: "Jane".to_string(),
+        });
+        let mut json = serde_json::to_value(&message).unwrap();
+        json.as_object_mut().unwrap().remove("held");
+        assert_eq!(
+            serde_json::from_value::<OutboxMessage>(json).unwrap(),
+            message
+        );
+        assert_eq!(message.event.user_id(), "u1");
+
+        let event = DomainEvent::ErasureRequested {
+            user_id: "u1".to_string(),
+            purge_at: 1700000000,
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], event.kind());
+        assert_eq!(json["purge_at"], 1700000000);
+        assert_eq!(serde_json::from_value::<DomainEvent>(json).unwrap(), event);
+    }
+}
@@ -0,0 +1,314 @@
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::error::{OutboxError, Result};
+use super::{OutboxMessage, OutboxStore};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS outbox_messages (
+        id TEXT PRIMARY KEY,
+        event TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        attempts INTEGER NOT NULL,
+        next_attempt_at INTEGER NOT NULL,
+        last_error TEXT,
+        dead INTEGER NOT NULL,
+        held INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX IF NOT EXISTS outbox_messages_due ON outbox_messages (dead, next_attempt_at);
+";
+
+const COLUMNS: &str = "id, event, created_at, attempts, next_attempt_at, last_error, dead, held";
+
+// Messages that survive a restart, so a crash doesn't lose the events not yet delivered.
+// rusqlite is blocking, queries run on the blocking pool.
+pub struct SqliteOutbox {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteOutbox {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(Connection::open(path).map_err(|e| OutboxError::Open(e.to_string()))?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory().map_err(|e| OutboxError::Open(e.to_string()))?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .map_err(|e| OutboxError::Open(e.to_string()))?;
+        // outboxes created before messages could be held lack the column
+        if conn
+            .prepare("SELECT held FROM outbox_messages LIMIT 0")
+            .is_err()
+        {
+            conn.execute(
+                "ALTER TABLE outbox_messages ADD COLUMN held INTEGER NOT NULL DEFAULT 0",
+                [],
+            )
+            .map_err(|e| OutboxError::Open(e.to_string()))?;
+        }
+        // a message is released when its unit of work commits, the ones still held
+        // belong to a unit that never did and are dropped
+        conn.execute("DELETE FROM outbox_messages WHERE held = 1", [])
+            .map_err(|e| OutboxError::Open(e.to_string()))?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn run<T, F>(&self, error: fn(String) -> OutboxError, query: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|e| error(e.to_string()))?;
+            query(&mut conn).map_err(|e| error(e.to_string()))
+        })
+        .await
+        .map_err(|e| error(e.to_string()))?
+    }
+}
+
+fn message_from_row(row: &Row) -> rusqlite::Result<OutboxMessage> {
+    let event: String = row.get(1)?;
+
+    Ok(OutboxMessage {
+        id: row.get(0)?,
+        event: serde_json::from_str(&event).map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(e))
+        })?,
+        created_at: row.get(2)?,
+        attempts: row.get(3)?,
+        next_attempt_at: row.get(4)?,
+        last_error: row.get(5)?,
+        dead: row.get(6)?,
+        held: row.get(7)?,
+    })
+}
+
+#[async_trait]
+impl OutboxStore for SqliteOutbox {
+    // in one transaction, either every message is written or none is
+    async fn enqueue(&self, messages: Vec<OutboxMessage>) -> Result<()> {
+        let mut rows = Vec::with_capacity(messages.len());
+        for message in messages {
+            let event = serde_json::to_string(&message.event)
+                .map_err(|e| OutboxError::Write(e.to_string()))?;
+            rows.push((message, event));
+        }
+
+        self.run(OutboxError::Write, move |conn| {
+            let tx = conn.transaction()?;
+            for (message, event) in rows {
+                tx.execute(
+                    &format!("INSERT INTO outbox_messages ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"),
+                    params![
+                        message.id,
+                        event,
+                        message.created_at,
+                        message.attempts,
+                        message.next_attempt_at,
+                        message.last_error,
+                        message.dead,
+                        message.held,
+                    ],
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn release(&self, ids: &[String]) -> Result<()> {
+        let ids = ids.to_vec();
+        self.run(OutboxError::Write, move |conn| {
+            let tx = conn.transaction()?;
+            for id in ids {
+                tx.execute(
+                    "UPDATE outbox_messages SET held = 0 WHERE id = ?1",
+                    params![id],
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn discard(&self, ids: &[String]) -> Result<()> {
+        let ids = ids.to_vec();
+        self.run(OutboxError::Write, move |conn| {
+            let tx = conn.transaction()?;
+            for id in ids {
+                tx.execute("DELETE FROM outbox_messages WHERE id = ?1", params![id])?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn due(&self, now: i64, limit: usize) -> Result<Vec<OutboxMessage>> {
+        self.run(OutboxError::Read, move |conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM outbox_messages WHERE held = 0 AND dead = 0 AND next_attempt_at <= ?1
+                 ORDER BY created_at, rowid LIMIT ?2"
+            ))?;
+            statement
+                .query_map(params![now, limit as i64], message_from_row)?
+                .collect()
+        })
+        .await
+    }
+
+    async fn delivered(&self, id: &str) -> Result<()> {
+        self.discard(&[id.to_string()]).await
+    }
+
+    async fn failed(&self, message: &OutboxMessage) -> Result<()> {
+        let message = message.clone();
+        self.run(OutboxError::Write, move |conn| {
+            conn.execute(
+                "UPDATE outbox_messages SET attempts = ?2, next_attempt_at = ?3, last_error = ?4,
+                 dead = ?5 WHERE id = ?1",
+                params![
+                    message.id,
+                    message.attempts,
+                    message.next_attempt_at,
+                    message.last_error,
+                    message.dead,
+                ],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    async fn dead_letters(&self) -> Result<Vec<OutboxMessage>> {
+        self.run(OutboxError::Read, move |conn| {
+            let mut statement = conn.prepare(&format!(
+                "SELECT {COLUMNS} FROM outbox_messages WHERE dead = 1
+                 ORDER BY created_at DESC, rowid DESC"
+            ))?;
+            statement.query_map([], message_from_row)?.collect()
+        })
+        .await
+    }
+
+    async fn retry(&self, id: &str, now: i64) -> Result<()> {
+        let id = id.to_string();
+        let found = id.clone();
+        let retried = self
+            .run(OutboxError::Write, move |conn| {
+                conn.query_row(
+                    "UPDATE outbox_messages SET dead = 0, attempts = 0, next_attempt_at = ?2
+                     WHERE id = ?1 AND dead = 1 RETURNING id",
+                    params![found, now],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        match retried {
+            Some(_) => Ok(()),
+            None => Err(OutboxError::NotFound(id)),
+        }
+    }
+
+    async fn delete_by_user(&self, user_id: &str) -> Result<usize> {
+        let user_id = user_id.to_string();
+        self.run(OutboxError::Write, move |conn| {
+            conn.execute(
+                "DELETE FROM outbox_messages WHERE json_extract(event, '$.user_id') = ?1",
+                params![user_id],
+            )
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbox::DomainEvent;
+
+    fn message(user_id: &str, created_at: i64) -> OutboxMessage {
+        OutboxMessage {
+            created_at,
+            next_attempt_at: created_at,
+            ..OutboxMessage::new(DomainEvent::ErasureRequested {
+                user_id: user_id.to_string(),
+                purge_at: 1000,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_outbox() {
+        let outbox = SqliteOutbox::open_in_memory().unwrap();
+
+        let first = message("u1", 100);
+        let second = message("u2", 200);
+        outbox
+            .enqueue(vec![second.clone(), first.clone()])
+            .await
+            .unwrap();
+        // a batch with a clash writes nothing
+        assert!(
+            outbox
+                .enqueue(vec![message("u3", 300), first.clone()])
+                .await
+                .is_err()
+        );
+
+        assert_eq!(
+            outbox.due(250, 10).await.unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+        assert_eq!(outbox.due(150, 10).await.unwrap(), vec![first.clone()]);
+
+        let dead = OutboxMessage {
+            attempts: 3,
+            last_error: Some("timed out".to_string()),
+            dead: true,
+            ..first.clone()
+        };
+        outbox.failed(&dead).await.unwrap();
+        assert_eq!(outbox.due(250, 10).await.unwrap(), vec![second.clone()]);
+        assert_eq!(outbox.dead_letters().await.unwrap(), vec![dead]);
+
+        outbox.retry(&first.id, 400).await.unwrap();
+        assert!(outbox.dead_letters().await.unwrap().is_empty());
+        assert!(matches!(
+            outbox.retry(&first.id, 400).await,
+            Err(OutboxError::NotFound(_))
+        ));
+        let retried = &outbox.due(400, 10).await.unwrap()[0];
+        assert_eq!((retried.attempts, retried.next_attempt_at), (0, 400));
+
+        let held = OutboxMessage {
+            held: true,
+            ..message("u3", 300)
+        };
+        outbox.enqueue(vec![held.clone()]).await.unwrap();
+        assert_eq!(outbox.due(i64::MAX, 10).await.unwrap().len(), 2);
+        outbox
+            .release(std::slice::from_ref(&held.id))
+            .await
+            .unwrap();
+        assert_eq!(outbox.due(i64::MAX, 10).await.unwrap().len(), 3);
+        assert_eq!(outbox.delete_by_user("u3").await.unwrap(), 1);
+
+        outbox.delivered(&first.id).await.unwrap();
+        outbox.discard(&[second.id]).await.unwrap();
+        assert!(outbox.due(i64::MAX, 10).await.unwrap().is_empty());
+    }
+}
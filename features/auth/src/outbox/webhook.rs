@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+use std::time::Duration;
+
+use super::error::{OutboxError, Result};
+use super::{OutboxHandler, OutboxMessage};
+
+// a slow endpoint holds up every message behind it
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// POSTs each message as JSON, anything but a 2xx response is a failed delivery. The
+// message id doubles as an idempotency key for the receiver.
+pub struct WebhookHandler {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookHandler {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(|e| OutboxError::Open(e.to_string()))?;
+
+        Ok(Self {
+            url: url.to_string(),
+            client,
+        })
+    }
+}
+
+#[async_trait]
+impl OutboxHandler for WebhookHandler {
+    async fn handle(&self, message: &OutboxMessage) -> Result<()> {
+        self.client
+            .post(&self.url)
+            .header("Idempotency-Key", &message.id)
+            .json(&serde_json::json!({
+                "id": message.id,
+                "created_at": message.created_at,
+                "event": message.event,
+            }))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map(|_| ())
+            .map_err(|e| OutboxError::Handler(e.to_string()))
+    }
+}
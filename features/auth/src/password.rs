@@ -57,6 +57,14 @@ pub fn verify_password(passwd: &str, passwd_ref: &str) -> Result<SchemeStatus> {
     }
 }
 
+// Whether a hash made elsewhere, e.g. in an export, can be stored as is
+pub fn check_password_hash(passwd_ref: &str) -> Result<()> {
+    let PasswordParts { scheme_name, hash } = passwd_ref.parse()?;
+
+    get_scheme(&scheme_name)?.check_format(&hash)?;
+    Ok(())
+}
+
 fn verify_with_scheme(scheme_name: &str, passwd: &str, pwd_ref: &str) -> Result<()> {
     get_scheme(scheme_name)?.validate(passwd, pwd_ref)?;
     Ok(())
@@ -86,7 +94,7 @@ mod tests {
         let hash = hash_result.unwrap();
         assert!(!hash.is_empty(), "Hash should not be empty");
 
-        let validate_result = verify_password(&TEST_PASSWORD, &hash);
+        let validate_result = verify_password(TEST_PASSWORD, &hash);
         assert!(
             validate_result.is_ok(),
             "Password validation should succeed"
@@ -95,7 +103,7 @@ mod tests {
 
     #[test]
     fn test_scheme_status_ok() {
-        let schema_status = verify_password(&TEST_PASSWORD, &TEST_HASH_OK).unwrap();
+        let schema_status = verify_password(TEST_PASSWORD, TEST_HASH_OK).unwrap();
         assert_eq!(
             schema_status,
             SchemeStatus::Ok,
@@ -105,7 +113,7 @@ mod tests {
 
     #[test]
     fn test_scheme_status_not_found() {
-        let scheme_status_result = verify_password(&TEST_PASSWORD, &TEST_UNKNOWN_SCHEME_HASH);
+        let scheme_status_result = verify_password(TEST_PASSWORD, TEST_UNKNOWN_SCHEME_HASH);
         assert!(
             scheme_status_result.is_err(),
             "Should not validate unknown scheme"
@@ -114,10 +122,21 @@ mod tests {
 
     #[test]
     fn test_no_scheme_in_hash() {
-        let scheme_status_result = verify_password(&TEST_PASSWORD, &TEST_NO_SCHEME_HASH);
+        let scheme_status_result = verify_password(TEST_PASSWORD, TEST_NO_SCHEME_HASH);
         assert!(
             scheme_status_result.is_err(),
             "Password should fail if missing scheme"
         );
     }
+
+    #[test]
+    fn test_check_password_hash() {
+        assert!(check_password_hash(TEST_HASH_OK).is_ok());
+        assert!(check_password_hash(TEST_UNKNOWN_SCHEME_HASH).is_err());
+        assert!(check_password_hash(TEST_NO_SCHEME_HASH).is_err());
+        assert!(check_password_hash("01#not a hash").is_err());
+        assert!(
+            check_password_hash("01#$argon2i$v=19$m=16,t=2,p=1$c2FsdHNhbHQ$aGFzaGhhc2g").is_err()
+        );
+    }
 }
@@ -0,0 +1,39 @@
+pub type Result<T> = std::result::Result<T, PiiError>;
+
+#[derive(Debug)]
+pub enum PiiError {
+    // a key that isn't 32 bytes of base64, or a malformed PII_KEYS
+    InvalidKey(String),
+    // the record was sealed with a key that's no longer configured
+    UnknownKey(String),
+    // the record is sealed and there are no keys to open it with
+    KeysRequired,
+    Seal,
+    Open,
+}
+
+impl std::fmt::Display for PiiError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            PiiError::InvalidKey(e) => write!(fmt, "Invalid encryption key: {e}"),
+            PiiError::UnknownKey(id) => write!(fmt, "Unknown encryption key: {id}"),
+            PiiError::KeysRequired | PiiError::Seal | PiiError::Open => write!(fmt, "{self:?}"),
+        }
+    }
+}
+
+impl std::error::Error for PiiError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pii_error_rendering() {
+        assert_eq!(
+            "Unknown encryption key: 2024-01",
+            PiiError::UnknownKey("2024-01".to_string()).to_string()
+        );
+        assert_eq!("KeysRequired", PiiError::KeysRequired.to_string());
+    }
+}
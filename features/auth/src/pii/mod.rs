@@ -0,0 +1,435 @@
+pub mod error;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::models::User;
+use crate::outbox::OutboxMessage;
+use crate::utils::normalize_email;
+
+use error::{PiiError, Result};
+
+const KEY_LEN: usize = 32;
+
+// Keys for the personal data in stored users. Each record is sealed with a data key of its
+// own, stored wrapped by one of these, so rotating a key only rewraps data keys. New
+// records use the current key, the previous ones are kept to open older records.
+pub struct PiiKeys {
+    // the current key first
+    keys: Vec<(String, LessSafeKey)>,
+    // emails are indexed by their HMAC, changing it means rebuilding the index
+    index_key: hmac::Key,
+    rng: SystemRandom,
+}
+
+impl PiiKeys {
+    pub fn new(key_id: &str, key: &[u8], index_key: &[u8]) -> Result<Self> {
+        if index_key.len() != KEY_LEN {
+            return Err(PiiError::InvalidKey("index key".to_string()));
+        }
+        Ok(Self {
+            keys: vec![(key_id.to_string(), aead_key(key_id, key)?)],
+            index_key: hmac::Key::new(hmac::HMAC_SHA256, index_key),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    // a key that records sealed before the last rotation still use
+    pub fn with_previous_key(mut self, key_id: &str, key: &[u8]) -> Result<Self> {
+        self.keys.push((key_id.to_string(), aead_key(key_id, key)?));
+        Ok(self)
+    }
+
+    // PII_KEYS lists `id:key` pairs separated by commas, the current key first, and
+    // PII_INDEX_KEY the key emails are indexed with, all base64. None without PII_KEYS.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(keys) = std::env::var("PII_KEYS") else {
+            return Ok(None);
+        };
+        let index_key = std::env::var("PII_INDEX_KEY")
+            .map_err(|_| PiiError::InvalidKey("PII_INDEX_KEY is not set".to_string()))?;
+        let decode = |id: &str, key: &str| {
+            STANDARD
+                .decode(key.trim())
+                .map_err(|_| PiiError::InvalidKey(id.to_string()))
+        };
+
+        let mut pairs = keys.split(',').map(|pair| {
+            pair.trim()
+                .split_once(':')
+                .ok_or_else(|| PiiError::InvalidKey("PII_KEYS".to_string()))
+        });
+        let (id, key) = pairs
+            .next()
+            .ok_or_else(|| PiiError::InvalidKey("PII_KEYS".to_string()))??;
+        let mut pii_keys = Self::new(id, &decode(id, key)?, &decode("PII_INDEX_KEY", &index_key)?)?;
+        for pair in pairs {
+            let (id, key) = pair?;
+            pii_keys = pii_keys.with_previous_key(id, &decode(id, key)?)?;
+        }
+        Ok(Some(pii_keys))
+    }
+
+    pub fn current_key_id(&self) -> &str {
+        &self.keys[0].0
+    }
+
+    // What the email is indexed by, the same for every way of writing the address
+    pub(crate) fn email_index(&self, email: &str) -> String {
+        hmac::sign(&self.index_key, normalize_email(email).as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    pub(crate) fn seal(&self, user: &User) -> Result<SealedUser> {
+        let pii = serde_json::to_vec(&Pii {
+            email: user.email.clone(),
+            name: user.name.clone(),
+        })
+        .map_err(|_| PiiError::Seal)?;
+
+        let mut data_key = [0u8; KEY_LEN];
+        self.rng.fill(&mut data_key).map_err(|_| PiiError::Seal)?;
+        let (key_id, key) = &self.keys[0];
+        Ok(SealedUser {
+            key_id: key_id.clone(),
+            wrapped_key: self.encrypt(key, &user.id, data_key.to_vec())?,
+            pii: self.encrypt(&aead_key(key_id, &data_key)?, &user.id, pii)?,
+            // the personal fields are only kept sealed
+            user: User {
+                email: String::new(),
+                name: String::new(),
+                ..user.clone()
+            },
+        })
+    }
+
+    pub(crate) fn open(&self, sealed: SealedUser) -> Result<User> {
+        let data_key = self.unwrap_key(&sealed.key_id, &sealed.user.id, &sealed.wrapped_key)?;
+        let pii = decrypt(
+            &aead_key(&sealed.key_id, &data_key)?,
+            &sealed.user.id,
+            &sealed.pii,
+        )?;
+        let Pii { email, name } = serde_json::from_slice(&pii).map_err(|_| PiiError::Open)?;
+
+        Ok(User {
+            email,
+            name,
+            ..sealed.user
+        })
+    }
+
+    // Wraps the record's data key with the current key, the fields stay sealed as they are
+    pub(crate) fn rewrap(&self, sealed: SealedUser) -> Result<SealedUser> {
+        let data_key = self.unwrap_key(&sealed.key_id, &sealed.user.id, &sealed.wrapped_key)?;
+        let (key_id, key) = &self.keys[0];
+        Ok(SealedUser {
+            key_id: key_id.clone(),
+            wrapped_key: self.encrypt(key, &sealed.user.id, data_key)?,
+            ..sealed
+        })
+    }
+
+    // The whole message is sealed, its event has the user's email and name
+    pub(crate) fn seal_message(&self, message: &OutboxMessage) -> Result<SealedMessage> {
+        let json = serde_json::to_vec(message).map_err(|_| PiiError::Seal)?;
+
+        let mut data_key = [0u8; KEY_LEN];
+        self.rng.fill(&mut data_key).map_err(|_| PiiError::Seal)?;
+        let (key_id, key) = &self.keys[0];
+        Ok(SealedMessage {
+            id: message.id.clone(),
+            key_id: key_id.clone(),
+            wrapped_key: self.encrypt(key, &message.id, data_key.to_vec())?,
+            message: self.encrypt(&aead_key(key_id, &data_key)?, &message.id, json)?,
+        })
+    }
+
+    pub(crate) fn open_message(&self, sealed: SealedMessage) -> Result<OutboxMessage> {
+        let data_key = self.unwrap_key(&sealed.key_id, &sealed.id, &sealed.wrapped_key)?;
+        let json = decrypt(
+            &aead_key(&sealed.key_id, &data_key)?,
+            &sealed.id,
+            &sealed.message,
+        )?;
+        let message: OutboxMessage = serde_json::from_slice(&json).map_err(|_| PiiError::Open)?;
+        if message.id != sealed.id {
+            return Err(PiiError::Open);
+        }
+        Ok(message)
+    }
+
+    fn unwrap_key(&self, key_id: &str, record_id: &str, wrapped_key: &str) -> Result<Vec<u8>> {
+        let (_, key) = self
+            .keys
+            .iter()
+            .find(|(id, _)| id == key_id)
+            .ok_or_else(|| PiiError::UnknownKey(key_id.to_string()))?;
+        decrypt(key, record_id, wrapped_key)
+    }
+
+    // The nonce followed by the ciphertext, bound to the user so it can't be moved to
+    // another record
+    fn encrypt(&self, key: &LessSafeKey, user_id: &str, mut data: Vec<u8>) -> Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce).map_err(|_| PiiError::Seal)?;
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(user_id.as_bytes()),
+            &mut data,
+        )
+        .map_err(|_| PiiError::Seal)?;
+
+        Ok(STANDARD.encode([nonce.as_slice(), &data].concat()))
+    }
+}
+
+fn decrypt(key: &LessSafeKey, user_id: &str, sealed: &str) -> Result<Vec<u8>> {
+    let sealed = STANDARD.decode(sealed).map_err(|_| PiiError::Open)?;
+    if sealed.len() < NONCE_LEN {
+        return Err(PiiError::Open);
+    }
+    let (nonce, data) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| PiiError::Open)?;
+
+    let mut data = data.to_vec();
+    let opened = key
+        .open_in_place(nonce, Aad::from(user_id.as_bytes()), &mut data)
+        .map_err(|_| PiiError::Open)?;
+    Ok(opened.to_vec())
+}
+
+fn aead_key(key_id: &str, key: &[u8]) -> Result<LessSafeKey> {
+    UnboundKey::new(&AES_256_GCM, key)
+        .map(LessSafeKey::new)
+        .map_err(|_| PiiError::InvalidKey(key_id.to_string()))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Pii {
+    email: String,
+    name: String,
+}
+
+// A user with its personal fields encrypted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SealedUser {
+    // the user with the personal fields left empty
+    user: User,
+    key_id: String,
+    // the record's data key, encrypted with the key `key_id`
+    wrapped_key: String,
+    // the personal fields, encrypted with the data key
+    pii: String,
+}
+
+// How a user is stored, sealed when there are keys. Records written before encryption was
+// turned on stay readable until they're sealed by `reseal`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum StoredUser {
+    Sealed(Box<SealedUser>),
+    Plain(Box<User>),
+}
+
+impl StoredUser {
+    pub(crate) fn new(user: &User, keys: Option<&PiiKeys>) -> Result<Self> {
+        match keys {
+            Some(keys) => Ok(StoredUser::Sealed(Box::new(keys.seal(user)?))),
+            None => Ok(StoredUser::Plain(Box::new(user.clone()))),
+        }
+    }
+
+    pub(crate) fn open(self, keys: Option<&PiiKeys>) -> Result<User> {
+        match (self, keys) {
+            (StoredUser::Sealed(sealed), Some(keys)) => keys.open(*sealed),
+            (StoredUser::Sealed(_), None) => Err(PiiError::KeysRequired),
+            (StoredUser::Plain(user), _) => Ok(*user),
+        }
+    }
+
+    // Whether the record is stored the way new ones are
+    pub(crate) fn is_current(&self, keys: Option<&PiiKeys>) -> bool {
+        match (self, keys) {
+            (StoredUser::Sealed(sealed), Some(keys)) => sealed.key_id == keys.current_key_id(),
+            (StoredUser::Plain(_), None) => true,
+            _ => false,
+        }
+    }
+
+    // The record stored the way new ones are
+    pub(crate) fn reseal(self, keys: Option<&PiiKeys>) -> Result<Self> {
+        match (self, keys) {
+            (StoredUser::Sealed(sealed), Some(keys)) => {
+                Ok(StoredUser::Sealed(Box::new(keys.rewrap(*sealed)?)))
+            }
+            (stored, keys) => StoredUser::new(&stored.open(keys)?, keys),
+        }
+    }
+}
+
+// An outbox message encrypted as a whole
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SealedMessage {
+    id: String,
+    key_id: String,
+    // the record's data key, encrypted with the key `key_id`
+    wrapped_key: String,
+    // the message, encrypted with the data key
+    message: String,
+}
+
+// How an outbox message is stored, sealed when there are keys like the user it's about
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum StoredMessage {
+    Sealed(Box<SealedMessage>),
+    Plain(Box<OutboxMessage>),
+}
+
+impl StoredMessage {
+    pub(crate) fn new(message: &OutboxMessage, keys: Option<&PiiKeys>) -> Result<Self> {
+        match keys {
+            Some(keys) => Ok(StoredMessage::Sealed(Box::new(keys.seal_message(message)?))),
+            None => Ok(StoredMessage::Plain(Box::new(message.clone()))),
+        }
+    }
+
+    pub(crate) fn open(self, keys: Option<&PiiKeys>) -> Result<OutboxMessage> {
+        match (self, keys) {
+            (StoredMessage::Sealed(sealed), Some(keys)) => keys.open_message(*sealed),
+            (StoredMessage::Sealed(_), None) => Err(PiiError::KeysRequired),
+            (StoredMessage::Plain(message), _) => Ok(*message),
+        }
+    }
+
+    // Whether the record is stored the way new ones are
+    pub(crate) fn is_current(&self, keys: Option<&PiiKeys>) -> bool {
+        match (self, keys) {
+            (StoredMessage::Sealed(sealed), Some(keys)) => sealed.key_id == keys.current_key_id(),
+            (StoredMessage::Plain(_), None) => true,
+            _ => false,
+        }
+    }
+
+    // The record stored the way new ones are, messages are few so they're sealed afresh
+    pub(crate) fn reseal(self, keys: Option<&PiiKeys>) -> Result<Self> {
+        StoredMessage::new(&self.open(keys)?, keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(id: &str) -> PiiKeys {
+        PiiKeys::new(id, &[id.len() as u8; KEY_LEN], &[7; KEY_LEN]).unwrap()
+    }
+
+    #[test]
+    fn test_sealed_users() {
+        let user = User::new(
+            "Ada@Example.com".to_string(),
+            "01#hash".to_string(),
+            "Ada Lovelace".to_string(),
+        );
+        let old = keys("2024");
+
+        // Nothing personal is stored in the clear
+        let stored = StoredUser::new(&user, Some(&old)).unwrap();
+        let json = serde_json::to_string(&stored).unwrap();
+        assert!(!json.to_lowercase().contains("ada"));
+        assert!(json.contains(&user.id));
+
+        // a rotated key rewraps the data key and still opens the record
+        let rotated = keys("2025-01")
+            .with_previous_key("2024", &[4; KEY_LEN])
+            .unwrap();
+        let stored: StoredUser = serde_json::from_str(&json).unwrap();
+        assert!(!stored.is_current(Some(&rotated)));
+        let resealed = stored.reseal(Some(&rotated)).unwrap();
+        assert!(resealed.is_current(Some(&rotated)));
+        let opened = resealed.clone().open(Some(&rotated)).unwrap();
+        assert_eq!(
+            (opened.email, opened.name),
+            (user.email.clone(), user.name.clone())
+        );
+        assert!(matches!(
+            resealed.clone().open(Some(&old)),
+            Err(PiiError::UnknownKey(_))
+        ));
+        assert!(matches!(resealed.open(None), Err(PiiError::KeysRequired)));
+
+        // A record moved to another user doesn't open
+        let StoredUser::Sealed(mut sealed) = StoredUser::new(&user, Some(&old)).unwrap() else {
+            panic!("not sealed");
+        };
+        sealed.user.id = "someone else".to_string();
+        assert!(matches!(old.open(*sealed), Err(PiiError::Open)));
+
+        // Plain records from before encryption still read, and get sealed
+        let plain: StoredUser =
+            serde_json::from_str(&serde_json::to_string(&user).unwrap()).unwrap();
+        assert!(matches!(plain, StoredUser::Plain(_)));
+        assert!(matches!(
+            plain.reseal(Some(&old)).unwrap(),
+            StoredUser::Sealed(_)
+        ));
+
+        // and the index is blind to how the email is written
+        assert_eq!(
+            old.email_index(" ADA@example.com"),
+            old.email_index("ada@example.com")
+        );
+        assert!(!old.email_index("ada@example.com").contains("ada"));
+    }
+
+    #[test]
+    fn test_sealed_messages() {
+        use crate::outbox::DomainEvent;
+
+        let message = OutboxMessage::new(DomainEvent::UserRegistered {
+            user_id: "u1".to_string(),
+            email: "ada@example.com".to_string(),
+            name: "Ada Lovelace".to_string(),
+        });
+        let old = keys("2024");
+
+        // The event's email and name aren't stored in the clear
+        let stored = StoredMessage::new(&message, Some(&old)).unwrap();
+        let json = serde_json::to_string(&stored).unwrap();
+        assert!(!json.to_lowercase().contains("ada"));
+        let stored: StoredMessage = serde_json::from_str(&json).unwrap();
+        assert!(stored.is_current(Some(&old)));
+        assert_eq!(stored.clone().open(Some(&old)).unwrap(), message);
+
+        // a rotated key reseals it
+        let rotated = keys("2025-01")
+            .with_previous_key("2024", &[4; KEY_LEN])
+            .unwrap();
+        assert!(!stored.is_current(Some(&rotated)));
+        let resealed = stored.clone().reseal(Some(&rotated)).unwrap();
+        assert!(resealed.is_current(Some(&rotated)));
+        assert_eq!(resealed.open(Some(&rotated)).unwrap(), message);
+        assert!(matches!(stored.open(None), Err(PiiError::KeysRequired)));
+
+        // a sealed message can't pass for another one
+        let StoredMessage::Sealed(mut sealed) = StoredMessage::new(&message, Some(&old)).unwrap()
+        else {
+            panic!("not sealed");
+        };
+        sealed.id = "m2".to_string();
+        assert!(matches!(old.open_message(*sealed), Err(PiiError::Open)));
+
+        // Plain messages from before encryption still read
+        let plain: StoredMessage =
+            serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+        assert_eq!(plain.open(Some(&old)).unwrap(), message);
+    }
+}
@@ -11,6 +11,8 @@ pub const DEFAULT_SCHEME: &str = "01";
 pub trait Scheme {
     fn hash(&self, to_hash: &ContentToHash) -> Result<String>;
     fn validate(&self, passwd: &str, passwd_ref: &str) -> Result<()>;
+    // whether the hash is one this scheme produces, without a password to check it with
+    fn check_format(&self, passwd_ref: &str) -> Result<()>;
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -38,6 +38,14 @@ impl Scheme for Scheme01Argon2id {
             .verify_password(passwd.as_bytes(), &parsed_hash)
             .map_err(|_| SchemeError::PasswordValidate)
     }
+
+    fn check_format(&self, passwd_ref: &str) -> Result<()> {
+        let parsed_hash = PasswordHash::new(passwd_ref).map_err(|_| SchemeError::Hash)?;
+        if parsed_hash.algorithm != Algorithm::Argon2id.ident() || parsed_hash.hash.is_none() {
+            return Err(SchemeError::Hash);
+        }
+        Ok(())
+    }
 }
 
 fn get_argon2() -> &'static Argon2<'static> {
@@ -111,6 +119,6 @@ mod tests {
 
         let hash = scheme.hash(&content).unwrap();
         let validate_result = scheme.validate(TEST_INCORRECT_PASSWORD, &hash);
-        assert!(!validate_result.is_ok(), "Incorrect Password should fail");
+        assert!(validate_result.is_err(), "Incorrect Password should fail");
     }
 }
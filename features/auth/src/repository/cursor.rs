@@ -0,0 +1,185 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use super::error::{RepoError, Result};
+use crate::models::{User, UserList, UserQuery};
+
+// Where a user listing left off, the sort key and id of the last user returned. The sort
+// and direction are part of the cursor so it can't be replayed against another order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct UserCursor {
+    key: i64,
+    id: String,
+}
+
+impl UserCursor {
+    pub(crate) fn after(user: &User, query: &UserQuery) -> Self {
+        Self {
+            key: query.sort.key(user),
+            id: user.id.clone(),
+        }
+    }
+
+    pub(crate) fn encode(&self, query: &UserQuery) -> String {
+        URL_SAFE_NO_PAD.encode(format!(
+            "{}:{}:{}:{}",
+            query.sort.as_str(),
+            direction(query),
+            self.key,
+            self.id
+        ))
+    }
+
+    // The query's cursor, None on the first page
+    pub(crate) fn decode(query: &UserQuery) -> Result<Option<Self>> {
+        let Some(cursor) = &query.cursor else {
+            return Ok(None);
+        };
+
+        let decoded = URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or(RepoError::InvalidCursor)?;
+        let mut parts = decoded.splitn(4, ':');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(sort), Some(dir), Some(key), Some(id))
+                if sort == query.sort.as_str() && dir == direction(query) =>
+            {
+                Ok(Some(Self {
+                    key: key.parse().map_err(|_| RepoError::InvalidCursor)?,
+                    id: id.to_string(),
+                }))
+            }
+            _ => Err(RepoError::InvalidCursor),
+        }
+    }
+
+    // Whether the user comes after the cursor in the query's order
+    pub(crate) fn precedes(&self, user: &User, query: &UserQuery) -> bool {
+        compare(
+            (self.key, &self.id),
+            (query.sort.key(user), &user.id),
+            query,
+        ) == Ordering::Less
+    }
+}
+
+// Builds a page of a listing for repositories that go through every user, in any order
+pub(crate) struct UserPager<'a> {
+    query: &'a UserQuery,
+    after: Option<UserCursor>,
+    // by id, a scan may come across the same user twice
+    matches: HashMap<String, User>,
+}
+
+impl<'a> UserPager<'a> {
+    pub(crate) fn new(query: &'a UserQuery) -> Result<Self> {
+        Ok(Self {
+            query,
+            after: UserCursor::decode(query)?,
+            matches: HashMap::new(),
+        })
+    }
+
+    pub(crate) fn offer(&mut self, user: &User) {
+        if self.query.filter.matches(user)
+            && self
+                .after
+                .as_ref()
+                .is_none_or(|c| c.precedes(user, self.query))
+        {
+            self.matches.insert(user.id.clone(), user.clone());
+        }
+    }
+
+    pub(crate) fn finish(self) -> UserList {
+        let query = self.query;
+        let mut users: Vec<User> = self.matches.into_values().collect();
+        users.sort_by(|a, b| compare_users(a, b, query));
+
+        let limit = query.limit.max(1);
+        let next_cursor = match users.len() > limit {
+            true => Some(UserCursor::after(&users[limit - 1], query).encode(query)),
+            false => None,
+        };
+        users.truncate(limit);
+
+        UserList { users, next_cursor }
+    }
+}
+
+// The order of a listing, by the sort key and then by id
+fn compare_users(a: &User, b: &User, query: &UserQuery) -> Ordering {
+    compare(
+        (query.sort.key(a), &a.id),
+        (query.sort.key(b), &b.id),
+        query,
+    )
+}
+
+fn compare(a: (i64, &str), b: (i64, &str), query: &UserQuery) -> Ordering {
+    match query.descending {
+        true => b.cmp(&a),
+        false => a.cmp(&b),
+    }
+}
+
+fn direction(query: &UserQuery) -> &'static str {
+    match query.descending {
+        true => "desc",
+        false => "asc",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserSort;
+
+    fn user(id: &str, created_at: i64) -> User {
+        User {
+            id: id.to_string(),
+            created_at,
+            ..User::new(String::new(), String::new(), String::new())
+        }
+    }
+
+    #[test]
+    fn test_user_cursor() {
+        let query = UserQuery {
+            descending: true,
+            ..Default::default()
+        };
+        let cursor = UserCursor::after(&user("b", 100), &query);
+
+        let resumed = UserQuery {
+            cursor: Some(cursor.encode(&query)),
+            ..query.clone()
+        };
+        assert_eq!(UserCursor::decode(&resumed).unwrap(), Some(cursor.clone()));
+
+        // newest first, ties broken by id
+        assert!(cursor.precedes(&user("a", 100), &query));
+        assert!(cursor.precedes(&user("c", 99), &query));
+        assert!(!cursor.precedes(&user("c", 100), &query));
+        assert!(!cursor.precedes(&user("b", 100), &query));
+
+        // a cursor only fits the order it came from
+        let other_order = UserQuery {
+            sort: UserSort::UpdatedAt,
+            ..resumed.clone()
+        };
+        assert!(matches!(
+            UserCursor::decode(&other_order),
+            Err(RepoError::InvalidCursor)
+        ));
+        let garbage = UserQuery {
+            cursor: Some("not a cursor".to_string()),
+            ..Default::default()
+        };
+        assert!(UserCursor::decode(&garbage).is_err());
+    }
+}
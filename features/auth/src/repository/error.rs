@@ -5,7 +5,46 @@ pub enum RepoError {
     DataReadError,
     CreateUser,
     UpdateUser,
+    // another user already has the email
+    DuplicateEmail,
+    // the record was saved by someone else since it was read
+    Conflict,
+    DeleteUser,
     UserNotFound,
+    InvalidCursor,
+    // the snapshot or log of persisted users can't be read, or is in a format we don't know
+    LoadUsers,
+    UnsupportedFormat,
+    WriteUserLog,
+    WriteSnapshot,
+    CreateApiKey,
+    UpdateApiKey,
+    DeleteApiKey,
+    ApiKeyNotFound,
+    CreateIdentity,
+    DeleteIdentity,
+    CreateClientApp,
+    DeleteClientApp,
+    CreateGrant,
+    UpdateGrant,
+    SaveSsoConnection,
+    CreateScimToken,
+    SaveProvisionedUser,
+    DeleteProvisionedUser,
+    CreateGroup,
+    UpdateGroup,
+    GroupNotFound,
+    OpenUserStore,
+    // a stored user couldn't be sealed or opened, e.g. its key isn't configured
+    Encryption,
+    OpenSessionStore,
+    CreateSession,
+    UpdateSession,
+    DeleteSession,
+    SaveEmailChange,
+    DeleteEmailChange,
+    SavePasswordReset,
+    DeletePasswordReset,
 }
 
 impl std::fmt::Display for RepoError {
@@ -0,0 +1,93 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::error::Result;
+use super::{ApiKeyRepositoryTrait, error::RepoError};
+
+use crate::models::ApiKey;
+
+pub struct InMemoryApiKeyRepository {
+    api_keys: Arc<RwLock<HashMap<String, ApiKey>>>,
+}
+
+impl Default for InMemoryApiKeyRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryApiKeyRepository {
+    pub fn new() -> Self {
+        Self {
+            api_keys: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepositoryTrait for InMemoryApiKeyRepository {
+    async fn create_api_key(&self, api_key: ApiKey) -> Result<ApiKey> {
+        let mut api_keys = self.api_keys.write().map_err(|_| RepoError::CreateApiKey)?;
+
+        if api_keys.values().any(|k| k.prefix == api_key.prefix) {
+            return Err(RepoError::CreateApiKey);
+        }
+
+        let api_key_clone = api_key.clone();
+        api_keys.insert(api_key.id.clone(), api_key);
+        Ok(api_key_clone)
+    }
+    async fn find_by_id(&self, id: &str) -> Result<Option<ApiKey>> {
+        let api_keys = self.api_keys.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(api_keys.get(id).cloned())
+    }
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Option<ApiKey>> {
+        let api_keys = self.api_keys.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(api_keys.values().find(|k| k.prefix == prefix).cloned())
+    }
+    async fn list_by_user(&self, user_id: &str) -> Result<Vec<ApiKey>> {
+        let api_keys = self.api_keys.read().map_err(|_| RepoError::DataReadError)?;
+
+        let mut user_keys: Vec<ApiKey> = api_keys
+            .values()
+            .filter(|k| k.user_id == user_id)
+            .cloned()
+            .collect();
+        user_keys.sort_by_key(|k| k.created_at);
+
+        Ok(user_keys)
+    }
+    async fn update_api_key(&self, api_key: &ApiKey) -> Result<ApiKey> {
+        let mut api_keys = self.api_keys.write().map_err(|_| RepoError::UpdateApiKey)?;
+
+        if !api_keys.contains_key(&api_key.id) {
+            return Err(RepoError::ApiKeyNotFound);
+        }
+
+        api_keys.insert(api_key.id.clone(), api_key.clone());
+        Ok(api_key.clone())
+    }
+    async fn touch_last_used(&self, id: &str, now: i64) -> Result<Option<ApiKey>> {
+        let mut api_keys = self.api_keys.write().map_err(|_| RepoError::UpdateApiKey)?;
+
+        match api_keys.get_mut(id) {
+            Some(api_key) if !api_key.is_revoked() => {
+                api_key.last_used_at = Some(now);
+                Ok(Some(api_key.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+    async fn delete_by_user(&self, user_id: &str) -> Result<usize> {
+        let mut api_keys = self.api_keys.write().map_err(|_| RepoError::DeleteApiKey)?;
+
+        let before = api_keys.len();
+        api_keys.retain(|_, k| k.user_id != user_id);
+        Ok(before - api_keys.len())
+    }
+}
@@ -0,0 +1,75 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::error::Result;
+use super::{ClientAppRepositoryTrait, error::RepoError};
+
+use crate::models::ClientApp;
+
+pub struct InMemoryClientAppRepository {
+    client_apps: Arc<RwLock<HashMap<String, ClientApp>>>,
+}
+
+impl Default for InMemoryClientAppRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryClientAppRepository {
+    pub fn new() -> Self {
+        Self {
+            client_apps: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl ClientAppRepositoryTrait for InMemoryClientAppRepository {
+    async fn create_client_app(&self, client_app: ClientApp) -> Result<ClientApp> {
+        let mut client_apps = self
+            .client_apps
+            .write()
+            .map_err(|_| RepoError::CreateClientApp)?;
+
+        let client_app_clone = client_app.clone();
+        client_apps.insert(client_app.id.clone(), client_app);
+        Ok(client_app_clone)
+    }
+    async fn find_client_app(&self, id: &str) -> Result<Option<ClientApp>> {
+        let client_apps = self
+            .client_apps
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        Ok(client_apps.get(id).cloned())
+    }
+    async fn list_by_owner(&self, owner_id: &str) -> Result<Vec<ClientApp>> {
+        let client_apps = self
+            .client_apps
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        let mut owned: Vec<ClientApp> = client_apps
+            .values()
+            .filter(|c| c.owner_id == owner_id)
+            .cloned()
+            .collect();
+        owned.sort_by_key(|c| c.created_at);
+
+        Ok(owned)
+    }
+    async fn delete_by_owner(&self, owner_id: &str) -> Result<usize> {
+        let mut client_apps = self
+            .client_apps
+            .write()
+            .map_err(|_| RepoError::DeleteClientApp)?;
+
+        let before = client_apps.len();
+        client_apps.retain(|_, c| c.owner_id != owner_id);
+        Ok(before - client_apps.len())
+    }
+}
@@ -0,0 +1,64 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::error::Result;
+use super::{EmailChangeRepositoryTrait, error::RepoError};
+
+use crate::models::EmailChange;
+
+pub struct InMemoryEmailChangeRepository {
+    // keyed by user id, a user has at most one pending change
+    changes: Arc<RwLock<HashMap<String, EmailChange>>>,
+}
+
+impl Default for InMemoryEmailChangeRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryEmailChangeRepository {
+    pub fn new() -> Self {
+        Self {
+            changes: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl EmailChangeRepositoryTrait for InMemoryEmailChangeRepository {
+    async fn save_email_change(&self, change: EmailChange) -> Result<EmailChange> {
+        let mut changes = self
+            .changes
+            .write()
+            .map_err(|_| RepoError::SaveEmailChange)?;
+
+        changes.insert(change.user_id.clone(), change.clone());
+        Ok(change)
+    }
+    async fn find_by_user(&self, user_id: &str) -> Result<Option<EmailChange>> {
+        let changes = self.changes.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(changes.get(user_id).cloned())
+    }
+    async fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<EmailChange>> {
+        let changes = self.changes.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(changes
+            .values()
+            .find(|c| c.confirm_token_hash == token_hash || c.cancel_token_hash == token_hash)
+            .cloned())
+    }
+    async fn delete_email_change(&self, user_id: &str) -> Result<()> {
+        let mut changes = self
+            .changes
+            .write()
+            .map_err(|_| RepoError::DeleteEmailChange)?;
+
+        changes.remove(user_id);
+        Ok(())
+    }
+}
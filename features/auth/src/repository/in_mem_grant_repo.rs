@@ -0,0 +1,118 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use time::OffsetDateTime;
+
+use super::error::Result;
+use super::{GrantRepositoryTrait, error::RepoError};
+
+use crate::models::{AuthorizationCode, RefreshToken};
+
+pub struct InMemoryGrantRepository {
+    codes: Arc<RwLock<HashMap<String, AuthorizationCode>>>,
+    refresh_tokens: Arc<RwLock<HashMap<String, RefreshToken>>>,
+    // jti -> expiry, entries can be dropped once the token has expired
+    revoked_access_tokens: Arc<RwLock<HashMap<String, i64>>>,
+}
+
+impl Default for InMemoryGrantRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryGrantRepository {
+    pub fn new() -> Self {
+        Self {
+            codes: Arc::new(RwLock::new(HashMap::new())),
+            refresh_tokens: Arc::new(RwLock::new(HashMap::new())),
+            revoked_access_tokens: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl GrantRepositoryTrait for InMemoryGrantRepository {
+    async fn create_code(&self, code: AuthorizationCode) -> Result<AuthorizationCode> {
+        let mut codes = self.codes.write().map_err(|_| RepoError::CreateGrant)?;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        codes.retain(|_, c| c.expires_at > now);
+
+        let code_clone = code.clone();
+        codes.insert(code.code.clone(), code);
+        Ok(code_clone)
+    }
+    async fn take_code(&self, code: &str) -> Result<Option<AuthorizationCode>> {
+        let mut codes = self.codes.write().map_err(|_| RepoError::UpdateGrant)?;
+
+        Ok(codes.remove(code))
+    }
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<RefreshToken> {
+        let mut refresh_tokens = self
+            .refresh_tokens
+            .write()
+            .map_err(|_| RepoError::CreateGrant)?;
+
+        let token_clone = token.clone();
+        refresh_tokens.insert(token.jti.clone(), token);
+        Ok(token_clone)
+    }
+    async fn find_refresh_token(&self, jti: &str) -> Result<Option<RefreshToken>> {
+        let refresh_tokens = self
+            .refresh_tokens
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        Ok(refresh_tokens.get(jti).cloned())
+    }
+    async fn update_refresh_token(&self, token: &RefreshToken) -> Result<RefreshToken> {
+        let mut refresh_tokens = self
+            .refresh_tokens
+            .write()
+            .map_err(|_| RepoError::UpdateGrant)?;
+
+        if !refresh_tokens.contains_key(&token.jti) {
+            return Err(RepoError::UpdateGrant);
+        }
+
+        refresh_tokens.insert(token.jti.clone(), token.clone());
+        Ok(token.clone())
+    }
+    async fn revoke_access_token(&self, jti: &str, expires_at: i64) -> Result<()> {
+        let mut revoked = self
+            .revoked_access_tokens
+            .write()
+            .map_err(|_| RepoError::UpdateGrant)?;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        revoked.retain(|_, exp| *exp > now);
+        revoked.insert(jti.to_string(), expires_at);
+        Ok(())
+    }
+    async fn is_access_token_revoked(&self, jti: &str) -> Result<bool> {
+        let revoked = self
+            .revoked_access_tokens
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        Ok(revoked.contains_key(jti))
+    }
+    async fn revoke_refresh_tokens_by_user(&self, user_id: &str, now: i64) -> Result<usize> {
+        let mut refresh_tokens = self
+            .refresh_tokens
+            .write()
+            .map_err(|_| RepoError::UpdateGrant)?;
+
+        let mut revoked = 0;
+        for token in refresh_tokens.values_mut() {
+            if token.user_id == user_id && token.revoked_at.is_none() {
+                token.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+}
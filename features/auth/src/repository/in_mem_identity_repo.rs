@@ -0,0 +1,99 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::error::Result;
+use super::{IdentityRepositoryTrait, error::RepoError};
+
+use crate::models::Identity;
+
+pub struct InMemoryIdentityRepository {
+    identities: Arc<RwLock<HashMap<String, Identity>>>,
+}
+
+impl Default for InMemoryIdentityRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryIdentityRepository {
+    pub fn new() -> Self {
+        Self {
+            identities: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityRepositoryTrait for InMemoryIdentityRepository {
+    async fn create_identity(&self, identity: Identity) -> Result<Identity> {
+        let mut identities = self
+            .identities
+            .write()
+            .map_err(|_| RepoError::CreateIdentity)?;
+
+        // a provider account can only be linked to one user
+        if identities
+            .values()
+            .any(|i| i.provider == identity.provider && i.subject == identity.subject)
+        {
+            return Err(RepoError::CreateIdentity);
+        }
+
+        let identity_clone = identity.clone();
+        identities.insert(identity.id.clone(), identity);
+        Ok(identity_clone)
+    }
+    async fn find_by_provider_subject(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Identity>> {
+        let identities = self
+            .identities
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        Ok(identities
+            .values()
+            .find(|i| i.provider == provider && i.subject == subject)
+            .cloned())
+    }
+    async fn list_by_user(&self, user_id: &str) -> Result<Vec<Identity>> {
+        let identities = self
+            .identities
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        let mut user_identities: Vec<Identity> = identities
+            .values()
+            .filter(|i| i.user_id == user_id)
+            .cloned()
+            .collect();
+        user_identities.sort_by_key(|i| i.created_at);
+
+        Ok(user_identities)
+    }
+    async fn delete_identity(&self, id: &str) -> Result<()> {
+        let mut identities = self
+            .identities
+            .write()
+            .map_err(|_| RepoError::DeleteIdentity)?;
+
+        identities.remove(id);
+        Ok(())
+    }
+    async fn delete_by_user(&self, user_id: &str) -> Result<usize> {
+        let mut identities = self
+            .identities
+            .write()
+            .map_err(|_| RepoError::DeleteIdentity)?;
+
+        let before = identities.len();
+        identities.retain(|_, i| i.user_id != user_id);
+        Ok(before - identities.len())
+    }
+}
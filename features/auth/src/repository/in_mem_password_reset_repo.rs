@@ -0,0 +1,64 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::error::Result;
+use super::{PasswordResetRepositoryTrait, error::RepoError};
+
+use crate::models::PasswordReset;
+
+pub struct InMemoryPasswordResetRepository {
+    // keyed by user id, a user has at most one pending reset
+    resets: Arc<RwLock<HashMap<String, PasswordReset>>>,
+}
+
+impl Default for InMemoryPasswordResetRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryPasswordResetRepository {
+    pub fn new() -> Self {
+        Self {
+            resets: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepositoryTrait for InMemoryPasswordResetRepository {
+    async fn save_password_reset(&self, reset: PasswordReset) -> Result<PasswordReset> {
+        let mut resets = self
+            .resets
+            .write()
+            .map_err(|_| RepoError::SavePasswordReset)?;
+
+        resets.insert(reset.user_id.clone(), reset.clone());
+        Ok(reset)
+    }
+    async fn find_by_user(&self, user_id: &str) -> Result<Option<PasswordReset>> {
+        let resets = self.resets.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(resets.get(user_id).cloned())
+    }
+    async fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<PasswordReset>> {
+        let resets = self.resets.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(resets
+            .values()
+            .find(|r| r.token_hash == token_hash)
+            .cloned())
+    }
+    async fn delete_password_reset(&self, user_id: &str) -> Result<()> {
+        let mut resets = self
+            .resets
+            .write()
+            .map_err(|_| RepoError::DeletePasswordReset)?;
+
+        resets.remove(user_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use time::OffsetDateTime;
+
+use super::error::Result;
+use super::{ScimRepositoryTrait, error::RepoError};
+
+use crate::models::{Group, ProvisionedUser, ScimToken};
+
+pub struct InMemoryScimRepository {
+    // prefix -> token
+    tokens: Arc<RwLock<HashMap<String, ScimToken>>>,
+    // (org, user id) -> user
+    provisioned_users: Arc<RwLock<HashMap<(String, String), ProvisionedUser>>>,
+    groups: Arc<RwLock<HashMap<String, Group>>>,
+}
+
+impl Default for InMemoryScimRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemoryScimRepository {
+    pub fn new() -> Self {
+        Self {
+            tokens: Arc::new(RwLock::new(HashMap::new())),
+            provisioned_users: Arc::new(RwLock::new(HashMap::new())),
+            groups: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl ScimRepositoryTrait for InMemoryScimRepository {
+    async fn create_token(&self, token: ScimToken) -> Result<ScimToken> {
+        let mut tokens = self
+            .tokens
+            .write()
+            .map_err(|_| RepoError::CreateScimToken)?;
+
+        if tokens.contains_key(&token.prefix) {
+            return Err(RepoError::CreateScimToken);
+        }
+
+        tokens.insert(token.prefix.clone(), token.clone());
+        Ok(token)
+    }
+    async fn find_token_by_prefix(&self, prefix: &str) -> Result<Option<ScimToken>> {
+        let tokens = self.tokens.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(tokens.get(prefix).cloned())
+    }
+
+    async fn save_provisioned_user(&self, user: ProvisionedUser) -> Result<ProvisionedUser> {
+        let mut users = self
+            .provisioned_users
+            .write()
+            .map_err(|_| RepoError::SaveProvisionedUser)?;
+
+        users.insert((user.org.clone(), user.user_id.clone()), user.clone());
+        Ok(user)
+    }
+    async fn find_provisioned_user(
+        &self,
+        org: &str,
+        user_id: &str,
+    ) -> Result<Option<ProvisionedUser>> {
+        let users = self
+            .provisioned_users
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        Ok(users.get(&(org.to_string(), user_id.to_string())).cloned())
+    }
+    async fn list_provisioned_users(&self, org: &str) -> Result<Vec<ProvisionedUser>> {
+        let users = self
+            .provisioned_users
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        let mut org_users: Vec<ProvisionedUser> =
+            users.values().filter(|u| u.org == org).cloned().collect();
+        org_users.sort_by(|a, b| (a.created_at, &a.user_id).cmp(&(b.created_at, &b.user_id)));
+        Ok(org_users)
+    }
+    async fn list_provisioned_by_user(&self, user_id: &str) -> Result<Vec<ProvisionedUser>> {
+        let users = self
+            .provisioned_users
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        let mut records: Vec<ProvisionedUser> = users
+            .values()
+            .filter(|u| u.user_id == user_id)
+            .cloned()
+            .collect();
+        records.sort_by(|a, b| (a.created_at, &a.org).cmp(&(b.created_at, &b.org)));
+        Ok(records)
+    }
+    async fn delete_provisioned_user(&self, org: &str, user_id: &str) -> Result<()> {
+        let mut users = self
+            .provisioned_users
+            .write()
+            .map_err(|_| RepoError::DeleteProvisionedUser)?;
+
+        users.remove(&(org.to_string(), user_id.to_string()));
+        Ok(())
+    }
+
+    async fn create_group(&self, group: Group) -> Result<Group> {
+        let mut groups = self.groups.write().map_err(|_| RepoError::CreateGroup)?;
+
+        groups.insert(group.id.clone(), group.clone());
+        Ok(group)
+    }
+    async fn find_group(&self, org: &str, id: &str) -> Result<Option<Group>> {
+        let groups = self.groups.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(groups.get(id).filter(|g| g.org == org).cloned())
+    }
+    async fn list_groups(&self, org: &str) -> Result<Vec<Group>> {
+        let groups = self.groups.read().map_err(|_| RepoError::DataReadError)?;
+
+        let mut org_groups: Vec<Group> =
+            groups.values().filter(|g| g.org == org).cloned().collect();
+        org_groups.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(org_groups)
+    }
+    async fn update_group(&self, group: &Group) -> Result<Group> {
+        let mut groups = self.groups.write().map_err(|_| RepoError::UpdateGroup)?;
+
+        if groups.get(&group.id).is_none_or(|g| g.org != group.org) {
+            return Err(RepoError::GroupNotFound);
+        }
+
+        let updated_group = Group {
+            updated_at: OffsetDateTime::now_utc().unix_timestamp(),
+            ..group.clone()
+        };
+
+        groups.insert(group.id.clone(), updated_group.clone());
+        Ok(updated_group)
+    }
+    async fn delete_group(&self, org: &str, id: &str) -> Result<()> {
+        let mut groups = self.groups.write().map_err(|_| RepoError::UpdateGroup)?;
+
+        match groups.get(id) {
+            Some(group) if group.org == org => {
+                groups.remove(id);
+                Ok(())
+            }
+            _ => Err(RepoError::GroupNotFound),
+        }
+    }
+    async fn list_groups_by_member(&self, user_id: &str) -> Result<Vec<Group>> {
+        let groups = self.groups.read().map_err(|_| RepoError::DataReadError)?;
+
+        let mut member_of: Vec<Group> = groups
+            .values()
+            .filter(|g| g.members.iter().any(|m| m == user_id))
+            .cloned()
+            .collect();
+        member_of.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(member_of)
+    }
+}
@@ -0,0 +1,142 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::error::Result;
+use super::{SessionStore, error::RepoError};
+
+use crate::models::Session;
+
+pub struct InMemorySessionStore {
+    sessions: Arc<RwLock<HashMap<String, Session>>>,
+}
+
+impl Default for InMemorySessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemorySessionStore {
+    pub fn new() -> Self {
+        Self {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for InMemorySessionStore {
+    async fn create_session(&self, session: Session) -> Result<Session> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| RepoError::CreateSession)?;
+
+        if sessions.contains_key(&session.id) {
+            return Err(RepoError::CreateSession);
+        }
+
+        sessions.insert(session.id.clone(), session.clone());
+        Ok(session)
+    }
+
+    async fn find_session(&self, id: &str) -> Result<Option<Session>> {
+        let sessions = self.sessions.read().map_err(|_| RepoError::DataReadError)?;
+
+        Ok(sessions.get(id).cloned())
+    }
+
+    async fn update_session(&self, session: &Session) -> Result<Session> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| RepoError::UpdateSession)?;
+
+        match sessions.get_mut(&session.id) {
+            Some(existing) => *existing = session.clone(),
+            None => return Err(RepoError::UpdateSession),
+        }
+
+        Ok(session.clone())
+    }
+
+    async fn delete_session(&self, id: &str) -> Result<()> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| RepoError::DeleteSession)?;
+
+        sessions.remove(id);
+        Ok(())
+    }
+
+    async fn list_user_sessions(&self, user_id: &str, now: i64) -> Result<Vec<Session>> {
+        let sessions = self.sessions.read().map_err(|_| RepoError::DataReadError)?;
+
+        let mut user_sessions: Vec<Session> = sessions
+            .values()
+            .filter(|s| s.user_id == user_id && !s.is_expired(now))
+            .cloned()
+            .collect();
+        user_sessions.sort_by_key(|s| std::cmp::Reverse(s.last_seen_at));
+
+        Ok(user_sessions)
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<usize> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| RepoError::DeleteSession)?;
+
+        let before = sessions.len();
+        sessions.retain(|_, session| session.user_id != user_id);
+        Ok(before - sessions.len())
+    }
+
+    async fn take_user_sessions(&self, user_id: &str) -> Result<Vec<Session>> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| RepoError::DeleteSession)?;
+
+        let ids: Vec<String> = sessions
+            .values()
+            .filter(|session| session.user_id == user_id)
+            .map(|session| session.id.clone())
+            .collect();
+        Ok(ids.iter().filter_map(|id| sessions.remove(id)).collect())
+    }
+
+    async fn restore_sessions(&self, restored: Vec<Session>) -> Result<()> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| RepoError::CreateSession)?;
+
+        if restored
+            .iter()
+            .any(|session| sessions.contains_key(&session.id))
+        {
+            return Err(RepoError::CreateSession);
+        }
+        for session in restored {
+            sessions.insert(session.id.clone(), session);
+        }
+        Ok(())
+    }
+
+    async fn delete_expired_sessions(&self, now: i64) -> Result<usize> {
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|_| RepoError::DeleteSession)?;
+
+        let before = sessions.len();
+        sessions.retain(|_, session| !session.is_expired(now));
+        Ok(before - sessions.len())
+    }
+}
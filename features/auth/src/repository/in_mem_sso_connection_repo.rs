@@ -0,0 +1,49 @@
+use async_trait::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::error::Result;
+use super::{SsoConnectionRepositoryTrait, error::RepoError};
+
+use crate::models::SsoConnection;
+
+pub struct InMemorySsoConnectionRepository {
+    connections: Arc<RwLock<HashMap<String, SsoConnection>>>,
+}
+
+impl Default for InMemorySsoConnectionRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InMemorySsoConnectionRepository {
+    pub fn new() -> Self {
+        Self {
+            connections: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl SsoConnectionRepositoryTrait for InMemorySsoConnectionRepository {
+    async fn save_connection(&self, connection: SsoConnection) -> Result<SsoConnection> {
+        let mut connections = self
+            .connections
+            .write()
+            .map_err(|_| RepoError::SaveSsoConnection)?;
+
+        connections.insert(connection.org.clone(), connection.clone());
+        Ok(connection)
+    }
+    async fn find_by_org(&self, org: &str) -> Result<Option<SsoConnection>> {
+        let connections = self
+            .connections
+            .read()
+            .map_err(|_| RepoError::DataReadError)?;
+
+        Ok(connections.get(org).cloned())
+    }
+}
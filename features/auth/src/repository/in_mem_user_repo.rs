@@ -1,75 +1,542 @@
 use async_trait::async_trait;
-use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
-};
+use scc::{HashMap, hash_map::Entry};
+use std::path::Path;
 use time::OffsetDateTime;
+use tokio::sync::RwLockReadGuard;
 
+use super::cursor::UserPager;
 use super::error::Result;
-use super::{UserRepositoryTrait, error::RepoError};
+use super::snapshot::{LogRecord, UserFiles};
+use super::{UserOutboxStore, UserRepositoryTrait, error::RepoError};
 
-use crate::models::User;
+use crate::models::{User, UserList, UserQuery};
+use crate::outbox::{
+    OutboxMessage, OutboxStore, error::OutboxError, error::Result as OutboxResult,
+    in_mem_store::InMemoryOutbox,
+};
+use crate::pii::PiiKeys;
+use crate::utils::normalize_email;
 
+// Users by id, with an index of their normalized emails. Both maps lock single buckets
+// and wait for them asynchronously, and no operation holds an entry of one map while
+// waiting on the other, so concurrent requests only contend on the same users.
 pub struct InMemoryUserRepository {
-    users: Arc<RwLock<HashMap<String, User>>>,
+    users: HashMap<String, User>,
+    // normalized email to user id, claiming an email here is what makes it unique
+    emails: HashMap<String, String>,
+    // where the users are persisted, if anywhere
+    files: Option<UserFiles>,
+    // the events about the users, logged with them. A message has one writer at a time,
+    // the unit of work that holds it, then the dispatcher, then an admin retrying it once
+    // it's dead, so changes to it needn't wait on each other.
+    outbox: InMemoryOutbox,
+}
+
+impl Default for InMemoryUserRepository {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InMemoryUserRepository {
     pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    // sized up front, e.g. when seeding users for a load test
+    pub fn with_capacity(capacity: usize) -> Self {
         Self {
-            users: Arc::new(RwLock::new(HashMap::new())),
+            users: HashMap::with_capacity(capacity),
+            emails: HashMap::with_capacity(capacity),
+            files: None,
+            outbox: InMemoryOutbox::new(),
+        }
+    }
+
+    // Keeps the users in `dir` across restarts, starting with the ones already there.
+    // Every change is logged before it's acknowledged, `snapshot` folds the log into a
+    // snapshot of all users so it doesn't grow forever.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(dir, None)
+    }
+
+    // Like `open`, with the users' email and name encrypted in the files. Users already
+    // written in the clear are sealed by the next snapshot.
+    pub fn open_encrypted(dir: impl AsRef<Path>, keys: PiiKeys) -> Result<Self> {
+        Self::open_with(dir, Some(keys))
+    }
+
+    fn open_with(dir: impl AsRef<Path>, keys: Option<PiiKeys>) -> Result<Self> {
+        let (files, users, messages) = UserFiles::open(dir, keys)?;
+
+        let repo = Self::with_capacity(users.len());
+        for user in users {
+            let _ = repo
+                .emails
+                .insert(normalize_email(&user.email), user.id.clone());
+            let _ = repo.users.insert(user.id.clone(), user);
+        }
+        Ok(Self {
+            files: Some(files),
+            outbox: InMemoryOutbox::with_messages(messages),
+            ..repo
+        })
+    }
+
+    // Writes every user and message to the snapshots and empties the log, returns whether there was
+    // anything to write. Meant to run periodically and on shutdown.
+    pub async fn snapshot(&self) -> Result<bool> {
+        self.write_snapshot(false).await
+    }
+
+    // Writes the snapshot even when nothing changed, so every user is sealed with the
+    // current key after a rotation and the log that held older records is emptied
+    pub async fn reencrypt(&self) -> Result<bool> {
+        self.write_snapshot(true).await
+    }
+
+    async fn write_snapshot(&self, always: bool) -> Result<bool> {
+        let Some(files) = &self.files else {
+            return Ok(false);
+        };
+        let _snapshotting = files.snapshotting().await;
+        let logged = match files.changed_since_snapshot().await? {
+            Some(logged) => logged,
+            None if always => files.logged().await?,
+            None => return Ok(false),
+        };
+
+        // a scan racing a resize can visit a user twice
+        let mut users = std::collections::HashMap::new();
+        self.users
+            .scan_async(|id, user| {
+                users.insert(id.clone(), user.clone());
+            })
+            .await;
+        let messages = self.outbox.all().await;
+        files
+            .write_snapshot(users.into_values().collect(), messages, logged)
+            .await?;
+        Ok(true)
+    }
+
+    async fn writing(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        match &self.files {
+            Some(files) => Some(files.writing().await),
+            None => None,
         }
     }
+
+    async fn log(&self, record: LogRecord) -> Result<()> {
+        match &self.files {
+            Some(files) => files.append(&record).await,
+            None => Ok(()),
+        }
+    }
+
+    // logs a change to the outbox alone, failing like the outbox would
+    async fn log_outbox(&self, record: LogRecord) -> OutboxResult<()> {
+        self.log(record)
+            .await
+            .map_err(|e| OutboxError::Write(e.to_string()))
+    }
+
+    // Takes back the messages written with a change that wasn't logged
+    async fn take_back_messages(&self, ids: &[String]) {
+        if let Err(e) = self.outbox.discard(ids).await {
+            eprintln!("Can't take back {} outbox messages: {e}", ids.len());
+        }
+    }
+
+    // Puts back the messages of a user whose deletion wasn't logged
+    async fn put_back_messages(&self, messages: Vec<OutboxMessage>) {
+        let count = messages.len();
+        if let Err(e) = self.outbox.enqueue(messages).await {
+            eprintln!("Can't put back {count} outbox messages: {e}");
+        }
+    }
+
+    // Takes the email for the user, fails if another user has it
+    async fn claim_email(&self, email: String, id: &str) -> Result<()> {
+        match self.emails.entry_async(email).await {
+            Entry::Occupied(owner) if owner.get() != id => Err(RepoError::DuplicateEmail),
+            Entry::Occupied(_) => Ok(()),
+            Entry::Vacant(vacant) => {
+                vacant.insert_entry(id.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    // Gives the email up, unless someone else has claimed it since
+    async fn release_email(&self, email: &str, id: &str) {
+        self.emails
+            .remove_if_async(email, |owner| owner == id)
+            .await;
+    }
 }
 
 #[async_trait]
 impl UserRepositoryTrait for InMemoryUserRepository {
     async fn create_user(&self, user: User) -> Result<User> {
-        let mut users = self
+        self.create_user_publishing(user, Vec::new()).await
+    }
+    async fn find_by_id(&self, id: &str) -> Result<Option<User>> {
+        Ok(self.users.read_async(id, |_, user| user.clone()).await)
+    }
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        let email = normalize_email(email);
+        let Some(id) = self.emails.read_async(&email, |_, id| id.clone()).await else {
+            return Ok(None);
+        };
+
+        // the index may briefly point at a user whose email has just changed
+        Ok(self
             .users
-            .write()
-            .map_err(|_| return RepoError::CreateUser)?;
+            .read_async(&id, |_, user| user.clone())
+            .await
+            .filter(|user| normalize_email(&user.email) == email))
+    }
+    async fn update_user(&self, user: &User) -> Result<User> {
+        self.update_user_publishing(user, Vec::new()).await
+    }
+    // deletes the messages about the user with them
+    async fn delete_user(&self, id: &str) -> Result<()> {
+        let _writing = self.writing().await;
+        let Some((_, user)) = self.users.remove_async(id).await else {
+            return Err(RepoError::UserNotFound);
+        };
+        let messages = self.outbox.take_user(id).await;
+        // a user whose deletion isn't logged is put back, the email and messages are
+        // still theirs
+        if let Err(e) = self.log(LogRecord::Delete { id: id.to_string() }).await {
+            self.put_back_messages(messages).await;
+            let _ = self.users.insert_async(id.to_string(), user).await;
+            return Err(e);
+        }
+        self.release_email(&normalize_email(&user.email), id).await;
+        Ok(())
+    }
+    async fn list_deleted_before(&self, before: i64) -> Result<Vec<User>> {
+        let mut deleted = Vec::new();
+        self.users
+            .scan_async(|_, user| {
+                if user.deleted_at.is_some_and(|at| at <= before) {
+                    deleted.push(user.clone());
+                }
+            })
+            .await;
+        // a scan racing a resize can visit a user twice
+        deleted.sort_by(|a: &User, b| (a.deleted_at, &a.id).cmp(&(b.deleted_at, &b.id)));
+        deleted.dedup_by(|a, b| a.id == b.id);
 
-        let user_clone = user.clone();
-        users.insert(user.id.clone(), user);
-        Ok(user_clone)
+        Ok(deleted)
     }
-    async fn find_by_id(&self, id: &str) -> Result<Option<User>> {
-        let users = self.users.read().map_err(|_| {
-            return RepoError::DataReadError;
-        })?;
+    async fn list_users(&self, query: &UserQuery) -> Result<UserList> {
+        let mut pager = UserPager::new(query)?;
+        self.users.scan_async(|_, user| pager.offer(user)).await;
 
-        Ok(users.get(id).cloned())
+        Ok(pager.finish())
     }
-    async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
-        let users = self.users.read().map_err(|_| {
-            return RepoError::DataReadError;
-        })?;
+}
 
-        for user in users.values() {
-            if user.email == email {
-                return Ok(Some(user.clone()));
+#[async_trait]
+impl UserOutboxStore for InMemoryUserRepository {
+    async fn create_user_publishing(
+        &self,
+        user: User,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<User> {
+        let _writing = self.writing().await;
+        let messages = held(messages);
+        let ids = message_ids(&messages);
+        let email = normalize_email(&user.email);
+        self.claim_email(email.clone(), &user.id).await?;
+
+        if self
+            .users
+            .insert_async(user.id.clone(), user.clone())
+            .await
+            .is_err()
+        {
+            self.release_email(&email, &user.id).await;
+            return Err(RepoError::CreateUser);
+        }
+        let enqueued = self.outbox.enqueue(messages.clone()).await;
+        // a change that isn't logged is taken back, it'd be gone after a restart
+        let logged = match enqueued {
+            Ok(()) => {
+                self.log(LogRecord::Put {
+                    user: Box::new(user.clone()),
+                    messages,
+                })
+                .await
             }
+            Err(_) => Err(RepoError::CreateUser),
+        };
+        if let Err(e) = logged {
+            self.take_back_messages(&ids).await;
+            self.users
+                .remove_if_async(&user.id, |stored| stored.version == user.version)
+                .await;
+            self.release_email(&email, &user.id).await;
+            return Err(e);
         }
-
-        Ok(None)
+        Ok(user)
     }
-    async fn update_user(&self, user: &User) -> Result<User> {
-        let mut users = self
+    async fn update_user_publishing(
+        &self,
+        user: &User,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<User> {
+        let _writing = self.writing().await;
+        let messages = held(messages);
+        let ids = message_ids(&messages);
+        let Some((version, old_email)) = self
             .users
-            .write()
-            .map_err(|_| return RepoError::UpdateUser)?;
-
-        if !users.contains_key(&user.id) {
+            .read_async(&user.id, |_, stored| {
+                (stored.version, normalize_email(&stored.email))
+            })
+            .await
+        else {
             return Err(RepoError::UpdateUser);
+        };
+        if version != user.version {
+            return Err(RepoError::Conflict);
+        }
+
+        let email = normalize_email(&user.email);
+        let email_changed = email != old_email;
+        if email_changed {
+            self.claim_email(email.clone(), &user.id).await?;
         }
 
         let updated_user = User {
             updated_at: OffsetDateTime::now_utc().unix_timestamp(),
+            version: user.version + 1,
             ..user.clone()
         };
+        // the version check and the write happen under the same bucket lock
+        let mut previous = None;
+        let saved = self
+            .users
+            .update_async(&user.id, |_, stored| {
+                if stored.version != user.version {
+                    return false;
+                }
+                previous = Some(std::mem::replace(stored, updated_user.clone()));
+                true
+            })
+            .await;
+        let saved = match (saved, previous) {
+            (Some(true), Some(previous)) => {
+                let logged = match self.outbox.enqueue(messages.clone()).await {
+                    Ok(()) => {
+                        self.log(LogRecord::Put {
+                            user: Box::new(updated_user.clone()),
+                            messages,
+                        })
+                        .await
+                    }
+                    Err(_) => Err(RepoError::UpdateUser),
+                };
+                // a change that isn't logged is taken back, unless it's been saved over
+                if logged.is_err() {
+                    self.take_back_messages(&ids).await;
+                    self.users
+                        .update_async(&user.id, |_, stored| {
+                            if stored.version == updated_user.version {
+                                *stored = previous;
+                            }
+                        })
+                        .await;
+                }
+                logged
+            }
+            (Some(_), _) => Err(RepoError::Conflict),
+            (None, _) => Err(RepoError::UpdateUser),
+        };
+
+        // whichever email the user didn't end up with is given up
+        if email_changed {
+            let unused = match saved {
+                Ok(()) => &old_email,
+                Err(_) => &email,
+            };
+            self.release_email(unused, &user.id).await;
+        }
+        saved.map(|_| updated_user)
+    }
+}
+
+#[async_trait]
+impl OutboxStore for InMemoryUserRepository {
+    async fn enqueue(&self, messages: Vec<OutboxMessage>) -> OutboxResult<()> {
+        let _writing = self.writing().await;
+        let ids = message_ids(&messages);
+        self.outbox.enqueue(messages.clone()).await?;
+        // messages that aren't logged are taken back, they'd be gone after a restart
+        if let Err(e) = self.log_outbox(LogRecord::Messages { messages }).await {
+            self.take_back_messages(&ids).await;
+            return Err(e);
+        }
+        Ok(())
+    }
+    async fn release(&self, ids: &[String]) -> OutboxResult<()> {
+        let _writing = self.writing().await;
+        let mut released = Vec::new();
+        for id in ids {
+            if let Some(message) = self.outbox.get(id).await
+                && message.held
+            {
+                released.push(OutboxMessage {
+                    held: false,
+                    ..message
+                });
+            }
+        }
+        if released.is_empty() {
+            return Ok(());
+        }
+        self.log_outbox(LogRecord::Messages { messages: released })
+            .await?;
+        self.outbox.release(ids).await
+    }
+    async fn discard(&self, ids: &[String]) -> OutboxResult<()> {
+        let _writing = self.writing().await;
+        let mut found = Vec::new();
+        for id in ids {
+            if self.outbox.get(id).await.is_some() {
+                found.push(id.clone());
+            }
+        }
+        if found.is_empty() {
+            return Ok(());
+        }
+        self.log_outbox(LogRecord::Discard { ids: found }).await?;
+        self.outbox.discard(ids).await
+    }
+    async fn due(&self, now: i64, limit: usize) -> OutboxResult<Vec<OutboxMessage>> {
+        self.outbox.due(now, limit).await
+    }
+    async fn delivered(&self, id: &str) -> OutboxResult<()> {
+        self.discard(&[id.to_string()]).await
+    }
+    async fn failed(&self, message: &OutboxMessage) -> OutboxResult<()> {
+        let _writing = self.writing().await;
+        // delivered or deleted with its user meanwhile
+        if self.outbox.get(&message.id).await.is_none() {
+            return Ok(());
+        }
+        self.log_outbox(LogRecord::Messages {
+            messages: vec![message.clone()],
+        })
+        .await?;
+        self.outbox.failed(message).await
+    }
+    async fn dead_letters(&self) -> OutboxResult<Vec<OutboxMessage>> {
+        self.outbox.dead_letters().await
+    }
+    async fn retry(&self, id: &str, now: i64) -> OutboxResult<()> {
+        let _writing = self.writing().await;
+        let Some(message) = self.outbox.get(id).await.filter(|message| message.dead) else {
+            return Err(OutboxError::NotFound(id.to_string()));
+        };
+        self.log_outbox(LogRecord::Messages {
+            messages: vec![OutboxMessage {
+                dead: false,
+                attempts: 0,
+                next_attempt_at: now,
+                ..message
+            }],
+        })
+        .await?;
+        self.outbox.retry(id, now).await
+    }
+    async fn delete_by_user(&self, user_id: &str) -> OutboxResult<usize> {
+        let _writing = self.writing().await;
+        let messages = self.outbox.take_user(user_id).await;
+        if messages.is_empty() {
+            return Ok(0);
+        }
+        let count = messages.len();
+        let ids = message_ids(&messages);
+        if let Err(e) = self.log_outbox(LogRecord::Discard { ids }).await {
+            self.put_back_messages(messages).await;
+            return Err(e);
+        }
+        Ok(count)
+    }
+}
+
+// messages written with a change wait for its unit of work to be committed
+fn held(messages: Vec<OutboxMessage>) -> Vec<OutboxMessage> {
+    messages
+        .into_iter()
+        .map(|message| OutboxMessage {
+            held: true,
+            ..message
+        })
+        .collect()
+}
+
+fn message_ids(messages: &[OutboxMessage]) -> Vec<String> {
+    messages.iter().map(|message| message.id.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unlogged_changes_are_taken_back() {
+        let dir = std::env::temp_dir().join(format!("users-{}", uuid::Uuid::new_v4()));
+        let repo = InMemoryUserRepository::open(&dir).unwrap();
+        let user = |email: &str| User::new(email.to_string(), String::new(), email.to_string());
+        let ada = repo.create_user(user("ada@example.com")).await.unwrap();
+        let as_created = Some((
+            0,
+            "ada@example.com".to_string(),
+            "ada@example.com".to_string(),
+        ));
+        let seen = |found: Option<User>| found.map(|u| (u.version, u.email, u.name));
+
+        // Once the log can't be written, e.g. on a full disk, nothing changes
+        repo.files.as_ref().unwrap().fail_appends();
+        let grace = user("grace@example.com");
+        assert!(matches!(
+            repo.create_user(grace.clone()).await,
+            Err(RepoError::WriteUserLog)
+        ));
+        assert!(repo.find_by_id(&grace.id).await.unwrap().is_none());
+        assert!(!repo.emails.contains_async("grace@example.com").await);
+
+        let renamed = User {
+            email: "ada.lovelace@example.com".to_string(),
+            name: "Ada Lovelace".to_string(),
+            ..ada.clone()
+        };
+        assert!(matches!(
+            repo.update_user(&renamed).await,
+            Err(RepoError::WriteUserLog)
+        ));
+        assert_eq!(seen(repo.find_by_id(&ada.id).await.unwrap()), as_created);
+        assert!(!repo.emails.contains_async("ada.lovelace@example.com").await);
+
+        assert!(matches!(
+            repo.delete_user(&ada.id).await,
+            Err(RepoError::WriteUserLog)
+        ));
+        assert_eq!(
+            seen(repo.find_by_email("ada@example.com").await.unwrap()),
+            as_created
+        );
 
-        users.insert(user.id.clone(), updated_user.clone());
-        Ok(updated_user)
+        // and what's in memory is what's on disk
+        drop(repo);
+        let repo = InMemoryUserRepository::open(&dir).unwrap();
+        assert_eq!(seen(repo.find_by_id(&ada.id).await.unwrap()), as_created);
+        assert!(repo.find_by_id(&grace.id).await.unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
@@ -1,9 +1,26 @@
 use async_trait::async_trait;
 
-use super::models::User;
+use super::models::{
+    ApiKey, AuthorizationCode, ClientApp, EmailChange, Group, Identity, PasswordReset,
+    ProvisionedUser, RefreshToken, ScimToken, Session, SsoConnection, User, UserList, UserQuery,
+};
+use super::outbox::{OutboxMessage, OutboxStore};
 
+pub(crate) mod cursor;
 pub mod error;
+pub mod in_mem_api_key_repo;
+pub mod in_mem_client_app_repo;
+pub mod in_mem_email_change_repo;
+pub mod in_mem_grant_repo;
+pub mod in_mem_identity_repo;
+pub mod in_mem_password_reset_repo;
+pub mod in_mem_scim_repo;
+pub mod in_mem_session_store;
+pub mod in_mem_sso_connection_repo;
 pub mod in_mem_user_repo;
+pub mod redb_user_repo;
+pub(crate) mod snapshot;
+pub mod sqlite_session_store;
 
 use error::Result;
 
@@ -12,5 +29,159 @@ pub trait UserRepositoryTrait: Send + Sync + 'static {
     async fn create_user(&self, user: User) -> Result<User>;
     async fn find_by_id(&self, id: &str) -> Result<Option<User>>;
     async fn find_by_email(&self, email: &str) -> Result<Option<User>>;
+    // saves the user if it's still at the version it was read at, otherwise fails with
+    // Conflict. Returns the saved user with its new version.
     async fn update_user(&self, user: &User) -> Result<User>;
+    async fn delete_user(&self, id: &str) -> Result<()>;
+    // users whose erasure was requested at or before `before`
+    async fn list_deleted_before(&self, before: i64) -> Result<Vec<User>>;
+    // one page of the matching users, see UserQuery for the ordering guarantees
+    async fn list_users(&self, query: &UserQuery) -> Result<UserList>;
+}
+
+// A user repository that keeps the outbox in the same store, so a change and the
+// messages about it are written in one transaction. Deleting a user deletes their
+// messages too. See AuthService::with_user_outbox.
+#[async_trait]
+pub trait UserOutboxStore: UserRepositoryTrait + OutboxStore {
+    // creates the user and writes the messages in the same transaction
+    async fn create_user_publishing(
+        &self,
+        user: User,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<User>;
+    // like update_user, writing the messages in the same transaction
+    async fn update_user_publishing(
+        &self,
+        user: &User,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<User>;
+}
+
+#[async_trait]
+pub trait ApiKeyRepositoryTrait: Send + Sync + 'static {
+    async fn create_api_key(&self, api_key: ApiKey) -> Result<ApiKey>;
+    async fn find_by_id(&self, id: &str) -> Result<Option<ApiKey>>;
+    async fn find_by_prefix(&self, prefix: &str) -> Result<Option<ApiKey>>;
+    async fn list_by_user(&self, user_id: &str) -> Result<Vec<ApiKey>>;
+    async fn update_api_key(&self, api_key: &ApiKey) -> Result<ApiKey>;
+    // records that the key was used at `now`, unless it's been revoked or deleted since it
+    // was read, which returns None. Only touches last_used_at.
+    async fn touch_last_used(&self, id: &str, now: i64) -> Result<Option<ApiKey>>;
+    // returns how many keys were deleted
+    async fn delete_by_user(&self, user_id: &str) -> Result<usize>;
+}
+
+#[async_trait]
+pub trait IdentityRepositoryTrait: Send + Sync + 'static {
+    async fn create_identity(&self, identity: Identity) -> Result<Identity>;
+    async fn find_by_provider_subject(
+        &self,
+        provider: &str,
+        subject: &str,
+    ) -> Result<Option<Identity>>;
+    async fn list_by_user(&self, user_id: &str) -> Result<Vec<Identity>>;
+    async fn delete_identity(&self, id: &str) -> Result<()>;
+    // unlinks every provider account of the user, returns how many were
+    async fn delete_by_user(&self, user_id: &str) -> Result<usize>;
+}
+
+#[async_trait]
+pub trait ClientAppRepositoryTrait: Send + Sync + 'static {
+    async fn create_client_app(&self, client_app: ClientApp) -> Result<ClientApp>;
+    async fn find_client_app(&self, id: &str) -> Result<Option<ClientApp>>;
+    async fn list_by_owner(&self, owner_id: &str) -> Result<Vec<ClientApp>>;
+    // returns how many clients were deleted
+    async fn delete_by_owner(&self, owner_id: &str) -> Result<usize>;
+}
+
+// Authorization codes, refresh tokens and revoked access tokens
+#[async_trait]
+pub trait GrantRepositoryTrait: Send + Sync + 'static {
+    async fn create_code(&self, code: AuthorizationCode) -> Result<AuthorizationCode>;
+    // codes are single use, taking a code removes it
+    async fn take_code(&self, code: &str) -> Result<Option<AuthorizationCode>>;
+    async fn create_refresh_token(&self, token: RefreshToken) -> Result<RefreshToken>;
+    async fn find_refresh_token(&self, jti: &str) -> Result<Option<RefreshToken>>;
+    async fn update_refresh_token(&self, token: &RefreshToken) -> Result<RefreshToken>;
+    async fn revoke_access_token(&self, jti: &str, expires_at: i64) -> Result<()>;
+    async fn is_access_token_revoked(&self, jti: &str) -> Result<bool>;
+    // revokes every refresh token issued for the user, returns how many were
+    async fn revoke_refresh_tokens_by_user(&self, user_id: &str, now: i64) -> Result<usize>;
+}
+
+// SAML identity providers, one per organization
+#[async_trait]
+pub trait SsoConnectionRepositoryTrait: Send + Sync + 'static {
+    // replaces an existing connection, e.g. when the IdP rotates its certificate
+    async fn save_connection(&self, connection: SsoConnection) -> Result<SsoConnection>;
+    async fn find_by_org(&self, org: &str) -> Result<Option<SsoConnection>>;
+}
+
+// What organizations' directories manage through SCIM
+#[async_trait]
+pub trait ScimRepositoryTrait: Send + Sync + 'static {
+    async fn create_token(&self, token: ScimToken) -> Result<ScimToken>;
+    async fn find_token_by_prefix(&self, prefix: &str) -> Result<Option<ScimToken>>;
+
+    // creates or replaces the org's record of the user
+    async fn save_provisioned_user(&self, user: ProvisionedUser) -> Result<ProvisionedUser>;
+    async fn find_provisioned_user(
+        &self,
+        org: &str,
+        user_id: &str,
+    ) -> Result<Option<ProvisionedUser>>;
+    // in provisioning order
+    async fn list_provisioned_users(&self, org: &str) -> Result<Vec<ProvisionedUser>>;
+    // the user's records across organizations
+    async fn list_provisioned_by_user(&self, user_id: &str) -> Result<Vec<ProvisionedUser>>;
+    async fn delete_provisioned_user(&self, org: &str, user_id: &str) -> Result<()>;
+
+    async fn create_group(&self, group: Group) -> Result<Group>;
+    async fn find_group(&self, org: &str, id: &str) -> Result<Option<Group>>;
+    // in creation order
+    async fn list_groups(&self, org: &str) -> Result<Vec<Group>>;
+    async fn update_group(&self, group: &Group) -> Result<Group>;
+    async fn delete_group(&self, org: &str, id: &str) -> Result<()>;
+    // the groups the user is a member of across organizations
+    async fn list_groups_by_member(&self, user_id: &str) -> Result<Vec<Group>>;
+}
+
+// Pending login email changes
+#[async_trait]
+pub trait EmailChangeRepositoryTrait: Send + Sync + 'static {
+    // replaces the user's pending change, if any
+    async fn save_email_change(&self, change: EmailChange) -> Result<EmailChange>;
+    async fn find_by_user(&self, user_id: &str) -> Result<Option<EmailChange>>;
+    // matches either the confirm or the cancel token
+    async fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<EmailChange>>;
+    async fn delete_email_change(&self, user_id: &str) -> Result<()>;
+}
+
+// Password resets waiting for the user to choose a new password
+#[async_trait]
+pub trait PasswordResetRepositoryTrait: Send + Sync + 'static {
+    // replaces the user's pending reset, if any
+    async fn save_password_reset(&self, reset: PasswordReset) -> Result<PasswordReset>;
+    async fn find_by_user(&self, user_id: &str) -> Result<Option<PasswordReset>>;
+    async fn find_by_token_hash(&self, token_hash: &str) -> Result<Option<PasswordReset>>;
+    async fn delete_password_reset(&self, user_id: &str) -> Result<()>;
+}
+
+// Server-side sessions, looked up by the hash of the token in the cookie
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    async fn create_session(&self, session: Session) -> Result<Session>;
+    async fn find_session(&self, id: &str) -> Result<Option<Session>>;
+    async fn update_session(&self, session: &Session) -> Result<Session>;
+    async fn delete_session(&self, id: &str) -> Result<()>;
+    // unexpired sessions, most recently seen first
+    async fn list_user_sessions(&self, user_id: &str, now: i64) -> Result<Vec<Session>>;
+    // returns how many sessions were deleted
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<usize>;
+    // deletes the user's sessions and returns them, in one go so none is lost in between
+    async fn take_user_sessions(&self, user_id: &str) -> Result<Vec<Session>>;
+    // puts taken sessions back, all of them or none
+    async fn restore_sessions(&self, sessions: Vec<Session>) -> Result<()>;
+    async fn delete_expired_sessions(&self, now: i64) -> Result<usize>;
 }
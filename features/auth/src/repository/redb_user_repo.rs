@@ -0,0 +1,737 @@
+use async_trait::async_trait;
+use redb::{Database, ReadableTable, TableDefinition, backends::InMemoryBackend};
+use std::{path::Path, sync::Arc};
+use time::OffsetDateTime;
+
+use super::cursor::UserPager;
+use super::error::Result;
+use super::{UserOutboxStore, UserRepositoryTrait, error::RepoError};
+
+use crate::models::{User, UserList, UserQuery};
+use crate::outbox::{
+    OutboxMessage, OutboxStore, error::OutboxError, error::Result as OutboxResult,
+};
+use crate::pii::{PiiKeys, StoredMessage, StoredUser, error::PiiError};
+use crate::utils::normalize_email;
+
+// Users by id, as JSON
+const USERS: TableDefinition<&str, &[u8]> = TableDefinition::new("users");
+// Normalized email, or its blind index once encrypted, to user id. A user's email is
+// only theirs once it's in here.
+const EMAILS: TableDefinition<&str, &str> = TableDefinition::new("user_emails");
+// Outbox messages by id, as JSON, written in the transaction of the change they're about
+const OUTBOX: TableDefinition<&str, &[u8]> = TableDefinition::new("outbox");
+// How many users are resealed in one transaction
+const RESEAL_BATCH: usize = 100;
+
+// What a transaction returns, errors of the store itself on the outside and the
+// operation's own outcome inside
+type Txn<T> = std::result::Result<Result<T>, StoreError>;
+
+// The store failed, callers learn which operation did unless there's a more specific error
+struct StoreError(Option<RepoError>);
+
+impl<E: Into<redb::Error>> From<E> for StoreError {
+    fn from(_: E) -> Self {
+        StoreError(None)
+    }
+}
+
+// Users in an embedded redb file, for deployments that want them to survive restarts
+// without running a database. Every change is a single transaction, redb is blocking so
+// they run on the blocking pool. The outbox is a table of its own.
+pub struct RedbUserRepository {
+    db: Arc<Database>,
+    // personal fields are sealed with these, and emails indexed blindly
+    keys: Option<Arc<PiiKeys>>,
+}
+
+impl RedbUserRepository {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(
+            Database::create(path).map_err(|_| RepoError::OpenUserStore)?,
+            None,
+        )
+    }
+
+    // Users written from now on have their email and name encrypted, the ones already
+    // there are once `reencrypt` runs
+    pub fn open_encrypted(path: impl AsRef<Path>, keys: PiiKeys) -> Result<Self> {
+        Self::init(
+            Database::create(path).map_err(|_| RepoError::OpenUserStore)?,
+            Some(Arc::new(keys)),
+        )
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(
+            Database::builder()
+                .create_with_backend(InMemoryBackend::new())
+                .map_err(|_| RepoError::OpenUserStore)?,
+            None,
+        )
+    }
+
+    fn init(db: Database, keys: Option<Arc<PiiKeys>>) -> Result<Self> {
+        // Reads fail on tables that were never written, so they're created up front. A
+        // message is released when its unit of work commits, the ones still held belong
+        // to a unit that never did, e.g. cut short by a crash, and are dropped.
+        let create_tables = || -> std::result::Result<(), StoreError> {
+            let txn = db.begin_write()?;
+            txn.open_table(USERS)?;
+            txn.open_table(EMAILS)?;
+            {
+                let mut outbox = txn.open_table(OUTBOX)?;
+                let mut held = Vec::new();
+                for entry in outbox.iter()? {
+                    let (id, record) = entry?;
+                    // sealed ones wait for the store to be opened with its keys
+                    let Ok(message) = parse_message(record.value())?.open(keys.as_deref()) else {
+                        continue;
+                    };
+                    if message.held {
+                        held.push(id.value().to_string());
+                    }
+                }
+                for id in held {
+                    outbox.remove(id.as_str())?;
+                }
+            }
+            txn.commit()?;
+            Ok(())
+        };
+        create_tables().map_err(|_| RepoError::OpenUserStore)?;
+
+        Ok(Self {
+            db: Arc::new(db),
+            keys,
+        })
+    }
+
+    // Brings every user and message to the current key after a rotation, sealing the ones
+    // stored before encryption was turned on. Returns how many were rewritten.
+    pub async fn reencrypt(&self) -> Result<usize> {
+        let keys = self.keys.clone();
+        let stale = self
+            .run(
+                || RepoError::DataReadError,
+                move |db| {
+                    let mut stale = Vec::new();
+                    let users = db.begin_read()?.open_table(USERS)?;
+                    for entry in users.iter()? {
+                        let (id, record) = entry?;
+                        if !parse(record.value())?.is_current(keys.as_deref()) {
+                            stale.push(id.value().to_string());
+                        }
+                    }
+                    Ok(Ok(stale))
+                },
+            )
+            .await?;
+
+        let mut resealed = 0;
+        for batch in stale.chunks(RESEAL_BATCH) {
+            let batch = batch.to_vec();
+            let keys = self.keys.clone();
+            resealed += self
+                .run(
+                    || RepoError::UpdateUser,
+                    move |db| {
+                        let keys = keys.as_deref();
+                        let txn = db.begin_write()?;
+                        let mut resealed = 0;
+                        {
+                            let mut users = txn.open_table(USERS)?;
+                            let mut emails = txn.open_table(EMAILS)?;
+                            for id in &batch {
+                                // deleted or resealed since it was found
+                                let stored = match users.get(id.as_str())? {
+                                    Some(record) => parse(record.value())?,
+                                    None => continue,
+                                };
+                                if stored.is_current(keys) {
+                                    continue;
+                                }
+
+                                // only the record changes, the user keeps its version
+                                let email = stored.clone().open(keys).map_err(encryption)?.email;
+                                let stored = stored.reseal(keys).map_err(encryption)?;
+                                users.insert(id.as_str(), serialize(&stored)?.as_slice())?;
+
+                                // and its email moves to where it's indexed now
+                                let indexed = email_keys(keys, &email);
+                                for old in &indexed[1..] {
+                                    emails.remove(old.as_str())?;
+                                }
+                                emails.insert(indexed[0].as_str(), id.as_str())?;
+                                resealed += 1;
+                            }
+                        }
+                        txn.commit()?;
+                        Ok(Ok(resealed))
+                    },
+                )
+                .await?;
+        }
+
+        // the messages too, their events have emails and names
+        let keys = self.keys.clone();
+        resealed += self
+            .run(
+                || RepoError::UpdateUser,
+                move |db| {
+                    let keys = keys.as_deref();
+                    let txn = db.begin_write()?;
+                    let mut resealed = 0;
+                    {
+                        let mut outbox = txn.open_table(OUTBOX)?;
+                        let mut stale = Vec::new();
+                        for entry in outbox.iter()? {
+                            let (id, record) = entry?;
+                            let stored = parse_message(record.value())?;
+                            if !stored.is_current(keys) {
+                                stale.push((id.value().to_string(), stored));
+                            }
+                        }
+                        for (id, stored) in stale {
+                            let stored = stored.reseal(keys).map_err(encryption)?;
+                            outbox.insert(id.as_str(), serialize_message(&stored)?.as_slice())?;
+                            resealed += 1;
+                        }
+                    }
+                    txn.commit()?;
+                    Ok(Ok(resealed))
+                },
+            )
+            .await?;
+        Ok(resealed)
+    }
+
+    // Rewrites the file without the space freed by earlier changes, which still holds
+    // what they replaced, e.g. records from before `reencrypt`. Returns whether there was
+    // anything to compact, the repository can't be shared while it runs.
+    pub fn compact(&mut self) -> Result<bool> {
+        let db = Arc::get_mut(&mut self.db).ok_or(RepoError::UpdateUser)?;
+        // a pass can leave freed pages behind, it's repeated until one finds none
+        let mut compacted = false;
+        while db.compact().map_err(|_| RepoError::UpdateUser)? {
+            compacted = true;
+        }
+        Ok(compacted)
+    }
+
+    async fn run<T, F>(&self, error: fn() -> RepoError, txn: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Txn<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || {
+            txn(&db).map_err(|StoreError(e)| e.unwrap_or_else(error))?
+        })
+        .await
+        .map_err(|_| error())?
+    }
+
+    // Runs a transaction on the outbox, failing with `error` when the store does
+    async fn run_outbox<T, F>(&self, error: fn(String) -> OutboxError, txn: F) -> OutboxResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database, Option<&PiiKeys>) -> std::result::Result<T, StoreError>
+            + Send
+            + 'static,
+    {
+        let db = self.db.clone();
+        let keys = self.keys.clone();
+        tokio::task::spawn_blocking(move || {
+            txn(&db, keys.as_deref())
+                .map_err(|StoreError(e)| error(e.unwrap_or(RepoError::DataReadError).to_string()))
+        })
+        .await
+        .map_err(|e| error(e.to_string()))?
+    }
+
+    // Goes through every message, oldest first
+    async fn messages(&self) -> OutboxResult<Vec<OutboxMessage>> {
+        self.run_outbox(OutboxError::Read, |db, keys| {
+            let outbox = db.begin_read()?.open_table(OUTBOX)?;
+            let mut messages = Vec::new();
+            for entry in outbox.iter()? {
+                messages.push(decode_message(entry?.1.value(), keys)?);
+            }
+            messages.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+            Ok(messages)
+        })
+        .await
+    }
+
+    async fn read_user(&self, id: String) -> Result<Option<User>> {
+        let keys = self.keys.clone();
+        self.run(
+            || RepoError::DataReadError,
+            move |db| {
+                let users = db.begin_read()?.open_table(USERS)?;
+                match users.get(id.as_str())? {
+                    Some(user) => Ok(Ok(Some(decode(user.value(), keys.as_deref())?))),
+                    None => Ok(Ok(None)),
+                }
+            },
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl UserRepositoryTrait for RedbUserRepository {
+    async fn create_user(&self, user: User) -> Result<User> {
+        self.create_user_publishing(user, Vec::new()).await
+    }
+    async fn find_by_id(&self, id: &str) -> Result<Option<User>> {
+        self.read_user(id.to_string()).await
+    }
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>> {
+        let email = email.to_string();
+        let keys = self.keys.clone();
+        self.run(
+            || RepoError::DataReadError,
+            move |db| {
+                let keys = keys.as_deref();
+                // one read transaction, the index and the users agree
+                let txn = db.begin_read()?;
+                let emails = txn.open_table(EMAILS)?;
+                for key in email_keys(keys, &email) {
+                    if let Some(id) = emails.get(key.as_str())? {
+                        return match txn.open_table(USERS)?.get(id.value())? {
+                            Some(user) => Ok(Ok(Some(decode(user.value(), keys)?))),
+                            None => Ok(Ok(None)),
+                        };
+                    }
+                }
+                Ok(Ok(None))
+            },
+        )
+        .await
+    }
+    async fn update_user(&self, user: &User) -> Result<User> {
+        self.update_user_publishing(user, Vec::new()).await
+    }
+    // deletes the messages about the user in the same transaction
+    async fn delete_user(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        let keys = self.keys.clone();
+        self.run(
+            || RepoError::DeleteUser,
+            move |db| {
+                let keys = keys.as_deref();
+                let txn = db.begin_write()?;
+                {
+                    let mut users = txn.open_table(USERS)?;
+                    let user = match users.remove(id.as_str())? {
+                        Some(user) => decode(user.value(), keys)?,
+                        None => return Ok(Err(RepoError::UserNotFound)),
+                    };
+                    let mut emails = txn.open_table(EMAILS)?;
+                    for key in email_keys(keys, &user.email) {
+                        emails.remove(key.as_str())?;
+                    }
+
+                    delete_messages_of(&txn, &id, keys)?;
+                }
+                txn.commit()?;
+                Ok(Ok(()))
+            },
+        )
+        .await
+    }
+    async fn list_deleted_before(&self, before: i64) -> Result<Vec<User>> {
+        let keys = self.keys.clone();
+        self.run(
+            || RepoError::DataReadError,
+            move |db| {
+                let mut deleted = Vec::new();
+                for_each_user(db, keys.as_deref(), |user| {
+                    if user.deleted_at.is_some_and(|at| at <= before) {
+                        deleted.push(user);
+                    }
+                })?;
+                deleted.sort_by_key(|u| u.deleted_at);
+                Ok(Ok(deleted))
+            },
+        )
+        .await
+    }
+    async fn list_users(&self, query: &UserQuery) -> Result<UserList> {
+        let query = query.clone();
+        let keys = self.keys.clone();
+        self.run(
+            || RepoError::DataReadError,
+            move |db| {
+                let mut pager = match UserPager::new(&query) {
+                    Ok(pager) => pager,
+                    Err(e) => return Ok(Err(e)),
+                };
+                for_each_user(db, keys.as_deref(), |user| pager.offer(&user))?;
+                Ok(Ok(pager.finish()))
+            },
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl UserOutboxStore for RedbUserRepository {
+    async fn create_user_publishing(
+        &self,
+        user: User,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<User> {
+        let keys = self.keys.clone();
+        self.run(
+            || RepoError::CreateUser,
+            move |db| {
+                let keys = keys.as_deref();
+                let txn = db.begin_write()?;
+                {
+                    let mut users = txn.open_table(USERS)?;
+                    let mut emails = txn.open_table(EMAILS)?;
+                    let indexed = email_keys(keys, &user.email);
+                    for key in &indexed {
+                        if emails.get(key.as_str())?.is_some() {
+                            return Ok(Err(RepoError::DuplicateEmail));
+                        }
+                    }
+                    if users.get(user.id.as_str())?.is_some() {
+                        return Ok(Err(RepoError::CreateUser));
+                    }
+
+                    users.insert(user.id.as_str(), encode(&user, keys)?.as_slice())?;
+                    emails.insert(indexed[0].as_str(), user.id.as_str())?;
+                    hold(&txn, &messages, keys)?;
+                }
+                txn.commit()?;
+                Ok(Ok(user))
+            },
+        )
+        .await
+    }
+    async fn update_user_publishing(
+        &self,
+        user: &User,
+        messages: Vec<OutboxMessage>,
+    ) -> Result<User> {
+        let user = user.clone();
+        let keys = self.keys.clone();
+        self.run(
+            || RepoError::UpdateUser,
+            move |db| {
+                let keys = keys.as_deref();
+                let txn = db.begin_write()?;
+                let updated_user = {
+                    let mut users = txn.open_table(USERS)?;
+                    let mut emails = txn.open_table(EMAILS)?;
+                    let stored = match users.get(user.id.as_str())? {
+                        Some(stored) => decode(stored.value(), keys)?,
+                        None => return Ok(Err(RepoError::UpdateUser)),
+                    };
+                    if stored.version != user.version {
+                        return Ok(Err(RepoError::Conflict));
+                    }
+
+                    let indexed = email_keys(keys, &user.email);
+                    if normalize_email(&user.email) != normalize_email(&stored.email) {
+                        for key in &indexed {
+                            if emails.get(key.as_str())?.is_some() {
+                                return Ok(Err(RepoError::DuplicateEmail));
+                            }
+                        }
+                        for old in email_keys(keys, &stored.email) {
+                            emails.remove(old.as_str())?;
+                        }
+                    } else {
+                        // a user stored before encryption was turned on is sealed by this
+                        // write, its email doesn't stay indexed in the clear
+                        for old in &indexed[1..] {
+                            emails.remove(old.as_str())?;
+                        }
+                    }
+                    emails.insert(indexed[0].as_str(), user.id.as_str())?;
+
+                    let updated_user = User {
+                        updated_at: OffsetDateTime::now_utc().unix_timestamp(),
+                        version: user.version + 1,
+                        ..user
+                    };
+                    users.insert(
+                        updated_user.id.as_str(),
+                        encode(&updated_user, keys)?.as_slice(),
+                    )?;
+                    hold(&txn, &messages, keys)?;
+                    updated_user
+                };
+                txn.commit()?;
+                Ok(Ok(updated_user))
+            },
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl OutboxStore for RedbUserRepository {
+    async fn enqueue(&self, messages: Vec<OutboxMessage>) -> OutboxResult<()> {
+        let clash = self
+            .run_outbox(OutboxError::Write, move |db, keys| {
+                let txn = db.begin_write()?;
+                {
+                    let mut outbox = txn.open_table(OUTBOX)?;
+                    for message in &messages {
+                        // a batch with a clash writes nothing
+                        if outbox.get(message.id.as_str())?.is_some() {
+                            return Ok(Some(message.id.clone()));
+                        }
+                        outbox.insert(
+                            message.id.as_str(),
+                            encode_message(message, keys)?.as_slice(),
+                        )?;
+                    }
+                }
+                txn.commit()?;
+                Ok(None)
+            })
+            .await?;
+        match clash {
+            Some(id) => Err(OutboxError::Write(format!(
+                "message {id} is already queued"
+            ))),
+            None => Ok(()),
+        }
+    }
+    async fn release(&self, ids: &[String]) -> OutboxResult<()> {
+        let ids = ids.to_vec();
+        self.run_outbox(OutboxError::Write, move |db, keys| {
+            let txn = db.begin_write()?;
+            {
+                let mut outbox = txn.open_table(OUTBOX)?;
+                for id in &ids {
+                    let message = match outbox.get(id.as_str())? {
+                        Some(record) => decode_message(record.value(), keys)?,
+                        None => continue,
+                    };
+                    let released = OutboxMessage {
+                        held: false,
+                        ..message
+                    };
+                    outbox.insert(id.as_str(), encode_message(&released, keys)?.as_slice())?;
+                }
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    async fn discard(&self, ids: &[String]) -> OutboxResult<()> {
+        let ids = ids.to_vec();
+        self.run_outbox(OutboxError::Write, move |db, _| {
+            let txn = db.begin_write()?;
+            {
+                let mut outbox = txn.open_table(OUTBOX)?;
+                for id in &ids {
+                    outbox.remove(id.as_str())?;
+                }
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    async fn due(&self, now: i64, limit: usize) -> OutboxResult<Vec<OutboxMessage>> {
+        Ok(self
+            .messages()
+            .await?
+            .into_iter()
+            .filter(|message| !message.held && !message.dead && message.next_attempt_at <= now)
+            .take(limit)
+            .collect())
+    }
+    async fn delivered(&self, id: &str) -> OutboxResult<()> {
+        self.discard(&[id.to_string()]).await
+    }
+    async fn failed(&self, message: &OutboxMessage) -> OutboxResult<()> {
+        let message = message.clone();
+        self.run_outbox(OutboxError::Write, move |db, keys| {
+            let txn = db.begin_write()?;
+            {
+                let mut outbox = txn.open_table(OUTBOX)?;
+                // delivered or deleted with its user meanwhile
+                if outbox.get(message.id.as_str())?.is_none() {
+                    return Ok(());
+                }
+                outbox.insert(
+                    message.id.as_str(),
+                    encode_message(&message, keys)?.as_slice(),
+                )?;
+            }
+            txn.commit()?;
+            Ok(())
+        })
+        .await
+    }
+    async fn dead_letters(&self) -> OutboxResult<Vec<OutboxMessage>> {
+        Ok(self
+            .messages()
+            .await?
+            .into_iter()
+            .rev()
+            .filter(|message| message.dead)
+            .collect())
+    }
+    async fn retry(&self, id: &str, now: i64) -> OutboxResult<()> {
+        let id = id.to_string();
+        let found = self
+            .run_outbox(OutboxError::Write, {
+                let id = id.clone();
+                move |db, keys| {
+                    let txn = db.begin_write()?;
+                    {
+                        let mut outbox = txn.open_table(OUTBOX)?;
+                        let message = match outbox.get(id.as_str())? {
+                            Some(record) => decode_message(record.value(), keys)?,
+                            None => return Ok(false),
+                        };
+                        if !message.dead {
+                            return Ok(false);
+                        }
+                        let retried = OutboxMessage {
+                            dead: false,
+                            attempts: 0,
+                            next_attempt_at: now,
+                            ..message
+                        };
+                        outbox.insert(id.as_str(), encode_message(&retried, keys)?.as_slice())?;
+                    }
+                    txn.commit()?;
+                    Ok(true)
+                }
+            })
+            .await?;
+        match found {
+            true => Ok(()),
+            false => Err(OutboxError::NotFound(id)),
+        }
+    }
+    async fn delete_by_user(&self, user_id: &str) -> OutboxResult<usize> {
+        let user_id = user_id.to_string();
+        self.run_outbox(OutboxError::Write, move |db, keys| {
+            let txn = db.begin_write()?;
+            let deleted = delete_messages_of(&txn, &user_id, keys)?;
+            txn.commit()?;
+            Ok(deleted)
+        })
+        .await
+    }
+}
+
+// Writes the messages of a change in its transaction, held until it's committed
+fn hold(
+    txn: &redb::WriteTransaction,
+    messages: &[OutboxMessage],
+    keys: Option<&PiiKeys>,
+) -> std::result::Result<(), StoreError> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let mut outbox = txn.open_table(OUTBOX)?;
+    for message in messages {
+        let held = OutboxMessage {
+            held: true,
+            ..message.clone()
+        };
+        outbox.insert(held.id.as_str(), encode_message(&held, keys)?.as_slice())?;
+    }
+    Ok(())
+}
+
+// Deletes the messages about the user, returns how many there were
+fn delete_messages_of(
+    txn: &redb::WriteTransaction,
+    user_id: &str,
+    keys: Option<&PiiKeys>,
+) -> std::result::Result<usize, StoreError> {
+    let mut outbox = txn.open_table(OUTBOX)?;
+    let mut theirs = Vec::new();
+    for entry in outbox.iter()? {
+        let (id, record) = entry?;
+        if decode_message(record.value(), keys)?.event.user_id() == user_id {
+            theirs.push(id.value().to_string());
+        }
+    }
+    for id in &theirs {
+        outbox.remove(id.as_str())?;
+    }
+    Ok(theirs.len())
+}
+
+// Goes through every user, for the listings that have to look at all of them
+fn for_each_user(
+    db: &Database,
+    keys: Option<&PiiKeys>,
+    mut visit: impl FnMut(User),
+) -> std::result::Result<(), StoreError> {
+    let users = db.begin_read()?.open_table(USERS)?;
+    for entry in users.iter()? {
+        visit(decode(entry?.1.value(), keys)?);
+    }
+    Ok(())
+}
+
+// Where an email is indexed, first where it's indexed now and then, when encrypted, where
+// a user stored before encryption was turned on has it
+fn email_keys(keys: Option<&PiiKeys>, email: &str) -> Vec<String> {
+    match keys {
+        Some(keys) => vec![keys.email_index(email), normalize_email(email)],
+        None => vec![normalize_email(email)],
+    }
+}
+
+fn encode(user: &User, keys: Option<&PiiKeys>) -> std::result::Result<Vec<u8>, StoreError> {
+    serialize(&StoredUser::new(user, keys).map_err(encryption)?)
+}
+
+fn decode(bytes: &[u8], keys: Option<&PiiKeys>) -> std::result::Result<User, StoreError> {
+    parse(bytes)?.open(keys).map_err(encryption)
+}
+
+fn serialize(stored: &StoredUser) -> std::result::Result<Vec<u8>, StoreError> {
+    serde_json::to_vec(stored).map_err(|_| StoreError(None))
+}
+
+// A record that doesn't parse is as good as corrupted
+fn parse(bytes: &[u8]) -> std::result::Result<StoredUser, StoreError> {
+    serde_json::from_slice(bytes).map_err(|_| StoreError(None))
+}
+
+fn encode_message(
+    message: &OutboxMessage,
+    keys: Option<&PiiKeys>,
+) -> std::result::Result<Vec<u8>, StoreError> {
+    serialize_message(&StoredMessage::new(message, keys).map_err(encryption)?)
+}
+
+fn decode_message(
+    bytes: &[u8],
+    keys: Option<&PiiKeys>,
+) -> std::result::Result<OutboxMessage, StoreError> {
+    parse_message(bytes)?.open(keys).map_err(encryption)
+}
+
+fn serialize_message(stored: &StoredMessage) -> std::result::Result<Vec<u8>, StoreError> {
+    serde_json::to_vec(stored).map_err(|_| StoreError(None))
+}
+
+fn parse_message(bytes: &[u8]) -> std::result::Result<StoredMessage, StoreError> {
+    serde_json::from_slice(bytes).map_err(|_| StoreError(None))
+}
+
+fn encryption(_: PiiError) -> StoreError {
+    StoreError(Some(RepoError::Encryption))
+}
@@ -0,0 +1,645 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard, RwLock, RwLockReadGuard};
+
+use super::error::{RepoError, Result};
+use crate::models::User;
+use crate::outbox::OutboxMessage;
+use crate::pii::{PiiKeys, StoredMessage, StoredUser};
+
+const SNAPSHOT_FILE: &str = "users.snapshot";
+const OUTBOX_SNAPSHOT_FILE: &str = "outbox.snapshot";
+const LOG_FILE: &str = "users.wal";
+// The first line of each file, a new format gets a new version
+const SNAPSHOT_HEADER: &str = "users-snapshot v1";
+const OUTBOX_SNAPSHOT_HEADER: &str = "outbox-snapshot v1";
+const LOG_HEADER: &str = "users-wal v1";
+
+// A change to a user, logged once it's been made in memory and taken back if it can't be.
+// Changes to messages that are awkward to take back, e.g. a release, are logged before
+// they're made instead.
+#[derive(Debug)]
+pub(crate) enum LogRecord {
+    // the user with the messages written along with it
+    Put {
+        user: Box<User>,
+        messages: Vec<OutboxMessage>,
+    },
+    // the user and their messages
+    Delete {
+        id: String,
+    },
+    // messages saved as they are now, e.g. released or failed
+    Messages {
+        messages: Vec<OutboxMessage>,
+    },
+    // messages delivered or discarded
+    Discard {
+        ids: Vec<String>,
+    },
+}
+
+// A change as it's written to the log, the user and messages sealed when there are keys
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum LogLine {
+    Put {
+        user: StoredUser,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        messages: Vec<StoredMessage>,
+    },
+    Delete {
+        id: String,
+    },
+    Messages {
+        messages: Vec<StoredMessage>,
+    },
+    Discard {
+        ids: Vec<String>,
+    },
+}
+
+// Users kept in a directory, as a snapshot of every user and a log of the changes made
+// since it was taken. Replaying keeps the highest version of each user and never brings
+// a deleted one back, so it doesn't matter in which order concurrent changes were logged.
+// The outbox is kept alongside, with a snapshot of its own.
+pub(crate) struct UserFiles {
+    dir: PathBuf,
+    // personal fields are written sealed with these
+    keys: Option<Arc<PiiKeys>>,
+    log: Arc<Mutex<File>>,
+    // held shared while a change is made and logged, and exclusively by a snapshot to
+    // find a point in the log that every change made so far has reached
+    gate: RwLock<()>,
+    // one snapshot at a time, each cuts the log where it found it
+    snapshotting: AsyncMutex<()>,
+}
+
+impl UserFiles {
+    // Opens the directory, returning the users and outbox messages it holds. A message is
+    // logged again once released, that's its unit of work's commit. The ones still held
+    // belong to a unit that never committed, e.g. cut short by a crash, they're dropped.
+    pub(crate) fn open(
+        dir: impl AsRef<Path>,
+        keys: Option<PiiKeys>,
+    ) -> Result<(Self, Vec<User>, Vec<OutboxMessage>)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|_| RepoError::LoadUsers)?;
+        let keys = keys.map(Arc::new);
+        let open = |stored: StoredUser| {
+            stored
+                .open(keys.as_deref())
+                .map_err(|_| RepoError::Encryption)
+        };
+        let open_messages = |stored: Vec<StoredMessage>| {
+            stored
+                .into_iter()
+                .map(|message| message.open(keys.as_deref()))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|_| RepoError::Encryption)
+        };
+
+        let mut replay = Replay::default();
+        if let Some(records) = read_records(&dir.join(SNAPSHOT_FILE), SNAPSHOT_HEADER)? {
+            for line in records.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+                replay.put(open(
+                    serde_json::from_slice(line).map_err(|_| RepoError::LoadUsers)?,
+                )?);
+            }
+        }
+        if let Some(records) =
+            read_records(&dir.join(OUTBOX_SNAPSHOT_FILE), OUTBOX_SNAPSHOT_HEADER)?
+        {
+            for line in records.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+                let stored = serde_json::from_slice(line).map_err(|_| RepoError::LoadUsers)?;
+                replay.save_messages(open_messages(vec![stored])?);
+            }
+        }
+
+        // where the valid records of the log end, None when it has to be started afresh
+        let mut log_end = None;
+        if let Some(records) = read_records(&dir.join(LOG_FILE), LOG_HEADER)? {
+            let mut end = 0;
+            let mut lines = records.split_inclusive(|b| *b == b'\n').peekable();
+            while let Some(line) = lines.next() {
+                match serde_json::from_slice::<LogLine>(line) {
+                    Ok(_) if !line.ends_with(b"\n") => break,
+                    Ok(LogLine::Put { user, messages }) => {
+                        replay.put(open(user)?);
+                        replay.save_messages(open_messages(messages)?);
+                    }
+                    Ok(LogLine::Delete { id }) => replay.delete(id),
+                    Ok(LogLine::Messages { messages }) => {
+                        replay.save_messages(open_messages(messages)?)
+                    }
+                    Ok(LogLine::Discard { ids }) => replay.discard(&ids),
+                    // a crash mid-append tears the last record, its change was never
+                    // acknowledged so it's dropped
+                    Err(_) if lines.peek().is_none() => break,
+                    Err(_) => return Err(RepoError::LoadUsers),
+                }
+                end += line.len();
+            }
+            log_end = Some((LOG_HEADER.len() + 1 + end) as u64);
+        }
+
+        let log = open_log(&dir.join(LOG_FILE), log_end).map_err(|_| RepoError::LoadUsers)?;
+        let files = Self {
+            dir,
+            keys,
+            log: Arc::new(Mutex::new(log)),
+            gate: RwLock::new(()),
+            snapshotting: AsyncMutex::new(()),
+        };
+        let messages = replay.messages();
+        Ok((files, replay.users.into_values().collect(), messages))
+    }
+
+    pub(crate) async fn writing(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read().await
+    }
+
+    pub(crate) async fn append(&self, record: &LogRecord) -> Result<()> {
+        let keys = self.keys.as_deref();
+        let store_messages = |messages: &[OutboxMessage]| {
+            messages
+                .iter()
+                .map(|message| StoredMessage::new(message, keys))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|_| RepoError::Encryption)
+        };
+        let line = match record {
+            LogRecord::Put { user, messages } => LogLine::Put {
+                user: StoredUser::new(user, keys).map_err(|_| RepoError::Encryption)?,
+                messages: store_messages(messages)?,
+            },
+            LogRecord::Delete { id } => LogLine::Delete { id: id.clone() },
+            LogRecord::Messages { messages } => LogLine::Messages {
+                messages: store_messages(messages)?,
+            },
+            LogRecord::Discard { ids } => LogLine::Discard { ids: ids.clone() },
+        };
+        let mut line = serde_json::to_vec(&line).map_err(|_| RepoError::WriteUserLog)?;
+        line.push(b'\n');
+
+        let log = self.log.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut log = log.lock().map_err(|_| RepoError::WriteUserLog)?;
+            let end = log.metadata().map_err(|_| RepoError::WriteUserLog)?.len();
+            // a single write so concurrent records don't interleave, a failed one is cut
+            // off so the next record doesn't land after half of it
+            if log.write_all(&line).and_then(|_| log.sync_data()).is_err() {
+                let _ = log.set_len(end);
+                return Err(RepoError::WriteUserLog);
+            }
+            Ok(())
+        })
+        .await
+        .map_err(|_| RepoError::WriteUserLog)?
+    }
+
+    // every append from here on fails, like on a full disk
+    #[cfg(test)]
+    pub(crate) fn fail_appends(&self) {
+        let read_only = File::open(self.dir.join(LOG_FILE)).unwrap();
+        *self.log.lock().unwrap() = read_only;
+    }
+
+    pub(crate) async fn snapshotting(&self) -> MutexGuard<'_, ()> {
+        self.snapshotting.lock().await
+    }
+
+    // Where the log stands once the changes being made have been logged, None when
+    // nothing was logged since the last snapshot
+    pub(crate) async fn changed_since_snapshot(&self) -> Result<Option<u64>> {
+        let end = self.logged().await?;
+        Ok(Some(end).filter(|end| *end > (LOG_HEADER.len() + 1) as u64))
+    }
+
+    // Where the log stands once the changes being made have been logged
+    pub(crate) async fn logged(&self) -> Result<u64> {
+        let _gate = self.gate.write().await;
+
+        let log = self.log.clone();
+        tokio::task::spawn_blocking(move || {
+            let log = log.lock().map_err(|_| RepoError::WriteSnapshot)?;
+            log.metadata()
+                .map(|m| m.len())
+                .map_err(|_| RepoError::WriteSnapshot)
+        })
+        .await
+        .map_err(|_| RepoError::WriteSnapshot)?
+    }
+
+    // Replaces the snapshots with the users and messages, then drops the log up to
+    // `logged`, every change up to there being in them
+    pub(crate) async fn write_snapshot(
+        &self,
+        users: Vec<User>,
+        messages: Vec<OutboxMessage>,
+        logged: u64,
+    ) -> Result<()> {
+        let dir = self.dir.clone();
+        let log = self.log.clone();
+        let keys = self.keys.clone();
+
+        tokio::task::spawn_blocking(move || {
+            // every user is sealed afresh, with the current key
+            let mut stored = Vec::with_capacity(users.len());
+            for user in &users {
+                stored.push(
+                    StoredUser::new(user, keys.as_deref()).map_err(|_| RepoError::Encryption)?,
+                );
+            }
+            let mut stored_messages = Vec::with_capacity(messages.len());
+            for message in &messages {
+                stored_messages.push(
+                    StoredMessage::new(message, keys.as_deref())
+                        .map_err(|_| RepoError::Encryption)?,
+                );
+            }
+            replace(&dir, SNAPSHOT_FILE, |file| {
+                writeln!(file, "{SNAPSHOT_HEADER}")?;
+                for user in &stored {
+                    serde_json::to_writer(&mut *file, user)?;
+                    writeln!(file)?;
+                }
+                Ok(())
+            })
+            .map_err(|_| RepoError::WriteSnapshot)?;
+            replace(&dir, OUTBOX_SNAPSHOT_FILE, |file| {
+                writeln!(file, "{OUTBOX_SNAPSHOT_HEADER}")?;
+                for message in &stored_messages {
+                    serde_json::to_writer(&mut *file, message)?;
+                    writeln!(file)?;
+                }
+                Ok(())
+            })
+            .map_err(|_| RepoError::WriteSnapshot)?;
+
+            // appends wait while the rest of the log is moved to a new one
+            let mut log = log.lock().map_err(|_| RepoError::WriteSnapshot)?;
+            let mut rest = Vec::new();
+            let mut current =
+                File::open(dir.join(LOG_FILE)).map_err(|_| RepoError::WriteSnapshot)?;
+            current
+                .seek(SeekFrom::Start(logged))
+                .and_then(|_| current.read_to_end(&mut rest))
+                .map_err(|_| RepoError::WriteSnapshot)?;
+            replace(&dir, LOG_FILE, |file| {
+                writeln!(file, "{LOG_HEADER}")?;
+                file.write_all(&rest)
+            })
+            .map_err(|_| RepoError::WriteSnapshot)?;
+            *log = OpenOptions::new()
+                .append(true)
+                .open(dir.join(LOG_FILE))
+                .map_err(|_| RepoError::WriteSnapshot)?;
+            Ok(())
+        })
+        .await
+        .map_err(|_| RepoError::WriteSnapshot)?
+    }
+}
+
+#[derive(Default)]
+struct Replay {
+    users: HashMap<String, User>,
+    deleted: HashSet<String>,
+    messages: HashMap<String, OutboxMessage>,
+}
+
+impl Replay {
+    fn put(&mut self, user: User) {
+        if self.deleted.contains(&user.id)
+            || self
+                .users
+                .get(&user.id)
+                .is_some_and(|known| known.version >= user.version)
+        {
+            return;
+        }
+        self.users.insert(user.id.clone(), user);
+    }
+
+    fn delete(&mut self, id: String) {
+        self.users.remove(&id);
+        self.messages
+            .retain(|_, message| message.event.user_id() != id);
+        self.deleted.insert(id);
+    }
+
+    // the messages of a deleted user went with them
+    fn save_messages(&mut self, messages: Vec<OutboxMessage>) {
+        for message in messages {
+            if !self.deleted.contains(message.event.user_id()) {
+                self.messages.insert(message.id.clone(), message);
+            }
+        }
+    }
+
+    fn discard(&mut self, ids: &[String]) {
+        for id in ids {
+            self.messages.remove(id);
+        }
+    }
+
+    // the released ones, oldest first
+    fn messages(&mut self) -> Vec<OutboxMessage> {
+        let mut messages: Vec<OutboxMessage> = self
+            .messages
+            .drain()
+            .map(|(_, message)| message)
+            .filter(|message| !message.held)
+            .collect();
+        messages.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        messages
+    }
+}
+
+// The file's records after its header line, None when there's no file yet. A file cut
+// off before the end of its header was never written to.
+fn read_records(path: &Path, header: &str) -> Result<Option<Vec<u8>>> {
+    let mut contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(_) => return Err(RepoError::LoadUsers),
+    };
+
+    match contents.iter().position(|b| *b == b'\n') {
+        Some(end) if &contents[..end] == header.as_bytes() => Ok(Some(contents.split_off(end + 1))),
+        None if header.as_bytes().starts_with(&contents) => Ok(None),
+        _ => Err(RepoError::UnsupportedFormat),
+    }
+}
+
+// Opens the log for appending, cut back to `end` or started afresh
+fn open_log(path: &Path, end: Option<u64>) -> io::Result<File> {
+    let mut log = private_options().append(true).open(path)?;
+    match end {
+        Some(end) => log.set_len(end)?,
+        None => {
+            log.set_len(0)?;
+            writeln!(log, "{LOG_HEADER}")?;
+        }
+    }
+    log.sync_all()?;
+    Ok(log)
+}
+
+// Writes the file next to its destination and renames it into place, a crash leaves
+// either the old file or the new one
+fn replace(
+    dir: &Path,
+    name: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let tmp = dir.join(format!("{name}.tmp"));
+    let mut file = BufWriter::new(private_options().write(true).truncate(true).open(&tmp)?);
+    write(&mut file)?;
+    file.into_inner()?.sync_all()?;
+
+    fs::rename(&tmp, dir.join(name))?;
+    // the rename only survives a crash once the directory is synced
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+// The files hold password hashes, only the owner may read them
+fn private_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::in_mem_user_repo::InMemoryUserRepository;
+    use crate::repository::{UserOutboxStore, UserRepositoryTrait};
+
+    fn user(email: &str) -> User {
+        User::new(email.to_string(), String::new(), email.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_user_files() {
+        let dir = std::env::temp_dir().join(format!("users-{}", uuid::Uuid::new_v4()));
+
+        // Changes are logged and replayed
+        let repo = InMemoryUserRepository::open(&dir).unwrap();
+        let ada = repo.create_user(user("ada@example.com")).await.unwrap();
+        let grace = repo.create_user(user("grace@example.com")).await.unwrap();
+        let ada = repo
+            .update_user(&User {
+                name: "Ada Lovelace".to_string(),
+                ..ada
+            })
+            .await
+            .unwrap();
+        repo.delete_user(&grace.id).await.unwrap();
+        drop(repo);
+
+        let repo = InMemoryUserRepository::open(&dir).unwrap();
+        let reloaded = repo
+            .find_by_email("ada@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (reloaded.name.as_str(), reloaded.version),
+            ("Ada Lovelace", 1)
+        );
+        assert!(repo.find_by_id(&grace.id).await.unwrap().is_none());
+
+        // A snapshot takes the log's place, later changes are logged after it
+        assert!(repo.snapshot().await.unwrap());
+        assert!(!repo.snapshot().await.unwrap());
+        let log = fs::read_to_string(dir.join(LOG_FILE)).unwrap();
+        assert_eq!(log, format!("{LOG_HEADER}\n"));
+        let alan = repo.create_user(user("alan@example.com")).await.unwrap();
+        drop(repo);
+
+        // a record torn by a crash is dropped, and doesn't get in the way of later ones
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(dir.join(LOG_FILE))
+            .unwrap();
+        log.write_all(br#"{"op":"delete","id":"#).unwrap();
+        drop(log);
+        let repo = InMemoryUserRepository::open(&dir).unwrap();
+        assert!(repo.find_by_id(&ada.id).await.unwrap().is_some());
+        assert!(repo.find_by_id(&alan.id).await.unwrap().is_some());
+        let edsger = repo.create_user(user("edsger@example.com")).await.unwrap();
+        drop(repo);
+        let repo = InMemoryUserRepository::open(&dir).unwrap();
+        assert!(repo.find_by_id(&edsger.id).await.unwrap().is_some());
+        drop(repo);
+
+        // Files from another version aren't guessed at
+        fs::write(dir.join(SNAPSHOT_FILE), "users-snapshot v2\n").unwrap();
+        assert!(matches!(
+            InMemoryUserRepository::open(&dir),
+            Err(RepoError::UnsupportedFormat)
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_user_files() {
+        let dir = std::env::temp_dir().join(format!("users-{}", uuid::Uuid::new_v4()));
+        let keys = |current: &str| {
+            let key = |id: &str| [id.len() as u8; 32];
+            PiiKeys::new(current, &key(current), &[9; 32])
+                .unwrap()
+                .with_previous_key("2024", &key("2024"))
+                .unwrap()
+        };
+        let on_disk = || {
+            fs::read_to_string(dir.join(SNAPSHOT_FILE)).unwrap_or_default()
+                + &fs::read_to_string(dir.join(LOG_FILE)).unwrap()
+        };
+
+        // Users written before encryption was turned on are sealed by the next snapshot
+        let repo = InMemoryUserRepository::open(&dir).unwrap();
+        let ada = repo.create_user(user("ada@example.com")).await.unwrap();
+        drop(repo);
+        assert!(on_disk().contains("ada@example.com"));
+        let repo = InMemoryUserRepository::open_encrypted(&dir, keys("2024")).unwrap();
+        let grace = repo.create_user(user("grace@example.com")).await.unwrap();
+        assert!(!on_disk().contains("grace@example.com"));
+        assert!(repo.reencrypt().await.unwrap());
+        assert!(!on_disk().contains("ada@example.com"));
+        drop(repo);
+
+        // after a rotation they're opened with the previous key until resealed
+        let repo = InMemoryUserRepository::open_encrypted(&dir, keys("2025-01")).unwrap();
+        assert!(
+            repo.find_by_email("ada@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(repo.reencrypt().await.unwrap());
+        assert!(!on_disk().contains(r#""key_id":"2024""#));
+        drop(repo);
+
+        // and can't be opened at all without keys
+        assert!(matches!(
+            InMemoryUserRepository::open(&dir),
+            Err(RepoError::Encryption)
+        ));
+        let repo = InMemoryUserRepository::open_encrypted(&dir, keys("2025-01")).unwrap();
+        let reloaded = repo.find_by_id(&grace.id).await.unwrap().unwrap();
+        assert_eq!(reloaded.name, grace.name);
+        assert_eq!(
+            repo.find_by_id(&ada.id).await.unwrap().unwrap().email,
+            ada.email
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_outbox_files() {
+        use crate::outbox::{DomainEvent, OutboxStore};
+
+        let dir = std::env::temp_dir().join(format!("users-{}", uuid::Uuid::new_v4()));
+        let registered = |user: &User| {
+            OutboxMessage::new(DomainEvent::UserRegistered {
+                user_id: user.id.clone(),
+                email: user.email.clone(),
+                name: user.name.clone(),
+            })
+        };
+        let keys = || PiiKeys::new("2024", &[1; 32], &[2; 32]).unwrap();
+
+        // Messages are logged with their change and again once released, a held one was
+        // never committed and is dropped once the files are opened again
+        let repo = InMemoryUserRepository::open_encrypted(&dir, keys()).unwrap();
+        let ada = user("ada@example.com");
+        let ada_registered = registered(&ada);
+        let ada = repo
+            .create_user_publishing(ada, vec![ada_registered.clone()])
+            .await
+            .unwrap();
+        let grace = user("grace@example.com");
+        let grace_registered = registered(&grace);
+        let grace = repo
+            .create_user_publishing(grace, vec![grace_registered.clone()])
+            .await
+            .unwrap();
+        repo.release(std::slice::from_ref(&grace_registered.id))
+            .await
+            .unwrap();
+        let alan = user("alan@example.com");
+        let alan_registered = registered(&alan);
+        repo.create_user_publishing(alan, vec![alan_registered.clone()])
+            .await
+            .unwrap();
+        repo.discard(std::slice::from_ref(&alan_registered.id))
+            .await
+            .unwrap();
+        drop(repo);
+        let log = fs::read_to_string(dir.join(LOG_FILE)).unwrap();
+        assert!(!log.contains("ada@example.com"));
+
+        let repo = InMemoryUserRepository::open_encrypted(&dir, keys()).unwrap();
+        let due = repo.due(i64::MAX, 10).await.unwrap();
+        let ids: Vec<&str> = due.iter().map(|message| message.id.as_str()).collect();
+        assert_eq!(ids, vec![grace_registered.id.as_str()]);
+        assert!(repo.find_by_id(&ada.id).await.unwrap().is_some());
+
+        // A snapshot carries them, and a deleted user's go with them
+        assert!(repo.snapshot().await.unwrap());
+        assert!(
+            !fs::read_to_string(dir.join(OUTBOX_SNAPSHOT_FILE))
+                .unwrap()
+                .contains("grace@example.com")
+        );
+        repo.delete_user(&grace.id).await.unwrap();
+        drop(repo);
+        let repo = InMemoryUserRepository::open_encrypted(&dir, keys()).unwrap();
+        assert!(repo.due(i64::MAX, 10).await.unwrap().is_empty());
+        assert!(repo.find_by_id(&ada.id).await.unwrap().is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_order() {
+        let mut ada = user("ada@example.com");
+        let mut replay = Replay::default();
+
+        // a later version logged first wins over the earlier one
+        ada.version = 2;
+        replay.put(ada.clone());
+        ada.version = 1;
+        replay.put(ada.clone());
+        assert_eq!(replay.users[&ada.id].version, 2);
+
+        // and a deleted user stays deleted
+        replay.delete(ada.id.clone());
+        ada.version = 3;
+        replay.put(ada.clone());
+        assert!(replay.users.is_empty());
+
+        // with their messages, whenever they were logged
+        let registered = OutboxMessage::new(crate::outbox::DomainEvent::UserRegistered {
+            user_id: ada.id.clone(),
+            email: ada.email.clone(),
+            name: ada.name.clone(),
+        });
+        replay.save_messages(vec![registered]);
+        assert!(replay.messages().is_empty());
+    }
+}
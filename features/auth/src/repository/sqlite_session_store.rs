@@ -0,0 +1,339 @@
+use async_trait::async_trait;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::error::Result;
+use super::{SessionStore, error::RepoError};
+
+use crate::models::Session;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        last_seen_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL,
+        ip TEXT,
+        user_agent TEXT,
+        data TEXT NOT NULL,
+        impersonator_id TEXT
+    );
+    CREATE INDEX IF NOT EXISTS sessions_user_id ON sessions (user_id);
+    CREATE INDEX IF NOT EXISTS sessions_expires_at ON sessions (expires_at);
+";
+
+// Sessions that survive a restart. rusqlite is blocking, queries run on the blocking pool.
+pub struct SqliteSessionStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteSessionStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(Connection::open(path).map_err(|_| RepoError::OpenSessionStore)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory().map_err(|_| RepoError::OpenSessionStore)?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .map_err(|_| RepoError::OpenSessionStore)?;
+        // stores created before impersonation lack the column
+        if conn
+            .prepare("SELECT impersonator_id FROM sessions LIMIT 0")
+            .is_err()
+        {
+            conn.execute("ALTER TABLE sessions ADD COLUMN impersonator_id TEXT", [])
+                .map_err(|_| RepoError::OpenSessionStore)?;
+        }
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    async fn run<T, F>(&self, error: fn() -> RepoError, query: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|_| error())?;
+            query(&mut conn).map_err(|_| error())
+        })
+        .await
+        .map_err(|_| error())?
+    }
+}
+
+const COLUMNS: &str =
+    "id, user_id, created_at, last_seen_at, expires_at, ip, user_agent, data, impersonator_id";
+
+fn session_from_row(row: &Row) -> rusqlite::Result<Session> {
+    let data: String = row.get(7)?;
+
+    Ok(Session {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        created_at: row.get(2)?,
+        last_seen_at: row.get(3)?,
+        expires_at: row.get(4)?,
+        ip: row.get(5)?,
+        user_agent: row.get(6)?,
+        data: serde_json::from_str(&data).unwrap_or_default(),
+        impersonator_id: row.get(8)?,
+    })
+}
+
+fn data_to_json(data: &HashMap<String, String>) -> String {
+    serde_json::to_string(data).unwrap_or_else(|_| "{}".to_string())
+}
+
+#[async_trait]
+impl SessionStore for SqliteSessionStore {
+    async fn create_session(&self, session: Session) -> Result<Session> {
+        let created = session.clone();
+        self.run(
+            || RepoError::CreateSession,
+            move |conn| {
+                conn.execute(
+                    &format!(
+                        "INSERT INTO sessions ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
+                    ),
+                    params![
+                        session.id,
+                        session.user_id,
+                        session.created_at,
+                        session.last_seen_at,
+                        session.expires_at,
+                        session.ip,
+                        session.user_agent,
+                        data_to_json(&session.data),
+                        session.impersonator_id,
+                    ],
+                )
+            },
+        )
+        .await?;
+
+        Ok(created)
+    }
+
+    async fn find_session(&self, id: &str) -> Result<Option<Session>> {
+        let id = id.to_string();
+        self.run(
+            || RepoError::DataReadError,
+            move |conn| {
+                conn.query_row(
+                    &format!("SELECT {COLUMNS} FROM sessions WHERE id = ?1"),
+                    params![id],
+                    session_from_row,
+                )
+                .optional()
+            },
+        )
+        .await
+    }
+
+    async fn update_session(&self, session: &Session) -> Result<Session> {
+        let updated = session.clone();
+        let session = session.clone();
+        let changed = self
+            .run(
+                || RepoError::UpdateSession,
+                move |conn| {
+                    conn.execute(
+                        "UPDATE sessions SET last_seen_at = ?2, expires_at = ?3, data = ?4
+                         WHERE id = ?1",
+                        params![
+                            session.id,
+                            session.last_seen_at,
+                            session.expires_at,
+                            data_to_json(&session.data),
+                        ],
+                    )
+                },
+            )
+            .await?;
+
+        match changed {
+            0 => Err(RepoError::UpdateSession),
+            _ => Ok(updated),
+        }
+    }
+
+    async fn delete_session(&self, id: &str) -> Result<()> {
+        let id = id.to_string();
+        self.run(
+            || RepoError::DeleteSession,
+            move |conn| conn.execute("DELETE FROM sessions WHERE id = ?1", params![id]),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_user_sessions(&self, user_id: &str, now: i64) -> Result<Vec<Session>> {
+        let user_id = user_id.to_string();
+        self.run(
+            || RepoError::DataReadError,
+            move |conn| {
+                conn.prepare(&format!(
+                    "SELECT {COLUMNS} FROM sessions WHERE user_id = ?1 AND expires_at > ?2
+                     ORDER BY last_seen_at DESC"
+                ))?
+                .query_map(params![user_id, now], session_from_row)?
+                .collect()
+            },
+        )
+        .await
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str) -> Result<usize> {
+        let user_id = user_id.to_string();
+        self.run(
+            || RepoError::DeleteSession,
+            move |conn| conn.execute("DELETE FROM sessions WHERE user_id = ?1", params![user_id]),
+        )
+        .await
+    }
+
+    async fn take_user_sessions(&self, user_id: &str) -> Result<Vec<Session>> {
+        let user_id = user_id.to_string();
+        self.run(
+            || RepoError::DeleteSession,
+            move |conn| {
+                conn.prepare(&format!(
+                    "DELETE FROM sessions WHERE user_id = ?1 RETURNING {COLUMNS}"
+                ))?
+                .query_map(params![user_id], session_from_row)?
+                .collect()
+            },
+        )
+        .await
+    }
+
+    // in one transaction, either every session is put back or none is
+    async fn restore_sessions(&self, sessions: Vec<Session>) -> Result<()> {
+        self.run(
+            || RepoError::CreateSession,
+            move |conn| {
+                let tx = conn.transaction()?;
+                for session in sessions {
+                    tx.execute(
+                        &format!(
+                            "INSERT INTO sessions ({COLUMNS}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)"
+                        ),
+                        params![
+                            session.id,
+                            session.user_id,
+                            session.created_at,
+                            session.last_seen_at,
+                            session.expires_at,
+                            session.ip,
+                            session.user_agent,
+                            data_to_json(&session.data),
+                            session.impersonator_id,
+                        ],
+                    )?;
+                }
+                tx.commit()
+            },
+        )
+        .await
+    }
+
+    async fn delete_expired_sessions(&self, now: i64) -> Result<usize> {
+        self.run(
+            || RepoError::DeleteSession,
+            move |conn| conn.execute("DELETE FROM sessions WHERE expires_at <= ?1", params![now]),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: &str, user_id: &str, expires_at: i64) -> Session {
+        Session {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            created_at: 100,
+            last_seen_at: 100,
+            expires_at,
+            ip: Some("127.0.0.1".to_string()),
+            user_agent: None,
+            data: HashMap::new(),
+            impersonator_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_session_store() {
+        let store = SqliteSessionStore::open_in_memory().unwrap();
+
+        store.create_session(session("a", "u1", 200)).await.unwrap();
+        store.create_session(session("b", "u1", 300)).await.unwrap();
+        store
+            .create_session(Session {
+                impersonator_id: Some("admin".to_string()),
+                ..session("c", "u2", 300)
+            })
+            .await
+            .unwrap();
+        assert!(store.create_session(session("a", "u2", 300)).await.is_err());
+
+        let mut found = store.find_session("a").await.unwrap().unwrap();
+        assert_eq!(found.user_id, "u1");
+        found.last_seen_at = 150;
+        found.data.insert("theme".to_string(), "dark".to_string());
+        store.update_session(&found).await.unwrap();
+
+        let found = store.find_session("a").await.unwrap().unwrap();
+        assert_eq!(found.last_seen_at, 150);
+        assert_eq!(found.data["theme"], "dark");
+        assert!(found.impersonator_id.is_none());
+        let found = store.find_session("c").await.unwrap().unwrap();
+        assert_eq!(found.impersonator_id.as_deref(), Some("admin"));
+        assert!(store.update_session(&session("x", "u1", 1)).await.is_err());
+
+        let listed = store.list_user_sessions("u1", 100).await.unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].id, "a");
+        assert_eq!(listed[0].ip.as_deref(), Some("127.0.0.1"));
+
+        assert_eq!(store.delete_expired_sessions(200).await.unwrap(), 1);
+        assert!(store.find_session("a").await.unwrap().is_none());
+        assert_eq!(store.delete_user_sessions("u1").await.unwrap(), 1);
+        store.delete_session("c").await.unwrap();
+        assert!(store.find_session("c").await.unwrap().is_none());
+
+        // Taken sessions can be put back, but not over ones that exist
+        store.create_session(session("d", "u3", 300)).await.unwrap();
+        store.create_session(session("e", "u3", 300)).await.unwrap();
+        let taken = store.take_user_sessions("u3").await.unwrap();
+        assert_eq!(taken.len(), 2);
+        assert!(
+            store
+                .list_user_sessions("u3", 100)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        store.create_session(session("e", "u4", 300)).await.unwrap();
+        assert!(store.restore_sessions(taken.clone()).await.is_err());
+        assert!(store.find_session("d").await.unwrap().is_none());
+        store.delete_session("e").await.unwrap();
+        store.restore_sessions(taken).await.unwrap();
+        assert_eq!(store.list_user_sessions("u3", 100).await.unwrap().len(), 2);
+    }
+}
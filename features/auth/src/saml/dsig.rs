@@ -0,0 +1,112 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::signature::{RSA_PKCS1_2048_8192_SHA256, UnparsedPublicKey};
+use roxmltree::Node;
+use sha2::{Digest, Sha256};
+use x509_parser::parse_x509_certificate;
+
+use super::error::{Result, SamlError};
+use super::xml::{DS_NS, EXC_C14N_NS, canonicalize, child, children, text};
+
+const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+// Checks the enveloped signature of `element` against the IdP's certificates.
+// Only the element carrying the signature is trusted afterwards, the reference
+// has to point at it, which keeps signature wrapping attacks out.
+pub fn verify_enveloped(element: Node, certificates: &[Vec<u8>]) -> Result<()> {
+    let signature = child(element, DS_NS, "Signature").ok_or_else(|| invalid("missing"))?;
+    let signed_info = child(signature, DS_NS, "SignedInfo").ok_or_else(|| invalid("SignedInfo"))?;
+
+    let c14n_method = child(signed_info, DS_NS, "CanonicalizationMethod")
+        .filter(|n| n.attribute("Algorithm") == Some(EXC_C14N_NS))
+        .ok_or_else(|| invalid("unsupported canonicalization"))?;
+    child(signed_info, DS_NS, "SignatureMethod")
+        .filter(|n| n.attribute("Algorithm") == Some(RSA_SHA256))
+        .ok_or_else(|| invalid("unsupported signature method"))?;
+
+    let mut references = children(signed_info, DS_NS, "Reference");
+    let reference = match (references.next(), references.next()) {
+        (Some(reference), None) => reference,
+        _ => return Err(invalid("expected a single reference")),
+    };
+
+    let id = element
+        .attribute("ID")
+        .ok_or_else(|| invalid("unsigned element"))?;
+    if reference.attribute("URI") != Some(format!("#{id}").as_str()) {
+        return Err(invalid("reference doesn't point at the element"));
+    }
+
+    let mut digest_prefixes = None;
+    let transforms = child(reference, DS_NS, "Transforms").ok_or_else(|| invalid("transforms"))?;
+    for transform in children(transforms, DS_NS, "Transform") {
+        match transform.attribute("Algorithm") {
+            Some(ENVELOPED_SIGNATURE) => {}
+            Some(EXC_C14N_NS) => digest_prefixes = Some(prefix_list(transform)),
+            _ => return Err(invalid("unsupported transform")),
+        }
+    }
+    let digest_prefixes = digest_prefixes.ok_or_else(|| invalid("missing canonicalization"))?;
+
+    child(reference, DS_NS, "DigestMethod")
+        .filter(|n| n.attribute("Algorithm") == Some(SHA256))
+        .ok_or_else(|| invalid("unsupported digest method"))?;
+    let expected_digest = decode_base64(child(reference, DS_NS, "DigestValue"))?;
+
+    let prefixes: Vec<&str> = digest_prefixes.split_whitespace().collect();
+    let canonical = canonicalize(element, Some(signature.id()), &prefixes);
+    if Sha256::digest(canonical.as_bytes()).as_slice() != expected_digest.as_slice() {
+        return Err(invalid("digest mismatch"));
+    }
+
+    let prefixes = prefix_list(c14n_method);
+    let prefixes: Vec<&str> = prefixes.split_whitespace().collect();
+    let canonical_signed_info = canonicalize(signed_info, None, &prefixes);
+    let signature_value = decode_base64(child(signature, DS_NS, "SignatureValue"))?;
+
+    // the key in the message's KeyInfo is never trusted, only the imported ones
+    for certificate in certificates {
+        let public_key = rsa_public_key(certificate)?;
+        if UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, public_key)
+            .verify(canonical_signed_info.as_bytes(), &signature_value)
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+
+    Err(invalid("signature mismatch"))
+}
+
+// PKCS#1 RSAPublicKey of a DER certificate
+pub fn rsa_public_key(certificate: &[u8]) -> Result<Vec<u8>> {
+    let (_, cert) = parse_x509_certificate(certificate)
+        .map_err(|e| SamlError::InvalidMetadata(e.to_string()))?;
+
+    Ok(cert.public_key().subject_public_key.data.to_vec())
+}
+
+pub fn decode_base64(node: Option<Node>) -> Result<Vec<u8>> {
+    let encoded: String = node
+        .map(text)
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+
+    STANDARD
+        .decode(encoded)
+        .map_err(|_| invalid("invalid base64"))
+}
+
+fn prefix_list(transform: Node) -> String {
+    child(transform, EXC_C14N_NS, "InclusiveNamespaces")
+        .and_then(|n| n.attribute("PrefixList"))
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn invalid(reason: &str) -> SamlError {
+    SamlError::InvalidSignature(reason.to_string())
+}
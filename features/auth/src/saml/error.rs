@@ -0,0 +1,56 @@
+pub type Result<T> = std::result::Result<T, SamlError>;
+
+#[derive(Debug)]
+pub enum SamlError {
+    ConnectionNotFound(String),
+    InvalidMetadata(String),
+    InvalidXml(String),
+    InvalidResponse(String),
+    InvalidSignature(String),
+    RequestFailed(String),
+    UnknownRequest,
+    InvalidAudience,
+    Expired,
+    Replayed,
+    MissingEmail,
+    DomainNotAllowed,
+}
+
+impl std::fmt::Display for SamlError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            SamlError::ConnectionNotFound(e) => write!(fmt, "SSO connection not found: {e}"),
+            SamlError::InvalidMetadata(e) => write!(fmt, "Invalid IdP metadata: {e}"),
+            SamlError::InvalidXml(e) => write!(fmt, "Invalid XML: {e}"),
+            SamlError::InvalidResponse(e) => write!(fmt, "Invalid response: {e}"),
+            SamlError::InvalidSignature(e) => write!(fmt, "Invalid signature: {e}"),
+            SamlError::RequestFailed(e) => write!(fmt, "IdP rejected the request: {e}"),
+            SamlError::UnknownRequest
+            | SamlError::InvalidAudience
+            | SamlError::Expired
+            | SamlError::Replayed
+            | SamlError::MissingEmail
+            | SamlError::DomainNotAllowed => write!(fmt, "{self:?}"),
+        }
+    }
+}
+
+impl std::error::Error for SamlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saml_error_rendering() {
+        assert_eq!(
+            "SSO connection not found: acme",
+            SamlError::ConnectionNotFound("acme".to_string()).to_string()
+        );
+        assert_eq!(
+            "Invalid signature: digest mismatch",
+            SamlError::InvalidSignature("digest mismatch".to_string()).to_string()
+        );
+        assert_eq!("Replayed", SamlError::Replayed.to_string());
+    }
+}
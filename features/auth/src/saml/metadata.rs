@@ -0,0 +1,120 @@
+use super::dsig::{decode_base64, rsa_public_key};
+use super::error::{Result, SamlError};
+use super::xml::{DS_NS, MD_NS, child, children, escape_attr, parse};
+
+pub const HTTP_REDIRECT_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-Redirect";
+pub const HTTP_POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+pub const EMAIL_NAME_ID_FORMAT: &str = "urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress";
+
+// What we need from an IdP's EntityDescriptor
+pub struct IdpMetadata {
+    pub entity_id: String,
+    pub sso_url: String,
+    pub certificates: Vec<Vec<u8>>,
+}
+
+pub fn parse_idp_metadata(xml: &str) -> Result<IdpMetadata> {
+    let doc = parse(xml).map_err(|e| SamlError::InvalidMetadata(e.to_string()))?;
+    let root = doc.root_element();
+    if !root.has_tag_name((MD_NS, "EntityDescriptor")) {
+        return Err(invalid("expected an EntityDescriptor"));
+    }
+
+    let entity_id = root
+        .attribute("entityID")
+        .ok_or_else(|| invalid("entityID"))?;
+    let descriptor =
+        child(root, MD_NS, "IDPSSODescriptor").ok_or_else(|| invalid("IDPSSODescriptor"))?;
+
+    // we send AuthnRequests with the redirect binding
+    let sso_url = children(descriptor, MD_NS, "SingleSignOnService")
+        .find(|n| n.attribute("Binding") == Some(HTTP_REDIRECT_BINDING))
+        .and_then(|n| n.attribute("Location"))
+        .ok_or_else(|| invalid("no HTTP-Redirect SingleSignOnService"))?;
+
+    let mut certificates = Vec::new();
+    for key_descriptor in children(descriptor, MD_NS, "KeyDescriptor") {
+        // keys without a use are valid for both signing and encryption
+        if key_descriptor
+            .attribute("use")
+            .is_some_and(|key_use| key_use != "signing")
+        {
+            continue;
+        }
+
+        let certificate = child(key_descriptor, DS_NS, "KeyInfo")
+            .and_then(|n| child(n, DS_NS, "X509Data"))
+            .and_then(|n| child(n, DS_NS, "X509Certificate"));
+        let der = decode_base64(certificate).map_err(|_| invalid("X509Certificate"))?;
+        rsa_public_key(&der)?;
+        certificates.push(der);
+    }
+    if certificates.is_empty() {
+        return Err(invalid("no signing certificate"));
+    }
+
+    Ok(IdpMetadata {
+        entity_id: entity_id.to_string(),
+        sso_url: sso_url.to_string(),
+        certificates,
+    })
+}
+
+// Our side of the connection, handed to the customer's IdP admin
+pub fn sp_metadata(entity_id: &str, acs_url: &str) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<md:EntityDescriptor xmlns:md="{md}" entityID="{entity_id}">"#,
+            r#"<md:SPSSODescriptor AuthnRequestsSigned="false" protocolSupportEnumeration="urn:oasis:names:tc:SAML:2.0:protocol">"#,
+            r#"<md:NameIDFormat>{name_id_format}</md:NameIDFormat>"#,
+            r#"<md:AssertionConsumerService Binding="{binding}" Location="{acs_url}" index="0" isDefault="true"/>"#,
+            r#"</md:SPSSODescriptor>"#,
+            r#"</md:EntityDescriptor>"#,
+        ),
+        md = MD_NS,
+        entity_id = escape_attr(entity_id),
+        name_id_format = EMAIL_NAME_ID_FORMAT,
+        binding = HTTP_POST_BINDING,
+        acs_url = escape_attr(acs_url),
+    )
+}
+
+fn invalid(reason: &str) -> SamlError {
+    SamlError::InvalidMetadata(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_idp_metadata() {
+        let metadata = parse_idp_metadata(include_str!("fixtures/idp_metadata.xml")).unwrap();
+        assert_eq!(metadata.entity_id, "https://idp.acme.example.com/metadata");
+        assert_eq!(
+            metadata.sso_url,
+            "https://idp.acme.example.com/sso/redirect"
+        );
+        assert_eq!(metadata.certificates.len(), 1);
+
+        assert!(parse_idp_metadata("<EntityDescriptor/>").is_err());
+    }
+
+    #[test]
+    fn test_sp_metadata_is_well_formed() {
+        let xml = sp_metadata(
+            "https://app.example.com/auth/sso",
+            "https://app.example.com/a?b&c",
+        );
+        let doc = parse(&xml).unwrap();
+        let acs = doc
+            .descendants()
+            .find(|n| n.has_tag_name((MD_NS, "AssertionConsumerService")))
+            .unwrap();
+        assert_eq!(
+            acs.attribute("Location"),
+            Some("https://app.example.com/a?b&c")
+        );
+    }
+}
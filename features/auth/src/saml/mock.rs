@@ -0,0 +1,191 @@
+use std::io::Read;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::read::DeflateDecoder;
+use ring::rand::SystemRandom;
+use ring::signature::{RSA_PKCS1_SHA256, RsaKeyPair};
+use sha2::{Digest, Sha256};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use url::Url;
+use uuid::Uuid;
+
+use super::xml::{DS_NS, SAML_NS, SAMLP_NS, canonicalize, escape_attr, parse};
+
+pub const ISSUER: &str = "https://idp.acme.example.com/metadata";
+
+// What the mock IdP asserts, tests tweak the fields to produce invalid responses
+pub struct MockAssertion {
+    pub audience: String,
+    pub recipient: String,
+    pub request_id: String,
+    pub name_id: String,
+    pub email: String,
+    pub name: String,
+    pub not_before: i64,
+    pub not_on_or_after: i64,
+}
+
+impl MockAssertion {
+    pub fn new(audience: &str, recipient: &str, request_id: &str, now: i64) -> Self {
+        Self {
+            audience: audience.to_string(),
+            recipient: recipient.to_string(),
+            request_id: request_id.to_string(),
+            name_id: "jane.doe@acme.example.com".to_string(),
+            email: "jane.doe@acme.example.com".to_string(),
+            name: "Jane Doe".to_string(),
+            not_before: now,
+            not_on_or_after: now + 300,
+        }
+    }
+}
+
+// IdP signing with the fixture key, matching `fixtures/idp_metadata.xml`
+pub struct MockIdp {
+    key_pair: RsaKeyPair,
+}
+
+impl MockIdp {
+    pub fn new() -> Self {
+        let der = Self::pem_to_der(include_str!("fixtures/idp_key.pem"));
+        Self {
+            key_pair: RsaKeyPair::from_pkcs8(&der).unwrap(),
+        }
+    }
+
+    pub fn pem_to_der(pem: &str) -> Vec<u8> {
+        let body: String = pem
+            .lines()
+            .filter(|line| !line.starts_with("-----"))
+            .collect();
+        STANDARD.decode(body).unwrap()
+    }
+
+    // The AuthnRequest xml carried by an HTTP-Redirect url
+    pub fn authn_request(redirect_url: &str) -> String {
+        let url = Url::parse(redirect_url).unwrap();
+        let encoded = url
+            .query_pairs()
+            .find(|(k, _)| k == "SAMLRequest")
+            .unwrap()
+            .1
+            .to_string();
+
+        let mut xml = String::new();
+        DeflateDecoder::new(STANDARD.decode(encoded).unwrap().as_slice())
+            .read_to_string(&mut xml)
+            .unwrap();
+        xml
+    }
+
+    // The id of the request, which the response has to answer
+    pub fn request_id(redirect_url: &str) -> String {
+        let xml = Self::authn_request(redirect_url);
+        parse(&xml)
+            .unwrap()
+            .root_element()
+            .attribute("ID")
+            .unwrap()
+            .to_string()
+    }
+
+    // A Response with an enveloped signature on the assertion
+    pub fn response(&self, assertion: &MockAssertion) -> String {
+        let assertion_id = format!("_{}", Uuid::new_v4());
+        let instant = |ts: i64| {
+            OffsetDateTime::from_unix_timestamp(ts)
+                .unwrap()
+                .format(&Rfc3339)
+                .unwrap()
+        };
+
+        let issuer = format!("<saml:Issuer>{ISSUER}</saml:Issuer>");
+        let body = format!(
+            concat!(
+                r#"<saml:Subject><saml:NameID>{name_id}</saml:NameID>"#,
+                r#"<saml:SubjectConfirmation Method="urn:oasis:names:tc:SAML:2.0:cm:bearer">"#,
+                r#"<saml:SubjectConfirmationData InResponseTo="{request_id}" NotOnOrAfter="{not_on_or_after}" Recipient="{recipient}"/>"#,
+                r#"</saml:SubjectConfirmation></saml:Subject>"#,
+                r#"<saml:Conditions NotBefore="{not_before}" NotOnOrAfter="{not_on_or_after}">"#,
+                r#"<saml:AudienceRestriction><saml:Audience>{audience}</saml:Audience></saml:AudienceRestriction>"#,
+                r#"</saml:Conditions>"#,
+                r#"<saml:AttributeStatement>"#,
+                r#"<saml:Attribute Name="email"><saml:AttributeValue>{email}</saml:AttributeValue></saml:Attribute>"#,
+                r#"<saml:Attribute Name="displayName"><saml:AttributeValue>{name}</saml:AttributeValue></saml:Attribute>"#,
+                r#"</saml:AttributeStatement>"#,
+            ),
+            name_id = escape_attr(&assertion.name_id),
+            request_id = escape_attr(&assertion.request_id),
+            recipient = escape_attr(&assertion.recipient),
+            audience = escape_attr(&assertion.audience),
+            email = escape_attr(&assertion.email),
+            name = escape_attr(&assertion.name),
+            not_before = instant(assertion.not_before),
+            not_on_or_after = instant(assertion.not_on_or_after),
+        );
+        let open_assertion = format!(
+            r#"<saml:Assertion xmlns:saml="{SAML_NS}" ID="{assertion_id}" Version="2.0" IssueInstant="{}">"#,
+            instant(assertion.not_before)
+        );
+
+        // digest the assertion as it will look without the signature
+        let unsigned = format!("{open_assertion}{issuer}{body}</saml:Assertion>");
+        let doc = parse(&unsigned).unwrap();
+        let digest = Sha256::digest(canonicalize(doc.root_element(), None, &[]).as_bytes());
+
+        let signed_info = format!(
+            concat!(
+                r#"<ds:SignedInfo xmlns:ds="{ds}">"#,
+                r#"<ds:CanonicalizationMethod Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/>"#,
+                r#"<ds:SignatureMethod Algorithm="http://www.w3.org/2001/04/xmldsig-more#rsa-sha256"/>"#,
+                r##"<ds:Reference URI="#{id}"><ds:Transforms>"##,
+                r#"<ds:Transform Algorithm="http://www.w3.org/2000/09/xmldsig#enveloped-signature"/>"#,
+                r#"<ds:Transform Algorithm="http://www.w3.org/2001/10/xml-exc-c14n#"/>"#,
+                r#"</ds:Transforms>"#,
+                r#"<ds:DigestMethod Algorithm="http://www.w3.org/2001/04/xmlenc#sha256"/>"#,
+                r#"<ds:DigestValue>{digest}</ds:DigestValue>"#,
+                r#"</ds:Reference></ds:SignedInfo>"#,
+            ),
+            ds = DS_NS,
+            id = assertion_id,
+            digest = STANDARD.encode(digest),
+        );
+        let doc = parse(&signed_info).unwrap();
+        let canonical_signed_info = canonicalize(doc.root_element(), None, &[]);
+
+        let mut signature = vec![0; self.key_pair.public().modulus_len()];
+        self.key_pair
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                canonical_signed_info.as_bytes(),
+                &mut signature,
+            )
+            .unwrap();
+
+        // the signature sits right after the issuer
+        let signature = format!(
+            r#"<ds:Signature xmlns:ds="{DS_NS}">{}<ds:SignatureValue>{}</ds:SignatureValue></ds:Signature>"#,
+            signed_info.replacen(&format!(r#" xmlns:ds="{DS_NS}""#), "", 1),
+            STANDARD.encode(signature),
+        );
+
+        format!(
+            concat!(
+                r#"<samlp:Response xmlns:samlp="{samlp}" xmlns:saml="{saml}" ID="_{response_id}" Version="2.0" IssueInstant="{instant}" InResponseTo="{request_id}">"#,
+                r#"{issuer}<samlp:Status><samlp:StatusCode Value="urn:oasis:names:tc:SAML:2.0:status:Success"/></samlp:Status>"#,
+                r#"{open_assertion}{issuer}{signature}{body}</saml:Assertion>"#,
+                r#"</samlp:Response>"#,
+            ),
+            samlp = SAMLP_NS,
+            saml = SAML_NS,
+            response_id = Uuid::new_v4(),
+            instant = instant(assertion.not_before),
+            request_id = escape_attr(&assertion.request_id),
+            issuer = issuer,
+            open_assertion = open_assertion,
+            signature = signature,
+            body = body,
+        )
+    }
+}
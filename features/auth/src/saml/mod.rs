@@ -0,0 +1,450 @@
+pub(crate) mod dsig;
+pub mod error;
+pub(crate) mod metadata;
+#[cfg(test)]
+pub mod mock;
+pub(crate) mod response;
+pub(crate) mod xml;
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::{Compression, write::DeflateEncoder};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use url::Url;
+
+use crate::error::{AuthError, Result};
+use crate::models::{ExternalIdentity, SsoConnection};
+use crate::repository::SsoConnectionRepositoryTrait;
+use crate::repository::in_mem_sso_connection_repo::InMemorySsoConnectionRepository;
+use crate::utils::{is_valid_org, random_string};
+
+use error::SamlError;
+use metadata::{EMAIL_NAME_ID_FORMAT, HTTP_POST_BINDING, parse_idp_metadata, sp_metadata};
+use response::{Expected, ValidatedAssertion, validate_response};
+use xml::{SAML_NS, SAMLP_NS, escape_attr};
+
+// How long a user has to complete the flow at the IdP
+const PENDING_TTL_SECONDS: i64 = 600;
+const REQUEST_ID_LEN: usize = 32;
+
+struct PendingRequest {
+    org: String,
+    expires_at: i64,
+}
+
+// SAML 2.0 service provider, SP initiated with the redirect and POST bindings
+pub struct ServiceProvider {
+    base_url: String,
+    connection_repo: Arc<dyn SsoConnectionRepositoryTrait>,
+    pending: Mutex<HashMap<String, PendingRequest>>,
+    // assertion ids already consumed, kept until the assertion expires
+    seen_assertions: Mutex<HashMap<String, i64>>,
+}
+
+impl ServiceProvider {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            connection_repo: Arc::new(InMemorySsoConnectionRepository::new()),
+            pending: Mutex::new(HashMap::new()),
+            seen_assertions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_connection_repo(
+        mut self,
+        connection_repo: Arc<dyn SsoConnectionRepositoryTrait>,
+    ) -> Self {
+        self.connection_repo = connection_repo;
+        self
+    }
+
+    pub fn entity_id(&self) -> String {
+        format!("{}/auth/sso", self.base_url)
+    }
+
+    pub fn acs_url(&self, org: &str) -> String {
+        format!("{}/auth/sso/{org}/acs", self.base_url)
+    }
+
+    pub fn metadata(&self, org: &str) -> String {
+        sp_metadata(&self.entity_id(), &self.acs_url(org))
+    }
+
+    // Creates or replaces the organization's connection from its IdP metadata
+    pub async fn import_metadata(
+        &self,
+        org: &str,
+        metadata_xml: &str,
+        email_domains: Vec<String>,
+    ) -> Result<SsoConnection> {
+        if !is_valid_org(org) {
+            return Err(SamlError::InvalidMetadata("invalid organization name".to_string()).into());
+        }
+
+        let metadata = parse_idp_metadata(metadata_xml)?;
+        let connection = SsoConnection {
+            org: org.to_string(),
+            entity_id: metadata.entity_id,
+            sso_url: metadata.sso_url,
+            certificates: metadata.certificates,
+            email_domains: email_domains
+                .into_iter()
+                .map(|d| d.trim().to_lowercase())
+                .filter(|d| !d.is_empty())
+                .collect(),
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+        };
+
+        Ok(self.connection_repo.save_connection(connection).await?)
+    }
+
+    async fn connection(&self, org: &str) -> Result<SsoConnection> {
+        self.connection_repo
+            .find_by_org(org)
+            .await?
+            .ok_or_else(|| AuthError::Saml(SamlError::ConnectionNotFound(org.to_string())))
+    }
+
+    // Url of the IdP carrying an AuthnRequest (HTTP-Redirect binding)
+    pub async fn authn_request(&self, org: &str) -> Result<String> {
+        let connection = self.connection(org).await?;
+
+        // ids are xs:ID, they can't start with a digit
+        let id = format!("_{}", random_string(REQUEST_ID_LEN));
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let issue_instant = OffsetDateTime::from_unix_timestamp(now)
+            .ok()
+            .and_then(|dt| dt.format(&Rfc3339).ok())
+            .unwrap_or_default();
+
+        let request = format!(
+            concat!(
+                r#"<samlp:AuthnRequest xmlns:samlp="{samlp}" xmlns:saml="{saml}" ID="{id}" Version="2.0" "#,
+                r#"IssueInstant="{issue_instant}" Destination="{destination}" "#,
+                r#"AssertionConsumerServiceURL="{acs_url}" ProtocolBinding="{binding}">"#,
+                r#"<saml:Issuer>{issuer}</saml:Issuer>"#,
+                r#"<samlp:NameIDPolicy Format="{name_id_format}" AllowCreate="true"/>"#,
+                r#"</samlp:AuthnRequest>"#,
+            ),
+            samlp = SAMLP_NS,
+            saml = SAML_NS,
+            id = id,
+            issue_instant = issue_instant,
+            destination = escape_attr(&connection.sso_url),
+            acs_url = escape_attr(&self.acs_url(org)),
+            binding = HTTP_POST_BINDING,
+            issuer = escape_attr(&self.entity_id()),
+            name_id_format = EMAIL_NAME_ID_FORMAT,
+        );
+
+        // redirect binding, deflate then base64
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(request.as_bytes())
+            .map_err(|e| SamlError::InvalidXml(e.to_string()))?;
+        let deflated = encoder
+            .finish()
+            .map_err(|e| SamlError::InvalidXml(e.to_string()))?;
+
+        let mut url = Url::parse(&connection.sso_url)
+            .map_err(|e| SamlError::InvalidMetadata(e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("SAMLRequest", &STANDARD.encode(deflated));
+
+        let mut pending = self.pending.lock().map_err(|_| lock_poisoned())?;
+        pending.retain(|_, p| p.expires_at > now);
+        pending.insert(
+            id,
+            PendingRequest {
+                org: org.to_string(),
+                expires_at: now + PENDING_TTL_SECONDS,
+            },
+        );
+
+        Ok(url.to_string())
+    }
+
+    // Validates the base64 SAMLResponse posted to the org's ACS
+    pub async fn consume_response(
+        &self,
+        org: &str,
+        saml_response: &str,
+    ) -> Result<ExternalIdentity> {
+        let connection = self.connection(org).await?;
+
+        let encoded: String = saml_response
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        let xml = STANDARD
+            .decode(encoded)
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(|| SamlError::InvalidResponse("invalid encoding".to_string()))?;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        Ok(self.consume_at(&connection, &xml, now)?)
+    }
+
+    fn consume_at(
+        &self,
+        connection: &SsoConnection,
+        xml: &str,
+        now: i64,
+    ) -> error::Result<ExternalIdentity> {
+        let assertion = validate_response(
+            xml,
+            &Expected {
+                issuer: &connection.entity_id,
+                certificates: &connection.certificates,
+                audience: &self.entity_id(),
+                acs_url: &self.acs_url(&connection.org),
+                now,
+            },
+        )?;
+
+        let mut seen = self.seen_assertions.lock().map_err(|_| lock_poisoned())?;
+        seen.retain(|_, expires_at| *expires_at > now);
+        if seen.contains_key(&assertion.id) {
+            return Err(SamlError::Replayed);
+        }
+
+        // unsolicited responses are rejected, each request can only be answered once
+        self.pending
+            .lock()
+            .map_err(|_| lock_poisoned())?
+            .remove(&assertion.in_response_to)
+            .filter(|p| p.org == connection.org && p.expires_at > now)
+            .ok_or(SamlError::UnknownRequest)?;
+        seen.insert(assertion.id.clone(), assertion.expires_at);
+
+        external_identity(connection, assertion)
+    }
+}
+
+fn external_identity(
+    connection: &SsoConnection,
+    assertion: ValidatedAssertion,
+) -> error::Result<ExternalIdentity> {
+    let email = assertion
+        .email
+        .map(|e| e.to_lowercase())
+        .ok_or(SamlError::MissingEmail)?;
+    let domain = email.rsplit_once('@').map(|(_, d)| d).unwrap_or_default();
+
+    // with domains configured the IdP is authoritative for them, anything else is refused.
+    // Without, its emails aren't trusted enough to link to existing accounts.
+    let email_verified = match connection.email_domains.is_empty() {
+        true => false,
+        false if connection.email_domains.iter().any(|d| d == domain) => true,
+        false => return Err(SamlError::DomainNotAllowed),
+    };
+
+    Ok(ExternalIdentity {
+        provider: format!("saml:{}", connection.org),
+        subject: assertion.name_id,
+        name: assertion.name.unwrap_or_else(|| email.clone()),
+        email,
+        email_verified,
+    })
+}
+
+fn lock_poisoned() -> SamlError {
+    SamlError::InvalidResponse("lock poisoned".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{MockAssertion, MockIdp};
+    use super::*;
+
+    const ORG: &str = "acme";
+    const BASE_URL: &str = "http://localhost:3000";
+    // 2025-01-01T00:01:00Z, inside the fixture's validity window
+    const FIXTURE_NOW: i64 = 1_735_689_660;
+
+    async fn setup(email_domains: &[&str]) -> (ServiceProvider, SsoConnection) {
+        let sp = ServiceProvider::new(BASE_URL);
+        let connection = sp
+            .import_metadata(
+                ORG,
+                include_str!("fixtures/idp_metadata.xml"),
+                email_domains.iter().map(|d| d.to_string()).collect(),
+            )
+            .await
+            .unwrap();
+        (sp, connection)
+    }
+
+    fn expect_pending(sp: &ServiceProvider, id: &str, now: i64) {
+        sp.pending.lock().unwrap().insert(
+            id.to_string(),
+            PendingRequest {
+                org: ORG.to_string(),
+                expires_at: now + PENDING_TTL_SECONDS,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fixture_response() {
+        let (sp, connection) = setup(&["acme.example.com"]).await;
+
+        // signed with the JDK's xml-dsig implementation, assertion and envelope variants
+        for fixture in [
+            include_str!("fixtures/signed_response.xml"),
+            include_str!("fixtures/signed_response_envelope.xml"),
+        ] {
+            expect_pending(&sp, "_req-fixture-1", FIXTURE_NOW);
+            sp.seen_assertions.lock().unwrap().clear();
+
+            let identity = sp.consume_at(&connection, fixture, FIXTURE_NOW).unwrap();
+            assert_eq!(identity.provider, "saml:acme");
+            assert_eq!(identity.subject, "jane.doe@acme.example.com");
+            assert_eq!(identity.email, "jane.doe@acme.example.com");
+            assert_eq!(identity.name, "Jane Doe & Co");
+            assert!(identity.email_verified);
+        }
+
+        // too late
+        expect_pending(&sp, "_req-fixture-1", FIXTURE_NOW);
+        sp.seen_assertions.lock().unwrap().clear();
+        let result = sp.consume_at(
+            &connection,
+            include_str!("fixtures/signed_response.xml"),
+            FIXTURE_NOW + 3600,
+        );
+        assert!(matches!(result, Err(SamlError::Expired)));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_or_foreign_signature() {
+        let (sp, connection) = setup(&[]).await;
+        expect_pending(&sp, "_req-fixture-1", FIXTURE_NOW);
+
+        let tampered =
+            include_str!("fixtures/signed_response.xml").replace("Jane Doe &amp; Co", "Mallory");
+        assert!(matches!(
+            sp.consume_at(&connection, &tampered, FIXTURE_NOW),
+            Err(SamlError::InvalidSignature(_))
+        ));
+
+        // a valid signature from a key the org didn't register
+        let other_cert = include_str!("fixtures/other_idp_cert.pem");
+        let foreign = SsoConnection {
+            certificates: vec![MockIdp::pem_to_der(other_cert)],
+            ..connection
+        };
+        assert!(matches!(
+            sp.consume_at(
+                &foreign,
+                include_str!("fixtures/signed_response.xml"),
+                FIXTURE_NOW
+            ),
+            Err(SamlError::InvalidSignature(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_unsolicited_responses() {
+        let (sp, connection) = setup(&["acme.example.com"]).await;
+        let idp = MockIdp::new();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let assertion = MockAssertion::new(&sp.entity_id(), &sp.acs_url(ORG), "_req-1", now);
+        let response = idp.response(&assertion);
+
+        // not a request we sent
+        assert!(matches!(
+            sp.consume_at(&connection, &response, now),
+            Err(SamlError::UnknownRequest)
+        ));
+
+        expect_pending(&sp, "_req-1", now);
+        assert!(sp.consume_at(&connection, &response, now).is_ok());
+
+        // the same assertion can't be used twice, even for a fresh request
+        expect_pending(&sp, "_req-1", now);
+        assert!(matches!(
+            sp.consume_at(&connection, &response, now),
+            Err(SamlError::Replayed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_assertion_checks() {
+        let (sp, connection) = setup(&["acme.example.com"]).await;
+        let idp = MockIdp::new();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let acs_url = sp.acs_url(ORG);
+
+        let mut assertion =
+            MockAssertion::new("https://other-sp.example.com", &acs_url, "_req-a", now);
+        expect_pending(&sp, "_req-a", now);
+        assert!(matches!(
+            sp.consume_at(&connection, &idp.response(&assertion), now),
+            Err(SamlError::InvalidAudience)
+        ));
+
+        assertion = MockAssertion::new(&sp.entity_id(), &acs_url, "_req-b", now);
+        assertion.not_before = now + 600;
+        expect_pending(&sp, "_req-b", now);
+        assert!(matches!(
+            sp.consume_at(&connection, &idp.response(&assertion), now),
+            Err(SamlError::Expired)
+        ));
+
+        assertion = MockAssertion::new(
+            &sp.entity_id(),
+            "https://evil.example.com/acs",
+            "_req-c",
+            now,
+        );
+        expect_pending(&sp, "_req-c", now);
+        assert!(
+            sp.consume_at(&connection, &idp.response(&assertion), now)
+                .is_err()
+        );
+
+        // the org's IdP can't vouch for other domains
+        assertion = MockAssertion::new(&sp.entity_id(), &acs_url, "_req-d", now);
+        assertion.email = "someone@gmail.com".to_string();
+        expect_pending(&sp, "_req-d", now);
+        assert!(matches!(
+            sp.consume_at(&connection, &idp.response(&assertion), now),
+            Err(SamlError::DomainNotAllowed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_authn_request() {
+        let (sp, _) = setup(&[]).await;
+
+        let redirect = sp.authn_request(ORG).await.unwrap();
+        assert!(redirect.starts_with("https://idp.acme.example.com/sso/redirect?"));
+
+        let xml = MockIdp::authn_request(&redirect);
+        let doc = xml::parse(&xml).unwrap();
+        let request = doc.root_element();
+        assert!(request.has_tag_name((SAMLP_NS, "AuthnRequest")));
+        assert_eq!(
+            request.attribute("AssertionConsumerServiceURL"),
+            Some("http://localhost:3000/auth/sso/acme/acs")
+        );
+
+        // the request id is what the response has to answer
+        let id = request.attribute("ID").unwrap();
+        assert!(sp.pending.lock().unwrap().contains_key(id));
+
+        assert!(matches!(
+            sp.authn_request("unknown").await,
+            Err(AuthError::Saml(SamlError::ConnectionNotFound(_)))
+        ));
+    }
+}
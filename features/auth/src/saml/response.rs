@@ -0,0 +1,205 @@
+use roxmltree::Node;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+use super::dsig::verify_enveloped;
+use super::error::{Result, SamlError};
+use super::metadata::EMAIL_NAME_ID_FORMAT;
+use super::xml::{DS_NS, SAML_NS, SAMLP_NS, child, children, parse, text};
+
+const SUCCESS_STATUS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+const BEARER_METHOD: &str = "urn:oasis:names:tc:SAML:2.0:cm:bearer";
+const CLOCK_SKEW_SECONDS: i64 = 60;
+
+// Attribute names IdPs commonly use, in order of preference
+const EMAIL_ATTRIBUTES: [&str; 4] = [
+    "email",
+    "mail",
+    "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/emailaddress",
+    "urn:oid:0.9.2342.19200300.100.1.3",
+];
+const NAME_ATTRIBUTES: [&str; 4] = [
+    "name",
+    "displayName",
+    "http://schemas.xmlsoap.org/ws/2005/05/identity/claims/name",
+    "urn:oid:2.16.840.1.113730.3.1.241",
+];
+
+// What the response has to match
+pub struct Expected<'a> {
+    pub issuer: &'a str,
+    pub certificates: &'a [Vec<u8>],
+    pub audience: &'a str,
+    pub acs_url: &'a str,
+    pub now: i64,
+}
+
+#[derive(Debug)]
+pub struct ValidatedAssertion {
+    pub id: String,
+    pub in_response_to: String,
+    pub name_id: String,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    // the assertion can't be used after this, replays only need tracking until then
+    pub expires_at: i64,
+}
+
+pub fn validate_response(xml: &str, expected: &Expected) -> Result<ValidatedAssertion> {
+    let doc = parse(xml)?;
+    let response = doc.root_element();
+    if !response.has_tag_name((SAMLP_NS, "Response")) {
+        return Err(invalid("expected a Response"));
+    }
+
+    if let Some(destination) = response.attribute("Destination")
+        && destination != expected.acs_url
+    {
+        return Err(invalid("Destination"));
+    }
+
+    let status = child(response, SAMLP_NS, "Status")
+        .and_then(|n| child(n, SAMLP_NS, "StatusCode"))
+        .and_then(|n| n.attribute("Value"))
+        .unwrap_or_default();
+    if status != SUCCESS_STATUS {
+        return Err(SamlError::RequestFailed(status.to_string()));
+    }
+
+    if child(response, SAML_NS, "EncryptedAssertion").is_some() {
+        return Err(invalid("encrypted assertions are not supported"));
+    }
+    let mut assertions = children(response, SAML_NS, "Assertion");
+    let assertion = match (assertions.next(), assertions.next()) {
+        (Some(assertion), None) => assertion,
+        _ => return Err(invalid("expected a single Assertion")),
+    };
+
+    // either the assertion or the whole response has to be signed
+    if child(assertion, DS_NS, "Signature").is_some() {
+        verify_enveloped(assertion, expected.certificates)?;
+    } else if child(response, DS_NS, "Signature").is_some() {
+        verify_enveloped(response, expected.certificates)?;
+    } else {
+        return Err(SamlError::InvalidSignature("unsigned".to_string()));
+    }
+
+    let issuer = child(assertion, SAML_NS, "Issuer").map(text);
+    if issuer != Some(expected.issuer) {
+        return Err(invalid("Issuer"));
+    }
+
+    let conditions_expire_at = validate_conditions(assertion, expected)?;
+
+    let subject = child(assertion, SAML_NS, "Subject").ok_or_else(|| invalid("Subject"))?;
+    let name_id = child(subject, SAML_NS, "NameID").ok_or_else(|| invalid("NameID"))?;
+    let (in_response_to, confirmation_expires_at) = validate_confirmation(subject, expected)?;
+
+    if let Some(response_to) = response.attribute("InResponseTo")
+        && response_to != in_response_to
+    {
+        return Err(invalid("InResponseTo"));
+    }
+
+    let attributes = child(assertion, SAML_NS, "AttributeStatement");
+    let email = match name_id.attribute("Format") {
+        Some(EMAIL_NAME_ID_FORMAT) => Some(text(name_id).to_string()),
+        _ => attributes.and_then(|n| attribute_value(n, &EMAIL_ATTRIBUTES)),
+    };
+
+    Ok(ValidatedAssertion {
+        id: assertion
+            .attribute("ID")
+            .ok_or_else(|| invalid("ID"))?
+            .to_string(),
+        in_response_to,
+        name_id: text(name_id).to_string(),
+        email,
+        name: attributes.and_then(|n| attribute_value(n, &NAME_ATTRIBUTES)),
+        expires_at: conditions_expire_at.min(confirmation_expires_at),
+    })
+}
+
+fn validate_conditions(assertion: Node, expected: &Expected) -> Result<i64> {
+    let conditions =
+        child(assertion, SAML_NS, "Conditions").ok_or_else(|| invalid("Conditions"))?;
+
+    if let Some(not_before) = conditions.attribute("NotBefore")
+        && parse_instant(not_before)? > expected.now + CLOCK_SKEW_SECONDS
+    {
+        return Err(SamlError::Expired);
+    }
+    let expires_at = parse_instant(
+        conditions
+            .attribute("NotOnOrAfter")
+            .ok_or_else(|| invalid("NotOnOrAfter"))?,
+    )?;
+    if expires_at <= expected.now - CLOCK_SKEW_SECONDS {
+        return Err(SamlError::Expired);
+    }
+
+    // every restriction has to include us
+    let mut restrictions = children(conditions, SAML_NS, "AudienceRestriction").peekable();
+    if restrictions.peek().is_none() {
+        return Err(SamlError::InvalidAudience);
+    }
+    for restriction in restrictions {
+        if !children(restriction, SAML_NS, "Audience").any(|n| text(n) == expected.audience) {
+            return Err(SamlError::InvalidAudience);
+        }
+    }
+
+    Ok(expires_at)
+}
+
+// Finds a bearer confirmation meant for our ACS, returns the request it answers
+fn validate_confirmation(subject: Node, expected: &Expected) -> Result<(String, i64)> {
+    let mut result = Err(invalid("SubjectConfirmation"));
+
+    for confirmation in children(subject, SAML_NS, "SubjectConfirmation") {
+        if confirmation.attribute("Method") != Some(BEARER_METHOD) {
+            continue;
+        }
+        let Some(data) = child(confirmation, SAML_NS, "SubjectConfirmationData") else {
+            continue;
+        };
+        if data.attribute("Recipient") != Some(expected.acs_url) {
+            continue;
+        }
+
+        let (Some(in_response_to), Some(not_on_or_after)) = (
+            data.attribute("InResponseTo"),
+            data.attribute("NotOnOrAfter"),
+        ) else {
+            continue;
+        };
+        let expires_at = parse_instant(not_on_or_after)?;
+        if expires_at <= expected.now - CLOCK_SKEW_SECONDS {
+            result = Err(SamlError::Expired);
+            continue;
+        }
+
+        return Ok((in_response_to.to_string(), expires_at));
+    }
+
+    result
+}
+
+fn attribute_value(statement: Node, names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| {
+        children(statement, SAML_NS, "Attribute")
+            .find(|n| n.attribute("Name") == Some(name))
+            .and_then(|n| child(n, SAML_NS, "AttributeValue"))
+            .map(|n| text(n).to_string())
+            .filter(|value| !value.is_empty())
+    })
+}
+
+fn parse_instant(value: &str) -> Result<i64> {
+    OffsetDateTime::parse(value, &Rfc3339)
+        .map(|dt| dt.unix_timestamp())
+        .map_err(|_| invalid("invalid timestamp"))
+}
+
+fn invalid(reason: &str) -> SamlError {
+    SamlError::InvalidResponse(reason.to_string())
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+
+use roxmltree::{Document, Node, NodeId, NodeType};
+
+use super::error::{Result, SamlError};
+
+pub const SAMLP_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+pub const SAML_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+pub const MD_NS: &str = "urn:oasis:names:tc:SAML:2.0:metadata";
+pub const DS_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+pub const EXC_C14N_NS: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+
+// DTDs are rejected by the parser, which rules out entity expansion attacks
+pub fn parse(xml: &str) -> Result<Document<'_>> {
+    Document::parse(xml).map_err(|e| SamlError::InvalidXml(e.to_string()))
+}
+
+pub fn child<'a, 'input>(node: Node<'a, 'input>, ns: &str, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name((ns, name)))
+}
+
+pub fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    ns: &'a str,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |n| n.has_tag_name((ns, name)))
+}
+
+pub fn text<'a>(node: Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or_default().trim()
+}
+
+pub fn escape_attr(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn escape_text(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Exclusive XML canonicalization (without comments) of the subtree rooted at `node`.
+// `exclude` drops an element, e.g. the enveloped signature, and `inclusive_prefixes`
+// is the InclusiveNamespaces PrefixList of the transform.
+pub fn canonicalize(node: Node, exclude: Option<NodeId>, inclusive_prefixes: &[&str]) -> String {
+    let mut out = String::new();
+    write_element(node, exclude, inclusive_prefixes, &HashMap::new(), &mut out);
+    out
+}
+
+fn write_element<'a>(
+    node: Node<'a, '_>,
+    exclude: Option<NodeId>,
+    inclusive_prefixes: &[&str],
+    rendered: &HashMap<&'a str, &'a str>,
+    out: &mut String,
+) {
+    let qname = element_qname(node);
+
+    // only namespaces the element or its attributes actually use are rendered
+    let mut prefixes = vec![prefix_of(qname)];
+    for attr in node.attributes() {
+        let prefix = prefix_of(attribute_qname(node, &attr));
+        if !prefix.is_empty() && prefix != "xml" {
+            prefixes.push(prefix);
+        }
+    }
+    for prefix in inclusive_prefixes {
+        let prefix = if *prefix == "#default" { "" } else { prefix };
+        if node.lookup_namespace_uri(non_empty(prefix)).is_some() {
+            prefixes.push(prefix);
+        }
+    }
+    prefixes.sort_unstable();
+    prefixes.dedup();
+
+    let mut rendered = rendered.clone();
+    out.push('<');
+    out.push_str(qname);
+
+    for prefix in prefixes {
+        let uri = node
+            .lookup_namespace_uri(non_empty(prefix))
+            .unwrap_or_default();
+        match rendered.get(prefix) {
+            Some(current) if *current == uri => continue,
+            // an empty default namespace only needs undeclaring if one was rendered
+            None if prefix.is_empty() && uri.is_empty() => continue,
+            _ => {}
+        }
+        rendered.insert(prefix, uri);
+
+        if prefix.is_empty() {
+            out.push_str(" xmlns=\"");
+        } else {
+            out.push_str(" xmlns:");
+            out.push_str(prefix);
+            out.push_str("=\"");
+        }
+        out.push_str(&escape_attr(uri));
+        out.push('"');
+    }
+
+    let mut attributes: Vec<_> = node
+        .attributes()
+        .map(|attr| {
+            (
+                attr.namespace().unwrap_or_default(),
+                attr.name(),
+                attribute_qname(node, &attr),
+                attr.value(),
+            )
+        })
+        .collect();
+    attributes.sort_unstable_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    for (_, _, qname, value) in attributes {
+        out.push(' ');
+        out.push_str(qname);
+        out.push_str("=\"");
+        out.push_str(&escape_attr(value));
+        out.push('"');
+    }
+    out.push('>');
+
+    for child in node.children() {
+        match child.node_type() {
+            NodeType::Element if Some(child.id()) != exclude => {
+                write_element(child, exclude, inclusive_prefixes, &rendered, out);
+            }
+            NodeType::Text => out.push_str(&escape_text(child.text().unwrap_or_default())),
+            NodeType::PI => {
+                if let Some(pi) = child.pi() {
+                    out.push_str("<?");
+                    out.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        out.push(' ');
+                        out.push_str(value);
+                    }
+                    out.push_str("?>");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(qname);
+    out.push('>');
+}
+
+// roxmltree resolves names but drops prefixes, which c14n has to preserve
+fn element_qname<'input>(node: Node<'_, 'input>) -> &'input str {
+    let input = node.document().input_text();
+    let tag = &input[node.range().start + 1..];
+    let end = tag
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+fn attribute_qname<'input>(node: Node<'_, 'input>, attr: &roxmltree::Attribute) -> &'input str {
+    &node.document().input_text()[attr.range_qname()]
+}
+
+fn prefix_of(qname: &str) -> &str {
+    qname
+        .split_once(':')
+        .map(|(prefix, _)| prefix)
+        .unwrap_or("")
+}
+
+fn non_empty(prefix: &str) -> Option<&str> {
+    (!prefix.is_empty()).then_some(prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exclusive_canonicalization() {
+        let input = r#"<?xml version="1.0"?>
+<root xmlns="urn:default" xmlns:a="urn:a" xmlns:b="urn:b" xmlns:unused="urn:unused"><a:child b:attr="1" z="tab&#9;nl&#10;" attr2="x&quot;&lt;&gt;" a:attr="2"><inner xmlns="">text &amp; &lt;more&gt; <![CDATA[<cdata>]]></inner><!-- comment --><empty   /></a:child></root>"#;
+
+        // reference output from `xmllint --exc-c14n`, minus the comment
+        let expected = r#"<root xmlns="urn:default"><a:child xmlns:a="urn:a" xmlns:b="urn:b" attr2="x&quot;&lt;>" z="tab&#x9;nl&#xA;" a:attr="2" b:attr="1"><inner xmlns="">text &amp; &lt;more&gt; &lt;cdata&gt;</inner><empty></empty></a:child></root>"#;
+
+        let doc = parse(input).unwrap();
+        assert_eq!(canonicalize(doc.root_element(), None, &[]), expected);
+    }
+
+    #[test]
+    fn test_subtree_canonicalization() {
+        let input = r#"<a:root xmlns:a="urn:a" xmlns:xs="urn:xs"><a:keep a:x="1"><a:drop/>value</a:keep></a:root>"#;
+        let doc = parse(input).unwrap();
+        let keep = doc.root_element().first_element_child().unwrap();
+        let drop = keep.first_element_child().unwrap();
+
+        // the subtree redeclares the namespaces it uses, excluded nodes vanish
+        assert_eq!(
+            canonicalize(keep, Some(drop.id()), &[]),
+            r#"<a:keep xmlns:a="urn:a" a:x="1">value</a:keep>"#
+        );
+        // inclusive prefixes are rendered even when unused
+        assert_eq!(
+            canonicalize(keep, Some(drop.id()), &["xs"]),
+            r#"<a:keep xmlns:a="urn:a" xmlns:xs="urn:xs" a:x="1">value</a:keep>"#
+        );
+    }
+
+    #[test]
+    fn test_rejects_dtd() {
+        let input = r#"<!DOCTYPE r [<!ENTITY x "boom">]><r>&x;</r>"#;
+        assert!(parse(input).is_err());
+    }
+}
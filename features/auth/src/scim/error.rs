@@ -0,0 +1,80 @@
+pub type Result<T> = std::result::Result<T, ScimError>;
+
+// Errors defined by RFC 7644, rendered with their status and scimType
+#[derive(Debug)]
+pub enum ScimError {
+    InvalidToken,
+    NotFound(String),
+    Uniqueness,
+    PreconditionFailed,
+    InvalidFilter(String),
+    InvalidPath(String),
+    InvalidValue(String),
+    InvalidSyntax(String),
+    Mutability(String),
+    NoTarget,
+}
+
+impl ScimError {
+    pub fn status(&self) -> u16 {
+        match self {
+            ScimError::InvalidToken => 401,
+            ScimError::NotFound(_) => 404,
+            ScimError::Uniqueness => 409,
+            ScimError::PreconditionFailed => 412,
+            _ => 400,
+        }
+    }
+
+    // value of the `scimType` field, only defined for some errors
+    pub fn scim_type(&self) -> Option<&'static str> {
+        match self {
+            ScimError::Uniqueness => Some("uniqueness"),
+            ScimError::InvalidFilter(_) => Some("invalidFilter"),
+            ScimError::InvalidPath(_) => Some("invalidPath"),
+            ScimError::InvalidValue(_) => Some("invalidValue"),
+            ScimError::InvalidSyntax(_) => Some("invalidSyntax"),
+            ScimError::Mutability(_) => Some("mutability"),
+            ScimError::NoTarget => Some("noTarget"),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ScimError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            ScimError::NotFound(e) => write!(fmt, "Resource not found: {e}"),
+            ScimError::InvalidFilter(e) => write!(fmt, "Invalid filter: {e}"),
+            ScimError::InvalidPath(e) => write!(fmt, "Invalid path: {e}"),
+            ScimError::InvalidValue(e) => write!(fmt, "Invalid value: {e}"),
+            ScimError::InvalidSyntax(e) => write!(fmt, "Invalid syntax: {e}"),
+            ScimError::Mutability(e) => write!(fmt, "Attribute is read only: {e}"),
+            ScimError::InvalidToken
+            | ScimError::Uniqueness
+            | ScimError::PreconditionFailed
+            | ScimError::NoTarget => write!(fmt, "{self:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ScimError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scim_error_rendering() {
+        assert_eq!(
+            "Invalid filter: unexpected end",
+            ScimError::InvalidFilter("unexpected end".to_string()).to_string()
+        );
+        assert_eq!("Uniqueness", ScimError::Uniqueness.to_string());
+        assert_eq!(409, ScimError::Uniqueness.status());
+        assert_eq!(
+            Some("invalidPath"),
+            ScimError::InvalidPath("x".to_string()).scim_type()
+        );
+    }
+}
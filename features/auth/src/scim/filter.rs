@@ -0,0 +1,451 @@
+use serde_json::Value;
+
+use super::error::{Result, ScimError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Co,
+    Sw,
+    Ew,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl CompareOp {
+    fn parse(op: &str) -> Option<Self> {
+        match op.to_ascii_lowercase().as_str() {
+            "eq" => Some(CompareOp::Eq),
+            "ne" => Some(CompareOp::Ne),
+            "co" => Some(CompareOp::Co),
+            "sw" => Some(CompareOp::Sw),
+            "ew" => Some(CompareOp::Ew),
+            "gt" => Some(CompareOp::Gt),
+            "ge" => Some(CompareOp::Ge),
+            "lt" => Some(CompareOp::Lt),
+            "le" => Some(CompareOp::Le),
+            _ => None,
+        }
+    }
+}
+
+// An attribute with an optional sub-attribute, e.g. `name.givenName`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttrPath {
+    pub attr: String,
+    pub sub_attr: Option<String>,
+}
+
+impl AttrPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        // fully qualified names carry the schema, e.g. urn:...:User:userName
+        let path = match path.starts_with("urn:") {
+            true => path.rsplit(':').next().unwrap_or_default(),
+            false => path,
+        };
+
+        let (attr, sub_attr) = match path.split_once('.') {
+            Some((attr, sub_attr)) => (attr, Some(sub_attr)),
+            None => (path, None),
+        };
+
+        let valid = |name: &str| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '$'))
+        };
+        if !valid(attr) || !sub_attr.is_none_or(valid) {
+            return Err(ScimError::InvalidPath(path.to_string()));
+        }
+
+        Ok(Self {
+            attr: attr.to_string(),
+            sub_attr: sub_attr.map(str::to_string),
+        })
+    }
+
+    // Values at the path, multi-valued attributes are flattened. Without a sub-attribute
+    // complex values compare on their `value`.
+    fn values<'a>(&self, resource: &'a Value) -> Vec<&'a Value> {
+        let Some(value) = get(resource, &self.attr) else {
+            return Vec::new();
+        };
+
+        let entries: Vec<&Value> = match value {
+            Value::Array(entries) => entries.iter().collect(),
+            value => vec![value],
+        };
+
+        entries
+            .into_iter()
+            .filter_map(|entry| match (&self.sub_attr, entry) {
+                (Some(sub_attr), entry) => get(entry, sub_attr),
+                (None, entry @ Value::Object(_)) => get(entry, "value"),
+                (None, entry) => Some(entry),
+            })
+            .collect()
+    }
+}
+
+// Filters as defined in RFC 7644 section 3.4.2.2
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Compare(AttrPath, CompareOp, Value),
+    Present(AttrPath),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    // e.g. emails[type eq "work"], the inner filter applies to each value
+    ValuePath(AttrPath, Box<Filter>),
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(filter)?,
+            pos: 0,
+        };
+
+        let parsed = parser.or()?;
+        match parser.next() {
+            None => Ok(parsed),
+            Some(token) => Err(unexpected(&token)),
+        }
+    }
+
+    pub fn matches(&self, resource: &Value) -> bool {
+        match self {
+            Filter::Compare(path, CompareOp::Ne, expected) => !path
+                .values(resource)
+                .into_iter()
+                .any(|value| compare(value, CompareOp::Eq, expected)),
+            Filter::Compare(path, op, expected) => path
+                .values(resource)
+                .into_iter()
+                .any(|value| compare(value, *op, expected)),
+            Filter::Present(path) => path.values(resource).into_iter().any(|value| match value {
+                Value::Null => false,
+                Value::String(s) => !s.is_empty(),
+                Value::Array(a) => !a.is_empty(),
+                _ => true,
+            }),
+            Filter::And(left, right) => left.matches(resource) && right.matches(resource),
+            Filter::Or(left, right) => left.matches(resource) || right.matches(resource),
+            Filter::Not(filter) => !filter.matches(resource),
+            Filter::ValuePath(path, filter) => {
+                let values = match get(resource, &path.attr) {
+                    Some(Value::Array(values)) => values.iter().collect(),
+                    Some(value) => vec![value],
+                    None => Vec::new(),
+                };
+                values.into_iter().any(|value| filter.matches(value))
+            }
+        }
+    }
+}
+
+// Attribute names are case insensitive
+pub fn get<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+    value
+        .as_object()?
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+// Strings compare case insensitively, as none of the attributes we expose are caseExact
+fn compare(value: &Value, op: CompareOp, expected: &Value) -> bool {
+    match (value, expected) {
+        (Value::String(value), Value::String(expected)) => {
+            let (value, expected) = (value.to_lowercase(), expected.to_lowercase());
+            match op {
+                CompareOp::Eq => value == expected,
+                CompareOp::Ne => value != expected,
+                CompareOp::Co => value.contains(&expected),
+                CompareOp::Sw => value.starts_with(&expected),
+                CompareOp::Ew => value.ends_with(&expected),
+                CompareOp::Gt => value > expected,
+                CompareOp::Ge => value >= expected,
+                CompareOp::Lt => value < expected,
+                CompareOp::Le => value <= expected,
+            }
+        }
+        (Value::Number(value), Value::Number(expected)) => {
+            let (Some(value), Some(expected)) = (value.as_f64(), expected.as_f64()) else {
+                return false;
+            };
+            match op {
+                CompareOp::Eq => value == expected,
+                CompareOp::Ne => value != expected,
+                CompareOp::Gt => value > expected,
+                CompareOp::Ge => value >= expected,
+                CompareOp::Lt => value < expected,
+                CompareOp::Le => value <= expected,
+                _ => false,
+            }
+        }
+        (value, expected) => match op {
+            CompareOp::Eq => value == expected,
+            CompareOp::Ne => value != expected,
+            _ => false,
+        },
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Word(String),
+    Str(String),
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::OpenParen),
+            ')' => tokens.push(Token::CloseParen),
+            '[' => tokens.push(Token::OpenBracket),
+            ']' => tokens.push(Token::CloseBracket),
+            '"' => {
+                let mut end = None;
+                let mut escaped = false;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        '\\' if !escaped => escaped = true,
+                        '"' if !escaped => {
+                            end = Some(i);
+                            break;
+                        }
+                        _ => escaped = false,
+                    }
+                }
+                let end = end.ok_or_else(|| invalid("unterminated string"))?;
+
+                // strings use the JSON escapes
+                let value = serde_json::from_str(&filter[start..=end])
+                    .map_err(|_| invalid("invalid string"))?;
+                tokens.push(Token::Str(value));
+            }
+            _ => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '"') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                tokens.push(Token::Word(filter[start..end].to_string()));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(invalid("unexpected end")),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(
+            self.tokens.get(self.pos),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword)
+        );
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<Filter> {
+        let mut filter = self.and()?;
+        while self.keyword("or") {
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter> {
+        let mut filter = self.unary()?;
+        while self.keyword("and") {
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter> {
+        if self.keyword("not") {
+            self.expect(Token::OpenParen)?;
+            let filter = self.or()?;
+            self.expect(Token::CloseParen)?;
+            return Ok(Filter::Not(Box::new(filter)));
+        }
+
+        match self.next() {
+            Some(Token::OpenParen) => {
+                let filter = self.or()?;
+                self.expect(Token::CloseParen)?;
+                Ok(filter)
+            }
+            Some(Token::Word(path)) => self.attribute(AttrPath::parse(&path)?),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(invalid("unexpected end")),
+        }
+    }
+
+    fn attribute(&mut self, path: AttrPath) -> Result<Filter> {
+        if self.tokens.get(self.pos) == Some(&Token::OpenBracket) {
+            self.pos += 1;
+            let filter = self.or()?;
+            self.expect(Token::CloseBracket)?;
+            return Ok(Filter::ValuePath(path, Box::new(filter)));
+        }
+
+        if self.keyword("pr") {
+            return Ok(Filter::Present(path));
+        }
+
+        let op = match self.next() {
+            Some(Token::Word(op)) => {
+                CompareOp::parse(&op).ok_or_else(|| invalid(&format!("unknown operator {op}")))?
+            }
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(invalid("unexpected end")),
+        };
+
+        let value = match self.next() {
+            Some(Token::Str(value)) => Value::String(value),
+            Some(Token::Word(word)) => match word.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                number => serde_json::from_str::<serde_json::Number>(number)
+                    .map(Value::Number)
+                    .map_err(|_| invalid(&format!("invalid value {number}")))?,
+            },
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(invalid("unexpected end")),
+        };
+
+        Ok(Filter::Compare(path, op, value))
+    }
+}
+
+fn unexpected(token: &Token) -> ScimError {
+    invalid(&format!("unexpected {token:?}"))
+}
+
+fn invalid(reason: &str) -> ScimError {
+    ScimError::InvalidFilter(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn user() -> Value {
+        json!({
+            "userName": "bjensen@example.com",
+            "name": { "givenName": "Barbara", "familyName": "O'Malley" },
+            "active": true,
+            "emails": [
+                { "value": "bjensen@example.com", "type": "work", "primary": true },
+                { "value": "babs@jensen.org", "type": "home" }
+            ],
+            "meta": { "lastModified": "2025-05-13T04:42:34Z" }
+        })
+    }
+
+    fn matches(filter: &str) -> bool {
+        Filter::parse(filter).unwrap().matches(&user())
+    }
+
+    #[test]
+    fn test_rfc_examples() {
+        assert!(matches(r#"userName eq "BJensen@example.com""#));
+        assert!(matches(r#"name.familyName co "o'malley""#));
+        assert!(matches(r#"userName sw "bj""#));
+        assert!(matches(r#"userName ew "@example.com""#));
+        assert!(matches("name.givenName pr"));
+        assert!(!matches("displayName pr"));
+        assert!(matches(r#"meta.lastModified gt "2011-05-13T04:42:34Z""#));
+        assert!(!matches(r#"meta.lastModified lt "2011-05-13T04:42:34Z""#));
+        assert!(matches(
+            r#"urn:ietf:params:scim:schemas:core:2.0:User:userName pr"#
+        ));
+    }
+
+    #[test]
+    fn test_logical_and_value_path_filters() {
+        assert!(matches(r#"active eq true and userName sw "b""#));
+        assert!(matches(r#"active eq false or userName sw "b""#));
+        assert!(!matches("not (active eq true)"));
+        assert!(matches(
+            r#"userName eq "x" or (active eq true and name.givenName eq "barbara")"#
+        ));
+
+        // multi-valued attributes match when any value does
+        assert!(matches(r#"emails eq "babs@jensen.org""#));
+        assert!(matches(r#"emails.type eq "home""#));
+        assert!(matches(
+            r#"emails[type eq "work" and value co "@example.com"]"#
+        ));
+        assert!(!matches(
+            r#"emails[type eq "home" and value co "@example.com"]"#
+        ));
+        assert!(matches(r#"emails ne "nobody@example.com""#));
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        for filter in [
+            "",
+            "userName",
+            r#"userName eq"#,
+            r#"userName xx "a""#,
+            r#"userName eq "unterminated"#,
+            r#"(userName eq "a""#,
+            r#"userName eq "a" garbage"#,
+            r#"emails[type eq "work""#,
+            r#"user..name eq "a""#,
+        ] {
+            assert!(
+                matches!(
+                    Filter::parse(filter),
+                    Err(ScimError::InvalidFilter(_)) | Err(ScimError::InvalidPath(_))
+                ),
+                "{filter}"
+            );
+        }
+    }
+}
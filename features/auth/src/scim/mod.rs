@@ -0,0 +1,450 @@
+pub mod error;
+pub(crate) mod filter;
+pub(crate) mod patch;
+pub mod resources;
+
+use std::sync::Arc;
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use uuid::Uuid;
+
+use crate::error::{AuthError, Result};
+use crate::models::{AccountStatus, Group, ProvisionedUser, ScimToken, User};
+use crate::password::{self, hash_password, verify_password};
+use crate::repository::ScimRepositoryTrait;
+use crate::repository::in_mem_scim_repo::InMemoryScimRepository;
+use crate::utils::{is_valid_org, random_string};
+
+use error::ScimError;
+use filter::Filter;
+use resources::{
+    GROUP_SCHEMA, LIST_RESPONSE_SCHEMA, ListQuery, ListResponse, Meta, MultiValued, PatchRequest,
+    SERVICE_PROVIDER_CONFIG_SCHEMA, ScimGroup, ScimName, ScimUser, USER_SCHEMA,
+};
+
+pub const SCIM_TOKEN_PREFIX: &str = "scim";
+// most resources a list returns, also the default page size
+pub const MAX_RESULTS: usize = 100;
+
+const TOKEN_PREFIX_LEN: usize = 8;
+const TOKEN_SECRET_LEN: usize = 40;
+const TOKEN_DELIMETER: char = '_';
+
+// SCIM 2.0 (RFC 7643/7644) provisioning for organizations' directories. Groups live here
+// entirely, users are `User`s and `AuthService` keeps the two in sync.
+pub struct ScimProvisioner {
+    base_url: String,
+    scim_repo: Arc<dyn ScimRepositoryTrait>,
+}
+
+impl ScimProvisioner {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            scim_repo: Arc::new(InMemoryScimRepository::new()),
+        }
+    }
+
+    pub fn with_scim_repo(mut self, scim_repo: Arc<dyn ScimRepositoryTrait>) -> Self {
+        self.scim_repo = scim_repo;
+        self
+    }
+
+    // for the unit of work that writes a user's records with the user
+    pub(crate) fn scim_repo(&self) -> Arc<dyn ScimRepositoryTrait> {
+        self.scim_repo.clone()
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("{}/scim/v2", self.base_url)
+    }
+
+    // Bearer token for the org's directory, e.g. scim_AbCd1234_<secret>, only its hash is kept
+    pub async fn create_token(&self, org: &str) -> Result<String> {
+        if !is_valid_org(org) {
+            return Err(ScimError::InvalidValue("invalid organization name".to_string()).into());
+        }
+
+        let prefix = random_string(TOKEN_PREFIX_LEN);
+        let secret = random_string(TOKEN_SECRET_LEN);
+        let token_hash = hash_password(&password::ContentToHash {
+            content: secret.clone(),
+            salt: Uuid::new_v4(),
+        })?;
+
+        self.scim_repo
+            .create_token(ScimToken {
+                id: Uuid::new_v4().to_string(),
+                org: org.to_string(),
+                prefix: prefix.clone(),
+                token_hash,
+                created_at: OffsetDateTime::now_utc().unix_timestamp(),
+                revoked_at: None,
+            })
+            .await?;
+
+        Ok(format!(
+            "{SCIM_TOKEN_PREFIX}{TOKEN_DELIMETER}{prefix}{TOKEN_DELIMETER}{secret}"
+        ))
+    }
+
+    // The organization the token was issued to
+    pub async fn authenticate(&self, token: &str) -> Result<String> {
+        let mut parts = token.splitn(3, TOKEN_DELIMETER);
+        let (Some(SCIM_TOKEN_PREFIX), Some(prefix), Some(secret)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ScimError::InvalidToken.into());
+        };
+
+        let stored = self
+            .scim_repo
+            .find_token_by_prefix(prefix)
+            .await?
+            .filter(|t| t.revoked_at.is_none())
+            .ok_or(ScimError::InvalidToken)?;
+        verify_password(secret, &stored.token_hash).map_err(|_| ScimError::InvalidToken)?;
+
+        Ok(stored.org)
+    }
+
+    // Including deprovisioned users, which the directory can provision again
+    pub async fn find_provisioned_user(
+        &self,
+        org: &str,
+        user_id: &str,
+    ) -> Result<Option<ProvisionedUser>> {
+        Ok(self.scim_repo.find_provisioned_user(org, user_id).await?)
+    }
+
+    pub async fn provisioned_user(&self, org: &str, user_id: &str) -> Result<ProvisionedUser> {
+        self.find_provisioned_user(org, user_id)
+            .await?
+            .filter(|p| p.deprovisioned_at.is_none())
+            .ok_or_else(|| ScimError::NotFound(user_id.to_string()).into())
+    }
+
+    pub async fn provisioned_users(&self, org: &str) -> Result<Vec<ProvisionedUser>> {
+        let mut users = self.scim_repo.list_provisioned_users(org).await?;
+        users.retain(|p| p.deprovisioned_at.is_none());
+        Ok(users)
+    }
+
+    // Every organization's record of the user and the groups they're in
+    pub async fn memberships(&self, user_id: &str) -> Result<(Vec<ProvisionedUser>, Vec<Group>)> {
+        let records = self.scim_repo.list_provisioned_by_user(user_id).await?;
+        let groups = self.scim_repo.list_groups_by_member(user_id).await?;
+        Ok((records, groups))
+    }
+
+    pub async fn groups(&self, org: &str) -> Result<Vec<Group>> {
+        Ok(self.scim_repo.list_groups(org).await?)
+    }
+
+    pub fn user_resource(
+        &self,
+        user: &User,
+        provisioned: &ProvisionedUser,
+        groups: &[Group],
+    ) -> ScimUser {
+        let name = ScimName {
+            formatted: provisioned.formatted_name.clone(),
+            given_name: provisioned.given_name.clone(),
+            family_name: provisioned.family_name.clone(),
+        };
+        let mut email = MultiValued::new(&user.email);
+        email.kind = Some("work".to_string());
+        email.primary = Some(true);
+
+        let mut resource = ScimUser {
+            schemas: vec![USER_SCHEMA.to_string()],
+            id: Some(user.id.clone()),
+            external_id: provisioned.external_id.clone(),
+            user_name: provisioned.user_name.clone(),
+            name: (name.formatted.is_some()
+                || name.given_name.is_some()
+                || name.family_name.is_some())
+            .then_some(name),
+            display_name: provisioned.display_name.clone(),
+            emails: vec![email],
+            // suspensions are left out, writing them back would disable the account
+            active: user.status != AccountStatus::Disabled,
+            groups: groups
+                .iter()
+                .filter(|g| g.members.contains(&user.id))
+                .map(|g| MultiValued {
+                    display: Some(g.display_name.clone()),
+                    reference: Some(self.location("Groups", &g.id)),
+                    ..MultiValued::new(&g.id)
+                })
+                .collect(),
+            meta: None,
+        };
+        resource.meta = Some(Meta {
+            resource_type: "User".to_string(),
+            created: instant(user.created_at.min(provisioned.created_at)),
+            last_modified: instant(user.updated_at.max(provisioned.updated_at)),
+            location: self.location("Users", &user.id),
+            version: version(&resource),
+        });
+        resource
+    }
+
+    pub async fn list_groups(
+        &self,
+        org: &str,
+        query: &ListQuery,
+    ) -> Result<ListResponse<ScimGroup>> {
+        let groups = self.scim_repo.list_groups(org).await?;
+        list(
+            groups.iter().map(|g| self.group_resource(g)).collect(),
+            query,
+        )
+    }
+
+    pub async fn get_group(&self, org: &str, id: &str) -> Result<ScimGroup> {
+        Ok(self.group_resource(&self.group(org, id).await?))
+    }
+
+    pub async fn create_group(&self, org: &str, group: ScimGroup) -> Result<ScimGroup> {
+        let display_name = self.display_name(org, &group, None).await?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let created = self
+            .scim_repo
+            .create_group(Group {
+                id: Uuid::new_v4().to_string(),
+                org: org.to_string(),
+                display_name,
+                external_id: group.external_id,
+                members: self.member_ids(org, &group.members).await?,
+                created_at: now,
+                updated_at: now,
+            })
+            .await?;
+
+        Ok(self.group_resource(&created))
+    }
+
+    pub async fn replace_group(
+        &self,
+        org: &str,
+        id: &str,
+        group: ScimGroup,
+        if_match: Option<&str>,
+    ) -> Result<ScimGroup> {
+        let existing = self.group(org, id).await?;
+        check_version(self.group_resource(&existing).meta.as_ref(), if_match)?;
+
+        let updated = self
+            .scim_repo
+            .update_group(&Group {
+                display_name: self.display_name(org, &group, Some(id)).await?,
+                external_id: group.external_id,
+                members: self.member_ids(org, &group.members).await?,
+                ..existing
+            })
+            .await?;
+
+        Ok(self.group_resource(&updated))
+    }
+
+    pub async fn patch_group(
+        &self,
+        org: &str,
+        id: &str,
+        patch: &PatchRequest,
+        if_match: Option<&str>,
+    ) -> Result<ScimGroup> {
+        let existing = self.group_resource(&self.group(org, id).await?);
+        check_version(existing.meta.as_ref(), if_match)?;
+
+        let patched = apply_patch(&existing, patch)?;
+        self.replace_group(org, id, patched, None).await
+    }
+
+    pub async fn delete_group(&self, org: &str, id: &str, if_match: Option<&str>) -> Result<()> {
+        let existing = self.group(org, id).await?;
+        check_version(self.group_resource(&existing).meta.as_ref(), if_match)?;
+
+        Ok(self.scim_repo.delete_group(org, id).await?)
+    }
+
+    // What the directory can discover about our implementation
+    pub fn service_provider_config(&self) -> Value {
+        json!({
+            "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+            "documentationUri": format!("{}/ServiceProviderConfig", self.endpoint()),
+            "patch": { "supported": true },
+            "bulk": { "supported": false, "maxOperations": 0, "maxPayloadSize": 0 },
+            "filter": { "supported": true, "maxResults": MAX_RESULTS },
+            "changePassword": { "supported": false },
+            "sort": { "supported": false },
+            "etag": { "supported": true },
+            "authenticationSchemes": [{
+                "type": "oauthbearertoken",
+                "name": "OAuth Bearer Token",
+                "description": "Authentication with the organization's SCIM token",
+                "primary": true
+            }]
+        })
+    }
+
+    async fn group(&self, org: &str, id: &str) -> Result<Group> {
+        self.scim_repo
+            .find_group(org, id)
+            .await?
+            .ok_or_else(|| ScimError::NotFound(id.to_string()).into())
+    }
+
+    // Group names are unique within the organization
+    async fn display_name(&self, org: &str, group: &ScimGroup, id: Option<&str>) -> Result<String> {
+        let display_name = group.display_name.trim();
+        if display_name.is_empty() {
+            return Err(ScimError::InvalidValue("displayName".to_string()).into());
+        }
+
+        let taken = self.scim_repo.list_groups(org).await?.iter().any(|g| {
+            Some(g.id.as_str()) != id && g.display_name.eq_ignore_ascii_case(display_name)
+        });
+        if taken {
+            return Err(ScimError::Uniqueness.into());
+        }
+
+        Ok(display_name.to_string())
+    }
+
+    // Members have to be users the organization provisioned
+    async fn member_ids(&self, org: &str, members: &[MultiValued]) -> Result<Vec<String>> {
+        let mut ids: Vec<String> = Vec::new();
+        for member in members {
+            if ids.contains(&member.value) {
+                continue;
+            }
+            if self.provisioned_user(org, &member.value).await.is_err() {
+                return Err(
+                    ScimError::InvalidValue(format!("unknown member {}", member.value)).into(),
+                );
+            }
+            ids.push(member.value.clone());
+        }
+        Ok(ids)
+    }
+
+    fn group_resource(&self, group: &Group) -> ScimGroup {
+        let mut resource = ScimGroup {
+            schemas: vec![GROUP_SCHEMA.to_string()],
+            id: Some(group.id.clone()),
+            external_id: group.external_id.clone(),
+            display_name: group.display_name.clone(),
+            members: group
+                .members
+                .iter()
+                .map(|id| MultiValued {
+                    reference: Some(self.location("Users", id)),
+                    ..MultiValued::new(id)
+                })
+                .collect(),
+            meta: None,
+        };
+        resource.meta = Some(Meta {
+            resource_type: "Group".to_string(),
+            created: instant(group.created_at),
+            last_modified: instant(group.updated_at),
+            location: self.location("Groups", &group.id),
+            version: version(&resource),
+        });
+        resource
+    }
+
+    fn location(&self, resource_type: &str, id: &str) -> String {
+        format!("{}/{resource_type}/{id}", self.endpoint())
+    }
+}
+
+// Filters and paginates resources, in the order they were created
+pub fn list<T: Serialize>(resources: Vec<T>, query: &ListQuery) -> Result<ListResponse<T>> {
+    let filter = query.filter.as_deref().map(Filter::parse).transpose()?;
+
+    let mut matching = Vec::new();
+    for resource in resources {
+        let matches = match &filter {
+            Some(filter) => {
+                let json = serde_json::to_value(&resource)
+                    .map_err(|e| ScimError::InvalidValue(e.to_string()))?;
+                filter.matches(&json)
+            }
+            None => true,
+        };
+        if matches {
+            matching.push(resource);
+        }
+    }
+
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let count = query.count.unwrap_or(MAX_RESULTS).min(MAX_RESULTS);
+    let total_results = matching.len();
+    let resources: Vec<T> = matching
+        .into_iter()
+        .skip(start_index - 1)
+        .take(count)
+        .collect();
+
+    Ok(ListResponse {
+        schemas: vec![LIST_RESPONSE_SCHEMA.to_string()],
+        total_results,
+        start_index,
+        items_per_page: resources.len(),
+        resources,
+    })
+}
+
+// Applies a PatchOp request to a resource, through its JSON form
+pub fn apply_patch<T: Serialize + DeserializeOwned>(
+    resource: &T,
+    patch: &PatchRequest,
+) -> Result<T> {
+    let mut json =
+        serde_json::to_value(resource).map_err(|e| ScimError::InvalidValue(e.to_string()))?;
+    for operation in &patch.operations {
+        patch::apply(&mut json, operation)?;
+    }
+
+    Ok(serde_json::from_value(json).map_err(|e| ScimError::InvalidValue(e.to_string()))?)
+}
+
+// If-Match of a modifying request has to hold the current version
+pub fn check_version(meta: Option<&Meta>, if_match: Option<&str>) -> Result<()> {
+    let Some(if_match) = if_match else {
+        return Ok(());
+    };
+    let current = meta.map(|m| m.version.as_str()).unwrap_or_default();
+
+    // versions are weak, compare without the W/ marker
+    let opaque = |etag: &str| etag.trim().trim_start_matches("W/").to_string();
+    let matches = if_match.trim() == "*"
+        || if_match
+            .split(',')
+            .any(|etag| opaque(etag) == opaque(current));
+    match matches {
+        true => Ok(()),
+        false => Err(ScimError::PreconditionFailed.into()),
+    }
+}
+
+// Weak etag over everything but the meta, so it changes with any attribute
+fn version<T: Serialize>(resource: &T) -> String {
+    let digest = Sha256::digest(serde_json::to_vec(resource).unwrap_or_default());
+    let hex: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    format!("W/\"{hex}\"")
+}
+
+fn instant(ts: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(ts)
+        .ok()
+        .and_then(|dt| dt.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
@@ -0,0 +1,363 @@
+use serde_json::{Map, Value};
+
+use super::error::{Result, ScimError};
+use super::filter::{AttrPath, Filter};
+use super::resources::PatchOperation;
+
+// Set by the server, a patch can only repeat their current value
+const READ_ONLY: [&str; 3] = ["id", "meta", "schemas"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Replace,
+    Remove,
+}
+
+// A patch path, e.g. `members[value eq "2819c223"]` or `emails[type eq "work"].value`
+struct PatchPath {
+    attr: String,
+    filter: Option<Filter>,
+    sub_attr: Option<String>,
+}
+
+impl PatchPath {
+    fn parse(path: &str) -> Result<Self> {
+        let Some((attr, rest)) = path.split_once('[') else {
+            let path = AttrPath::parse(path)?;
+            return Ok(Self {
+                attr: path.attr,
+                filter: None,
+                sub_attr: path.sub_attr,
+            });
+        };
+
+        let (filter, sub_attr) = rest
+            .rsplit_once(']')
+            .ok_or_else(|| ScimError::InvalidPath(path.to_string()))?;
+        let sub_attr = match sub_attr {
+            "" => None,
+            sub_attr => Some(
+                sub_attr
+                    .strip_prefix('.')
+                    .and_then(|s| AttrPath::parse(s).ok())
+                    .filter(|s| s.sub_attr.is_none())
+                    .ok_or_else(|| ScimError::InvalidPath(path.to_string()))?
+                    .attr,
+            ),
+        };
+
+        let attr = AttrPath::parse(attr)?;
+        if attr.sub_attr.is_some() {
+            return Err(ScimError::InvalidPath(path.to_string()));
+        }
+
+        Ok(Self {
+            attr: attr.attr,
+            filter: Some(Filter::parse(filter)?),
+            sub_attr,
+        })
+    }
+}
+
+// Applies an operation of a PatchOp request to the JSON form of a resource
+pub fn apply(resource: &mut Value, operation: &PatchOperation) -> Result<()> {
+    let op = match operation.op.to_ascii_lowercase().as_str() {
+        "add" => Op::Add,
+        "replace" => Op::Replace,
+        "remove" => Op::Remove,
+        op => return Err(ScimError::InvalidSyntax(format!("unknown op {op}"))),
+    };
+    let resource = resource
+        .as_object_mut()
+        .ok_or_else(|| ScimError::InvalidSyntax("resource".to_string()))?;
+
+    let Some(path) = &operation.path else {
+        // without a path the value holds the attributes to add or replace
+        let Some(Value::Object(attributes)) = &operation.value else {
+            return match op {
+                Op::Remove => Err(ScimError::NoTarget),
+                _ => Err(ScimError::InvalidValue("expected an object".to_string())),
+            };
+        };
+
+        for (name, value) in attributes {
+            let path = PatchPath::parse(name)?;
+            apply_at(resource, op, &path, Some(value))?;
+        }
+        return Ok(());
+    };
+
+    let value = operation.value.as_ref();
+    if op != Op::Remove && value.is_none() {
+        return Err(ScimError::InvalidValue("missing value".to_string()));
+    }
+
+    apply_at(resource, op, &PatchPath::parse(path)?, value)
+}
+
+fn apply_at(
+    resource: &mut Map<String, Value>,
+    op: Op,
+    path: &PatchPath,
+    value: Option<&Value>,
+) -> Result<()> {
+    let key = key_of(resource, &path.attr);
+    if READ_ONLY.iter().any(|attr| attr.eq_ignore_ascii_case(&key)) {
+        if value.is_some() && value == resource.get(&key) {
+            return Ok(());
+        }
+        return Err(ScimError::Mutability(key));
+    }
+
+    if let Some(filter) = &path.filter {
+        return apply_filtered(resource, op, &key, filter, path.sub_attr.as_deref(), value);
+    }
+
+    match (op, &path.sub_attr, value) {
+        (Op::Remove, None, Some(Value::Array(removed))) => {
+            // removing specific values of a multi-valued attribute
+            if let Some(Value::Array(entries)) = resource.get_mut(&key) {
+                entries.retain(|entry| !removed.iter().any(|r| same_entry(entry, r)));
+            }
+        }
+        (Op::Remove, None, _) => {
+            resource.remove(&key);
+        }
+        (Op::Remove, Some(sub_attr), _) => {
+            if let Some(Value::Object(complex)) = resource.get_mut(&key) {
+                let sub_key = key_of(complex, sub_attr);
+                complex.remove(&sub_key);
+            }
+        }
+        (op, None, Some(value)) => {
+            let existing = resource.entry(key).or_insert(Value::Null);
+            set(existing, op, value.clone());
+        }
+        (op, Some(sub_attr), Some(value)) => {
+            let complex = resource
+                .entry(key)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if complex.is_null() {
+                *complex = Value::Object(Map::new());
+            }
+            let Value::Object(complex) = complex else {
+                return Err(ScimError::InvalidPath(sub_attr.to_string()));
+            };
+
+            let sub_key = key_of(complex, sub_attr);
+            let existing = complex.entry(sub_key).or_insert(Value::Null);
+            set(existing, op, value.clone());
+        }
+        (_, _, None) => return Err(ScimError::InvalidValue("missing value".to_string())),
+    }
+
+    Ok(())
+}
+
+// Operations on the values of a multi-valued attribute matching a filter
+fn apply_filtered(
+    resource: &mut Map<String, Value>,
+    op: Op,
+    key: &str,
+    filter: &Filter,
+    sub_attr: Option<&str>,
+    value: Option<&Value>,
+) -> Result<()> {
+    let Some(Value::Array(entries)) = resource.get_mut(key) else {
+        return match op {
+            Op::Remove => Ok(()),
+            _ => Err(ScimError::NoTarget),
+        };
+    };
+
+    match (op, sub_attr) {
+        (Op::Remove, None) => entries.retain(|entry| !filter.matches(entry)),
+        (Op::Remove, Some(sub_attr)) => {
+            for entry in entries.iter_mut().filter(|entry| filter.matches(entry)) {
+                if let Value::Object(entry) = entry {
+                    let sub_key = key_of(entry, sub_attr);
+                    entry.remove(&sub_key);
+                }
+            }
+        }
+        (op, sub_attr) => {
+            let value =
+                value.ok_or_else(|| ScimError::InvalidValue("missing value".to_string()))?;
+
+            let mut matched = false;
+            for entry in entries.iter_mut().filter(|entry| filter.matches(entry)) {
+                matched = true;
+                match (sub_attr, &mut *entry) {
+                    (Some(sub_attr), Value::Object(entry)) => {
+                        let sub_key = key_of(entry, sub_attr);
+                        entry.insert(sub_key, value.clone());
+                    }
+                    (_, entry) => set(entry, op, value.clone()),
+                }
+            }
+            if !matched {
+                return Err(ScimError::NoTarget);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// `add` appends to multi-valued attributes and merges complex ones, `replace` overwrites
+fn set(existing: &mut Value, op: Op, value: Value) {
+    match (op, existing, value) {
+        (Op::Add, Value::Array(entries), value) => {
+            let added = match value {
+                Value::Array(added) => added,
+                value => vec![value],
+            };
+            for entry in added {
+                if !entries.iter().any(|e| same_entry(e, &entry)) {
+                    entries.push(entry);
+                }
+            }
+        }
+        (_, Value::Object(complex), Value::Object(attributes)) => {
+            for (name, value) in attributes {
+                let key = key_of(complex, &name);
+                complex.insert(key, value);
+            }
+        }
+        (_, existing, value) => *existing = value,
+    }
+}
+
+// values of a multi-valued attribute are identified by their `value`
+fn same_entry(a: &Value, b: &Value) -> bool {
+    match (a.get("value"), b.get("value")) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+// the existing key matching `name` case insensitively, or `name` for a new attribute
+fn key_of(object: &Map<String, Value>, name: &str) -> String {
+    object
+        .keys()
+        .find(|key| key.eq_ignore_ascii_case(name))
+        .cloned()
+        .unwrap_or_else(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn patch(
+        resource: &mut Value,
+        op: &str,
+        path: Option<&str>,
+        value: Option<Value>,
+    ) -> Result<()> {
+        apply(
+            resource,
+            &PatchOperation {
+                op: op.to_string(),
+                path: path.map(str::to_string),
+                value,
+            },
+        )
+    }
+
+    #[test]
+    fn test_simple_attributes() {
+        let mut user = json!({ "id": "1", "userName": "bjensen", "active": true });
+
+        patch(&mut user, "replace", Some("active"), Some(json!(false))).unwrap();
+        patch(
+            &mut user,
+            "Add",
+            Some("name.givenName"),
+            Some(json!("Barbara")),
+        )
+        .unwrap();
+        patch(
+            &mut user,
+            "replace",
+            None,
+            Some(json!({ "displayName": "Babs", "id": "1" })),
+        )
+        .unwrap();
+        patch(&mut user, "remove", Some("userName"), None).unwrap();
+        assert_eq!(
+            user,
+            json!({ "id": "1", "active": false, "name": { "givenName": "Barbara" }, "displayName": "Babs" })
+        );
+
+        assert!(matches!(
+            patch(&mut user, "replace", Some("id"), Some(json!("2"))),
+            Err(ScimError::Mutability(_))
+        ));
+        assert!(matches!(
+            patch(&mut user, "remove", None, None),
+            Err(ScimError::NoTarget)
+        ));
+        assert!(matches!(
+            patch(&mut user, "move", Some("active"), Some(json!(true))),
+            Err(ScimError::InvalidSyntax(_))
+        ));
+    }
+
+    #[test]
+    fn test_multi_valued_attributes() {
+        let mut group = json!({ "displayName": "Admins", "members": [{ "value": "a" }] });
+
+        // adding an existing member is a no-op
+        patch(
+            &mut group,
+            "add",
+            Some("members"),
+            Some(json!([{ "value": "a" }, { "value": "b" }, { "value": "c" }])),
+        )
+        .unwrap();
+        assert_eq!(group["members"].as_array().unwrap().len(), 3);
+
+        patch(&mut group, "remove", Some(r#"members[value eq "b"]"#), None).unwrap();
+        patch(
+            &mut group,
+            "remove",
+            Some("members"),
+            Some(json!([{ "value": "c" }])),
+        )
+        .unwrap();
+        assert_eq!(group["members"], json!([{ "value": "a" }]));
+
+        let mut user = json!({ "emails": [{ "value": "old@example.com", "type": "work" }] });
+        patch(
+            &mut user,
+            "replace",
+            Some(r#"emails[type eq "work"].value"#),
+            Some(json!("new@example.com")),
+        )
+        .unwrap();
+        assert_eq!(user["emails"][0]["value"], "new@example.com");
+
+        assert!(matches!(
+            patch(
+                &mut user,
+                "replace",
+                Some(r#"emails[type eq "home"].value"#),
+                Some(json!("x"))
+            ),
+            Err(ScimError::NoTarget)
+        ));
+        assert!(matches!(
+            patch(
+                &mut user,
+                "replace",
+                Some(r#"emails[type eq "work""#),
+                Some(json!("x"))
+            ),
+            Err(ScimError::InvalidPath(_))
+        ));
+    }
+}
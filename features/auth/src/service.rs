@@ -1,64 +1,479 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
+use crate::api_key::{generate_api_key, parse_api_key};
+use crate::audit::{AuditEvent, AuditFilter, AuditSink, AuthEvent, in_mem_sink::InMemoryAuditSink};
+use crate::authz_server::oidc::{Jwks, ProviderMetadata, UserInfo};
+use crate::authz_server::{
+    AccessGrant, AuthorizationServer, AuthorizeParams, ClientCredentials, Introspection,
+    TokenRequest, TokenResponse,
+};
 use crate::error::{AuthError, Result};
+use crate::gdpr::{
+    ApiKeyRecord, ClientAppRecord, DataExport, ExportFormat, LinkedIdentity, Membership,
+    PendingEmailChange, SessionRecord,
+};
+use crate::hooks::{AuthHook, HookRegistry};
 use crate::jwt::JwtService;
-use crate::models::{Credentials, RegisterUser, User};
+use crate::mailer::{Email, LogMailer, Mailer};
+use crate::models::{
+    AccountStatus, ActiveSession, ApiKey, ClientApp, ClientInfo, Credentials, EmailChange,
+    ExternalIdentity, Identity, NewApiKey, NewClientApp, OAuthProvider, PasswordReset,
+    ProvisionedUser, RegisterUser, Role, Scope, Session, SessionMode, SsoConnection, User,
+    UserList, UserQuery,
+};
+use crate::oauth::{AuthorizationRequest, OAuthClient, transport::HttpTransport};
+use crate::outbox::{
+    DomainEvent, Outbox, OutboxMessage, OutboxStore, in_mem_store::InMemoryOutbox,
+};
 use crate::password::{self, hash_password, verify_password};
 use crate::pwd_scheme::SchemeStatus;
-use crate::repository::UserRepositoryTrait;
+use crate::repository::in_mem_api_key_repo::InMemoryApiKeyRepository;
+use crate::repository::in_mem_email_change_repo::InMemoryEmailChangeRepository;
+use crate::repository::in_mem_identity_repo::InMemoryIdentityRepository;
+use crate::repository::in_mem_password_reset_repo::InMemoryPasswordResetRepository;
+use crate::repository::{
+    ApiKeyRepositoryTrait, EmailChangeRepositoryTrait, IdentityRepositoryTrait,
+    PasswordResetRepositoryTrait, SessionStore, UserOutboxStore, UserRepositoryTrait,
+    error::RepoError,
+};
+use crate::saml::ServiceProvider;
+use crate::scim::error::ScimError;
+use crate::scim::resources::{ListQuery, ListResponse, PatchRequest, ScimGroup, ScimUser};
+use crate::scim::{ScimProvisioner, apply_patch, check_version, list};
+use crate::unit_of_work::UnitOfWork;
+use crate::user_agent::describe_user_agent;
+use crate::utils::random_string;
+
+// Server-side sessions expire after a week without activity
+const SESSION_TTL: Duration = Duration::days(7);
+// last seen is only written back once a minute
+const SESSION_TOUCH_INTERVAL: i64 = 60;
+const MAX_USER_AGENT_LEN: usize = 512;
+pub(crate) const MAX_NAME_LEN: usize = 100;
+const MAX_STATUS_REASON_LEN: usize = 500;
+// Email change links are valid for a day
+const EMAIL_CHANGE_TTL: Duration = Duration::hours(24);
+// Password reset links are valid for a day
+const PASSWORD_RESET_TTL: Duration = Duration::hours(24);
+// Impersonation sessions end after an hour, however active
+const IMPERSONATION_TTL: Duration = Duration::hours(1);
+// Deleted accounts are kept this long before they're purged
+const ERASURE_GRACE_PERIOD: Duration = Duration::days(30);
+// How often a change is reapplied when the user keeps being saved underneath it
+const MAX_UPDATE_ATTEMPTS: usize = 3;
 
 #[async_trait]
 pub trait AuthServiceTrait: Send + Sync + 'static {
-    async fn register(&self, user_data: RegisterUser) -> Result<User>;
-    async fn signin(&self, creds: Credentials) -> Result<String>;
+    async fn register(&self, user_data: RegisterUser, client: &ClientInfo) -> Result<User>;
+    async fn signin(&self, creds: Credentials, client: &ClientInfo) -> Result<String>;
+
+    async fn update_profile(&self, user_id: &str, name: &str) -> Result<User>;
+    // Changing credentials, exporting and deleting the account take the signed in user's
+    // token and fail with Impersonating for an admin acting as them
+    async fn change_password(
+        &self,
+        token: &str,
+        current_password: &str,
+        new_password: &str,
+        client: &ClientInfo,
+    ) -> Result<()>;
+    // the email only changes once the link sent to the new address is followed
+    async fn request_email_change(
+        &self,
+        token: &str,
+        new_email: &str,
+        password: &str,
+        client: &ClientInfo,
+    ) -> Result<EmailChange>;
+    async fn pending_email_change(&self, user_id: &str) -> Result<Option<EmailChange>>;
+    // the change a confirm or cancel link is for
+    async fn find_email_change(&self, token: &str) -> Result<EmailChange>;
+    async fn confirm_email_change(&self, token: &str, client: &ClientInfo) -> Result<User>;
+    async fn cancel_email_change(&self, token: &str, client: &ClientInfo) -> Result<()>;
+    // the reset a password reset link is for
+    async fn find_password_reset(&self, token: &str) -> Result<PasswordReset>;
+    async fn reset_password(
+        &self,
+        token: &str,
+        new_password: &str,
+        client: &ClientInfo,
+    ) -> Result<User>;
+    // signs the user out for good, the account is purged once the grace period is over
+    async fn delete_account(&self, token: &str, password: &str, client: &ClientInfo) -> Result<()>;
+    fn erasure_grace_period(&self) -> Duration;
+    // purges the accounts whose grace period is over, returns how many were
+    async fn purge_deleted_accounts(&self) -> Result<usize>;
+    // everything held about the user, ready to download
+    async fn export_user_data(&self, token: &str, format: ExportFormat) -> Result<Vec<u8>>;
     async fn validate_token(&self, token: &str) -> Result<User>;
+
+    fn session_mode(&self) -> SessionMode;
+    // ends the session, a no-op for JWTs which stay valid until they expire
+    async fn signout(&self, token: &str) -> Result<()>;
+    // replaces the session token, e.g. when the user's privileges change
+    async fn rotate_session(&self, token: &str) -> Result<String>;
+    async fn session_data(&self, token: &str) -> Result<HashMap<String, String>>;
+    // a value of None removes the key
+    async fn set_session_data(&self, token: &str, key: &str, value: Option<String>) -> Result<()>;
+    // the signed in user's sessions, the one `token` belongs to is marked current
+    async fn list_sessions(&self, token: &str) -> Result<Vec<ActiveSession>>;
+    async fn revoke_session(&self, token: &str, session_id: &str) -> Result<()>;
+    // returns how many sessions were signed out
+    async fn revoke_other_sessions(&self, token: &str) -> Result<usize>;
+
+    async fn audit_log(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>>;
+    // events whose delivery was given up on, newest first
+    async fn outbox_dead_letters(&self) -> Result<Vec<OutboxMessage>>;
+    // delivers a dead letter again
+    async fn retry_outbox_message(&self, id: &str) -> Result<()>;
+    async fn grant_role(&self, actor_id: &str, user_id: &str, role: Role) -> Result<User>;
+    async fn revoke_role(&self, actor_id: &str, user_id: &str, role: Role) -> Result<User>;
+    async fn list_users(&self, query: &UserQuery) -> Result<UserList>;
+    async fn get_user(&self, user_id: &str) -> Result<User>;
+    // any status but active signs the user out everywhere
+    async fn set_account_status(
+        &self,
+        actor_id: &str,
+        user_id: &str,
+        status: AccountStatus,
+        reason: Option<&str>,
+    ) -> Result<User>;
+    // signs the user out everywhere, returns how many stored sessions were ended
+    async fn revoke_user_sessions(&self, actor_id: &str, user_id: &str) -> Result<usize>;
+    async fn verify_email(&self, actor_id: &str, user_id: &str) -> Result<User>;
+    // the password stops working and the user is emailed a link to choose a new one
+    async fn force_password_reset(&self, actor_id: &str, user_id: &str) -> Result<()>;
+    // signs the admin in as the user, the returned token carries both
+    async fn start_impersonation(
+        &self,
+        actor_id: &str,
+        user_id: &str,
+        client: &ClientInfo,
+    ) -> Result<String>;
+    // the admin acting as the token's user, None when users act as themselves
+    async fn impersonator(&self, token: &str) -> Result<Option<User>>;
+    // ends the impersonation session, the admin's own session is untouched
+    async fn stop_impersonation(&self, token: &str, client: &ClientInfo) -> Result<()>;
+
+    // returns the stored key along with the plain text key, which is only shown once
+    async fn create_api_key(&self, token: &str, new_key: NewApiKey) -> Result<(ApiKey, String)>;
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>>;
+    async fn revoke_api_key(&self, user_id: &str, api_key_id: &str) -> Result<ApiKey>;
+    async fn validate_api_key(&self, key: &str) -> Result<(User, ApiKey)>;
+
+    fn oauth_providers(&self) -> Vec<OAuthProvider>;
+    fn oauth_authorize(&self, provider: &str) -> Result<AuthorizationRequest>;
+    async fn oauth_signin(
+        &self,
+        provider: &str,
+        code: &str,
+        state: &str,
+        client: &ClientInfo,
+    ) -> Result<String>;
+    async fn list_identities(&self, user_id: &str) -> Result<Vec<Identity>>;
+
+    // returns the stored client along with the plain text secret, which is only shown once
+    async fn register_client_app(
+        &self,
+        owner_id: &str,
+        new_client: NewClientApp,
+    ) -> Result<(ClientApp, String)>;
+    async fn list_client_apps(&self, owner_id: &str) -> Result<Vec<ClientApp>>;
+    async fn validate_authorize_request(
+        &self,
+        params: &AuthorizeParams,
+    ) -> Result<(ClientApp, Vec<Scope>)>;
+    async fn approve_authorize_request(
+        &self,
+        user_id: &str,
+        params: &AuthorizeParams,
+    ) -> Result<String>;
+    async fn deny_authorize_request(&self, params: &AuthorizeParams) -> Result<String>;
+    async fn exchange_token(
+        &self,
+        creds: &ClientCredentials,
+        request: TokenRequest,
+    ) -> Result<TokenResponse>;
+    async fn introspect_token(
+        &self,
+        creds: &ClientCredentials,
+        token: &str,
+    ) -> Result<Introspection>;
+    async fn revoke_token(&self, creds: &ClientCredentials, token: &str) -> Result<()>;
+    // accepts both session tokens and access tokens issued to third party clients
+    async fn validate_access_token(&self, token: &str) -> Result<(User, Option<AccessGrant>)>;
+
+    fn openid_configuration(&self) -> ProviderMetadata;
+    fn jwks(&self) -> Jwks;
+    // claims about the user behind an access token granted the openid scope
+    async fn userinfo(&self, access_token: &str) -> Result<UserInfo>;
+
+    async fn import_sso_metadata(
+        &self,
+        org: &str,
+        metadata_xml: &str,
+        email_domains: Vec<String>,
+    ) -> Result<SsoConnection>;
+    fn sso_metadata(&self, org: &str) -> String;
+    async fn sso_authorize(&self, org: &str) -> Result<String>;
+    async fn sso_signin(
+        &self,
+        org: &str,
+        saml_response: &str,
+        client: &ClientInfo,
+    ) -> Result<String>;
+
+    async fn create_scim_token(&self, org: &str) -> Result<String>;
+    // the organization the token was issued to
+    async fn validate_scim_token(&self, token: &str) -> Result<String>;
+    fn scim_service_provider_config(&self) -> Value;
+    async fn scim_list_users(&self, org: &str, query: &ListQuery)
+    -> Result<ListResponse<ScimUser>>;
+    async fn scim_get_user(&self, org: &str, id: &str) -> Result<ScimUser>;
+    async fn scim_create_user(&self, org: &str, user: ScimUser) -> Result<ScimUser>;
+    async fn scim_replace_user(
+        &self,
+        org: &str,
+        id: &str,
+        user: ScimUser,
+        if_match: Option<&str>,
+    ) -> Result<ScimUser>;
+    async fn scim_patch_user(
+        &self,
+        org: &str,
+        id: &str,
+        patch: PatchRequest,
+        if_match: Option<&str>,
+    ) -> Result<ScimUser>;
+    // deprovisioning, the user is disabled and signed out everywhere
+    async fn scim_delete_user(&self, org: &str, id: &str, if_match: Option<&str>) -> Result<()>;
+    async fn scim_list_groups(
+        &self,
+        org: &str,
+        query: &ListQuery,
+    ) -> Result<ListResponse<ScimGroup>>;
+    async fn scim_get_group(&self, org: &str, id: &str) -> Result<ScimGroup>;
+    async fn scim_create_group(&self, org: &str, group: ScimGroup) -> Result<ScimGroup>;
+    async fn scim_replace_group(
+        &self,
+        org: &str,
+        id: &str,
+        group: ScimGroup,
+        if_match: Option<&str>,
+    ) -> Result<ScimGroup>;
+    async fn scim_patch_group(
+        &self,
+        org: &str,
+        id: &str,
+        patch: PatchRequest,
+        if_match: Option<&str>,
+    ) -> Result<ScimGroup>;
+    async fn scim_delete_group(&self, org: &str, id: &str, if_match: Option<&str>) -> Result<()>;
 }
 
 pub struct AuthService<R: UserRepositoryTrait> {
     user_repo: Arc<R>,
     jwt_service: Arc<JwtService>,
+    api_key_repo: Arc<dyn ApiKeyRepositoryTrait>,
+    identity_repo: Arc<dyn IdentityRepositoryTrait>,
+    oauth_client: Arc<OAuthClient>,
+    authz_server: Arc<AuthorizationServer>,
+    service_provider: Arc<ServiceProvider>,
+    scim: Arc<ScimProvisioner>,
+    // sessions are JWTs unless a store is set
+    session_store: Option<Arc<dyn SessionStore>>,
+    audit_sink: Arc<dyn AuditSink>,
+    // events for other systems, delivered by an OutboxDispatcher
+    outbox: Outbox,
+    hooks: HookRegistry,
+    email_change_repo: Arc<dyn EmailChangeRepositoryTrait>,
+    password_reset_repo: Arc<dyn PasswordResetRepositoryTrait>,
+    mailer: Arc<dyn Mailer>,
+    // links in emails point here
+    base_url: String,
+    erasure_grace_period: Duration,
 }
 
 impl<R: UserRepositoryTrait> AuthService<R> {
     pub fn new(user_repo: Arc<R>, jwt_service: Arc<JwtService>) -> Self {
         Self {
             user_repo,
+            authz_server: Arc::new(AuthorizationServer::new(jwt_service.clone())),
             jwt_service,
+            api_key_repo: Arc::new(InMemoryApiKeyRepository::new()),
+            identity_repo: Arc::new(InMemoryIdentityRepository::new()),
+            oauth_client: Arc::new(OAuthClient::new(Arc::new(HttpTransport::new()))),
+            service_provider: Arc::new(ServiceProvider::new("http://localhost:3000")),
+            scim: Arc::new(ScimProvisioner::new("http://localhost:3000")),
+            session_store: None,
+            audit_sink: Arc::new(InMemoryAuditSink::new()),
+            outbox: Outbox::Separate(Arc::new(InMemoryOutbox::new())),
+            hooks: HookRegistry::new(),
+            email_change_repo: Arc::new(InMemoryEmailChangeRepository::new()),
+            password_reset_repo: Arc::new(InMemoryPasswordResetRepository::new()),
+            mailer: Arc::new(LogMailer),
+            base_url: "http://localhost:3000".to_string(),
+            erasure_grace_period: ERASURE_GRACE_PERIOD,
         }
     }
-}
 
-#[async_trait]
-impl<R: UserRepositoryTrait> AuthServiceTrait for AuthService<R> {
-    async fn register(&self, user_data: RegisterUser) -> Result<User> {
-        validate_email(&user_data.email)?;
+    pub fn with_api_key_repo(mut self, api_key_repo: Arc<dyn ApiKeyRepositoryTrait>) -> Self {
+        self.api_key_repo = api_key_repo;
+        self
+    }
 
-        // TODO: implement more robust password validation
-        if user_data.password.len() < 8 {
-            return Err(AuthError::PasswordValidation(
-                "Password must be at least 8 characters".to_string(),
-            ));
-        }
+    pub fn with_identity_repo(mut self, identity_repo: Arc<dyn IdentityRepositoryTrait>) -> Self {
+        self.identity_repo = identity_repo;
+        self
+    }
 
-        if let Ok(Some(_)) = self.user_repo.find_by_email(&user_data.email).await {
-            return Err(AuthError::UserExists);
-        }
+    pub fn with_oauth_client(mut self, oauth_client: Arc<OAuthClient>) -> Self {
+        self.oauth_client = oauth_client;
+        self
+    }
 
-        let password_hash = password::hash_password(&password::ContentToHash {
-            content: user_data.password,
-            salt: Uuid::new_v4(),
-        })?;
+    pub fn with_authz_server(mut self, authz_server: Arc<AuthorizationServer>) -> Self {
+        self.authz_server = authz_server;
+        self
+    }
 
-        let user = User::new(user_data.email, password_hash, user_data.name);
-        let user = self.user_repo.create_user(user).await?;
+    pub fn with_service_provider(mut self, service_provider: Arc<ServiceProvider>) -> Self {
+        self.service_provider = service_provider;
+        self
+    }
 
-        Ok(user)
+    pub fn with_scim_provisioner(mut self, scim: Arc<ScimProvisioner>) -> Self {
+        self.scim = scim;
+        self
+    }
+
+    pub fn with_session_store(mut self, session_store: Arc<dyn SessionStore>) -> Self {
+        self.session_store = Some(session_store);
+        self
+    }
+
+    pub fn with_audit_sink(mut self, audit_sink: Arc<dyn AuditSink>) -> Self {
+        self.audit_sink = audit_sink;
+        self
+    }
+
+    pub fn with_outbox(mut self, outbox: Arc<dyn OutboxStore>) -> Self {
+        self.outbox = Outbox::Separate(outbox);
+        self
+    }
+
+    // Keeps the events in the user repository, written in the same transaction as the
+    // change they're about
+    pub fn with_user_outbox(mut self) -> Self
+    where
+        R: UserOutboxStore,
+    {
+        self.outbox = Outbox::WithUsers(self.user_repo.clone());
+        self
+    }
+
+    pub fn with_email_change_repo(
+        mut self,
+        email_change_repo: Arc<dyn EmailChangeRepositoryTrait>,
+    ) -> Self {
+        self.email_change_repo = email_change_repo;
+        self
+    }
+
+    pub fn with_password_reset_repo(
+        mut self,
+        password_reset_repo: Arc<dyn PasswordResetRepositoryTrait>,
+    ) -> Self {
+        self.password_reset_repo = password_reset_repo;
+        self
+    }
+
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = mailer;
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_erasure_grace_period(mut self, erasure_grace_period: Duration) -> Self {
+        self.erasure_grace_period = erasure_grace_period;
+        self
+    }
+
+    // Hooks run in the order they're added
+    pub fn with_hook(mut self, hook: Arc<dyn AuthHook>) -> Self {
+        self.hooks.register(hook);
+        self
+    }
+
+    // Fails the operation if the event can't be recorded
+    async fn audit(&self, entry: AuditEvent) -> Result<()> {
+        Ok(self.audit_sink.record(entry).await?)
+    }
+
+    // For writes that have to happen together, see UnitOfWork
+    fn unit_of_work(&self) -> UnitOfWork {
+        UnitOfWork::new(
+            self.user_repo.clone(),
+            self.identity_repo.clone(),
+            self.email_change_repo.clone(),
+            self.password_reset_repo.clone(),
+            self.scim.scim_repo(),
+            self.audit_sink.clone(),
+            self.outbox.clone(),
+        )
+        .with_session_store(self.session_store.clone())
+        .with_api_key_repo(self.api_key_repo.clone())
+    }
+
+    // Records how a signin attempt went and hands out the session token
+    async fn audit_signin(
+        &self,
+        method: String,
+        email: Option<String>,
+        client: &ClientInfo,
+        signed_in: Result<(User, String)>,
+    ) -> Result<String> {
+        match signed_in {
+            Ok((user, token)) => {
+                let event = AuthEvent::SigninSucceeded { method };
+                self.audit(
+                    AuditEvent::new(event)
+                        .with_user(&user.id)
+                        .with_client(client),
+                )
+                .await?;
+                self.hooks.after_signin(&user, client).await;
+                Ok(token)
+            }
+            Err(e) => {
+                let event = AuthEvent::SigninFailed {
+                    method,
+                    email,
+                    reason: e.to_string(),
+                };
+                self.audit(AuditEvent::new(event).with_client(client))
+                    .await?;
+                Err(e)
+            }
+        }
     }
 
-    async fn signin(&self, creds: Credentials) -> Result<String> {
+    async fn password_signin(
+        &self,
+        creds: Credentials,
+        client: &ClientInfo,
+    ) -> Result<(User, String)> {
         let mut user = match self.user_repo.find_by_email(&creds.email).await? {
             Some(user) => user,
             None => return Err(AuthError::InvalidCredentials),
@@ -72,119 +487,4779 @@ impl<R: UserRepositoryTrait> AuthServiceTrait for AuthService<R> {
                     salt: Uuid::new_v4(),
                 })?;
 
-                user.password = new_hash;
+                // a password changed in the meantime isn't ours to rehash
+                let old_hash = user.password.clone();
+                user = self
+                    .modify_user(user, |user| {
+                        if user.password == old_hash {
+                            user.password = new_hash.clone();
+                        }
+                        Ok(())
+                    })
+                    .await?;
+            }
+        }
+
+        let token = self.session_token(&user, client).await?;
+        Ok((user, token))
+    }
+
+    async fn set_role(
+        &self,
+        actor_id: &str,
+        user_id: &str,
+        role: Role,
+        granted: bool,
+    ) -> Result<User> {
+        let user = match self.user_repo.find_by_id(user_id).await? {
+            Some(user) => user,
+            None => return Err(AuthError::UserNotFound),
+        };
+        if user.has_role(role) == granted {
+            return Ok(user);
+        }
+
+        let role_name = role.as_str().to_string();
+        let event = if granted {
+            AuthEvent::RoleGranted { role: role_name }
+        } else {
+            AuthEvent::RoleRevoked { role: role_name }
+        };
+        let user = self
+            .modify_user(user, |user| {
+                user.roles.retain(|r| *r != role);
+                if granted {
+                    user.roles.push(role);
+                }
+                Ok(())
+            })
+            .await?;
+        // a session id doesn't outlive a change of privileges, the user signs in again for
+        // a new one. JWTs can't be revoked, the roles are read with each request.
+        if let Some(session_store) = &self.session_store {
+            session_store.delete_user_sessions(&user.id).await?;
+        }
+        self.audit(
+            AuditEvent::new(event)
+                .with_actor(actor_id)
+                .with_subject(&user.id),
+        )
+        .await?;
+
+        Ok(user)
+    }
+
+    fn session_store(&self) -> Result<&Arc<dyn SessionStore>> {
+        self.session_store
+            .as_ref()
+            .ok_or(AuthError::SessionStoreRequired)
+    }
+
+    // Session token for a user who just proved who they are
+    async fn session_token(&self, user: &User, client: &ClientInfo) -> Result<String> {
+        check_status(user, OffsetDateTime::now_utc().unix_timestamp())?;
+        self.hooks.before_signin(user, client).await?;
+
+        if self.session_store.is_none() {
+            return self.jwt_service.generate_token(&user.id);
+        }
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        self.store_session(Session {
+            id: String::new(),
+            user_id: user.id.clone(),
+            created_at: now,
+            last_seen_at: now,
+            expires_at: now + SESSION_TTL.whole_seconds(),
+            ip: client.ip.clone(),
+            user_agent: client
+                .user_agent
+                .as_ref()
+                .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect()),
+            data: HashMap::new(),
+            impersonator_id: None,
+        })
+        .await
+    }
+
+    // Stores the session under a new token, returning the token for the cookie
+    async fn store_session(&self, mut session: Session) -> Result<String> {
+        let session_store = self.session_store()?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        session_store.delete_expired_sessions(now).await?;
+
+        let token = random_string(43);
+        session.id = token_hash(&token);
+        session_store.create_session(session).await?;
+
+        Ok(token)
+    }
+
+    // The stored session behind a token, its expiry slides with activity
+    async fn find_session(&self, token: &str) -> Result<Session> {
+        let session_store = self.session_store()?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let mut session = match session_store.find_session(&token_hash(token)).await? {
+            Some(session) if session.is_expired(now) => {
+                session_store.delete_session(&session.id).await?;
+                return Err(AuthError::Unauthorized);
+            }
+            Some(session) => session,
+            None => return Err(AuthError::Unauthorized),
+        };
 
-                self.user_repo.update_user(&user).await?;
+        if now - session.last_seen_at >= SESSION_TOUCH_INTERVAL {
+            session.last_seen_at = now;
+            // impersonation sessions keep their fixed lifetime
+            if session.impersonator_id.is_none() {
+                session.expires_at = now + SESSION_TTL.whole_seconds();
             }
+            session = session_store.update_session(&session).await?;
         }
 
-        self.jwt_service.generate_token(&user.id)
+        Ok(session)
     }
 
-    async fn validate_token(&self, token: &str) -> Result<User> {
-        let claims = self.jwt_service.validate_token(token)?;
+    // The session behind a token, as long as its user can still sign in
+    async fn live_session(&self, token: &str) -> Result<(User, Session)> {
+        let session = self.find_session(token).await?;
+        let user = self
+            .session_user(&session.user_id, session.created_at)
+            .await?;
+        if let Some(admin_id) = &session.impersonator_id {
+            self.impersonating_admin(admin_id, session.created_at)
+                .await?;
+        }
+
+        Ok((user, session))
+    }
+
+    // The admin behind an impersonation started at `issued_at`, who must still be one
+    async fn impersonating_admin(&self, admin_id: &str, issued_at: i64) -> Result<User> {
+        let admin = self.session_user(admin_id, issued_at).await?;
+        if !admin.has_role(Role::Admin) {
+            return Err(AuthError::Unauthorized);
+        }
+
+        Ok(admin)
+    }
+
+    // Fails for impersonation sessions, for what only the user themselves may do
+    async fn own_session(&self, token: &str) -> Result<(User, Session)> {
+        let (user, session) = self.live_session(token).await?;
+        if session.impersonator_id.is_some() {
+            return Err(AuthError::Impersonating);
+        }
+
+        Ok((user, session))
+    }
+
+    // The token's user, for what only the user themselves may do. Whoever is behind a
+    // token whose impersonator can't be told is refused too.
+    async fn own_user(&self, token: &str) -> Result<User> {
+        let user = self.validate_token(token).await?;
+        if self.impersonator(token).await?.is_some() {
+            return Err(AuthError::Impersonating);
+        }
+
+        Ok(user)
+    }
+
+    async fn user(&self, user_id: &str) -> Result<User> {
+        match self.user_repo.find_by_id(user_id).await? {
+            Some(user) => Ok(user),
+            None => Err(AuthError::UserNotFound),
+        }
+    }
+
+    // Applies the change to the user and saves it. If the user was saved by someone else
+    // in between, the change is applied again to a fresh copy, so it should only set what
+    // it means to change and fail on a copy it no longer makes sense for.
+    async fn modify_user<F>(&self, user: User, change: F) -> Result<User>
+    where
+        F: Fn(&mut User) -> Result<()> + Send + Sync,
+    {
+        let mut work = self.unit_of_work();
+        let modified = self.modify_user_in(&mut work, user, change).await;
+        work.finish(modified).await
+    }
+
+    // modify_user as part of a unit of work
+    async fn modify_user_in<F>(
+        &self,
+        work: &mut UnitOfWork,
+        mut user: User,
+        change: F,
+    ) -> Result<User>
+    where
+        F: Fn(&mut User) -> Result<()> + Send + Sync,
+    {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let before = user.clone();
+            change(&mut user)?;
+            match work.update_user(&before, &user).await {
+                Err(RepoError::Conflict) => user = self.user(&user.id).await?,
+                saved => return Ok(saved?),
+            }
+        }
+
+        Err(AuthError::Conflict)
+    }
+
+    // The unexpired change an emailed token is for, confirm and cancel tokens aren't
+    // interchangeable
+    async fn email_change(&self, token: &str, confirm: bool) -> Result<EmailChange> {
+        let hash = token_hash(token);
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        match self.email_change_repo.find_by_token_hash(&hash).await? {
+            Some(change) if !change.is_expired(now) => {
+                let expected = match confirm {
+                    true => &change.confirm_token_hash,
+                    false => &change.cancel_token_hash,
+                };
+                if *expected != hash {
+                    return Err(AuthError::EmailChangeNotFound);
+                }
+                Ok(change)
+            }
+            _ => Err(AuthError::EmailChangeNotFound),
+        }
+    }
+
+    // The unexpired reset an emailed token is for
+    async fn password_reset(&self, token: &str) -> Result<PasswordReset> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        match self
+            .password_reset_repo
+            .find_by_token_hash(&token_hash(token))
+            .await?
+        {
+            Some(reset) if !reset.is_expired(now) => Ok(reset),
+            _ => Err(AuthError::PasswordResetNotFound),
+        }
+    }
+
+    // Rejects every session and token issued to the user so far, returns how many stored
+    // sessions were deleted
+    async fn end_all_sessions(&self, user: &mut User) -> Result<usize> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        *user = self
+            .modify_user(user.clone(), |user| {
+                user.sessions_revoked_at = Some(now);
+                Ok(())
+            })
+            .await?;
+
+        self.authz_server.revoke_user_tokens(&user.id).await?;
+        match &self.session_store {
+            Some(session_store) => Ok(session_store.delete_user_sessions(&user.id).await?),
+            None => Ok(0),
+        }
+    }
 
-        let user = match self.user_repo.find_by_id(&claims.sub).await? {
+    // The user a session or token issued at `issued_at` belongs to, if it's still valid
+    async fn session_user(&self, user_id: &str, issued_at: i64) -> Result<User> {
+        let user = match self.user_repo.find_by_id(user_id).await? {
             Some(user) => user,
             None => return Err(AuthError::UserNotFound),
         };
 
+        check_status(&user, OffsetDateTime::now_utc().unix_timestamp())?;
+        if user.is_session_revoked(issued_at) {
+            return Err(AuthError::Unauthorized);
+        }
+
         Ok(user)
     }
-}
 
-// Simple email validation
-fn validate_email(email: &str) -> Result<bool> {
-    match regex::Regex::new(
-        r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
-    ) {
-        Ok(r) => Ok(r.is_match(email)),
-        Err(_) => Err(AuthError::EmailValidation),
+    // Erases the account and everything tied to it. Audit entries stay, the log is append only.
+    async fn purge_user(&self, user: &User) -> Result<()> {
+        let mut work = self.unit_of_work();
+        let purged = async {
+            // the provider accounts can be linked to a new account afterwards
+            work.delete_identities(&user.id).await?;
+            work.delete_api_keys(&user.id).await?;
+            work.forget_scim_user(&user.id).await?;
+            work.delete_email_change(&user.id).await?;
+            // clients and the events about the user still in the outbox, which have the
+            // email and name, aren't given back if the unit is rolled back. The purge is
+            // retried with what's left.
+            self.authz_server.delete_clients(&user.id).await?;
+            self.outbox.store().delete_by_user(&user.id).await?;
+            work.delete_user(&user.id).await?;
+
+            work.audit(AuditEvent::new(AuthEvent::AccountDeleted).with_subject(&user.id));
+            Ok(())
+        }
+        .await;
+        work.finish(purged).await?;
+        self.hooks.after_delete(user).await;
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::{InMemoryUserRepository, password::ContentToHash};
+    // Saves the new status, signing the user out everywhere unless they're let back in
+    async fn change_status(
+        &self,
+        user: User,
+        status: AccountStatus,
+        reason: Option<String>,
+        actor: &str,
+    ) -> Result<User> {
+        let mut work = self.unit_of_work();
+        let changed = self
+            .change_status_in(&mut work, user, status, reason, actor)
+            .await;
+        work.finish(changed).await
+    }
 
-    use super::*;
-    // use auth::{
-    //     AuthService, AuthServiceImpl, Credentials, JwtService, RegisterUser, User,
-    //     password::{hash_password, needs_rehash, verify_password},
-    //     repository::memory::InMemoryUserRepository,
-    // };
-    use std::sync::Arc;
+    // change_status as part of a unit of work
+    async fn change_status_in(
+        &self,
+        work: &mut UnitOfWork,
+        user: User,
+        status: AccountStatus,
+        reason: Option<String>,
+        actor: &str,
+    ) -> Result<User> {
+        // tokens issued before a suspension would work again once it's over
+        let signed_out = status != AccountStatus::Active;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let user = self
+            .modify_user_in(work, user, |user| {
+                user.status = status;
+                user.status_reason = reason.clone();
+                user.status_changed_by = Some(actor.to_string());
+                if signed_out {
+                    user.sessions_revoked_at = Some(now);
+                }
+                Ok(())
+            })
+            .await?;
+        if signed_out {
+            // revoked tokens aren't given back if the unit is rolled back, the user only
+            // has to sign in again
+            self.authz_server.revoke_user_tokens(&user.id).await?;
+            work.delete_user_sessions(&user.id).await?;
+        }
 
-    #[tokio::test]
-    async fn test_auth_service_end_to_end() {
-        // Setup dependencies
-        let user_repository = Arc::new(InMemoryUserRepository::new());
-        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let event = match status {
+            AccountStatus::Active => AuthEvent::AccountEnabled,
+            AccountStatus::Disabled => AuthEvent::AccountDisabled { reason },
+            AccountStatus::Suspended { until } => AuthEvent::AccountSuspended { until, reason },
+            AccountStatus::PendingVerification => AuthEvent::VerificationRequired { reason },
+        };
+        work.audit(
+            AuditEvent::new(event)
+                .with_actor(actor)
+                .with_subject(&user.id),
+        );
 
-        // Create auth service
-        let auth_service = AuthService::new(user_repository, jwt_service.clone());
+        Ok(user)
+    }
 
-        // Test data
-        let register_data = RegisterUser {
-            email: "test@example.com".to_string(),
-            password: "Password123!".to_string(),
-            name: "Test User".to_string(),
+    async fn scim_user(&self, org: &str, id: &str) -> Result<(User, ScimUser)> {
+        let provisioned = self.scim.provisioned_user(org, id).await?;
+        let user = match self.user_repo.find_by_id(id).await? {
+            Some(user) => user,
+            None => return Err(ScimError::NotFound(id.to_string()).into()),
         };
 
-        // Test registration
-        let registered_user = auth_service.register(register_data.clone()).await.unwrap();
-        assert_eq!(registered_user.email, "test@example.com");
-        assert_eq!(registered_user.name, "Test User".to_string());
+        let groups = self.scim.groups(org).await?;
+        let resource = self.scim.user_resource(&user, &provisioned, &groups);
+        Ok((user, resource))
+    }
 
-        // Test login
-        let credentials = Credentials {
-            email: "test@example.com".to_string(),
-            password: "Password123!".to_string(),
+    // Writes the directory's view of the user to `User` and the org's record of it, as
+    // part of a unit of work
+    async fn save_scim_user(
+        &self,
+        work: &mut UnitOfWork,
+        org: &str,
+        user: Option<User>,
+        resource: ScimUser,
+    ) -> Result<ScimUser> {
+        if resource.user_name.trim().is_empty() {
+            return Err(ScimError::InvalidValue("userName".to_string()).into());
+        }
+        let email = resource.email().trim().to_lowercase();
+        if !validate_email(&email)? {
+            return Err(ScimError::InvalidValue("email".to_string()).into());
+        }
+
+        if let Some(other) = self.user_repo.find_by_email(&email).await?
+            && user.as_ref().is_none_or(|u| u.id != other.id)
+        {
+            return Err(ScimError::Uniqueness.into());
+        }
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let name = resource.full_name().unwrap_or_else(|| email.clone());
+        let user = match user {
+            Some(user) => {
+                let user = self
+                    .modify_user_in(work, user, |user| {
+                        user.email = email.clone();
+                        user.name = name.clone();
+                        Ok(())
+                    })
+                    .await?;
+                // directories only disable and enable, other statuses are ours to manage
+                let disabled = user.status == AccountStatus::Disabled;
+                let actor = scim_actor(org);
+                match (disabled, resource.active) {
+                    (false, false) => {
+                        self.change_status_in(work, user, AccountStatus::Disabled, None, &actor)
+                            .await?
+                    }
+                    (true, true) => {
+                        self.change_status_in(work, user, AccountStatus::Active, None, &actor)
+                            .await?
+                    }
+                    _ => user,
+                }
+            }
+            None => {
+                // directory users sign in through their organization's SSO
+                let mut user = User::new(email, unusable_password()?, name);
+                if !resource.active {
+                    user.status = AccountStatus::Disabled;
+                    user.status_changed_by = Some(scim_actor(org));
+                }
+                work.create_user(user).await?
+            }
         };
 
-        let token = auth_service.signin(credentials).await.unwrap();
-        assert!(!token.is_empty());
+        let created_at = self
+            .scim
+            .find_provisioned_user(org, &user.id)
+            .await?
+            .map_or(now, |p| p.created_at);
+        let name = resource.name.unwrap_or_default();
+        let provisioned = work
+            .save_provisioned_user(ProvisionedUser {
+                org: org.to_string(),
+                user_id: user.id.clone(),
+                user_name: resource.user_name.trim().to_string(),
+                external_id: resource.external_id,
+                display_name: resource.display_name,
+                formatted_name: name.formatted,
+                given_name: name.given_name,
+                family_name: name.family_name,
+                created_at,
+                updated_at: now,
+                deprovisioned_at: None,
+            })
+            .await?;
 
-        // Test token validation
-        let user = auth_service.validate_token(&token).await.unwrap();
-        assert_eq!(user.email, "test@example.com");
+        let groups = self.scim.groups(org).await?;
+        Ok(self.scim.user_resource(&user, &provisioned, &groups))
+    }
 
-        // Test invalid login
-        let invalid_credentials = Credentials {
-            email: "test@example.com".to_string(),
-            password: "WrongPassword123!".to_string(),
+    // Existing accounts can only be taken over by the org that provisioned them
+    // before or whose IdP they signed in with
+    async fn adoptable_by(&self, org: &str, user: &User) -> Result<bool> {
+        if self
+            .scim
+            .find_provisioned_user(org, &user.id)
+            .await?
+            .is_some()
+        {
+            return Ok(true);
+        }
+
+        let provider = format!("saml:{org}");
+        Ok(self
+            .identity_repo
+            .list_by_user(&user.id)
+            .await?
+            .iter()
+            .any(|identity| identity.provider == provider))
+    }
+
+    // Finds the user linked to the external identity, linking or creating one if needed
+    async fn user_for_identity(&self, external: ExternalIdentity) -> Result<User> {
+        if let Some(identity) = self
+            .identity_repo
+            .find_by_provider_subject(&external.provider, &external.subject)
+            .await?
+        {
+            let user = match self.user_repo.find_by_id(&identity.user_id).await? {
+                Some(user) => user,
+                None => return Err(AuthError::UserNotFound),
+            };
+            check_status(&user, OffsetDateTime::now_utc().unix_timestamp())?;
+            return Ok(user);
+        }
+
+        let mut work = self.unit_of_work();
+        let user = match self.user_repo.find_by_email(&external.email).await? {
+            // only link to an existing account when the provider vouches for the email, and
+            // not to one that can't sign in
+            Some(user) if external.email_verified => {
+                check_status(&user, OffsetDateTime::now_utc().unix_timestamp())?;
+                user
+            }
+            Some(_) => return Err(AuthError::UserExists),
+            None => {
+                if !validate_email(&external.email)? {
+                    return Err(AuthError::EmailValidation);
+                }
+
+                // the account can only sign in through the provider
+                let mut user =
+                    User::new(external.email.clone(), unusable_password()?, external.name);
+                user.email_verified = external.email_verified;
+                work.publish(DomainEvent::UserRegistered {
+                    user_id: user.id.clone(),
+                    email: user.email.clone(),
+                    name: user.name.clone(),
+                });
+                work.create_user(user).await?
+            }
         };
 
-        let login_result = auth_service.signin(invalid_credentials).await;
-        assert!(login_result.is_err());
+        // a new account isn't kept without the link, e.g. when the provider account was
+        // linked by a concurrent signin
+        let linked = work
+            .create_identity(Identity {
+                id: Uuid::new_v4().to_string(),
+                user_id: user.id.clone(),
+                provider: external.provider,
+                subject: external.subject,
+                email: external.email,
+                created_at: OffsetDateTime::now_utc().unix_timestamp(),
+            })
+            .await;
+        work.finish(linked).await?;
+
+        Ok(user)
     }
+}
 
-    #[tokio::test]
-    async fn test_duplicate_user_registration() {
-        // Setup dependencies
-        let user_repository = Arc::new(InMemoryUserRepository::new());
-        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+#[async_trait]
+impl<R: UserRepositoryTrait> AuthServiceTrait for AuthService<R> {
+    async fn register(&self, user_data: RegisterUser, client: &ClientInfo) -> Result<User> {
+        if !validate_email(&user_data.email)? {
+            return Err(AuthError::EmailValidation);
+        }
+        validate_password(&user_data.password)?;
 
-        // Create auth service
-        let auth_service = AuthService::new(user_repository, jwt_service.clone());
+        if let Ok(Some(_)) = self.user_repo.find_by_email(&user_data.email).await {
+            return Err(AuthError::UserExists);
+        }
+        self.hooks.before_register(&user_data).await?;
 
-        // Test data
-        let register_data = RegisterUser {
-            email: "duplicate@example.com".to_string(),
-            password: "Password123!".to_string(),
-            name: "Test User".to_string(),
+        let password_hash = password::hash_password(&password::ContentToHash {
+            content: user_data.password,
+            salt: Uuid::new_v4(),
+        })?;
+
+        let mut work = self.unit_of_work();
+        let user = User::new(user_data.email, password_hash, user_data.name);
+        work.publish(DomainEvent::UserRegistered {
+            user_id: user.id.clone(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+        });
+        let user = work.create_user(user).await?;
+
+        let event = AuthEvent::Registered {
+            email: user.email.clone(),
         };
+        work.audit(
+            AuditEvent::new(event)
+                .with_user(&user.id)
+                .with_client(client),
+        );
+        work.commit().await?;
+        self.hooks.after_register(&user).await;
 
-        // First registration should succeed
-        let registered_user = auth_service.register(register_data.clone()).await.unwrap();
-        assert_eq!(registered_user.email, "duplicate@example.com");
+        Ok(user)
+    }
 
-        // Second registration with same email should fail
-        let duplicate_result = auth_service.register(register_data).await;
-        assert!(duplicate_result.is_err());
-        match duplicate_result {
-            Err(AuthError::UserExists) => (),
-            _ => panic!("Expected UserAlreadyExists error"),
+    async fn signin(&self, creds: Credentials, client: &ClientInfo) -> Result<String> {
+        let email = creds.email.clone();
+        let signed_in = self.password_signin(creds, client).await;
+
+        self.audit_signin("password".to_string(), Some(email), client, signed_in)
+            .await
+    }
+
+    async fn update_profile(&self, user_id: &str, name: &str) -> Result<User> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(AuthError::ProfileValidation("Name is required".to_string()));
+        }
+        if name.chars().count() > MAX_NAME_LEN {
+            return Err(AuthError::ProfileValidation(format!(
+                "Name must be at most {MAX_NAME_LEN} characters"
+            )));
         }
+
+        let user = self.user(user_id).await?;
+        let user = self
+            .modify_user(user, |user| {
+                user.name = name.to_string();
+                Ok(())
+            })
+            .await?;
+
+        self.audit(AuditEvent::new(AuthEvent::ProfileUpdated).with_user(&user.id))
+            .await?;
+
+        Ok(user)
+    }
+
+    async fn change_password(
+        &self,
+        token: &str,
+        current_password: &str,
+        new_password: &str,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        let user = self.own_user(token).await?;
+        verify_current_password(&user, current_password)?;
+        validate_password(new_password)?;
+        self.hooks.before_password_change(&user).await?;
+
+        // the current password was checked against this hash, not one set since
+        let verified_hash = user.password.clone();
+        let new_hash = hash_password(&password::ContentToHash {
+            content: new_password.to_string(),
+            salt: Uuid::new_v4(),
+        })?;
+        let user = self
+            .modify_user(user, |user| {
+                if user.password != verified_hash {
+                    return Err(AuthError::Conflict);
+                }
+                user.password = new_hash.clone();
+                Ok(())
+            })
+            .await?;
+
+        self.audit(
+            AuditEvent::new(AuthEvent::PasswordChanged)
+                .with_user(&user.id)
+                .with_client(client),
+        )
+        .await?;
+        self.hooks.after_password_change(&user).await;
+
+        Ok(())
+    }
+
+    async fn request_email_change(
+        &self,
+        token: &str,
+        new_email: &str,
+        password: &str,
+        client: &ClientInfo,
+    ) -> Result<EmailChange> {
+        let user = self.own_user(token).await?;
+        // a hijacked session alone can't move the account to another address
+        verify_current_password(&user, password)?;
+
+        let new_email = new_email.trim().to_string();
+        if !validate_email(&new_email)? {
+            return Err(AuthError::EmailValidation);
+        }
+        if new_email == user.email {
+            return Err(AuthError::EmailValidation);
+        }
+        // checked again on confirmation, the address may be taken in between
+        if self.user_repo.find_by_email(&new_email).await?.is_some() {
+            return Err(AuthError::UserExists);
+        }
+
+        let confirm_token = random_string(32);
+        let cancel_token = random_string(32);
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let change = self
+            .email_change_repo
+            .save_email_change(EmailChange {
+                user_id: user.id.clone(),
+                new_email: new_email.clone(),
+                confirm_token_hash: token_hash(&confirm_token),
+                cancel_token_hash: token_hash(&cancel_token),
+                created_at: now,
+                expires_at: now + EMAIL_CHANGE_TTL.whole_seconds(),
+            })
+            .await?;
+
+        self.mailer
+            .send(Email {
+                to: new_email.clone(),
+                subject: "Confirm your new email address".to_string(),
+                body: format!(
+                    "Follow this link to use {new_email} to sign in:\n\n{}/auth/email/confirm?token={confirm_token}\n\nThe link expires in 24 hours.",
+                    self.base_url
+                ),
+            })
+            .await?;
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "Your email address is being changed".to_string(),
+                body: format!(
+                    "Someone asked to change the email of your account to {new_email}. If it wasn't you, cancel the change and change your password:\n\n{}/auth/email/cancel?token={cancel_token}",
+                    self.base_url
+                ),
+            })
+            .await?;
+
+        let event = AuthEvent::EmailChangeRequested { new_email };
+        self.audit(
+            AuditEvent::new(event)
+                .with_user(&user.id)
+                .with_client(client),
+        )
+        .await?;
+
+        Ok(change)
+    }
+
+    async fn pending_email_change(&self, user_id: &str) -> Result<Option<EmailChange>> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        Ok(self
+            .email_change_repo
+            .find_by_user(user_id)
+            .await?
+            .filter(|change| !change.is_expired(now)))
+    }
+
+    async fn find_email_change(&self, token: &str) -> Result<EmailChange> {
+        match self.email_change(token, true).await {
+            Err(AuthError::EmailChangeNotFound) => self.email_change(token, false).await,
+            found => found,
+        }
+    }
+
+    async fn confirm_email_change(&self, token: &str, client: &ClientInfo) -> Result<User> {
+        let change = self.email_change(token, true).await?;
+        let user = self.user(&change.user_id).await?;
+
+        if let Some(other) = self.user_repo.find_by_email(&change.new_email).await?
+            && other.id != user.id
+        {
+            return Err(AuthError::UserExists);
+        }
+
+        let old_email = user.email.clone();
+        let mut work = self.unit_of_work();
+        let confirmed: Result<User> = async {
+            // written with the new email
+            work.publish(DomainEvent::EmailChanged {
+                user_id: user.id.clone(),
+                old_email: old_email.clone(),
+                new_email: change.new_email.clone(),
+            });
+            let user = self
+                .modify_user_in(&mut work, user, |user| {
+                    user.email = change.new_email.clone();
+                    // following the link proved the user reads the new address
+                    user.email_verified = true;
+                    Ok(())
+                })
+                .await?;
+            work.delete_email_change(&user.id).await?;
+
+            let event = AuthEvent::EmailChanged {
+                old_email,
+                new_email: change.new_email.clone(),
+            };
+            work.audit(
+                AuditEvent::new(event)
+                    .with_user(&user.id)
+                    .with_client(client),
+            );
+            Ok(user)
+        }
+        .await;
+
+        work.finish(confirmed).await
+    }
+
+    async fn cancel_email_change(&self, token: &str, client: &ClientInfo) -> Result<()> {
+        let change = self.email_change(token, false).await?;
+        self.email_change_repo
+            .delete_email_change(&change.user_id)
+            .await?;
+
+        let event = AuthEvent::EmailChangeCancelled {
+            new_email: change.new_email,
+        };
+        self.audit(
+            AuditEvent::new(event)
+                .with_user(&change.user_id)
+                .with_client(client),
+        )
+        .await
+    }
+
+    async fn find_password_reset(&self, token: &str) -> Result<PasswordReset> {
+        self.password_reset(token).await
+    }
+
+    async fn reset_password(
+        &self,
+        token: &str,
+        new_password: &str,
+        client: &ClientInfo,
+    ) -> Result<User> {
+        let reset = self.password_reset(token).await?;
+        let user = self.user(&reset.user_id).await?;
+        validate_password(new_password)?;
+        self.hooks.before_password_change(&user).await?;
+
+        let new_hash = hash_password(&password::ContentToHash {
+            content: new_password.to_string(),
+            salt: Uuid::new_v4(),
+        })?;
+        let mut work = self.unit_of_work();
+        let changed: Result<User> = async {
+            let user = self
+                .modify_user_in(&mut work, user, |user| {
+                    user.password = new_hash.clone();
+                    Ok(())
+                })
+                .await?;
+            // links are single use
+            work.delete_password_reset(&reset).await?;
+
+            work.audit(
+                AuditEvent::new(AuthEvent::PasswordReset)
+                    .with_user(&user.id)
+                    .with_client(client),
+            );
+            Ok(user)
+        }
+        .await;
+        let user = work.finish(changed).await?;
+        self.hooks.after_password_change(&user).await;
+
+        Ok(user)
+    }
+
+    async fn delete_account(&self, token: &str, password: &str, client: &ClientInfo) -> Result<()> {
+        let user = self.own_user(token).await?;
+        if user.is_deleted() {
+            return Err(AuthError::AccountDeleted);
+        }
+        verify_current_password(&user, password)?;
+        self.hooks.before_delete(&user).await?;
+
+        // api keys and JWTs of a deleted user are rejected from here on
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut work = self.unit_of_work();
+        let deleted: Result<()> = async {
+            let purge_at = now + self.erasure_grace_period.whole_seconds();
+            work.publish(DomainEvent::ErasureRequested {
+                user_id: user.id.clone(),
+                purge_at,
+            });
+            let user = self
+                .modify_user_in(&mut work, user, |user| {
+                    if user.is_deleted() {
+                        return Err(AuthError::AccountDeleted);
+                    }
+                    user.deleted_at = Some(now);
+                    user.sessions_revoked_at = Some(now);
+                    Ok(())
+                })
+                .await?;
+
+            // revoked tokens stay revoked if the deletion is rolled back, that only errs
+            // on the safe side
+            self.authz_server.revoke_user_tokens(&user.id).await?;
+            work.delete_user_sessions(&user.id).await?;
+            work.delete_email_change(&user.id).await?;
+
+            work.audit(
+                AuditEvent::new(AuthEvent::ErasureRequested { purge_at })
+                    .with_user(&user.id)
+                    .with_client(client),
+            );
+            Ok(())
+        }
+        .await;
+
+        work.finish(deleted).await
+    }
+
+    fn erasure_grace_period(&self) -> Duration {
+        self.erasure_grace_period
+    }
+
+    async fn purge_deleted_accounts(&self) -> Result<usize> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let cutoff = now - self.erasure_grace_period.whole_seconds();
+
+        let users = self.user_repo.list_deleted_before(cutoff).await?;
+        for user in &users {
+            self.purge_user(user).await?;
+        }
+
+        Ok(users.len())
+    }
+
+    async fn export_user_data(&self, token: &str, format: ExportFormat) -> Result<Vec<u8>> {
+        let user = self.own_user(token).await?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let sessions = match &self.session_store {
+            Some(session_store) => session_store.list_user_sessions(&user.id, now).await?,
+            None => Vec::new(),
+        };
+        let (records, groups) = self.scim.memberships(&user.id).await?;
+        let audit_events = self
+            .audit_sink
+            .query(&AuditFilter {
+                user_id: Some(user.id.clone()),
+                ..Default::default()
+            })
+            .await?;
+
+        let export = DataExport {
+            generated_at: now,
+            profile: (&user).into(),
+            identities: self
+                .identity_repo
+                .list_by_user(&user.id)
+                .await?
+                .iter()
+                .map(LinkedIdentity::from)
+                .collect(),
+            api_keys: self
+                .api_key_repo
+                .list_by_user(&user.id)
+                .await?
+                .iter()
+                .map(ApiKeyRecord::from)
+                .collect(),
+            client_apps: self
+                .authz_server
+                .list_clients(&user.id)
+                .await?
+                .iter()
+                .map(ClientAppRecord::from)
+                .collect(),
+            sessions: sessions.iter().map(SessionRecord::from).collect(),
+            memberships: Membership::from_records(&records, &groups),
+            pending_email_change: self
+                .email_change_repo
+                .find_by_user(&user.id)
+                .await?
+                .as_ref()
+                .map(PendingEmailChange::from),
+            audit_events,
+        };
+        let encoded = export.encode(format)?;
+
+        let event = AuthEvent::DataExported {
+            format: format.as_str().to_string(),
+        };
+        self.audit(AuditEvent::new(event).with_user(&user.id))
+            .await?;
+
+        Ok(encoded)
+    }
+
+    async fn validate_token(&self, token: &str) -> Result<User> {
+        if self.session_store.is_some() {
+            let (user, _) = self.live_session(token).await?;
+            return Ok(user);
+        }
+
+        let claims = self.jwt_service.validate_token(token)?;
+
+        // tokens issued to third party clients are not sessions
+        if claims.token_use.is_some() {
+            return Err(AuthError::Unauthorized);
+        }
+
+        let user = self.session_user(&claims.sub, claims.iat).await?;
+        if let Some(actor) = &claims.act {
+            self.impersonating_admin(&actor.sub, claims.iat).await?;
+        }
+
+        Ok(user)
+    }
+
+    fn session_mode(&self) -> SessionMode {
+        match self.session_store {
+            Some(_) => SessionMode::ServerSide,
+            None => SessionMode::Jwt,
+        }
+    }
+
+    async fn signout(&self, token: &str) -> Result<()> {
+        // the admin is done acting as the user
+        if let Ok(Some(_)) = self.impersonator(token).await {
+            return self.stop_impersonation(token, &ClientInfo::default()).await;
+        }
+
+        // signing out with a stale token still clears the cookie, there's just nothing to record
+        let user = self.validate_token(token).await.ok();
+        if let Some(session_store) = &self.session_store {
+            session_store.delete_session(&token_hash(token)).await?;
+        }
+
+        if let Some(user) = user {
+            self.audit(AuditEvent::new(AuthEvent::SignedOut).with_user(&user.id))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn rotate_session(&self, token: &str) -> Result<String> {
+        let Some(session_store) = &self.session_store else {
+            let user = self.validate_token(token).await?;
+            return self.jwt_service.generate_token(&user.id);
+        };
+
+        // the old token stops working, the session carries over
+        let (_, session) = self.live_session(token).await?;
+        let old_id = session.id.clone();
+        let rotated = self.store_session(session).await?;
+        session_store.delete_session(&old_id).await?;
+
+        Ok(rotated)
+    }
+
+    async fn session_data(&self, token: &str) -> Result<HashMap<String, String>> {
+        let (_, session) = self.live_session(token).await?;
+
+        Ok(session.data)
+    }
+
+    async fn set_session_data(&self, token: &str, key: &str, value: Option<String>) -> Result<()> {
+        let (_, mut session) = self.live_session(token).await?;
+        match value {
+            Some(value) => session.data.insert(key.to_string(), value),
+            None => session.data.remove(key),
+        };
+        self.session_store()?.update_session(&session).await?;
+
+        Ok(())
+    }
+
+    async fn list_sessions(&self, token: &str) -> Result<Vec<ActiveSession>> {
+        let (user, current) = self.live_session(token).await?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let sessions = self
+            .session_store()?
+            .list_user_sessions(&user.id, now)
+            .await?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|session| ActiveSession {
+                current: session.id == current.id,
+                device: describe_user_agent(session.user_agent.as_deref().unwrap_or_default()),
+                id: session.id,
+                created_at: session.created_at,
+                last_seen_at: session.last_seen_at,
+                ip: session.ip,
+            })
+            .collect())
+    }
+
+    async fn revoke_session(&self, token: &str, session_id: &str) -> Result<()> {
+        let (user, _) = self.own_session(token).await?;
+        let session_store = self.session_store()?;
+
+        // users can only sign out their own sessions
+        match session_store.find_session(session_id).await? {
+            Some(session) if session.user_id == user.id => {
+                session_store.delete_session(session_id).await?
+            }
+            _ => return Err(AuthError::SessionNotFound),
+        }
+
+        let event = AuthEvent::SessionRevoked {
+            session_id: session_id.to_string(),
+        };
+        self.audit(AuditEvent::new(event).with_user(&user.id)).await
+    }
+
+    async fn revoke_other_sessions(&self, token: &str) -> Result<usize> {
+        let (user, current) = self.own_session(token).await?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let session_store = self.session_store()?;
+
+        let mut revoked = 0;
+        for session in session_store.list_user_sessions(&user.id, now).await? {
+            if session.id != current.id {
+                session_store.delete_session(&session.id).await?;
+                revoked += 1;
+            }
+        }
+
+        let event = AuthEvent::OtherSessionsRevoked { count: revoked };
+        self.audit(AuditEvent::new(event).with_user(&user.id))
+            .await?;
+
+        Ok(revoked)
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>> {
+        Ok(self.audit_sink.query(filter).await?)
+    }
+
+    async fn outbox_dead_letters(&self) -> Result<Vec<OutboxMessage>> {
+        Ok(self.outbox.store().dead_letters().await?)
+    }
+
+    async fn retry_outbox_message(&self, id: &str) -> Result<()> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        Ok(self.outbox.store().retry(id, now).await?)
+    }
+
+    async fn grant_role(&self, actor_id: &str, user_id: &str, role: Role) -> Result<User> {
+        self.set_role(actor_id, user_id, role, true).await
+    }
+
+    async fn revoke_role(&self, actor_id: &str, user_id: &str, role: Role) -> Result<User> {
+        self.set_role(actor_id, user_id, role, false).await
+    }
+
+    async fn list_users(&self, query: &UserQuery) -> Result<UserList> {
+        Ok(self.user_repo.list_users(query).await?)
+    }
+
+    async fn get_user(&self, user_id: &str) -> Result<User> {
+        self.user(user_id).await
+    }
+
+    async fn set_account_status(
+        &self,
+        actor_id: &str,
+        user_id: &str,
+        status: AccountStatus,
+        reason: Option<&str>,
+    ) -> Result<User> {
+        let reason = reason
+            .map(str::trim)
+            .filter(|reason| !reason.is_empty())
+            .map(str::to_string);
+        if reason
+            .as_ref()
+            .is_some_and(|reason| reason.chars().count() > MAX_STATUS_REASON_LEN)
+        {
+            return Err(AuthError::InvalidStatus(format!(
+                "the reason can be at most {MAX_STATUS_REASON_LEN} characters"
+            )));
+        }
+        if let AccountStatus::Suspended { until } = status
+            && until <= OffsetDateTime::now_utc().unix_timestamp()
+        {
+            return Err(AuthError::InvalidStatus(
+                "a suspension must end in the future".to_string(),
+            ));
+        }
+
+        let user = self.user(user_id).await?;
+        if user.status == status && user.status_reason == reason {
+            return Ok(user);
+        }
+
+        self.change_status(user, status, reason, actor_id).await
+    }
+
+    async fn revoke_user_sessions(&self, actor_id: &str, user_id: &str) -> Result<usize> {
+        let mut user = self.user(user_id).await?;
+        let count = self.end_all_sessions(&mut user).await?;
+
+        self.audit(
+            AuditEvent::new(AuthEvent::AllSessionsRevoked { count })
+                .with_actor(actor_id)
+                .with_subject(&user.id),
+        )
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn verify_email(&self, actor_id: &str, user_id: &str) -> Result<User> {
+        let mut user = self.user(user_id).await?;
+        let pending = user.status == AccountStatus::PendingVerification;
+        if user.email_verified && !pending {
+            return Ok(user);
+        }
+
+        if !user.email_verified {
+            user = self
+                .modify_user(user, |user| {
+                    user.email_verified = true;
+                    Ok(())
+                })
+                .await?;
+            self.audit(
+                AuditEvent::new(AuthEvent::EmailVerified)
+                    .with_actor(actor_id)
+                    .with_subject(&user.id),
+            )
+            .await?;
+        }
+        // the account was only waiting for this
+        if pending {
+            user = self
+                .change_status(user, AccountStatus::Active, None, actor_id)
+                .await?;
+        }
+
+        Ok(user)
+    }
+
+    async fn force_password_reset(&self, actor_id: &str, user_id: &str) -> Result<()> {
+        let user = self.user(user_id).await?;
+
+        // whoever knows the old password is signed out and can't sign in again, api keys
+        // are separate credentials and keep working
+        let unusable = unusable_password()?;
+        let token = random_string(32);
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut work = self.unit_of_work();
+        let reset = async {
+            let user = self
+                .modify_user_in(&mut work, user, |user| {
+                    user.password = unusable.clone();
+                    user.sessions_revoked_at = Some(now);
+                    Ok(())
+                })
+                .await?;
+            // revoked tokens aren't given back if the unit is rolled back, the user only
+            // has to sign in again
+            self.authz_server.revoke_user_tokens(&user.id).await?;
+            work.delete_user_sessions(&user.id).await?;
+            work.save_password_reset(PasswordReset {
+                user_id: user.id.clone(),
+                token_hash: token_hash(&token),
+                created_at: now,
+                expires_at: now + PASSWORD_RESET_TTL.whole_seconds(),
+            })
+            .await?;
+
+            work.audit(
+                AuditEvent::new(AuthEvent::PasswordResetRequired)
+                    .with_actor(actor_id)
+                    .with_subject(&user.id),
+            );
+            Ok(user)
+        }
+        .await;
+        let user = work.finish(reset).await?;
+
+        // once the reset is saved, an email can't be taken back
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "Choose a new password".to_string(),
+                body: format!(
+                    "Your password was reset by an administrator. Follow this link to choose a new one:\n\n{}/auth/password/reset?token={token}\n\nThe link expires in 24 hours.",
+                    self.base_url
+                ),
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    async fn start_impersonation(
+        &self,
+        actor_id: &str,
+        user_id: &str,
+        client: &ClientInfo,
+    ) -> Result<String> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let admin = self.user(actor_id).await?;
+        if check_status(&admin, now).is_err() || !admin.has_role(Role::Admin) {
+            return Err(AuthError::ImpersonationNotAllowed);
+        }
+
+        let user = self.user(user_id).await?;
+        check_status(&user, now)?;
+        // admins can't be impersonated, so an impersonation session never has admin rights
+        if user.id == admin.id || user.has_role(Role::Admin) {
+            return Err(AuthError::ImpersonationNotAllowed);
+        }
+
+        let token = match self.session_store {
+            Some(_) => {
+                self.store_session(Session {
+                    id: String::new(),
+                    user_id: user.id.clone(),
+                    created_at: now,
+                    last_seen_at: now,
+                    expires_at: now + IMPERSONATION_TTL.whole_seconds(),
+                    ip: client.ip.clone(),
+                    user_agent: client
+                        .user_agent
+                        .as_ref()
+                        .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect()),
+                    data: HashMap::new(),
+                    impersonator_id: Some(admin.id.clone()),
+                })
+                .await?
+            }
+            None => self.jwt_service.generate_impersonation_token(
+                &user.id,
+                &admin.id,
+                IMPERSONATION_TTL,
+            )?,
+        };
+
+        self.audit(
+            AuditEvent::new(AuthEvent::ImpersonationStarted)
+                .with_actor(&admin.id)
+                .with_subject(&user.id)
+                .with_client(client),
+        )
+        .await?;
+
+        Ok(token)
+    }
+
+    async fn impersonator(&self, token: &str) -> Result<Option<User>> {
+        let (admin_id, issued_at) = match self.session_store {
+            Some(_) => {
+                let (_, session) = self.live_session(token).await?;
+                (session.impersonator_id, session.created_at)
+            }
+            None => {
+                self.validate_token(token).await?;
+                let claims = self.jwt_service.validate_token(token)?;
+                (claims.act.map(|actor| actor.sub), claims.iat)
+            }
+        };
+
+        match admin_id {
+            Some(admin_id) => Ok(Some(self.impersonating_admin(&admin_id, issued_at).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn stop_impersonation(&self, token: &str, client: &ClientInfo) -> Result<()> {
+        let user = self.validate_token(token).await?;
+        let Some(admin) = self.impersonator(token).await? else {
+            return Err(AuthError::NotImpersonating);
+        };
+
+        // impersonation JWTs can't be revoked, they're short lived and dropped with the cookie
+        if let Some(session_store) = &self.session_store {
+            session_store.delete_session(&token_hash(token)).await?;
+        }
+
+        self.audit(
+            AuditEvent::new(AuthEvent::ImpersonationStopped)
+                .with_actor(&admin.id)
+                .with_subject(&user.id)
+                .with_client(client),
+        )
+        .await
+    }
+
+    async fn create_api_key(&self, token: &str, new_key: NewApiKey) -> Result<(ApiKey, String)> {
+        let user = self.own_user(token).await?;
+        if new_key.name.trim().is_empty() {
+            return Err(AuthError::InvalidApiKey);
+        }
+
+        let generated = generate_api_key();
+
+        let key_hash = hash_password(&password::ContentToHash {
+            content: generated.secret.clone(),
+            salt: Uuid::new_v4(),
+        })?;
+
+        let api_key = ApiKey {
+            id: Uuid::new_v4().to_string(),
+            user_id: user.id.clone(),
+            name: new_key.name,
+            prefix: generated.prefix.clone(),
+            key_hash,
+            scopes: new_key.scopes,
+            created_at: OffsetDateTime::now_utc().unix_timestamp(),
+            expires_at: new_key.expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        };
+        let api_key = self.api_key_repo.create_api_key(api_key).await?;
+
+        let event = AuthEvent::ApiKeyCreated {
+            key_id: api_key.id.clone(),
+            name: api_key.name.clone(),
+        };
+        self.audit(AuditEvent::new(event).with_user(&user.id))
+            .await?;
+
+        Ok((api_key, generated.plain_text()))
+    }
+
+    async fn list_api_keys(&self, user_id: &str) -> Result<Vec<ApiKey>> {
+        Ok(self.api_key_repo.list_by_user(user_id).await?)
+    }
+
+    async fn revoke_api_key(&self, user_id: &str, api_key_id: &str) -> Result<ApiKey> {
+        // someone else's key is as good as missing
+        let mut api_key = match self.api_key_repo.find_by_id(api_key_id).await? {
+            Some(api_key) if api_key.user_id == user_id => api_key,
+            _ => return Err(AuthError::ApiKeyNotFound),
+        };
+
+        if api_key.revoked_at.is_none() {
+            api_key.revoked_at = Some(OffsetDateTime::now_utc().unix_timestamp());
+            api_key = self.api_key_repo.update_api_key(&api_key).await?;
+
+            let event = AuthEvent::ApiKeyRevoked {
+                key_id: api_key.id.clone(),
+            };
+            self.audit(AuditEvent::new(event).with_user(user_id))
+                .await?;
+        }
+
+        Ok(api_key)
+    }
+
+    async fn validate_api_key(&self, key: &str) -> Result<(User, ApiKey)> {
+        let parsed = parse_api_key(key).ok_or(AuthError::InvalidApiKey)?;
+
+        let api_key = match self.api_key_repo.find_by_prefix(&parsed.prefix).await? {
+            Some(api_key) => api_key,
+            None => return Err(AuthError::InvalidApiKey),
+        };
+
+        verify_password(&parsed.secret, &api_key.key_hash).map_err(|_| AuthError::InvalidApiKey)?;
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if api_key.is_revoked() {
+            return Err(AuthError::ApiKeyRevoked);
+        }
+        if api_key.is_expired(now) {
+            return Err(AuthError::ApiKeyExpired);
+        }
+
+        let user = match self.user_repo.find_by_id(&api_key.user_id).await? {
+            Some(user) => user,
+            None => return Err(AuthError::UserNotFound),
+        };
+        check_status(&user, now)?;
+
+        // the key may have been revoked while it was checked, only the time it was used
+        // is written so that sticks
+        match self.api_key_repo.touch_last_used(&api_key.id, now).await? {
+            Some(api_key) => Ok((user, api_key)),
+            None => Err(AuthError::ApiKeyRevoked),
+        }
+    }
+
+    fn oauth_providers(&self) -> Vec<OAuthProvider> {
+        self.oauth_client
+            .providers()
+            .iter()
+            .map(|p| OAuthProvider {
+                name: p.name.clone(),
+                display_name: p.display_name.clone(),
+            })
+            .collect()
+    }
+
+    fn oauth_authorize(&self, provider: &str) -> Result<AuthorizationRequest> {
+        Ok(self.oauth_client.authorize(provider)?)
+    }
+
+    async fn oauth_signin(
+        &self,
+        provider: &str,
+        code: &str,
+        state: &str,
+        client: &ClientInfo,
+    ) -> Result<String> {
+        let signed_in = async {
+            let external = self
+                .oauth_client
+                .exchange_code(provider, code, state)
+                .await?;
+
+            let user = self.user_for_identity(external).await?;
+            let token = self.session_token(&user, client).await?;
+            Ok((user, token))
+        }
+        .await;
+
+        self.audit_signin(format!("oauth:{provider}"), None, client, signed_in)
+            .await
+    }
+
+    async fn list_identities(&self, user_id: &str) -> Result<Vec<Identity>> {
+        Ok(self.identity_repo.list_by_user(user_id).await?)
+    }
+
+    async fn register_client_app(
+        &self,
+        owner_id: &str,
+        new_client: NewClientApp,
+    ) -> Result<(ClientApp, String)> {
+        let (client_app, secret) = self
+            .authz_server
+            .register_client(owner_id, new_client)
+            .await?;
+
+        let event = AuthEvent::ClientAppRegistered {
+            client_id: client_app.id.clone(),
+        };
+        self.audit(AuditEvent::new(event).with_user(owner_id))
+            .await?;
+
+        Ok((client_app, secret))
+    }
+
+    async fn list_client_apps(&self, owner_id: &str) -> Result<Vec<ClientApp>> {
+        self.authz_server.list_clients(owner_id).await
+    }
+
+    async fn validate_authorize_request(
+        &self,
+        params: &AuthorizeParams,
+    ) -> Result<(ClientApp, Vec<Scope>)> {
+        self.authz_server.validate_authorize(params).await
+    }
+
+    async fn approve_authorize_request(
+        &self,
+        user_id: &str,
+        params: &AuthorizeParams,
+    ) -> Result<String> {
+        let redirect = self.authz_server.approve(user_id, params).await?;
+
+        let event = AuthEvent::AuthorizationGranted {
+            client_id: params.client_id.clone(),
+            scopes: params.scope.clone().unwrap_or_default(),
+        };
+        self.audit(AuditEvent::new(event).with_user(user_id))
+            .await?;
+
+        Ok(redirect)
+    }
+
+    async fn deny_authorize_request(&self, params: &AuthorizeParams) -> Result<String> {
+        self.authz_server.deny(params).await
+    }
+
+    async fn exchange_token(
+        &self,
+        creds: &ClientCredentials,
+        request: TokenRequest,
+    ) -> Result<TokenResponse> {
+        let issued = self.authz_server.token(creds, request).await?;
+        if !issued.scopes.contains(&Scope::OpenId) {
+            return Ok(issued.response);
+        }
+
+        let user = match self.user_repo.find_by_id(&issued.user_id).await? {
+            Some(user) => user,
+            None => return Err(AuthError::UserNotFound),
+        };
+        let id_token = self.authz_server.id_token(&user, &issued)?;
+
+        Ok(TokenResponse {
+            id_token: Some(id_token),
+            ..issued.response
+        })
+    }
+
+    async fn introspect_token(
+        &self,
+        creds: &ClientCredentials,
+        token: &str,
+    ) -> Result<Introspection> {
+        self.authz_server.introspect(creds, token).await
+    }
+
+    async fn revoke_token(&self, creds: &ClientCredentials, token: &str) -> Result<()> {
+        self.authz_server.revoke(creds, token).await?;
+
+        let event = AuthEvent::TokenRevoked {
+            client_id: creds.client_id.clone(),
+        };
+        self.audit(AuditEvent::new(event).with_actor(&format!("client:{}", creds.client_id)))
+            .await
+    }
+
+    async fn validate_access_token(&self, token: &str) -> Result<(User, Option<AccessGrant>)> {
+        // anything but a client token is a session, whichever form sessions take
+        match self.jwt_service.validate_token(token) {
+            Ok(claims) if claims.token_use.is_some() => {}
+            _ => return Ok((self.validate_token(token).await?, None)),
+        }
+
+        let (claims, grant) = self.authz_server.validate_access_token(token).await?;
+        let user = self.session_user(&claims.sub, claims.iat).await?;
+
+        Ok((user, Some(grant)))
+    }
+
+    fn openid_configuration(&self) -> ProviderMetadata {
+        self.authz_server.metadata()
+    }
+
+    fn jwks(&self) -> Jwks {
+        self.authz_server.jwks()
+    }
+
+    async fn userinfo(&self, access_token: &str) -> Result<UserInfo> {
+        let (claims, grant) = self
+            .authz_server
+            .validate_access_token(access_token)
+            .await?;
+        if !grant.has_scope(Scope::OpenId) {
+            return Err(AuthError::InsufficientScope);
+        }
+
+        let user = self.session_user(&claims.sub, claims.iat).await?;
+
+        Ok(UserInfo::from_user(&user, &grant.scopes))
+    }
+
+    async fn import_sso_metadata(
+        &self,
+        org: &str,
+        metadata_xml: &str,
+        email_domains: Vec<String>,
+    ) -> Result<SsoConnection> {
+        let connection = self
+            .service_provider
+            .import_metadata(org, metadata_xml, email_domains)
+            .await?;
+
+        let event = AuthEvent::SsoConnectionImported {
+            org: org.to_string(),
+        };
+        self.audit(AuditEvent::new(event)).await?;
+
+        Ok(connection)
+    }
+
+    fn sso_metadata(&self, org: &str) -> String {
+        self.service_provider.metadata(org)
+    }
+
+    async fn sso_authorize(&self, org: &str) -> Result<String> {
+        self.service_provider.authn_request(org).await
+    }
+
+    async fn sso_signin(
+        &self,
+        org: &str,
+        saml_response: &str,
+        client: &ClientInfo,
+    ) -> Result<String> {
+        let signed_in = async {
+            let external = self
+                .service_provider
+                .consume_response(org, saml_response)
+                .await?;
+
+            // provisioned on first signin, later ones resolve through the identity link
+            let user = self.user_for_identity(external).await?;
+            let token = self.session_token(&user, client).await?;
+            Ok((user, token))
+        }
+        .await;
+
+        self.audit_signin(format!("saml:{org}"), None, client, signed_in)
+            .await
+    }
+
+    async fn create_scim_token(&self, org: &str) -> Result<String> {
+        let token = self.scim.create_token(org).await?;
+
+        let event = AuthEvent::ScimTokenCreated {
+            org: org.to_string(),
+        };
+        self.audit(AuditEvent::new(event)).await?;
+
+        Ok(token)
+    }
+
+    async fn validate_scim_token(&self, token: &str) -> Result<String> {
+        self.scim.authenticate(token).await
+    }
+
+    fn scim_service_provider_config(&self) -> Value {
+        self.scim.service_provider_config()
+    }
+
+    async fn scim_list_users(
+        &self,
+        org: &str,
+        query: &ListQuery,
+    ) -> Result<ListResponse<ScimUser>> {
+        let groups = self.scim.groups(org).await?;
+
+        let mut resources = Vec::new();
+        for provisioned in self.scim.provisioned_users(org).await? {
+            if let Some(user) = self.user_repo.find_by_id(&provisioned.user_id).await? {
+                resources.push(self.scim.user_resource(&user, &provisioned, &groups));
+            }
+        }
+
+        list(resources, query)
+    }
+
+    async fn scim_get_user(&self, org: &str, id: &str) -> Result<ScimUser> {
+        let (_, resource) = self.scim_user(org, id).await?;
+        Ok(resource)
+    }
+
+    async fn scim_create_user(&self, org: &str, user: ScimUser) -> Result<ScimUser> {
+        let email = user.email().trim().to_lowercase();
+
+        let existing = match self.user_repo.find_by_email(&email).await? {
+            Some(existing) => {
+                let provisioned = self.scim.find_provisioned_user(org, &existing.id).await?;
+                if provisioned.is_some_and(|p| p.deprovisioned_at.is_none())
+                    || !self.adoptable_by(org, &existing).await?
+                {
+                    return Err(ScimError::Uniqueness.into());
+                }
+                Some(existing)
+            }
+            None => None,
+        };
+
+        // the account, the org's record of it and the audit entry are kept together
+        let mut work = self.unit_of_work();
+        let created = async {
+            let created = self.save_scim_user(&mut work, org, existing, user).await?;
+            let event = AuthEvent::UserProvisioned {
+                org: org.to_string(),
+            };
+            work.audit(AuditEvent {
+                subject: created.id.clone(),
+                ..AuditEvent::new(event).with_actor(&scim_actor(org))
+            });
+            Ok(created)
+        }
+        .await;
+        work.finish(created).await
+    }
+
+    async fn scim_replace_user(
+        &self,
+        org: &str,
+        id: &str,
+        user: ScimUser,
+        if_match: Option<&str>,
+    ) -> Result<ScimUser> {
+        let (existing, resource) = self.scim_user(org, id).await?;
+        check_version(resource.meta.as_ref(), if_match)?;
+
+        let mut work = self.unit_of_work();
+        let saved = self
+            .save_scim_user(&mut work, org, Some(existing), user)
+            .await;
+        work.finish(saved).await
+    }
+
+    async fn scim_patch_user(
+        &self,
+        org: &str,
+        id: &str,
+        patch: PatchRequest,
+        if_match: Option<&str>,
+    ) -> Result<ScimUser> {
+        let (existing, resource) = self.scim_user(org, id).await?;
+        check_version(resource.meta.as_ref(), if_match)?;
+
+        let patched = apply_patch(&resource, &patch)?;
+        let mut work = self.unit_of_work();
+        let saved = self
+            .save_scim_user(&mut work, org, Some(existing), patched)
+            .await;
+        work.finish(saved).await
+    }
+
+    async fn scim_delete_user(&self, org: &str, id: &str, if_match: Option<&str>) -> Result<()> {
+        let (user, resource) = self.scim_user(org, id).await?;
+        check_version(resource.meta.as_ref(), if_match)?;
+
+        let provisioned = self.scim.provisioned_user(org, id).await?;
+        let mut work = self.unit_of_work();
+        let deprovisioned = async {
+            self.change_status_in(
+                &mut work,
+                user,
+                AccountStatus::Disabled,
+                None,
+                &scim_actor(org),
+            )
+            .await?;
+            // deprovisioned users are taken out of the org's groups
+            work.remove_member(org, id).await?;
+            work.save_provisioned_user(ProvisionedUser {
+                deprovisioned_at: Some(OffsetDateTime::now_utc().unix_timestamp()),
+                ..provisioned
+            })
+            .await?;
+
+            let event = AuthEvent::UserDeprovisioned {
+                org: org.to_string(),
+            };
+            work.audit(
+                AuditEvent::new(event)
+                    .with_actor(&scim_actor(org))
+                    .with_subject(id),
+            );
+            Ok(())
+        }
+        .await;
+        work.finish(deprovisioned).await
+    }
+
+    async fn scim_list_groups(
+        &self,
+        org: &str,
+        query: &ListQuery,
+    ) -> Result<ListResponse<ScimGroup>> {
+        self.scim.list_groups(org, query).await
+    }
+
+    async fn scim_get_group(&self, org: &str, id: &str) -> Result<ScimGroup> {
+        self.scim.get_group(org, id).await
+    }
+
+    async fn scim_create_group(&self, org: &str, group: ScimGroup) -> Result<ScimGroup> {
+        self.scim.create_group(org, group).await
+    }
+
+    async fn scim_replace_group(
+        &self,
+        org: &str,
+        id: &str,
+        group: ScimGroup,
+        if_match: Option<&str>,
+    ) -> Result<ScimGroup> {
+        self.scim.replace_group(org, id, group, if_match).await
+    }
+
+    async fn scim_patch_group(
+        &self,
+        org: &str,
+        id: &str,
+        patch: PatchRequest,
+        if_match: Option<&str>,
+    ) -> Result<ScimGroup> {
+        self.scim.patch_group(org, id, &patch, if_match).await
+    }
+
+    async fn scim_delete_group(&self, org: &str, id: &str, if_match: Option<&str>) -> Result<()> {
+        self.scim.delete_group(org, id, if_match).await
+    }
+}
+
+// Changes made by an org's directory are attributed to it
+fn scim_actor(org: &str) -> String {
+    format!("scim:{org}")
+}
+
+// Rejects accounts that can't be used right now, saying why
+fn check_status(user: &User, now: i64) -> Result<()> {
+    if user.is_deleted() {
+        return Err(AuthError::AccountDeleted);
+    }
+
+    match user.status {
+        AccountStatus::Disabled => Err(AuthError::AccountDisabled),
+        AccountStatus::Suspended { until } if until > now => {
+            Err(AuthError::AccountSuspended(until))
+        }
+        AccountStatus::PendingVerification => Err(AuthError::AccountPendingVerification),
+        _ => Ok(()),
+    }
+}
+
+// Sessions and emailed tokens are stored under their hash, a leaked store can't be replayed
+fn token_hash(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+// TODO: implement more robust password validation
+fn validate_password(password: &str) -> Result<()> {
+    if password.len() < 8 {
+        return Err(AuthError::PasswordValidation(
+            "Password must be at least 8 characters".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Sensitive changes ask for the password again, whatever the session
+fn verify_current_password(user: &User, password: &str) -> Result<()> {
+    verify_password(password, &user.password)
+        .map(|_| ())
+        .map_err(|_| AuthError::InvalidCredentials)
+}
+
+// Random password for accounts that sign in through a provider, nobody knows it
+pub(crate) fn unusable_password() -> Result<String> {
+    hash_password(&password::ContentToHash {
+        content: random_string(32),
+        salt: Uuid::new_v4(),
+    })
+}
+
+// Simple email validation
+pub(crate) fn validate_email(email: &str) -> Result<bool> {
+    match regex::Regex::new(
+        r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})",
+    ) {
+        Ok(r) => Ok(r.is_match(email)),
+        Err(_) => Err(AuthError::EmailValidation),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+
+    use crate::jwt::TokenUse;
+    use crate::models::{Scope, UserFilter, UserSort};
+    use crate::oauth::mock::{MockAuthorizationServer, MockUser};
+    use crate::repository::in_mem_session_store::InMemorySessionStore;
+    use crate::saml::error::SamlError;
+    use crate::saml::mock::{MockAssertion, MockIdp};
+    use crate::scim::resources::{MultiValued, PatchOperation, ScimName};
+    use crate::{InMemoryUserRepository, RedbUserRepository, password::ContentToHash};
+
+    use super::*;
+    // use auth::{
+    //     AuthService, AuthServiceImpl, Credentials, JwtService, RegisterUser, User,
+    //     password::{hash_password, needs_rehash, verify_password},
+    //     repository::memory::InMemoryUserRepository,
+    // };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_auth_service_end_to_end() {
+        check_auth_service_end_to_end(Arc::new(InMemoryUserRepository::new())).await;
+    }
+
+    async fn check_auth_service_end_to_end<R: UserRepositoryTrait>(user_repository: Arc<R>) {
+        // Setup dependencies
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+
+        // Create auth service
+        let auth_service = AuthService::new(user_repository, jwt_service.clone());
+
+        // Test data
+        let register_data = RegisterUser {
+            email: "test@example.com".to_string(),
+            password: "Password123!".to_string(),
+            name: "Test User".to_string(),
+        };
+
+        // Test registration
+        let registered_user = auth_service
+            .register(register_data.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+        assert_eq!(registered_user.email, "test@example.com");
+        assert_eq!(registered_user.name, "Test User".to_string());
+
+        // Test login
+        let credentials = Credentials {
+            email: "test@example.com".to_string(),
+            password: "Password123!".to_string(),
+        };
+
+        let token = auth_service
+            .signin(credentials, &ClientInfo::default())
+            .await
+            .unwrap();
+        assert!(!token.is_empty());
+
+        // Test token validation
+        let user = auth_service.validate_token(&token).await.unwrap();
+        assert_eq!(user.email, "test@example.com");
+
+        // Test invalid login
+        let invalid_credentials = Credentials {
+            email: "test@example.com".to_string(),
+            password: "WrongPassword123!".to_string(),
+        };
+
+        let login_result = auth_service
+            .signin(invalid_credentials, &ClientInfo::default())
+            .await;
+        assert!(login_result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_user_registration() {
+        // Setup dependencies
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+
+        // Create auth service
+        let auth_service = AuthService::new(user_repository, jwt_service.clone());
+
+        // Test data
+        let register_data = RegisterUser {
+            email: "duplicate@example.com".to_string(),
+            password: "Password123!".to_string(),
+            name: "Test User".to_string(),
+        };
+
+        // First registration should succeed
+        let registered_user = auth_service
+            .register(register_data.clone(), &ClientInfo::default())
+            .await
+            .unwrap();
+        assert_eq!(registered_user.email, "duplicate@example.com");
+
+        // Second registration with same email should fail
+        let duplicate_result = auth_service
+            .register(register_data, &ClientInfo::default())
+            .await;
+        assert!(duplicate_result.is_err());
+        match duplicate_result {
+            Err(AuthError::UserExists) => (),
+            _ => panic!("Expected UserAlreadyExists error"),
+        }
+    }
+
+    // signs in a user registered with the password the tests use
+    async fn signin_token<R: UserRepositoryTrait>(
+        auth_service: &AuthService<R>,
+        email: &str,
+    ) -> String {
+        let creds = Credentials {
+            email: email.to_string(),
+            password: "Password123!".to_string(),
+        };
+        auth_service
+            .signin(creds, &ClientInfo::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_api_key_lifecycle() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service);
+
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "apikey@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Test User".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        let token = signin_token(&auth_service, "apikey@example.com").await;
+
+        // Create a key, the plain text key is only returned here
+        let (api_key, plain_text) = auth_service
+            .create_api_key(
+                &token,
+                NewApiKey {
+                    name: "CI".to_string(),
+                    scopes: vec![Scope::Read],
+                    expires_at: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert!(!api_key.key_hash.contains(&plain_text));
+        assert!(api_key.last_used_at.is_none());
+
+        // Validate the key and check last used is recorded
+        let (key_user, used_key) = auth_service.validate_api_key(&plain_text).await.unwrap();
+        assert_eq!(key_user.id, user.id);
+        assert!(used_key.has_scope(Scope::Read));
+        assert!(!used_key.has_scope(Scope::Write));
+        assert!(used_key.last_used_at.is_some());
+
+        // Tampered secret should fail
+        let tampered = format!("{}x", &plain_text[..plain_text.len() - 1]);
+        assert!(matches!(
+            auth_service.validate_api_key(&tampered).await,
+            Err(AuthError::InvalidApiKey)
+        ));
+
+        // Only the owner can revoke a key
+        assert!(matches!(
+            auth_service
+                .revoke_api_key("someone-else", &api_key.id)
+                .await,
+            Err(AuthError::ApiKeyNotFound)
+        ));
+        assert!(matches!(
+            auth_service.revoke_api_key(&user.id, "missing").await,
+            Err(AuthError::ApiKeyNotFound)
+        ));
+
+        // Revoked key should fail
+        auth_service
+            .revoke_api_key(&user.id, &api_key.id)
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_service.validate_api_key(&plain_text).await,
+            Err(AuthError::ApiKeyRevoked)
+        ));
+
+        let keys = auth_service.list_api_keys(&user.id).await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert!(keys[0].is_revoked());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_revoked_while_validated() {
+        use crate::repository::error::Result as RepoResult;
+        use crate::repository::in_mem_api_key_repo::InMemoryApiKeyRepository;
+
+        // revokes the key right after it's looked up, as a concurrent request would
+        #[derive(Default)]
+        struct Revoking {
+            api_keys: InMemoryApiKeyRepository,
+        }
+
+        #[async_trait]
+        impl ApiKeyRepositoryTrait for Revoking {
+            async fn create_api_key(&self, api_key: ApiKey) -> RepoResult<ApiKey> {
+                self.api_keys.create_api_key(api_key).await
+            }
+            async fn find_by_id(&self, id: &str) -> RepoResult<Option<ApiKey>> {
+                self.api_keys.find_by_id(id).await
+            }
+            async fn find_by_prefix(&self, prefix: &str) -> RepoResult<Option<ApiKey>> {
+                let found = self.api_keys.find_by_prefix(prefix).await?;
+                if let Some(api_key) = &found {
+                    self.api_keys
+                        .update_api_key(&ApiKey {
+                            revoked_at: Some(1),
+                            ..api_key.clone()
+                        })
+                        .await?;
+                }
+                Ok(found)
+            }
+            async fn list_by_user(&self, user_id: &str) -> RepoResult<Vec<ApiKey>> {
+                self.api_keys.list_by_user(user_id).await
+            }
+            async fn update_api_key(&self, api_key: &ApiKey) -> RepoResult<ApiKey> {
+                self.api_keys.update_api_key(api_key).await
+            }
+            async fn touch_last_used(&self, id: &str, now: i64) -> RepoResult<Option<ApiKey>> {
+                self.api_keys.touch_last_used(id, now).await
+            }
+            async fn delete_by_user(&self, user_id: &str) -> RepoResult<usize> {
+                self.api_keys.delete_by_user(user_id).await
+            }
+        }
+
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let api_key_repo = Arc::new(Revoking::default());
+        let auth_service =
+            AuthService::new(user_repository, jwt_service).with_api_key_repo(api_key_repo.clone());
+        auth_service
+            .register(
+                RegisterUser {
+                    email: "revoked@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Test User".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        let token = signin_token(&auth_service, "revoked@example.com").await;
+        let (api_key, plain_text) = auth_service
+            .create_api_key(
+                &token,
+                NewApiKey {
+                    name: "CI".to_string(),
+                    scopes: vec![Scope::Read],
+                    expires_at: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        // the key is rejected and stays revoked
+        assert!(matches!(
+            auth_service.validate_api_key(&plain_text).await,
+            Err(AuthError::ApiKeyRevoked)
+        ));
+        let stored = api_key_repo.find_by_id(&api_key.id).await.unwrap().unwrap();
+        assert!(stored.is_revoked());
+        assert!(stored.last_used_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_api_key() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service);
+
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "expired@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Test User".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        let token = signin_token(&auth_service, &user.email).await;
+
+        let (_, plain_text) = auth_service
+            .create_api_key(
+                &token,
+                NewApiKey {
+                    name: "Old".to_string(),
+                    scopes: vec![Scope::Read],
+                    expires_at: Some(OffsetDateTime::now_utc().unix_timestamp() - 1),
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            auth_service.validate_api_key(&plain_text).await,
+            Err(AuthError::ApiKeyExpired)
+        ));
+    }
+
+    fn oauth_auth_service(
+        server: &Arc<MockAuthorizationServer>,
+    ) -> AuthService<InMemoryUserRepository> {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let oauth_client = OAuthClient::new(server.clone()).with_provider(server.provider_config());
+
+        AuthService::new(user_repository, jwt_service).with_oauth_client(Arc::new(oauth_client))
+    }
+
+    async fn mock_signin(
+        auth_service: &AuthService<InMemoryUserRepository>,
+        server: &MockAuthorizationServer,
+        sub: &str,
+    ) -> Result<String> {
+        let request = auth_service.oauth_authorize("mock")?;
+        let (code, state) = server.approve(&request.url, sub)?;
+        auth_service
+            .oauth_signin("mock", &code, &state, &ClientInfo::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_oauth_signin_creates_and_reuses_user() {
+        let server = Arc::new(MockAuthorizationServer::new());
+        server.add_user(MockUser {
+            sub: "mock-1".to_string(),
+            email: "social@example.com".to_string(),
+            email_verified: true,
+            name: "Social User".to_string(),
+        });
+        let auth_service = oauth_auth_service(&server);
+
+        // First signin creates the user and links the identity
+        let token = mock_signin(&auth_service, &server, "mock-1").await.unwrap();
+        let user = auth_service.validate_token(&token).await.unwrap();
+        assert_eq!(user.email, "social@example.com");
+        assert_eq!(user.name, "Social User");
+
+        // Second signin resolves to the same user through the identity link
+        let token = mock_signin(&auth_service, &server, "mock-1").await.unwrap();
+        let same_user = auth_service.validate_token(&token).await.unwrap();
+        assert_eq!(same_user.id, user.id);
+
+        let identities = auth_service.list_identities(&user.id).await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].provider, "mock");
+        assert_eq!(identities[0].subject, "mock-1");
+
+        // The generated password can't be used to sign in
+        let password_signin = auth_service
+            .signin(
+                Credentials {
+                    email: "social@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(password_signin.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oauth_signin_rejects_malformed_email() {
+        let server = Arc::new(MockAuthorizationServer::new());
+        server.add_user(MockUser {
+            sub: "mock-malformed".to_string(),
+            email: "not-an-email".to_string(),
+            email_verified: true,
+            name: "Malformed".to_string(),
+        });
+        let auth_service = oauth_auth_service(&server);
+
+        // No account is created for an address the provider got wrong
+        let result = mock_signin(&auth_service, &server, "mock-malformed").await;
+        assert!(matches!(result, Err(AuthError::EmailValidation)));
+        let users = auth_service
+            .list_users(&UserQuery::default())
+            .await
+            .unwrap();
+        assert!(users.users.is_empty());
+
+        // nor for one given when registering
+        let registered = auth_service
+            .register(
+                RegisterUser {
+                    email: "not-an-email".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Malformed".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(registered, Err(AuthError::EmailValidation)));
+    }
+
+    #[tokio::test]
+    async fn test_oauth_signin_links_verified_email() {
+        let server = Arc::new(MockAuthorizationServer::new());
+        server.add_user(MockUser {
+            sub: "mock-verified".to_string(),
+            email: "existing@example.com".to_string(),
+            email_verified: true,
+            name: "Existing".to_string(),
+        });
+        server.add_user(MockUser {
+            sub: "mock-unverified".to_string(),
+            email: "existing@example.com".to_string(),
+            email_verified: false,
+            name: "Attacker".to_string(),
+        });
+        let auth_service = oauth_auth_service(&server);
+
+        let existing = auth_service
+            .register(
+                RegisterUser {
+                    email: "existing@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Existing".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+
+        // An unverified email must not take over the existing account
+        let result = mock_signin(&auth_service, &server, "mock-unverified").await;
+        assert!(matches!(result, Err(AuthError::UserExists)));
+
+        let token = mock_signin(&auth_service, &server, "mock-verified")
+            .await
+            .unwrap();
+        let user = auth_service.validate_token(&token).await.unwrap();
+        assert_eq!(user.id, existing.id);
+
+        // A disabled account isn't handed out through its link, nor linked again
+        let disabled = auth_service
+            .change_status(user, AccountStatus::Disabled, None, "admin")
+            .await
+            .unwrap();
+        let external = |subject: &str| ExternalIdentity {
+            provider: "mock".to_string(),
+            subject: subject.to_string(),
+            email: disabled.email.clone(),
+            email_verified: true,
+            name: disabled.name.clone(),
+        };
+        assert!(matches!(
+            auth_service
+                .user_for_identity(external("mock-verified"))
+                .await,
+            Err(AuthError::AccountDisabled)
+        ));
+        assert!(matches!(
+            auth_service.user_for_identity(external("mock-other")).await,
+            Err(AuthError::AccountDisabled)
+        ));
+        let identities = auth_service.list_identities(&disabled.id).await.unwrap();
+        assert_eq!(identities.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_tokens_are_not_sessions() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service.clone());
+
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "client@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Test User".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+
+        let (access_token, _) = jwt_service
+            .generate_client_token(
+                &user.id,
+                "client-123",
+                "read",
+                TokenUse::Access,
+                time::Duration::hours(1),
+            )
+            .unwrap();
+
+        // a client token can't be used as a session cookie
+        assert!(matches!(
+            auth_service.validate_token(&access_token).await,
+            Err(AuthError::Unauthorized)
+        ));
+
+        // session tokens are accepted as bearer tokens without a grant
+        let session_token = jwt_service.generate_token(&user.id).unwrap();
+        let (session_user, grant) = auth_service
+            .validate_access_token(&session_token)
+            .await
+            .unwrap();
+        assert_eq!(session_user.id, user.id);
+        assert!(grant.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_openid_connect_flow() {
+        use crate::authz_server::oidc::{IdTokenClaims, SIGNING_ALG};
+        use crate::oauth::pkce;
+        use jsonwebtoken::{DecodingKey, Validation, decode};
+
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service);
+
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "oidc@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Oidc User".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+
+        let redirect_uri = "https://partner.example.com/callback";
+        let (client_app, secret) = auth_service
+            .register_client_app(
+                &user.id,
+                NewClientApp {
+                    name: "Partner".to_string(),
+                    redirect_uris: vec![redirect_uri.to_string()],
+                    scopes: vec![Scope::Read],
+                },
+            )
+            .await
+            .unwrap();
+        let creds = ClientCredentials {
+            client_id: client_app.id.clone(),
+            client_secret: secret,
+        };
+
+        let verifier = pkce::generate_verifier();
+        let redirect = auth_service
+            .approve_authorize_request(
+                &user.id,
+                &AuthorizeParams {
+                    response_type: "code".to_string(),
+                    client_id: client_app.id.clone(),
+                    redirect_uri: redirect_uri.to_string(),
+                    scope: Some("openid email profile".to_string()),
+                    state: None,
+                    code_challenge: Some(pkce::challenge(&verifier)),
+                    code_challenge_method: Some("S256".to_string()),
+                    nonce: Some("n-0S6_WzA2Mj".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+        let code = url::Url::parse(&redirect)
+            .unwrap()
+            .query_pairs()
+            .find(|(k, _)| k == "code")
+            .unwrap()
+            .1
+            .to_string();
+
+        let tokens = auth_service
+            .exchange_token(
+                &creds,
+                TokenRequest {
+                    grant_type: "authorization_code".to_string(),
+                    code: Some(code),
+                    redirect_uri: Some(redirect_uri.to_string()),
+                    code_verifier: Some(verifier),
+                    refresh_token: None,
+                    scope: None,
+                    client_id: None,
+                    client_secret: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        // the id token verifies against the published key
+        let jwk = &auth_service.jwks().keys[0];
+        let mut validation = Validation::new(SIGNING_ALG);
+        validation.set_audience(&[&client_app.id]);
+        validation.set_issuer(&[&auth_service.openid_configuration().issuer]);
+        let claims = decode::<IdTokenClaims>(
+            tokens.id_token.as_deref().unwrap(),
+            &DecodingKey::from_ec_components(&jwk.x, &jwk.y).unwrap(),
+            &validation,
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(claims.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+        assert_eq!(claims.user_info.sub, user.id);
+        assert_eq!(claims.user_info.email.as_deref(), Some("oidc@example.com"));
+        assert_eq!(claims.user_info.email_verified, Some(false));
+        assert_eq!(claims.user_info.name.as_deref(), Some("Oidc User"));
+
+        let user_info = auth_service.userinfo(&tokens.access_token).await.unwrap();
+        assert_eq!(user_info.sub, user.id);
+        assert_eq!(user_info.name.as_deref(), Some("Oidc User"));
+
+        // session tokens and tokens without the openid scope get nothing
+        let session_token = auth_service
+            .signin(
+                Credentials {
+                    email: "oidc@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        assert!(auth_service.userinfo(&session_token).await.is_err());
+
+        let refreshed = auth_service
+            .exchange_token(
+                &creds,
+                TokenRequest {
+                    grant_type: "refresh_token".to_string(),
+                    code: None,
+                    redirect_uri: None,
+                    code_verifier: None,
+                    refresh_token: Some(tokens.refresh_token),
+                    scope: Some("email".to_string()),
+                    client_id: None,
+                    client_secret: None,
+                },
+            )
+            .await
+            .unwrap();
+        assert!(refreshed.id_token.is_none());
+        assert!(matches!(
+            auth_service.userinfo(&refreshed.access_token).await,
+            Err(AuthError::InsufficientScope)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sso_signin_provisions_user() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service);
+        let idp = MockIdp::new();
+
+        auth_service
+            .import_sso_metadata(
+                "acme",
+                include_str!("saml/fixtures/idp_metadata.xml"),
+                vec!["acme.example.com".to_string()],
+            )
+            .await
+            .unwrap();
+        let metadata = auth_service.sso_metadata("acme");
+        assert!(metadata.contains("http://localhost:3000/auth/sso/acme/acs"));
+
+        let signin = async || {
+            let redirect = auth_service.sso_authorize("acme").await?;
+            let assertion = MockAssertion::new(
+                "http://localhost:3000/auth/sso",
+                "http://localhost:3000/auth/sso/acme/acs",
+                &MockIdp::request_id(&redirect),
+                OffsetDateTime::now_utc().unix_timestamp(),
+            );
+            let response = STANDARD.encode(idp.response(&assertion));
+            auth_service
+                .sso_signin("acme", &response, &ClientInfo::default())
+                .await
+        };
+
+        // First signin provisions the user just in time
+        let token = signin().await.unwrap();
+        let user = auth_service.validate_token(&token).await.unwrap();
+        assert_eq!(user.email, "jane.doe@acme.example.com");
+        assert_eq!(user.name, "Jane Doe");
+        assert!(user.email_verified);
+
+        let token = signin().await.unwrap();
+        let same_user = auth_service.validate_token(&token).await.unwrap();
+        assert_eq!(same_user.id, user.id);
+
+        let identities = auth_service.list_identities(&user.id).await.unwrap();
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].provider, "saml:acme");
+
+        assert!(matches!(
+            auth_service.sso_authorize("unknown").await,
+            Err(AuthError::Saml(SamlError::ConnectionNotFound(_)))
+        ));
+    }
+
+    fn scim_user(user_name: &str, given_name: &str) -> ScimUser {
+        ScimUser {
+            user_name: user_name.to_string(),
+            name: Some(ScimName {
+                given_name: Some(given_name.to_string()),
+                family_name: Some("Jensen".to_string()),
+                ..Default::default()
+            }),
+            emails: vec![MultiValued::new(user_name)],
+            active: true,
+            ..Default::default()
+        }
+    }
+
+    fn patch(op: &str, path: &str, value: serde_json::Value) -> PatchRequest {
+        PatchRequest {
+            schemas: vec![],
+            operations: vec![PatchOperation {
+                op: op.to_string(),
+                path: Some(path.to_string()),
+                value: Some(value),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scim_user_provisioning() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service);
+
+        let token = auth_service.create_scim_token("acme").await.unwrap();
+        assert_eq!(
+            auth_service.validate_scim_token(&token).await.unwrap(),
+            "acme"
+        );
+        assert!(matches!(
+            auth_service.validate_scim_token("scim_nope_nope").await,
+            Err(AuthError::Scim(ScimError::InvalidToken))
+        ));
+
+        let barbara = auth_service
+            .scim_create_user("acme", scim_user("bjensen@acme.example.com", "Barbara"))
+            .await
+            .unwrap();
+        let id = barbara.id.clone().unwrap();
+        assert_eq!(barbara.meta.as_ref().unwrap().resource_type, "User");
+        auth_service
+            .scim_create_user("acme", scim_user("kjensen@acme.example.com", "Kim"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_service
+                .scim_create_user("acme", scim_user("BJensen@acme.example.com", "Babs"))
+                .await,
+            Err(AuthError::Scim(ScimError::Uniqueness))
+        ));
+
+        // Filtering and pagination
+        let query = ListQuery {
+            filter: Some(r#"userName eq "bjensen@acme.example.com""#.to_string()),
+            ..Default::default()
+        };
+        let listed = auth_service.scim_list_users("acme", &query).await.unwrap();
+        assert_eq!(listed.total_results, 1);
+        assert_eq!(listed.resources[0].id.as_deref(), Some(id.as_str()));
+
+        let query = ListQuery {
+            start_index: Some(2),
+            count: Some(1),
+            ..Default::default()
+        };
+        let page = auth_service.scim_list_users("acme", &query).await.unwrap();
+        assert_eq!((page.total_results, page.items_per_page), (2, 1));
+        assert_eq!(page.resources[0].user_name, "kjensen@acme.example.com");
+
+        // Other orgs don't see the users
+        let all = ListQuery::default();
+        let other = auth_service.scim_list_users("globex", &all).await.unwrap();
+        assert_eq!(other.total_results, 0);
+        assert!(auth_service.scim_get_user("globex", &id).await.is_err());
+
+        // Stale versions are rejected
+        let version = barbara.meta.as_ref().unwrap().version.clone();
+        let renamed = auth_service
+            .scim_patch_user(
+                "acme",
+                &id,
+                patch("replace", "displayName", "Babs Jensen".into()),
+                Some(&version),
+            )
+            .await
+            .unwrap();
+        assert_ne!(renamed.meta.as_ref().unwrap().version, version);
+        assert!(matches!(
+            auth_service
+                .scim_patch_user(
+                    "acme",
+                    &id,
+                    patch("replace", "displayName", "Barbara".into()),
+                    Some(&version),
+                )
+                .await,
+            Err(AuthError::Scim(ScimError::PreconditionFailed))
+        ));
+
+        let user = auth_service
+            .user_repo
+            .find_by_id(&id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.name, "Babs Jensen");
+        let session = auth_service.jwt_service.generate_token(&user.id).unwrap();
+        assert!(auth_service.validate_token(&session).await.is_ok());
+
+        // Deactivating revokes existing sessions
+        let deactivated = auth_service
+            .scim_patch_user("acme", &id, patch("replace", "active", false.into()), None)
+            .await
+            .unwrap();
+        assert!(!deactivated.active);
+        assert!(matches!(
+            auth_service.validate_token(&session).await,
+            Err(AuthError::AccountDisabled)
+        ));
+
+        // Deprovisioned users are gone from the org
+        auth_service
+            .scim_delete_user("acme", &id, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_service.scim_get_user("acme", &id).await,
+            Err(AuthError::Scim(ScimError::NotFound(_)))
+        ));
+        let listed = auth_service.scim_list_users("acme", &all).await.unwrap();
+        assert_eq!(listed.total_results, 1);
+    }
+
+    #[tokio::test]
+    async fn test_scim_group_membership() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service);
+
+        let user = auth_service
+            .scim_create_user("acme", scim_user("bjensen@acme.example.com", "Barbara"))
+            .await
+            .unwrap();
+        let user_id = user.id.unwrap();
+
+        let group = auth_service
+            .scim_create_group(
+                "acme",
+                ScimGroup {
+                    schemas: vec![],
+                    id: None,
+                    external_id: None,
+                    display_name: "Admins".to_string(),
+                    members: vec![],
+                    meta: None,
+                },
+            )
+            .await
+            .unwrap();
+        let group_id = group.id.unwrap();
+
+        auth_service
+            .scim_patch_group(
+                "acme",
+                &group_id,
+                patch("add", "members", serde_json::json!([{ "value": user_id }])),
+                None,
+            )
+            .await
+            .unwrap();
+        let user = auth_service.scim_get_user("acme", &user_id).await.unwrap();
+        assert_eq!(user.groups.len(), 1);
+        assert_eq!(user.groups[0].display.as_deref(), Some("Admins"));
+
+        assert!(matches!(
+            auth_service
+                .scim_patch_group(
+                    "acme",
+                    &group_id,
+                    patch(
+                        "add",
+                        "members",
+                        serde_json::json!([{ "value": "unknown" }])
+                    ),
+                    None,
+                )
+                .await,
+            Err(AuthError::Scim(ScimError::InvalidValue(_)))
+        ));
+
+        // Deprovisioning drops the memberships
+        auth_service
+            .scim_delete_user("acme", &user_id, None)
+            .await
+            .unwrap();
+        let group = auth_service
+            .scim_get_group("acme", &group_id)
+            .await
+            .unwrap();
+        assert!(group.members.is_empty());
+
+        auth_service
+            .scim_delete_group("acme", &group_id, None)
+            .await
+            .unwrap();
+        assert!(
+            auth_service
+                .scim_get_group("acme", &group_id)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scim_provisioning_is_kept_whole() {
+        use crate::audit::error::{AuditError, Result as AuditResult};
+
+        struct FailingAuditSink;
+
+        #[async_trait]
+        impl AuditSink for FailingAuditSink {
+            async fn record(&self, _: AuditEvent) -> AuditResult<()> {
+                Err(AuditError::Write("disk full".to_string()))
+            }
+            async fn query(&self, _: &AuditFilter) -> AuditResult<Vec<AuditEvent>> {
+                Ok(Vec::new())
+            }
+        }
+
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service)
+            .with_audit_sink(Arc::new(FailingAuditSink));
+
+        // The account and the org's record of it aren't kept without the audit entry
+        assert!(matches!(
+            auth_service
+                .scim_create_user("acme", scim_user("bjensen@acme.example.com", "Barbara"))
+                .await,
+            Err(AuthError::Audit(_))
+        ));
+        assert!(
+            auth_service
+                .user_repo
+                .find_by_email("bjensen@acme.example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let listed = auth_service
+            .scim_list_users("acme", &ListQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(listed.total_results, 0);
+    }
+
+    #[tokio::test]
+    async fn test_server_side_sessions() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service.clone())
+            .with_session_store(Arc::new(InMemorySessionStore::new()));
+        assert_eq!(auth_service.session_mode(), SessionMode::ServerSide);
+
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "test@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Test User".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        let signin = async || {
+            auth_service
+                .signin(
+                    Credentials {
+                        email: "test@example.com".to_string(),
+                        password: "Password123!".to_string(),
+                    },
+                    &ClientInfo::default(),
+                )
+                .await
+        };
+
+        // Opaque tokens, JWTs are no longer sessions
+        let token = signin().await.unwrap();
+        assert!(!token.contains('.'));
+        assert_eq!(
+            auth_service.validate_token(&token).await.unwrap().id,
+            user.id
+        );
+        let (bearer_user, grant) = auth_service.validate_access_token(&token).await.unwrap();
+        assert_eq!((bearer_user.id, grant.is_none()), (user.id.clone(), true));
+        let jwt = jwt_service.generate_token(&user.id).unwrap();
+        assert!(auth_service.validate_token(&jwt).await.is_err());
+        assert!(auth_service.validate_access_token(&jwt).await.is_err());
+
+        // Per-session data carries over when the id is rotated
+        auth_service
+            .set_session_data(&token, "theme", Some("dark".to_string()))
+            .await
+            .unwrap();
+        let rotated = auth_service.rotate_session(&token).await.unwrap();
+        assert!(auth_service.validate_token(&token).await.is_err());
+        let data = auth_service.session_data(&rotated).await.unwrap();
+        assert_eq!(data.get("theme").map(String::as_str), Some("dark"));
+
+        // Signing out revokes instantly
+        let other = signin().await.unwrap();
+        auth_service.signout(&rotated).await.unwrap();
+        assert!(auth_service.validate_token(&rotated).await.is_err());
+        assert!(auth_service.validate_token(&other).await.is_ok());
+
+        // A role change ends the sessions from before it
+        let user = auth_service
+            .grant_role("root", &user.id, Role::Admin)
+            .await
+            .unwrap();
+        assert!(auth_service.validate_token(&other).await.is_err());
+        let other = signin().await.unwrap();
+        assert!(auth_service.validate_token(&other).await.is_ok());
+
+        // So does disabling the account
+        auth_service
+            .change_status(user, AccountStatus::Disabled, None, "admin")
+            .await
+            .unwrap();
+        assert!(auth_service.validate_token(&other).await.is_err());
+        assert!(matches!(signin().await, Err(AuthError::AccountDisabled)));
+    }
+
+    #[tokio::test]
+    async fn test_jwt_sessions_have_no_store() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service.clone());
+        assert_eq!(auth_service.session_mode(), SessionMode::Jwt);
+
+        let token = jwt_service.generate_token("user").unwrap();
+        auth_service.signout(&token).await.unwrap();
+        assert!(matches!(
+            auth_service.session_data(&token).await,
+            Err(AuthError::SessionStoreRequired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_list_and_revoke_sessions() {
+        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repository, jwt_service)
+            .with_session_store(Arc::new(InMemorySessionStore::new()));
+
+        for email in ["laptop@example.com", "other@example.com"] {
+            auth_service
+                .register(
+                    RegisterUser {
+                        email: email.to_string(),
+                        password: "Password123!".to_string(),
+                        name: "Test User".to_string(),
+                    },
+                    &ClientInfo::default(),
+                )
+                .await
+                .unwrap();
+        }
+        let signin = async |email: &str, user_agent: &str| {
+            let client = ClientInfo {
+                ip: Some("203.0.113.7".to_string()),
+                user_agent: Some(user_agent.to_string()),
+            };
+            let creds = Credentials {
+                email: email.to_string(),
+                password: "Password123!".to_string(),
+            };
+            auth_service.signin(creds, &client).await.unwrap()
+        };
+
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        let laptop = signin("laptop@example.com", firefox).await;
+        let phone = signin("laptop@example.com", "curl/8.5.0").await;
+        let tablet = signin("laptop@example.com", "curl/8.5.0").await;
+        let someone_else = signin("other@example.com", firefox).await;
+
+        let sessions = auth_service.list_sessions(&laptop).await.unwrap();
+        assert_eq!(sessions.len(), 3);
+        let current = sessions.iter().find(|s| s.current).unwrap();
+        assert_eq!(current.device, "Firefox on Linux");
+        assert_eq!(current.ip.as_deref(), Some("203.0.113.7"));
+
+        // Sessions of other users can't be revoked
+        let theirs = auth_service.list_sessions(&someone_else).await.unwrap();
+        assert!(matches!(
+            auth_service.revoke_session(&laptop, &theirs[0].id).await,
+            Err(AuthError::SessionNotFound)
+        ));
+
+        let phone_id = auth_service
+            .list_sessions(&phone)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|s| s.current)
+            .unwrap()
+            .id;
+        auth_service
+            .revoke_session(&laptop, &phone_id)
+            .await
+            .unwrap();
+        assert!(auth_service.validate_token(&phone).await.is_err());
+
+        assert_eq!(
+            auth_service.revoke_other_sessions(&laptop).await.unwrap(),
+            1
+        );
+        assert!(auth_service.validate_token(&tablet).await.is_err());
+        assert!(auth_service.validate_token(&laptop).await.is_ok());
+        assert!(auth_service.validate_token(&someone_else).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let user_repo = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repo, jwt_service);
+        let client = ClientInfo {
+            ip: Some("198.51.100.4".to_string()),
+            user_agent: None,
+        };
+
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "audited@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Audited".to_string(),
+                },
+                &client,
+            )
+            .await
+            .unwrap();
+        let signin = async |password: &str| {
+            let creds = Credentials {
+                email: "audited@example.com".to_string(),
+                password: password.to_string(),
+            };
+            auth_service.signin(creds, &client).await
+        };
+        assert!(signin("wrong password").await.is_err());
+        let token = signin("Password123!").await.unwrap();
+        auth_service
+            .grant_role("root", &user.id, Role::Admin)
+            .await
+            .unwrap();
+        auth_service.signout(&token).await.unwrap();
+
+        let entries = auth_service
+            .audit_log(&AuditFilter::default())
+            .await
+            .unwrap();
+        let kinds: Vec<_> = entries.iter().map(|e| e.event.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "signed_out",
+                "role_granted",
+                "signin_succeeded",
+                "signin_failed",
+                "registered"
+            ]
+        );
+
+        // failures are recorded with what was attempted
+        let failed = &entries[3];
+        assert_eq!(failed.ip.as_deref(), Some("198.51.100.4"));
+        assert_eq!(failed.subject, None);
+        assert!(matches!(
+            &failed.event,
+            AuthEvent::SigninFailed { method, email: Some(email), .. }
+                if method == "password" && email == "audited@example.com"
+        ));
+
+        let granted = &entries[1];
+        assert_eq!(granted.actor.as_deref(), Some("root"));
+        assert_eq!(granted.subject.as_deref(), Some(user.id.as_str()));
+
+        let filter = AuditFilter {
+            user_id: Some("root".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(auth_service.audit_log(&filter).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_events() {
+        let user_repo = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let outbox = Arc::new(InMemoryOutbox::new());
+        let auth_service = AuthService::new(user_repo, jwt_service).with_outbox(outbox.clone());
+
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "events@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Events".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        let token = signin_token(&auth_service, &user.email).await;
+        auth_service
+            .delete_account(&token, "Password123!", &ClientInfo::default())
+            .await
+            .unwrap();
+        // a failed deletion publishes nothing
+        assert!(
+            auth_service
+                .delete_account(&token, "Password123!", &ClientInfo::default())
+                .await
+                .is_err()
+        );
+
+        let messages = outbox.due(i64::MAX, 10).await.unwrap();
+        let kinds: Vec<_> = messages.iter().map(|m| m.event.kind()).collect();
+        assert_eq!(kinds, vec!["user_registered", "erasure_requested"]);
+        assert_eq!(
+            messages[0].event,
+            DomainEvent::UserRegistered {
+                user_id: user.id.clone(),
+                email: "events@example.com".to_string(),
+                name: "Events".to_string(),
+            }
+        );
+
+        // A dead letter shows up for admins until it's retried
+        let dispatcher = crate::OutboxDispatcher::new(outbox.clone())
+            .with_handler(Arc::new(crate::LogHandler))
+            .with_max_attempts(1);
+        outbox
+            .failed(&OutboxMessage {
+                dead: true,
+                ..messages[1].clone()
+            })
+            .await
+            .unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        assert_eq!(dispatcher.dispatch(now).await.unwrap().delivered, 1);
+        let dead = auth_service.outbox_dead_letters().await.unwrap();
+        assert_eq!(
+            dead,
+            vec![OutboxMessage {
+                dead: true,
+                ..messages[1].clone()
+            }]
+        );
+        auth_service
+            .retry_outbox_message(&messages[1].id)
+            .await
+            .unwrap();
+        assert!(auth_service.outbox_dead_letters().await.unwrap().is_empty());
+        assert!(matches!(
+            auth_service.retry_outbox_message(&messages[1].id).await,
+            Err(AuthError::Outbox(_))
+        ));
+        assert_eq!(dispatcher.dispatch(now + 1).await.unwrap().delivered, 1);
+
+        // Purging an account deletes the events about it, delivered or not
+        let purged = auth_service
+            .register(
+                RegisterUser {
+                    email: "purged@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Purged".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        let registered = outbox.due(i64::MAX, 10).await.unwrap();
+        outbox
+            .failed(&OutboxMessage {
+                dead: true,
+                ..registered[0].clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(auth_service.outbox_dead_letters().await.unwrap().len(), 1);
+        auth_service.purge_user(&purged).await.unwrap();
+        assert!(auth_service.outbox_dead_letters().await.unwrap().is_empty());
+        assert!(outbox.due(i64::MAX, 10).await.unwrap().is_empty());
+    }
+    #[tokio::test]
+    async fn test_lifecycle_hooks() {
+        use crate::hooks::error::HookError;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct Policy {
+            registered: AtomicUsize,
+            signed_in: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl AuthHook for Policy {
+            async fn before_register(
+                &self,
+                user_data: &RegisterUser,
+            ) -> std::result::Result<(), HookError> {
+                if user_data.email.ends_with("@blocked.example") {
+                    return Err(HookError::Rejected(
+                        "Signups from this domain are closed".into(),
+                    ));
+                }
+                Ok(())
+            }
+
+            async fn after_register(&self, _user: &User) {
+                self.registered.fetch_add(1, Ordering::SeqCst);
+            }
+
+            async fn before_signin(
+                &self,
+                user: &User,
+                _client: &ClientInfo,
+            ) -> std::result::Result<(), HookError> {
+                if user.name == "Locked" {
+                    return Err(HookError::Rejected("Pay your invoice first".into()));
+                }
+                Ok(())
+            }
+
+            async fn after_signin(&self, _user: &User, _client: &ClientInfo) {
+                self.signed_in.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let policy = Arc::new(Policy::default());
+        let user_repo = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service =
+            AuthService::new(user_repo.clone(), jwt_service).with_hook(policy.clone());
+
+        let register = async |email: &str, name: &str| {
+            let user_data = RegisterUser {
+                email: email.to_string(),
+                password: "Password123!".to_string(),
+                name: name.to_string(),
+            };
+            auth_service
+                .register(user_data, &ClientInfo::default())
+                .await
+        };
+        let signin = async |email: &str| {
+            let creds = Credentials {
+                email: email.to_string(),
+                password: "Password123!".to_string(),
+            };
+            auth_service.signin(creds, &ClientInfo::default()).await
+        };
+
+        // A veto cancels the operation and carries the hook's reason
+        assert!(matches!(
+            register("jane@blocked.example", "Jane").await,
+            Err(AuthError::Hook(HookError::Rejected(reason)))
+                if reason == "Signups from this domain are closed"
+        ));
+        assert!(
+            user_repo
+                .find_by_email("jane@blocked.example")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        register("jane@example.com", "Jane").await.unwrap();
+        register("locked@example.com", "Locked").await.unwrap();
+        assert_eq!(policy.registered.load(Ordering::SeqCst), 2);
+
+        signin("jane@example.com").await.unwrap();
+        assert!(matches!(
+            signin("locked@example.com").await,
+            Err(AuthError::Hook(_))
+        ));
+        assert_eq!(policy.signed_in.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_account_settings() {
+        check_account_settings(Arc::new(InMemoryUserRepository::new())).await;
+    }
+
+    async fn check_account_settings<R: UserRepositoryTrait>(user_repo: Arc<R>) {
+        use crate::hooks::error::HookError;
+
+        // keeps the last admin around
+        struct KeepAdmins;
+
+        #[async_trait]
+        impl AuthHook for KeepAdmins {
+            async fn before_delete(&self, user: &User) -> std::result::Result<(), HookError> {
+                if user.has_role(Role::Admin) {
+                    return Err(HookError::Rejected(
+                        "Admins can't delete their account".into(),
+                    ));
+                }
+                Ok(())
+            }
+        }
+
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service)
+            .with_hook(Arc::new(KeepAdmins))
+            .with_erasure_grace_period(Duration::ZERO);
+        let client = ClientInfo::default();
+
+        let register = async || {
+            let user_data = RegisterUser {
+                email: "settings@example.com".to_string(),
+                password: "Password123!".to_string(),
+                name: "Settings".to_string(),
+            };
+            auth_service.register(user_data, &client).await.unwrap()
+        };
+        let signin = async |password: &str| {
+            let creds = Credentials {
+                email: "settings@example.com".to_string(),
+                password: password.to_string(),
+            };
+            auth_service.signin(creds, &client).await
+        };
+        let user = register().await;
+
+        let updated = auth_service
+            .update_profile(&user.id, "  New Name ")
+            .await
+            .unwrap();
+        assert_eq!(updated.name, "New Name");
+        assert!(matches!(
+            auth_service.update_profile(&user.id, " ").await,
+            Err(AuthError::ProfileValidation(_))
+        ));
+
+        // The current password is required
+        let token = signin("Password123!").await.unwrap();
+        assert!(matches!(
+            auth_service
+                .change_password(&token, "wrong password", "NewPassword456!", &client)
+                .await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            auth_service
+                .change_password(&token, "Password123!", "short", &client)
+                .await,
+            Err(AuthError::PasswordValidation(_))
+        ));
+        auth_service
+            .change_password(&token, "Password123!", "NewPassword456!", &client)
+            .await
+            .unwrap();
+        assert!(signin("Password123!").await.is_err());
+        let token = signin("NewPassword456!").await.unwrap();
+
+        // Hooks can refuse the deletion
+        auth_service
+            .grant_role("root", &user.id, Role::Admin)
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_service
+                .delete_account(&token, "NewPassword456!", &client)
+                .await,
+            Err(AuthError::Hook(_))
+        ));
+        auth_service
+            .revoke_role("root", &user.id, Role::Admin)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            auth_service
+                .delete_account(&token, "Password123!", &client)
+                .await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        auth_service
+            .delete_account(&token, "NewPassword456!", &client)
+            .await
+            .unwrap();
+        assert!(auth_service.validate_token(&token).await.is_err());
+        assert!(matches!(
+            signin("NewPassword456!").await,
+            Err(AuthError::AccountDeleted)
+        ));
+
+        // The account is kept until it's purged
+        let deleted = user_repo.find_by_id(&user.id).await.unwrap().unwrap();
+        assert!(deleted.is_deleted());
+        assert_eq!(auth_service.purge_deleted_accounts().await.unwrap(), 1);
+        assert!(user_repo.find_by_id(&user.id).await.unwrap().is_none());
+        assert_eq!(auth_service.purge_deleted_accounts().await.unwrap(), 0);
+
+        // The email is free again
+        register().await;
+
+        let kinds: Vec<_> = auth_service
+            .audit_log(&AuditFilter {
+                user_id: Some(user.id.clone()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.event.kind())
+            .collect();
+        assert!(kinds.contains(&"password_changed"));
+        assert!(kinds.contains(&"profile_updated"));
+        assert_eq!(kinds[..2], ["account_deleted", "erasure_requested"]);
+    }
+
+    #[tokio::test]
+    async fn test_data_export_and_erasure() {
+        let user_repo = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let session_store = Arc::new(InMemorySessionStore::new());
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service)
+            .with_session_store(session_store)
+            .with_erasure_grace_period(Duration::days(30));
+        let client = ClientInfo {
+            ip: Some("203.0.113.7".to_string()),
+            user_agent: Some("curl/8.0".to_string()),
+        };
+
+        let user_data = RegisterUser {
+            email: "export@example.com".to_string(),
+            password: "Password123!".to_string(),
+            name: "Exporter".to_string(),
+        };
+        let user = auth_service.register(user_data, &client).await.unwrap();
+        let creds = Credentials {
+            email: "export@example.com".to_string(),
+            password: "Password123!".to_string(),
+        };
+        let token = auth_service.signin(creds.clone(), &client).await.unwrap();
+        let (api_key, _) = auth_service
+            .create_api_key(
+                &token,
+                NewApiKey {
+                    name: "laptop".to_string(),
+                    scopes: vec![Scope::Read],
+                    expires_at: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        let json = auth_service
+            .export_user_data(&token, ExportFormat::Json)
+            .await
+            .unwrap();
+        let export: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(export["profile"]["email"], "export@example.com");
+        assert_eq!(export["api_keys"][0]["prefix"], api_key.prefix.as_str());
+        assert_eq!(export["sessions"][0]["ip"], "203.0.113.7");
+        assert_eq!(export["audit_events"][0]["type"], "api_key_created");
+        // Secrets never leave
+        assert!(!String::from_utf8(json).unwrap().contains(&api_key.key_hash));
+
+        let zip = auth_service
+            .export_user_data(&token, ExportFormat::Zip)
+            .await
+            .unwrap();
+        assert!(zip.starts_with(b"PK\x03\x04"));
+
+        // Deleted accounts are kept through the grace period
+        auth_service
+            .delete_account(&token, "Password123!", &client)
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_service.signin(creds, &client).await,
+            Err(AuthError::AccountDeleted)
+        ));
+        assert_eq!(auth_service.purge_deleted_accounts().await.unwrap(), 0);
+        assert!(user_repo.find_by_id(&user.id).await.unwrap().is_some());
+        assert_eq!(auth_service.list_api_keys(&user.id).await.unwrap().len(), 1);
+
+        let mut deleted = user_repo.find_by_id(&user.id).await.unwrap().unwrap();
+        deleted.deleted_at = Some(deleted.deleted_at.unwrap() - Duration::days(31).whole_seconds());
+        user_repo.update_user(&deleted).await.unwrap();
+
+        assert_eq!(auth_service.purge_deleted_accounts().await.unwrap(), 1);
+        assert!(user_repo.find_by_id(&user.id).await.unwrap().is_none());
+        assert!(
+            auth_service
+                .list_api_keys(&user.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let exported = auth_service
+            .audit_log(&AuditFilter {
+                kind: Some("data_exported".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(exported.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_email_change() {
+        check_email_change(Arc::new(InMemoryUserRepository::new())).await;
+    }
+
+    async fn check_email_change<R: UserRepositoryTrait>(user_repo: Arc<R>) {
+        use crate::mailer::InMemoryMailer;
+
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let mailer = Arc::new(InMemoryMailer::new());
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service)
+            .with_mailer(mailer.clone())
+            .with_base_url("https://app.example.com/");
+        let client = ClientInfo::default();
+
+        let register = async |email: &str| {
+            let user_data = RegisterUser {
+                email: email.to_string(),
+                password: "Password123!".to_string(),
+                name: "Mover".to_string(),
+            };
+            auth_service.register(user_data, &client).await.unwrap()
+        };
+        // the token of the link in the last email sent to `to`
+        let token_sent_to = |to: &str| {
+            let email = mailer.sent().into_iter().rfind(|e| e.to == to).unwrap();
+            let link = email
+                .body
+                .lines()
+                .find(|l| l.starts_with("https://app.example.com/auth/email/"))
+                .unwrap()
+                .to_string();
+            link.split_once("token=").unwrap().1.to_string()
+        };
+        let user = register("old@example.com").await;
+        register("taken@example.com").await;
+        let token = signin_token(&auth_service, "old@example.com").await;
+
+        let request = async |new_email: &str, password: &str| {
+            auth_service
+                .request_email_change(&token, new_email, password, &client)
+                .await
+        };
+        assert!(matches!(
+            request("new@example.com", "wrong password").await,
+            Err(AuthError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            request("old@example.com", "Password123!").await,
+            Err(AuthError::EmailValidation)
+        ));
+        assert!(matches!(
+            request("taken@example.com", "Password123!").await,
+            Err(AuthError::UserExists)
+        ));
+
+        request("new@example.com", "Password123!").await.unwrap();
+        let confirm = token_sent_to("new@example.com");
+        let cancel = token_sent_to("old@example.com");
+        assert_eq!(
+            auth_service
+                .pending_email_change(&user.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .new_email,
+            "new@example.com"
+        );
+        // nothing changes until the new address confirms
+        let unchanged = user_repo.find_by_id(&user.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.email, "old@example.com");
+
+        // the old address can only cancel
+        assert!(matches!(
+            auth_service.confirm_email_change(&cancel, &client).await,
+            Err(AuthError::EmailChangeNotFound)
+        ));
+        let changed = auth_service
+            .confirm_email_change(&confirm, &client)
+            .await
+            .unwrap();
+        assert_eq!(changed.email, "new@example.com");
+        assert!(changed.email_verified);
+        assert!(
+            auth_service
+                .confirm_email_change(&confirm, &client)
+                .await
+                .is_err()
+        );
+        assert!(
+            auth_service
+                .pending_email_change(&user.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Cancelling from the old address
+        request("other@example.com", "Password123!").await.unwrap();
+        let confirm = token_sent_to("other@example.com");
+        let cancel = token_sent_to("new@example.com");
+        assert_eq!(
+            auth_service
+                .find_email_change(&cancel)
+                .await
+                .unwrap()
+                .new_email,
+            "other@example.com"
+        );
+        auth_service
+            .cancel_email_change(&cancel, &client)
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_service.confirm_email_change(&confirm, &client).await,
+            Err(AuthError::EmailChangeNotFound)
+        ));
+
+        // The address is checked again on confirmation
+        request("late@example.com", "Password123!").await.unwrap();
+        let confirm = token_sent_to("late@example.com");
+        register("late@example.com").await;
+        assert!(matches!(
+            auth_service.confirm_email_change(&confirm, &client).await,
+            Err(AuthError::UserExists)
+        ));
+        let user = user_repo.find_by_id(&user.id).await.unwrap().unwrap();
+        assert_eq!(user.email, "new@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_admin_user_management() {
+        use crate::mailer::InMemoryMailer;
+
+        let user_repo = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let mailer = Arc::new(InMemoryMailer::new());
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service)
+            .with_session_store(Arc::new(InMemorySessionStore::new()))
+            .with_mailer(mailer.clone());
+        let client = ClientInfo::default();
+
+        for (email, name) in [
+            ("ada@example.com", "Ada Lovelace"),
+            ("grace@example.com", "Grace Hopper"),
+            ("alan@example.org", "Alan Turing"),
+        ] {
+            let user_data = RegisterUser {
+                email: email.to_string(),
+                password: "Password123!".to_string(),
+                name: name.to_string(),
+            };
+            auth_service.register(user_data, &client).await.unwrap();
+        }
+        let signin = async |password: &str| {
+            let creds = Credentials {
+                email: "grace@example.com".to_string(),
+                password: password.to_string(),
+            };
+            auth_service.signin(creds, &client).await
+        };
+
+        // Searches match the email or the name, ignoring case
+        let search = async |search: &str| {
+            let query = UserQuery {
+                filter: UserFilter {
+                    search: Some(search.to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            auth_service.list_users(&query).await.unwrap().users
+        };
+        let found = search("HOPPER").await;
+        assert_eq!(found[0].email, "grace@example.com");
+        let found = search(".org").await;
+        assert_eq!(found.len(), 1);
+        let user = found[0].clone();
+        let grace = search("grace").await.remove(0);
+
+        // Disabling signs the user out, enabling lets them back in
+        let token = signin("Password123!").await.unwrap();
+        auth_service
+            .set_account_status("admin", &grace.id, AccountStatus::Disabled, None)
+            .await
+            .unwrap();
+        assert!(auth_service.validate_token(&token).await.is_err());
+        assert!(matches!(
+            signin("Password123!").await,
+            Err(AuthError::AccountDisabled)
+        ));
+        auth_service
+            .set_account_status("admin", &grace.id, AccountStatus::Active, None)
+            .await
+            .unwrap();
+        let token = signin("Password123!").await.unwrap();
+
+        assert_eq!(
+            auth_service
+                .revoke_user_sessions("admin", &grace.id)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(auth_service.validate_token(&token).await.is_err());
+
+        let verified = auth_service.verify_email("admin", &user.id).await.unwrap();
+        assert!(verified.email_verified);
+
+        // A forced reset locks the old password out until the emailed link is used
+        let token = signin("Password123!").await.unwrap();
+        auth_service
+            .force_password_reset("admin", &grace.id)
+            .await
+            .unwrap();
+        assert!(auth_service.validate_token(&token).await.is_err());
+        assert!(signin("Password123!").await.is_err());
+
+        let email = mailer.sent().pop().unwrap();
+        assert_eq!(email.to, "grace@example.com");
+        let reset_token = email
+            .body
+            .split_once("token=")
+            .unwrap()
+            .1
+            .split_whitespace()
+            .next()
+            .unwrap()
+            .to_string();
+        assert!(matches!(
+            auth_service
+                .reset_password(&reset_token, "short", &client)
+                .await,
+            Err(AuthError::PasswordValidation(_))
+        ));
+        auth_service
+            .reset_password(&reset_token, "NewPassword456!", &client)
+            .await
+            .unwrap();
+        signin("NewPassword456!").await.unwrap();
+        assert!(matches!(
+            auth_service.find_password_reset(&reset_token).await,
+            Err(AuthError::PasswordResetNotFound)
+        ));
+
+        let kinds: Vec<_> = auth_service
+            .audit_log(&AuditFilter {
+                user_id: Some(grace.id.clone()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.actor.as_deref() == Some("admin"))
+            .map(|e| e.event.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "password_reset_required",
+                "all_sessions_revoked",
+                "account_enabled",
+                "account_disabled"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_impersonation() {
+        for session_store in [None, Some(Arc::new(InMemorySessionStore::new()))] {
+            let user_repo = Arc::new(InMemoryUserRepository::new());
+            let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+            let mut auth_service = AuthService::new(user_repo.clone(), jwt_service);
+            if let Some(session_store) = session_store {
+                auth_service = auth_service.with_session_store(session_store);
+            }
+            let client = ClientInfo::default();
+
+            let mut users = Vec::new();
+            for email in ["admin@example.com", "user@example.com"] {
+                let user_data = RegisterUser {
+                    email: email.to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Test User".to_string(),
+                };
+                users.push(auth_service.register(user_data, &client).await.unwrap());
+            }
+            let (admin, user) = (users[0].clone(), users[1].clone());
+
+            // Only admins can impersonate, and only users who aren't admins themselves
+            assert!(matches!(
+                auth_service
+                    .start_impersonation(&admin.id, &user.id, &client)
+                    .await,
+                Err(AuthError::ImpersonationNotAllowed)
+            ));
+            auth_service
+                .grant_role("system", &admin.id, Role::Admin)
+                .await
+                .unwrap();
+            assert!(matches!(
+                auth_service
+                    .start_impersonation(&admin.id, &admin.id, &client)
+                    .await,
+                Err(AuthError::ImpersonationNotAllowed)
+            ));
+
+            // The token is the user's, and knows who is behind it
+            let token = auth_service
+                .start_impersonation(&admin.id, &user.id, &client)
+                .await
+                .unwrap();
+            assert_eq!(
+                auth_service.validate_token(&token).await.unwrap().id,
+                user.id
+            );
+            let impersonator = auth_service.impersonator(&token).await.unwrap().unwrap();
+            assert_eq!(impersonator.id, admin.id);
+
+            let creds = Credentials {
+                email: "user@example.com".to_string(),
+                password: "Password123!".to_string(),
+            };
+            let own_token = auth_service.signin(creds, &client).await.unwrap();
+            assert!(
+                auth_service
+                    .impersonator(&own_token)
+                    .await
+                    .unwrap()
+                    .is_none()
+            );
+            assert!(matches!(
+                auth_service.stop_impersonation(&own_token, &client).await,
+                Err(AuthError::NotImpersonating)
+            ));
+
+            // what only the user may do is refused, while the user can still do it
+            assert!(matches!(
+                auth_service
+                    .change_password(&token, "Password123!", "NewPassword456!", &client)
+                    .await,
+                Err(AuthError::Impersonating)
+            ));
+            assert!(matches!(
+                auth_service
+                    .request_email_change(&token, "new@example.com", "Password123!", &client)
+                    .await,
+                Err(AuthError::Impersonating)
+            ));
+            let new_key = || NewApiKey {
+                name: "ci".to_string(),
+                scopes: vec![Scope::Read],
+                expires_at: None,
+            };
+            assert!(matches!(
+                auth_service.create_api_key(&token, new_key()).await,
+                Err(AuthError::Impersonating)
+            ));
+            assert!(matches!(
+                auth_service
+                    .export_user_data(&token, ExportFormat::Json)
+                    .await,
+                Err(AuthError::Impersonating)
+            ));
+            assert!(matches!(
+                auth_service
+                    .delete_account(&token, "Password123!", &client)
+                    .await,
+                Err(AuthError::Impersonating)
+            ));
+            assert!(
+                auth_service
+                    .create_api_key(&own_token, new_key())
+                    .await
+                    .is_ok()
+            );
+
+            if auth_service.session_mode() == SessionMode::ServerSide {
+                assert!(matches!(
+                    auth_service.revoke_other_sessions(&token).await,
+                    Err(AuthError::Impersonating)
+                ));
+
+                auth_service
+                    .stop_impersonation(&token, &client)
+                    .await
+                    .unwrap();
+                assert!(auth_service.validate_token(&token).await.is_err());
+            }
+
+            // The impersonation ends with the admin's rights
+            let token = auth_service
+                .start_impersonation(&admin.id, &user.id, &client)
+                .await
+                .unwrap();
+            auth_service
+                .revoke_role("system", &admin.id, Role::Admin)
+                .await
+                .unwrap();
+            assert!(auth_service.validate_token(&token).await.is_err());
+            assert!(auth_service.validate_token(&own_token).await.is_ok());
+
+            let kinds: Vec<_> = auth_service
+                .audit_log(&AuditFilter {
+                    user_id: Some(user.id.clone()),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|e| e.actor.as_deref() == Some(admin.id.as_str()))
+                .map(|e| e.event.kind())
+                .collect();
+            let expected: &[&str] = match auth_service.session_mode() {
+                SessionMode::ServerSide => &[
+                    "impersonation_started",
+                    "impersonation_stopped",
+                    "impersonation_started",
+                ],
+                SessionMode::Jwt => &["impersonation_started", "impersonation_started"],
+            };
+            assert_eq!(kinds, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_account_status() {
+        let user_repo = Arc::new(InMemoryUserRepository::new());
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service);
+        let client = ClientInfo::default();
+
+        let user_data = RegisterUser {
+            email: "test@example.com".to_string(),
+            password: "Password123!".to_string(),
+            name: "Test User".to_string(),
+        };
+        let user = auth_service.register(user_data, &client).await.unwrap();
+        assert_eq!(user.status, AccountStatus::Active);
+        let signin = async || {
+            let creds = Credentials {
+                email: "test@example.com".to_string(),
+                password: "Password123!".to_string(),
+            };
+            auth_service.signin(creds, &client).await
+        };
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        // Suspensions must end, reasons are kept short
+        assert!(matches!(
+            auth_service
+                .set_account_status(
+                    "admin",
+                    &user.id,
+                    AccountStatus::Suspended { until: now },
+                    None
+                )
+                .await,
+            Err(AuthError::InvalidStatus(_))
+        ));
+        let long_reason = "x".repeat(MAX_STATUS_REASON_LEN + 1);
+        assert!(matches!(
+            auth_service
+                .set_account_status(
+                    "admin",
+                    &user.id,
+                    AccountStatus::Disabled,
+                    Some(&long_reason)
+                )
+                .await,
+            Err(AuthError::InvalidStatus(_))
+        ));
+
+        // Each status is rejected with its own error, and tokens issued before stay dead
+        let token = signin().await.unwrap();
+        let (_, api_key) = auth_service
+            .create_api_key(
+                &token,
+                NewApiKey {
+                    name: "ci".to_string(),
+                    scopes: vec![Scope::Read],
+                    expires_at: None,
+                },
+            )
+            .await
+            .unwrap();
+        let until = now + 3600;
+        let suspended = auth_service
+            .set_account_status(
+                "admin",
+                &user.id,
+                AccountStatus::Suspended { until },
+                Some(" spamming "),
+            )
+            .await
+            .unwrap();
+        assert_eq!(suspended.status_reason.as_deref(), Some("spamming"));
+        assert_eq!(suspended.status_changed_by.as_deref(), Some("admin"));
+        assert!(matches!(
+            auth_service.validate_token(&token).await,
+            Err(AuthError::AccountSuspended(at)) if at == until
+        ));
+        assert!(matches!(
+            signin().await,
+            Err(AuthError::AccountSuspended(_))
+        ));
+        assert!(matches!(
+            auth_service.validate_api_key(&api_key).await,
+            Err(AuthError::AccountSuspended(_))
+        ));
+
+        // A suspension lapses by itself
+        let mut lapsed = suspended.clone();
+        lapsed.status = AccountStatus::Suspended { until: now - 1 };
+        user_repo.update_user(&lapsed).await.unwrap();
+        let token = signin().await.unwrap();
+        assert!(auth_service.validate_token(&token).await.is_ok());
+        assert!(auth_service.validate_api_key(&api_key).await.is_ok());
+
+        auth_service
+            .set_account_status("admin", &user.id, AccountStatus::Disabled, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            auth_service.validate_token(&token).await,
+            Err(AuthError::AccountDisabled)
+        ));
+
+        // Pending accounts are let in once the email is verified
+        auth_service
+            .set_account_status("admin", &user.id, AccountStatus::PendingVerification, None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            signin().await,
+            Err(AuthError::AccountPendingVerification)
+        ));
+        let verified = auth_service.verify_email("admin", &user.id).await.unwrap();
+        assert_eq!(verified.status, AccountStatus::Active);
+        assert!(signin().await.is_ok());
+
+        let kinds: Vec<_> = auth_service
+            .audit_log(&AuditFilter {
+                user_id: Some(user.id.clone()),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.actor.as_deref() == Some("admin"))
+            .map(|e| e.event.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "account_enabled",
+                "email_verified",
+                "verification_required",
+                "account_disabled",
+                "account_suspended"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_users() {
+        check_list_users(Arc::new(InMemoryUserRepository::new())).await;
+    }
+
+    // What listing guarantees, whichever repository the users are in
+    async fn check_list_users<R: UserRepositoryTrait>(user_repo: Arc<R>) {
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service);
+
+        // several users share a creation time, as they do when created in bulk
+        for (i, (email, name)) in [
+            ("ada@example.com", "Ada Lovelace"),
+            ("alan@example.org", "Alan Turing"),
+            ("grace@example.com", "Grace Hopper"),
+            ("annie@example.com", "Annie Easley"),
+            ("edsger@example.org", "Edsger Dijkstra"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut user = User::new(email.to_string(), String::new(), name.to_string());
+            user.created_at = 1000 + (i as i64 / 2) * 10;
+            user.updated_at = user.created_at;
+            if email.starts_with('g') {
+                user.status = AccountStatus::Suspended { until: 5000 };
+            }
+            user_repo.create_user(user).await.unwrap();
+        }
+        let list = async |query: &UserQuery| auth_service.list_users(query).await.unwrap();
+        let emails = |users: &[User]| users.iter().map(|u| u.email.clone()).collect::<Vec<_>>();
+
+        // Paging with cursors visits everyone once, in the same order as a single page
+        for descending in [false, true] {
+            let everyone = UserQuery {
+                descending,
+                ..Default::default()
+            };
+            let all = list(&everyone).await;
+            assert_eq!(all.users.len(), 5);
+            assert!(all.next_cursor.is_none());
+            assert!(all.users.windows(2).all(|w| match descending {
+                false => w[0].created_at <= w[1].created_at,
+                true => w[0].created_at >= w[1].created_at,
+            }));
+
+            let mut paged = Vec::new();
+            let mut query = UserQuery {
+                limit: 2,
+                ..everyone.clone()
+            };
+            loop {
+                let page = list(&query).await;
+                paged.extend(page.users);
+                match page.next_cursor {
+                    Some(cursor) => query.cursor = Some(cursor),
+                    None => break,
+                }
+            }
+            assert_eq!(emails(&paged), emails(&all.users));
+        }
+
+        // Filters combine
+        let filtered = |filter: UserFilter| UserQuery {
+            filter,
+            ..Default::default()
+        };
+        let found = list(&filtered(UserFilter {
+            email_prefix: Some("A".to_string()),
+            ..Default::default()
+        }))
+        .await;
+        assert_eq!(found.users.len(), 3);
+        let found = list(&filtered(UserFilter {
+            email_prefix: Some("a".to_string()),
+            name_contains: Some("turing".to_string()),
+            ..Default::default()
+        }))
+        .await;
+        assert_eq!(emails(&found.users), ["alan@example.org"]);
+        let found = list(&filtered(UserFilter {
+            status: Some(AccountStatus::Suspended { until: 0 }),
+            ..Default::default()
+        }))
+        .await;
+        assert_eq!(emails(&found.users), ["grace@example.com"]);
+        let found = list(&filtered(UserFilter {
+            created_from: Some(1010),
+            created_until: Some(1020),
+            ..Default::default()
+        }))
+        .await;
+        assert_eq!(found.users.len(), 2);
+
+        // Updates reorder listings by update time
+        let mut ada = user_repo
+            .find_by_email("ada@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        user_repo.update_user(&ada).await.unwrap();
+        let recent = list(&UserQuery {
+            sort: UserSort::UpdatedAt,
+            descending: true,
+            limit: 1,
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(recent.users[0].email, "ada@example.com");
+
+        // A cursor only works with the order it was issued for
+        let page = list(&UserQuery {
+            limit: 1,
+            ..Default::default()
+        })
+        .await;
+        let replayed = auth_service
+            .list_users(&UserQuery {
+                sort: UserSort::UpdatedAt,
+                cursor: page.next_cursor,
+                ..Default::default()
+            })
+            .await;
+        assert!(matches!(
+            replayed,
+            Err(AuthError::Repository(RepoError::InvalidCursor))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_user_updates() {
+        check_concurrent_user_updates(InMemoryUserRepository::new()).await;
+    }
+
+    async fn check_concurrent_user_updates<R: UserRepositoryTrait>(users: R) {
+        use crate::repository::error::Result as RepoResult;
+        use std::sync::Mutex;
+
+        // saves a change of its own right before the next update, as a concurrent
+        // request would
+        struct Racing<R> {
+            users: R,
+            race: Mutex<Option<fn(&mut User)>>,
+        }
+
+        #[async_trait]
+        impl<R: UserRepositoryTrait> UserRepositoryTrait for Racing<R> {
+            async fn create_user(&self, user: User) -> RepoResult<User> {
+                self.users.create_user(user).await
+            }
+            async fn find_by_id(&self, id: &str) -> RepoResult<Option<User>> {
+                self.users.find_by_id(id).await
+            }
+            async fn find_by_email(&self, email: &str) -> RepoResult<Option<User>> {
+                self.users.find_by_email(email).await
+            }
+            async fn update_user(&self, user: &User) -> RepoResult<User> {
+                let race = self.race.lock().unwrap().take();
+                if let Some(race) = race {
+                    let mut other = self.users.find_by_id(&user.id).await?.unwrap();
+                    race(&mut other);
+                    self.users.update_user(&other).await?;
+                }
+                self.users.update_user(user).await
+            }
+            async fn delete_user(&self, id: &str) -> RepoResult<()> {
+                self.users.delete_user(id).await
+            }
+            async fn list_deleted_before(&self, before: i64) -> RepoResult<Vec<User>> {
+                self.users.list_deleted_before(before).await
+            }
+            async fn list_users(&self, query: &UserQuery) -> RepoResult<UserList> {
+                self.users.list_users(query).await
+            }
+        }
+
+        let user_repo = Arc::new(Racing {
+            users,
+            race: Mutex::default(),
+        });
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service);
+        let user = auth_service
+            .register(
+                RegisterUser {
+                    email: "ada@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Ada".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(user.version, 0);
+
+        // Saving a stale copy fails, every save bumps the version
+        let stale = user.clone();
+        let saved = user_repo.users.update_user(&user).await.unwrap();
+        assert_eq!(saved.version, 1);
+        assert!(matches!(
+            user_repo.users.update_user(&stale).await,
+            Err(RepoError::Conflict)
+        ));
+
+        // A change is reapplied on top of what was saved in between
+        *user_repo.race.lock().unwrap() = Some(|user| user.roles.push(Role::Admin));
+        let updated = auth_service
+            .update_profile(&user.id, "Ada Lovelace")
+            .await
+            .unwrap();
+        assert_eq!(updated.name, "Ada Lovelace");
+        assert!(updated.has_role(Role::Admin));
+        assert_eq!(updated.version, 3);
+
+        // unless it no longer applies, the current password was checked against a hash
+        // that's since been replaced
+        let token = signin_token(&auth_service, &user.email).await;
+        *user_repo.race.lock().unwrap() = Some(|user| user.password = "replaced".to_string());
+        let changed = auth_service
+            .change_password(
+                &token,
+                "Password123!",
+                "NewPassword123!",
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(changed, Err(AuthError::Conflict)));
+        let stored = user_repo.find_by_id(&user.id).await.unwrap().unwrap();
+        assert_eq!(stored.password, "replaced");
+    }
+
+    #[tokio::test]
+    async fn test_user_outbox() {
+        check_user_outbox(Arc::new(InMemoryUserRepository::new())).await;
+    }
+
+    async fn check_user_outbox<R: UserOutboxStore>(user_repo: Arc<R>) {
+        let ada = User::new(
+            "ada@example.com".to_string(),
+            String::new(),
+            "Ada".to_string(),
+        );
+        let registered = OutboxMessage::new(DomainEvent::UserRegistered {
+            user_id: ada.id.clone(),
+            email: ada.email.clone(),
+            name: ada.name.clone(),
+        });
+        let due = || async { user_repo.due(i64::MAX, 10).await.unwrap() };
+
+        // Messages written with a change wait for it to be committed
+        let ada = user_repo
+            .create_user_publishing(ada, vec![registered.clone()])
+            .await
+            .unwrap();
+        assert!(due().await.is_empty());
+        user_repo
+            .release(std::slice::from_ref(&registered.id))
+            .await
+            .unwrap();
+        assert_eq!(due().await, vec![registered.clone()]);
+
+        // and are gone when it's rolled back
+        let changed = OutboxMessage::new(DomainEvent::EmailChanged {
+            user_id: ada.id.clone(),
+            old_email: ada.email.clone(),
+            new_email: "lovelace@example.com".to_string(),
+        });
+        let ada = user_repo
+            .update_user_publishing(
+                &User {
+                    email: "lovelace@example.com".to_string(),
+                    ..ada
+                },
+                vec![changed.clone()],
+            )
+            .await
+            .unwrap();
+        user_repo
+            .discard(std::slice::from_ref(&changed.id))
+            .await
+            .unwrap();
+        user_repo
+            .release(std::slice::from_ref(&changed.id))
+            .await
+            .unwrap();
+        assert_eq!(due().await, vec![registered.clone()]);
+
+        // A dead letter waits for a retry
+        let dead = OutboxMessage {
+            dead: true,
+            attempts: 3,
+            ..registered.clone()
+        };
+        user_repo.failed(&dead).await.unwrap();
+        assert!(due().await.is_empty());
+        assert_eq!(user_repo.dead_letters().await.unwrap(), vec![dead]);
+        user_repo.retry(&registered.id, 100).await.unwrap();
+        assert_eq!(due().await[0].attempts, 0);
+
+        // Deleting the user deletes their messages, the events have their email
+        user_repo
+            .failed(&OutboxMessage {
+                dead: true,
+                ..registered.clone()
+            })
+            .await
+            .unwrap();
+        user_repo.delete_user(&ada.id).await.unwrap();
+        assert!(due().await.is_empty());
+        assert!(user_repo.dead_letters().await.unwrap().is_empty());
+        assert!(matches!(
+            user_repo.retry(&registered.id, 100).await,
+            Err(crate::outbox::error::OutboxError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_unique_emails() {
+        check_unique_emails(Arc::new(InMemoryUserRepository::new())).await;
+    }
+
+    async fn check_unique_emails<R: UserRepositoryTrait>(user_repo: Arc<R>) {
+        // Racing creations of the same address, however it's written, leave one account
+        let tasks: Vec<_> = (0..16)
+            .map(|i| {
+                let user_repo = user_repo.clone();
+                tokio::spawn(async move {
+                    let email = match i % 2 {
+                        0 => "ada@example.com",
+                        _ => " Ada@Example.com",
+                    };
+                    let user = User::new(email.to_string(), String::new(), "Ada".to_string());
+                    user_repo.create_user(user).await
+                })
+            })
+            .collect();
+        let mut created = 0;
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(_) => created += 1,
+                Err(e) => assert!(matches!(e, RepoError::DuplicateEmail)),
+            }
+        }
+        assert_eq!(created, 1);
+
+        let ada = user_repo
+            .find_by_email("ADA@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Changing email frees the old one and can't take someone else's
+        let grace = user_repo
+            .create_user(User::new(
+                "grace@example.com".to_string(),
+                String::new(),
+                "Grace".to_string(),
+            ))
+            .await
+            .unwrap();
+        let taken = User {
+            email: "Grace@example.com".to_string(),
+            ..ada.clone()
+        };
+        assert!(matches!(
+            user_repo.update_user(&taken).await,
+            Err(RepoError::DuplicateEmail)
+        ));
+        let moved = User {
+            email: "lovelace@example.com".to_string(),
+            ..ada.clone()
+        };
+        user_repo.update_user(&moved).await.unwrap();
+        assert!(
+            user_repo
+                .find_by_email("ada@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            user_repo
+                .find_by_email("lovelace@example.com")
+                .await
+                .unwrap()
+                .unwrap()
+                .id,
+            ada.id
+        );
+
+        // and deleting frees it for a new account
+        user_repo.delete_user(&grace.id).await.unwrap();
+        let again = User::new(
+            "grace@example.com".to_string(),
+            String::new(),
+            "Grace".to_string(),
+        );
+        user_repo.create_user(again).await.unwrap();
+
+        // the service reports a taken email as such
+        let jwt_service = Arc::new(JwtService::new(b"test_secret", 24));
+        let auth_service = AuthService::new(user_repo.clone(), jwt_service);
+        let registered = auth_service
+            .register(
+                RegisterUser {
+                    email: "lovelace@example.com".to_string(),
+                    password: "Password123!".to_string(),
+                    name: "Imposter".to_string(),
+                },
+                &ClientInfo::default(),
+            )
+            .await;
+        assert!(matches!(registered, Err(AuthError::UserExists)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_redb_user_repository() {
+        // behaves as the users kept in memory do
+        let open = || Arc::new(RedbUserRepository::open_in_memory().unwrap());
+        check_auth_service_end_to_end(open()).await;
+        check_account_settings(open()).await;
+        check_email_change(open()).await;
+        check_concurrent_user_updates(RedbUserRepository::open_in_memory().unwrap()).await;
+        check_list_users(open()).await;
+        check_unique_emails(open()).await;
+        check_user_outbox(open()).await;
+
+        // Saving a stale copy fails, and the users are still there once it's reopened
+        let dir = std::env::temp_dir().join(format!("users-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("users.redb");
+        let user_repo = RedbUserRepository::open(&path).unwrap();
+        let user = user_repo
+            .create_user(User::new(
+                "ada@example.com".to_string(),
+                String::new(),
+                "Ada".to_string(),
+            ))
+            .await
+            .unwrap();
+        let saved = user_repo.update_user(&user).await.unwrap();
+        assert_eq!(saved.version, 1);
+        assert!(matches!(
+            user_repo.update_user(&user).await,
+            Err(RepoError::Conflict)
+        ));
+
+        let mut deleted = saved.clone();
+        deleted.deleted_at = Some(1000);
+        // a message of a unit that was never committed, e.g. after a crash, is dropped
+        // once the store is reopened, a released one is kept
+        let requested = OutboxMessage::new(DomainEvent::ErasureRequested {
+            user_id: user.id.clone(),
+            purge_at: 1000,
+        });
+        let registered = OutboxMessage::new(DomainEvent::UserRegistered {
+            user_id: user.id.clone(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+        });
+        user_repo.enqueue(vec![registered.clone()]).await.unwrap();
+        user_repo
+            .update_user_publishing(&deleted, vec![requested])
+            .await
+            .unwrap();
+        assert_eq!(
+            user_repo.due(i64::MAX, 10).await.unwrap(),
+            vec![registered.clone()]
+        );
+        drop(user_repo);
+        let user_repo = RedbUserRepository::open(&path).unwrap();
+        assert_eq!(user_repo.due(i64::MAX, 10).await.unwrap(), vec![registered]);
+        let found = user_repo
+            .find_by_email("Ada@Example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.version, 2);
+        let erasable = user_repo.list_deleted_before(1000).await.unwrap();
+        assert_eq!(erasable.len(), 1);
+        assert!(user_repo.list_deleted_before(999).await.unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_encrypted_redb_user_repository() {
+        use crate::PiiKeys;
+
+        let dir = std::env::temp_dir().join(format!("users-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = |id: &str| match id {
+            "2024" => [2; 32],
+            _ => [1; 32],
+        };
+        let keys = |current: &str| {
+            PiiKeys::new(current, &key(current), &[3; 32])
+                .unwrap()
+                .with_previous_key("2024", &[2; 32])
+                .unwrap()
+        };
+        let open = |name: &str, current: &str| {
+            Arc::new(RedbUserRepository::open_encrypted(dir.join(name), keys(current)).unwrap())
+        };
+        check_list_users(open("list.redb", "2025")).await;
+        check_unique_emails(open("unique.redb", "2025")).await;
+
+        // Users stored in the clear are still found, and sealed by the re-encryption job
+        let path = dir.join("users.redb");
+        let user_repo = RedbUserRepository::open(&path).unwrap();
+        let ada = user_repo
+            .create_user(User::new(
+                "ada@example.com".to_string(),
+                String::new(),
+                "Ada Lovelace".to_string(),
+            ))
+            .await
+            .unwrap();
+        let alan = user_repo
+            .create_user(User::new(
+                "alan@example.com".to_string(),
+                String::new(),
+                "Alan".to_string(),
+            ))
+            .await
+            .unwrap();
+        drop(user_repo);
+        let mut user_repo = RedbUserRepository::open_encrypted(&path, keys("2024")).unwrap();
+        // saving one seals it, and moves its email out of the index in the clear
+        user_repo
+            .update_user(&User {
+                name: "Alan Turing".to_string(),
+                ..alan
+            })
+            .await
+            .unwrap();
+        assert!(
+            user_repo
+                .find_by_email("alan@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        let grace = User::new(
+            "grace@example.com".to_string(),
+            String::new(),
+            "Grace Hopper".to_string(),
+        );
+        // the event about her is sealed along with her
+        let registered = OutboxMessage::new(DomainEvent::UserRegistered {
+            user_id: grace.id.clone(),
+            email: grace.email.clone(),
+            name: grace.name.clone(),
+        });
+        let grace = user_repo
+            .create_user_publishing(grace, vec![registered.clone()])
+            .await
+            .unwrap();
+        user_repo.release(&[registered.id]).await.unwrap();
+        assert!(
+            user_repo
+                .find_by_email("ADA@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(user_repo.reencrypt().await.unwrap(), 1);
+        assert_eq!(user_repo.reencrypt().await.unwrap(), 0);
+        // what the records replaced is only gone from the file once it's compacted
+        assert!(user_repo.compact().unwrap());
+        drop(user_repo);
+        let on_disk = std::fs::read(&path).unwrap();
+        for clear in [
+            b"ada@example.com".as_slice(),
+            b"alan@example.com",
+            b"Grace Hopper",
+        ] {
+            assert!(
+                !on_disk.windows(clear.len()).any(|w| w == clear),
+                "{}",
+                String::from_utf8_lossy(clear)
+            );
+        }
+
+        // A rotated key reads what the previous one sealed, and takes its place
+        let user_repo = RedbUserRepository::open_encrypted(&path, keys("2025")).unwrap();
+        let found = user_repo
+            .find_by_email("grace@example.com")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((found.id, found.name), (grace.id.clone(), grace.name));
+        // the three users and the message
+        assert_eq!(user_repo.reencrypt().await.unwrap(), 4);
+        let moved = User {
+            email: "lovelace@example.com".to_string(),
+            ..user_repo.find_by_id(&ada.id).await.unwrap().unwrap()
+        };
+        user_repo.update_user(&moved).await.unwrap();
+        assert!(
+            user_repo
+                .find_by_email("ada@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        drop(user_repo);
+
+        // the previous key is no longer needed, no key at all opens nothing
+        let user_repo = RedbUserRepository::open_encrypted(
+            &path,
+            PiiKeys::new("2025", &[1; 32], &[3; 32]).unwrap(),
+        )
+        .unwrap();
+        assert!(
+            user_repo
+                .find_by_email("lovelace@example.com")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        drop(user_repo);
+        let user_repo = RedbUserRepository::open(&path).unwrap();
+        assert!(matches!(
+            user_repo.find_by_id(&ada.id).await,
+            Err(RepoError::Encryption)
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,1153 @@
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+use crate::audit::{AuditEvent, AuditSink};
+use crate::error::{AuthError, Result};
+use crate::models::{ApiKey, EmailChange, Identity, PasswordReset, ProvisionedUser, Session, User};
+use crate::outbox::{DomainEvent, Outbox, OutboxMessage};
+use crate::repository::{
+    ApiKeyRepositoryTrait, EmailChangeRepositoryTrait, IdentityRepositoryTrait,
+    PasswordResetRepositoryTrait, ScimRepositoryTrait, SessionStore, UserRepositoryTrait,
+    error::RepoError,
+};
+
+// What to write back to undo a write
+enum Undo {
+    DeleteUser(String),
+    // the user as it was, at the version the unit of work saved
+    RestoreUser(Box<User>),
+    // the deleted user, created again as it was
+    RecreateUser(Box<User>),
+    DeleteIdentity(String),
+    RestoreIdentity(Box<Identity>),
+    RestoreApiKeys(Vec<ApiKey>),
+    RestoreEmailChange(Box<EmailChange>),
+    DeletePasswordReset(String),
+    RestorePasswordReset(Box<PasswordReset>),
+    RestoreSessions(Vec<Session>),
+    // the org's record of the user as it was, None when there was none
+    RestoreProvisionedUser {
+        org: String,
+        user_id: String,
+        previous: Option<Box<ProvisionedUser>>,
+    },
+    // puts the user back in the group, leaving whatever else changed in it since
+    AddMember {
+        org: String,
+        group_id: String,
+        user_id: String,
+    },
+    // messages written to the outbox, with a user or on commit
+    DiscardMessages(Vec<String>),
+}
+
+// The repositories a unit of work writes to
+#[derive(Clone)]
+struct Stores {
+    user_repo: Arc<dyn UserRepositoryTrait>,
+    identity_repo: Arc<dyn IdentityRepositoryTrait>,
+    email_change_repo: Arc<dyn EmailChangeRepositoryTrait>,
+    password_reset_repo: Arc<dyn PasswordResetRepositoryTrait>,
+    scim_repo: Arc<dyn ScimRepositoryTrait>,
+    session_store: Option<Arc<dyn SessionStore>>,
+    api_key_repo: Option<Arc<dyn ApiKeyRepositoryTrait>>,
+    outbox: Outbox,
+}
+
+// Writes across repositories that are kept or undone together. Writes are made as they
+// come, so reads through the repositories see them, and each one is remembered with how
+// to undo it. Audit events are held back until `commit`, the log is append only. Outbox
+// messages are written held, so handlers don't hear about them until `commit` releases
+// them, and discarded if the unit is undone. An outbox kept with the users takes them with
+// the next user write, in its transaction, otherwise they're written on `commit`.
+//
+// Other requests can see the writes before the unit is committed. A unit dropped without
+// `commit` or `rollback`, e.g. when the request is cancelled, is rolled back in the
+// background. A write that can't be undone, e.g. a user saved again in between, fails the
+// unit with RollbackFailed. This undoes writes, it isn't a transaction: a crash part way
+// leaves the writes made so far, but never delivers their events, the messages of a unit
+// that wasn't committed are dropped when the outbox is next opened.
+pub(crate) struct UnitOfWork {
+    stores: Stores,
+    audit_sink: Arc<dyn AuditSink>,
+    // in the order the writes were made
+    undo: Vec<Undo>,
+    events: Vec<AuditEvent>,
+    // published and not written yet
+    messages: Vec<OutboxMessage>,
+    // written, released on commit
+    written: Vec<String>,
+}
+
+impl UnitOfWork {
+    pub(crate) fn new(
+        user_repo: Arc<dyn UserRepositoryTrait>,
+        identity_repo: Arc<dyn IdentityRepositoryTrait>,
+        email_change_repo: Arc<dyn EmailChangeRepositoryTrait>,
+        password_reset_repo: Arc<dyn PasswordResetRepositoryTrait>,
+        scim_repo: Arc<dyn ScimRepositoryTrait>,
+        audit_sink: Arc<dyn AuditSink>,
+        outbox: Outbox,
+    ) -> Self {
+        Self {
+            stores: Stores {
+                user_repo,
+                identity_repo,
+                email_change_repo,
+                password_reset_repo,
+                scim_repo,
+                session_store: None,
+                api_key_repo: None,
+                outbox,
+            },
+            audit_sink,
+            undo: Vec::new(),
+            events: Vec::new(),
+            messages: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_session_store(
+        mut self,
+        session_store: Option<Arc<dyn SessionStore>>,
+    ) -> Self {
+        self.stores.session_store = session_store;
+        self
+    }
+
+    pub(crate) fn with_api_key_repo(
+        mut self,
+        api_key_repo: Arc<dyn ApiKeyRepositoryTrait>,
+    ) -> Self {
+        self.stores.api_key_repo = Some(api_key_repo);
+        self
+    }
+
+    pub(crate) async fn create_user(&mut self, user: User) -> Result<User> {
+        let messages = self.messages_for_user_write();
+        let user = match &self.stores.outbox {
+            Outbox::WithUsers(users) => users.create_user_publishing(user, messages.clone()).await,
+            Outbox::Separate(_) => self.stores.user_repo.create_user(user).await,
+        };
+        let user = match user {
+            Ok(user) => user,
+            Err(e) => {
+                self.messages.extend(messages);
+                return Err(e.into());
+            }
+        };
+        self.undo.push(Undo::DeleteUser(user.id.clone()));
+        self.wrote(messages);
+        Ok(user)
+    }
+
+    // Saves `user`, read as `before`, see UserRepositoryTrait::update_user. Fails with the
+    // repository's error so a Conflict can be retried.
+    pub(crate) async fn update_user(
+        &mut self,
+        before: &User,
+        user: &User,
+    ) -> std::result::Result<User, RepoError> {
+        let messages = self.messages_for_user_write();
+        let saved = match &self.stores.outbox {
+            Outbox::WithUsers(users) => users.update_user_publishing(user, messages.clone()).await,
+            Outbox::Separate(_) => self.stores.user_repo.update_user(user).await,
+        };
+        let saved = match saved {
+            Ok(saved) => saved,
+            // a Conflict is retried with the messages
+            Err(e) => {
+                self.messages.extend(messages);
+                return Err(e);
+            }
+        };
+        self.undo.push(Undo::RestoreUser(Box::new(User {
+            version: saved.version,
+            ..before.clone()
+        })));
+        self.wrote(messages);
+        Ok(saved)
+    }
+
+    // Deletes the user as it's stored now, see UserRepositoryTrait::delete_user
+    pub(crate) async fn delete_user(&mut self, user_id: &str) -> Result<()> {
+        let Some(user) = self.stores.user_repo.find_by_id(user_id).await? else {
+            return Ok(());
+        };
+        self.stores.user_repo.delete_user(user_id).await?;
+        self.undo.push(Undo::RecreateUser(Box::new(user)));
+        Ok(())
+    }
+
+    // the messages a user write takes along, none unless the outbox is kept with the users
+    fn messages_for_user_write(&mut self) -> Vec<OutboxMessage> {
+        match self.stores.outbox {
+            Outbox::WithUsers(_) => std::mem::take(&mut self.messages),
+            Outbox::Separate(_) => Vec::new(),
+        }
+    }
+
+    fn wrote(&mut self, messages: Vec<OutboxMessage>) {
+        if messages.is_empty() {
+            return;
+        }
+        let ids: Vec<String> = messages.into_iter().map(|message| message.id).collect();
+        self.written.extend(ids.iter().cloned());
+        self.undo.push(Undo::DiscardMessages(ids));
+    }
+
+    pub(crate) async fn create_identity(&mut self, identity: Identity) -> Result<Identity> {
+        let identity = self.stores.identity_repo.create_identity(identity).await?;
+        self.undo.push(Undo::DeleteIdentity(identity.id.clone()));
+        Ok(identity)
+    }
+
+    // unlinks the user's provider accounts, returns how many were
+    pub(crate) async fn delete_identities(&mut self, user_id: &str) -> Result<usize> {
+        let identities = self.stores.identity_repo.list_by_user(user_id).await?;
+        let deleted = identities.len();
+        for identity in identities {
+            self.stores
+                .identity_repo
+                .delete_identity(&identity.id)
+                .await?;
+            self.undo.push(Undo::RestoreIdentity(Box::new(identity)));
+        }
+        Ok(deleted)
+    }
+
+    // returns how many keys were deleted, none without an api key repository
+    pub(crate) async fn delete_api_keys(&mut self, user_id: &str) -> Result<usize> {
+        let Some(api_key_repo) = &self.stores.api_key_repo else {
+            return Ok(0);
+        };
+        // a key created in between is deleted too, and not brought back
+        let api_keys = api_key_repo.list_by_user(user_id).await?;
+        let deleted = api_key_repo.delete_by_user(user_id).await?;
+        self.undo.push(Undo::RestoreApiKeys(api_keys));
+        Ok(deleted)
+    }
+
+    pub(crate) async fn delete_email_change(&mut self, user_id: &str) -> Result<()> {
+        let Some(change) = self.stores.email_change_repo.find_by_user(user_id).await? else {
+            return Ok(());
+        };
+        self.stores
+            .email_change_repo
+            .delete_email_change(user_id)
+            .await?;
+        self.undo.push(Undo::RestoreEmailChange(Box::new(change)));
+        Ok(())
+    }
+
+    // creates or replaces the user's pending reset
+    pub(crate) async fn save_password_reset(
+        &mut self,
+        reset: PasswordReset,
+    ) -> Result<PasswordReset> {
+        let previous = self
+            .stores
+            .password_reset_repo
+            .find_by_user(&reset.user_id)
+            .await?;
+        let saved = self
+            .stores
+            .password_reset_repo
+            .save_password_reset(reset)
+            .await?;
+        self.undo.push(match previous {
+            Some(previous) => Undo::RestorePasswordReset(Box::new(previous)),
+            None => Undo::DeletePasswordReset(saved.user_id.clone()),
+        });
+        Ok(saved)
+    }
+
+    pub(crate) async fn delete_password_reset(&mut self, reset: &PasswordReset) -> Result<()> {
+        self.stores
+            .password_reset_repo
+            .delete_password_reset(&reset.user_id)
+            .await?;
+        self.undo
+            .push(Undo::RestorePasswordReset(Box::new(reset.clone())));
+        Ok(())
+    }
+
+    // returns how many stored sessions were deleted, none without a session store
+    pub(crate) async fn delete_user_sessions(&mut self, user_id: &str) -> Result<usize> {
+        let Some(session_store) = &self.stores.session_store else {
+            return Ok(0);
+        };
+        let mut sessions = session_store.take_user_sessions(user_id).await?;
+        let deleted = sessions.len();
+        // expired sessions aren't worth bringing back
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        sessions.retain(|session| !session.is_expired(now));
+        self.undo.push(Undo::RestoreSessions(sessions));
+        Ok(deleted)
+    }
+
+    // creates or replaces the org's record of the user
+    pub(crate) async fn save_provisioned_user(
+        &mut self,
+        provisioned: ProvisionedUser,
+    ) -> Result<ProvisionedUser> {
+        let previous = self
+            .stores
+            .scim_repo
+            .find_provisioned_user(&provisioned.org, &provisioned.user_id)
+            .await?;
+        let saved = self
+            .stores
+            .scim_repo
+            .save_provisioned_user(provisioned)
+            .await?;
+        self.undo.push(Undo::RestoreProvisionedUser {
+            org: saved.org.clone(),
+            user_id: saved.user_id.clone(),
+            previous: previous.map(Box::new),
+        });
+        Ok(saved)
+    }
+
+    // takes the user out of the org's groups
+    pub(crate) async fn remove_member(&mut self, org: &str, user_id: &str) -> Result<()> {
+        for mut group in self.stores.scim_repo.list_groups(org).await? {
+            if group.members.iter().any(|m| m == user_id) {
+                group.members.retain(|m| m != user_id);
+                self.stores.scim_repo.update_group(&group).await?;
+                self.undo.push(Undo::AddMember {
+                    org: org.to_string(),
+                    group_id: group.id,
+                    user_id: user_id.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // takes the user out of every org's groups and drops the orgs' records of them
+    pub(crate) async fn forget_scim_user(&mut self, user_id: &str) -> Result<()> {
+        for mut group in self.stores.scim_repo.list_groups_by_member(user_id).await? {
+            group.members.retain(|m| m != user_id);
+            self.stores.scim_repo.update_group(&group).await?;
+            self.undo.push(Undo::AddMember {
+                org: group.org,
+                group_id: group.id,
+                user_id: user_id.to_string(),
+            });
+        }
+        for record in self
+            .stores
+            .scim_repo
+            .list_provisioned_by_user(user_id)
+            .await?
+        {
+            self.stores
+                .scim_repo
+                .delete_provisioned_user(&record.org, user_id)
+                .await?;
+            self.undo.push(Undo::RestoreProvisionedUser {
+                org: record.org.clone(),
+                user_id: user_id.to_string(),
+                previous: Some(Box::new(record)),
+            });
+        }
+        Ok(())
+    }
+
+    // recorded when the unit is committed
+    pub(crate) fn audit(&mut self, entry: AuditEvent) {
+        self.events.push(entry);
+    }
+
+    // written to the outbox held, with the next user write when the outbox is kept with
+    // the users, so it's saved with the change it's about
+    pub(crate) fn publish(&mut self, event: DomainEvent) {
+        self.messages.push(OutboxMessage {
+            held: true,
+            ..OutboxMessage::new(event)
+        });
+    }
+
+    // Keeps the writes, records the audit events and releases the messages. If the events
+    // can't be recorded or the messages released the writes are undone, like the operation
+    // never happened. Releasing is what marks the unit committed in the outbox, messages
+    // still held when it's next opened are dropped.
+    pub(crate) async fn commit(mut self) -> Result<()> {
+        // the messages no user write took along
+        let messages = std::mem::take(&mut self.messages);
+        if !messages.is_empty() {
+            if let Err(e) = self.stores.outbox.store().enqueue(messages.clone()).await {
+                return Err(self.abort(e.into()).await);
+            }
+            self.wrote(messages);
+        }
+
+        let events = std::mem::take(&mut self.events);
+        if !events.is_empty()
+            && let Err(e) = self.audit_sink.record_all(events).await
+        {
+            return Err(self.abort(e.into()).await);
+        }
+
+        // the audit log can't take anything back, its entries stay when this fails
+        let written = std::mem::take(&mut self.written);
+        if !written.is_empty()
+            && let Err(e) = self.stores.outbox.store().release(&written).await
+        {
+            return Err(self.abort(e.into()).await);
+        }
+        self.undo.clear();
+        Ok(())
+    }
+
+    // Undoes the writes, newest first, and drops the audit events and messages
+    pub(crate) async fn rollback(mut self) -> Result<()> {
+        self.stores.undo(&mut self.undo).await
+    }
+
+    // Commits when the work succeeded, otherwise rolls back and passes its error on
+    pub(crate) async fn finish<T>(mut self, done: Result<T>) -> Result<T> {
+        match done {
+            Ok(value) => {
+                self.commit().await?;
+                Ok(value)
+            }
+            Err(e) => Err(self.abort(e).await),
+        }
+    }
+
+    // Rolls back after `error`, which is passed on unless the rollback fails too
+    async fn abort(&mut self, error: AuthError) -> AuthError {
+        match self.stores.undo(&mut self.undo).await {
+            Ok(()) => error,
+            Err(undoing) => AuthError::RollbackFailed(Box::new(error), Box::new(undoing)),
+        }
+    }
+}
+
+impl Drop for UnitOfWork {
+    fn drop(&mut self) {
+        if self.undo.is_empty() {
+            return;
+        }
+        let mut undo = std::mem::take(&mut self.undo);
+        let stores = self.stores.clone();
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            eprintln!("Can't roll back an unfinished unit of work outside of a runtime");
+            return;
+        };
+        runtime.spawn(async move {
+            if let Err(e) = stores.undo(&mut undo).await {
+                eprintln!("Can't roll back an unfinished unit of work: {e}");
+            }
+        });
+    }
+}
+
+impl Stores {
+    // Pops the steps, newest first. Every step is tried, a step that fails doesn't keep the
+    // others from being undone. Fails with the first error.
+    async fn undo(&self, steps: &mut Vec<Undo>) -> Result<()> {
+        let mut undone = Ok(());
+        while let Some(step) = steps.pop() {
+            let result = self.undo_step(step).await;
+            if undone.is_ok() {
+                undone = result;
+            }
+        }
+        undone
+    }
+
+    async fn undo_step(&self, step: Undo) -> Result<()> {
+        match step {
+            Undo::DeleteUser(id) => self.user_repo.delete_user(&id).await?,
+            Undo::DiscardMessages(ids) => self.outbox.store().discard(&ids).await?,
+            Undo::RestoreUser(user) => {
+                self.user_repo.update_user(&user).await?;
+            }
+            Undo::RecreateUser(user) => {
+                self.user_repo.create_user(*user).await?;
+            }
+            Undo::DeleteIdentity(id) => self.identity_repo.delete_identity(&id).await?,
+            Undo::RestoreIdentity(identity) => {
+                self.identity_repo.create_identity(*identity).await?;
+            }
+            Undo::RestoreApiKeys(api_keys) => {
+                if let Some(api_key_repo) = &self.api_key_repo {
+                    for api_key in api_keys {
+                        api_key_repo.create_api_key(api_key).await?;
+                    }
+                }
+            }
+            Undo::RestoreEmailChange(change) => {
+                self.email_change_repo.save_email_change(*change).await?;
+            }
+            Undo::DeletePasswordReset(user_id) => {
+                self.password_reset_repo
+                    .delete_password_reset(&user_id)
+                    .await?
+            }
+            Undo::RestorePasswordReset(reset) => {
+                self.password_reset_repo.save_password_reset(*reset).await?;
+            }
+            Undo::RestoreSessions(sessions) => {
+                if let Some(session_store) = &self.session_store
+                    && !sessions.is_empty()
+                {
+                    session_store.restore_sessions(sessions).await?;
+                }
+            }
+            Undo::RestoreProvisionedUser {
+                org,
+                user_id,
+                previous,
+            } => match previous {
+                Some(previous) => {
+                    self.scim_repo.save_provisioned_user(*previous).await?;
+                }
+                None => {
+                    self.scim_repo
+                        .delete_provisioned_user(&org, &user_id)
+                        .await?
+                }
+            },
+            Undo::AddMember {
+                org,
+                group_id,
+                user_id,
+            } => {
+                // a group deleted since has no one to put back
+                if let Some(mut group) = self.scim_repo.find_group(&org, &group_id).await?
+                    && !group.members.contains(&user_id)
+                {
+                    group.members.push(user_id);
+                    self.scim_repo.update_group(&group).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::audit::{
+        AuditFilter, AuthEvent, error::AuditError, in_mem_sink::InMemoryAuditSink,
+        sqlite_sink::SqliteAuditSink,
+    };
+    use crate::models::{Group, Scope};
+    use crate::outbox::{
+        OutboxStore, error::OutboxError, error::Result as OutboxResult,
+        in_mem_store::InMemoryOutbox, sqlite_store::SqliteOutbox,
+    };
+    use crate::repository::{
+        in_mem_api_key_repo::InMemoryApiKeyRepository,
+        in_mem_email_change_repo::InMemoryEmailChangeRepository,
+        in_mem_identity_repo::InMemoryIdentityRepository,
+        in_mem_password_reset_repo::InMemoryPasswordResetRepository,
+        in_mem_scim_repo::InMemoryScimRepository, in_mem_session_store::InMemorySessionStore,
+        in_mem_user_repo::InMemoryUserRepository, sqlite_session_store::SqliteSessionStore,
+    };
+
+    struct FailingAuditSink;
+
+    #[async_trait]
+    impl AuditSink for FailingAuditSink {
+        async fn record(&self, _: AuditEvent) -> crate::audit::error::Result<()> {
+            Err(AuditError::Write("disk full".to_string()))
+        }
+        async fn query(&self, _: &AuditFilter) -> crate::audit::error::Result<Vec<AuditEvent>> {
+            Ok(Vec::new())
+        }
+    }
+
+    struct Repos {
+        users: Arc<InMemoryUserRepository>,
+        identities: Arc<InMemoryIdentityRepository>,
+        email_changes: Arc<InMemoryEmailChangeRepository>,
+        password_resets: Arc<InMemoryPasswordResetRepository>,
+        scim: Arc<InMemoryScimRepository>,
+        api_keys: Arc<InMemoryApiKeyRepository>,
+        outbox: Outbox,
+    }
+
+    impl Repos {
+        // with the outbox kept with the users unless there's one of its own
+        fn new(outbox: Option<Arc<dyn OutboxStore>>) -> Self {
+            let users = Arc::new(InMemoryUserRepository::new());
+            Self {
+                outbox: match outbox {
+                    Some(outbox) => Outbox::Separate(outbox),
+                    None => Outbox::WithUsers(users.clone()),
+                },
+                users,
+                identities: Arc::new(InMemoryIdentityRepository::new()),
+                email_changes: Arc::new(InMemoryEmailChangeRepository::new()),
+                password_resets: Arc::new(InMemoryPasswordResetRepository::new()),
+                scim: Arc::new(InMemoryScimRepository::new()),
+                api_keys: Arc::new(InMemoryApiKeyRepository::new()),
+            }
+        }
+
+        fn unit_of_work(
+            &self,
+            session_store: &Arc<dyn SessionStore>,
+            audit_sink: &Arc<dyn AuditSink>,
+        ) -> UnitOfWork {
+            UnitOfWork::new(
+                self.users.clone(),
+                self.identities.clone(),
+                self.email_changes.clone(),
+                self.password_resets.clone(),
+                self.scim.clone(),
+                audit_sink.clone(),
+                self.outbox.clone(),
+            )
+            .with_session_store(Some(session_store.clone()))
+            .with_api_key_repo(self.api_keys.clone())
+        }
+    }
+
+    fn session(id: &str, user_id: &str) -> Session {
+        Session {
+            id: id.to_string(),
+            user_id: user_id.to_string(),
+            created_at: 100,
+            last_seen_at: 100,
+            expires_at: i64::MAX,
+            ip: None,
+            user_agent: None,
+            data: HashMap::new(),
+            impersonator_id: None,
+        }
+    }
+
+    fn identity(user_id: &str) -> Identity {
+        Identity {
+            id: format!("identity-{user_id}"),
+            user_id: user_id.to_string(),
+            provider: "github".to_string(),
+            subject: "42".to_string(),
+            email: "jane@example.com".to_string(),
+            created_at: 100,
+        }
+    }
+
+    fn provisioned(org: &str, user_id: &str) -> ProvisionedUser {
+        ProvisionedUser {
+            org: org.to_string(),
+            user_id: user_id.to_string(),
+            user_name: user_id.to_string(),
+            external_id: None,
+            display_name: None,
+            formatted_name: None,
+            given_name: None,
+            family_name: None,
+            created_at: 100,
+            updated_at: 100,
+            deprovisioned_at: None,
+        }
+    }
+
+    async fn check_unit_of_work(
+        session_store: Arc<dyn SessionStore>,
+        audit_sink: Arc<dyn AuditSink>,
+        outbox: Option<Arc<dyn OutboxStore>>,
+    ) {
+        let repos = Repos::new(outbox);
+        let outbox = repos.outbox.store();
+        let jane = repos
+            .users
+            .create_user(User::new(
+                "jane@example.com".to_string(),
+                "01#hash".to_string(),
+                "Jane".to_string(),
+            ))
+            .await
+            .unwrap();
+        session_store
+            .create_session(session("s1", &jane.id))
+            .await
+            .unwrap();
+        repos
+            .email_changes
+            .save_email_change(EmailChange {
+                user_id: jane.id.clone(),
+                new_email: "jane@example.org".to_string(),
+                confirm_token_hash: "confirm".to_string(),
+                cancel_token_hash: "cancel".to_string(),
+                created_at: 100,
+                expires_at: i64::MAX,
+            })
+            .await
+            .unwrap();
+        repos
+            .scim
+            .save_provisioned_user(provisioned("acme", &jane.id))
+            .await
+            .unwrap();
+        repos
+            .scim
+            .create_group(Group {
+                id: "g1".to_string(),
+                org: "acme".to_string(),
+                display_name: "Engineering".to_string(),
+                external_id: None,
+                members: vec![jane.id.clone()],
+                created_at: 100,
+                updated_at: 100,
+            })
+            .await
+            .unwrap();
+        let reset = repos
+            .password_resets
+            .save_password_reset(PasswordReset {
+                user_id: jane.id.clone(),
+                token_hash: "reset".to_string(),
+                created_at: 100,
+                expires_at: i64::MAX,
+            })
+            .await
+            .unwrap();
+
+        // Rolling back undoes every write and records nothing
+        let mut work = repos.unit_of_work(&session_store, &audit_sink);
+        let renamed = User {
+            name: "Jane Doe".to_string(),
+            ..jane.clone()
+        };
+        work.update_user(&jane, &renamed).await.unwrap();
+        let john = User::new(
+            "john@example.com".to_string(),
+            "01#hash".to_string(),
+            "John".to_string(),
+        );
+        work.publish(DomainEvent::UserRegistered {
+            user_id: john.id.clone(),
+            email: john.email.clone(),
+            name: john.name.clone(),
+        });
+        let john = work.create_user(john).await.unwrap();
+        work.create_identity(identity(&john.id)).await.unwrap();
+        assert_eq!(work.delete_user_sessions(&jane.id).await.unwrap(), 1);
+        work.delete_email_change(&jane.id).await.unwrap();
+        work.delete_password_reset(&reset).await.unwrap();
+        work.save_provisioned_user(provisioned("acme", &john.id))
+            .await
+            .unwrap();
+        work.save_provisioned_user(ProvisionedUser {
+            deprovisioned_at: Some(200),
+            ..provisioned("acme", &jane.id)
+        })
+        .await
+        .unwrap();
+        work.remove_member("acme", &jane.id).await.unwrap();
+        work.audit(AuditEvent::new(AuthEvent::Registered {
+            email: john.email.clone(),
+        }));
+        // the writes are there before the unit is done, their messages held
+        assert!(repos.users.find_by_id(&john.id).await.unwrap().is_some());
+        assert!(outbox.due(i64::MAX, 10).await.unwrap().is_empty());
+
+        work.rollback().await.unwrap();
+        let restored = repos.users.find_by_id(&jane.id).await.unwrap().unwrap();
+        assert_eq!(restored.name, "Jane");
+        assert!(restored.version > jane.version);
+        assert!(repos.users.find_by_id(&john.id).await.unwrap().is_none());
+        assert!(
+            repos
+                .users
+                .find_by_email("john@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            repos
+                .identities
+                .list_by_user(&john.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(session_store.find_session("s1").await.unwrap().is_some());
+        assert!(
+            repos
+                .email_changes
+                .find_by_user(&jane.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            repos
+                .password_resets
+                .find_by_token_hash("reset")
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(
+            repos
+                .scim
+                .find_provisioned_user("acme", &john.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let record = repos.scim.find_provisioned_user("acme", &jane.id).await;
+        assert!(record.unwrap().unwrap().deprovisioned_at.is_none());
+        let group = repos.scim.find_group("acme", "g1").await.unwrap().unwrap();
+        assert_eq!(group.members, vec![jane.id.clone()]);
+        let audited = audit_sink.query(&AuditFilter::default()).await.unwrap();
+        assert!(audited.is_empty());
+        assert!(outbox.due(i64::MAX, 10).await.unwrap().is_empty());
+
+        // A failed step rolls back the ones before it
+        let mut work = repos.unit_of_work(&session_store, &audit_sink);
+        let linked: Result<Identity> = async {
+            let john = work
+                .create_user(User::new(
+                    "john@example.com".to_string(),
+                    "01#hash".to_string(),
+                    "John".to_string(),
+                ))
+                .await?;
+            work.create_identity(identity(&john.id)).await?;
+            // the provider account is already linked
+            work.create_identity(identity(&john.id)).await
+        }
+        .await;
+        assert!(work.finish(linked).await.is_err());
+        assert!(
+            repos
+                .users
+                .find_by_email("john@example.com")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        // Committing keeps the writes, records the events and releases the messages
+        let mut work = repos.unit_of_work(&session_store, &audit_sink);
+        let john = User::new(
+            "john@example.com".to_string(),
+            "01#hash".to_string(),
+            "John".to_string(),
+        );
+        work.publish(DomainEvent::UserRegistered {
+            user_id: john.id.clone(),
+            email: john.email.clone(),
+            name: john.name.clone(),
+        });
+        let john = work.create_user(john).await.unwrap();
+        work.delete_user_sessions(&jane.id).await.unwrap();
+        work.audit(AuditEvent::new(AuthEvent::Registered {
+            email: john.email.clone(),
+        }));
+        work.audit(AuditEvent::new(AuthEvent::AllSessionsRevoked { count: 1 }).with_user(&jane.id));
+        assert!(outbox.due(i64::MAX, 10).await.unwrap().is_empty());
+        work.commit().await.unwrap();
+        assert!(repos.users.find_by_id(&john.id).await.unwrap().is_some());
+        assert!(session_store.find_session("s1").await.unwrap().is_none());
+        let audited = audit_sink.query(&AuditFilter::default()).await.unwrap();
+        assert_eq!(audited.len(), 2);
+        let published = outbox.due(i64::MAX, 10).await.unwrap();
+        assert_eq!(published.len(), 1);
+        outbox.delivered(&published[0].id).await.unwrap();
+
+        // and events that can't be recorded undo the writes
+        let failing: Arc<dyn AuditSink> = Arc::new(FailingAuditSink);
+        let mut work = repos.unit_of_work(&session_store, &failing);
+        let ann = User::new(
+            "ann@example.com".to_string(),
+            "01#hash".to_string(),
+            "Ann".to_string(),
+        );
+        work.publish(DomainEvent::UserRegistered {
+            user_id: ann.id.clone(),
+            email: ann.email.clone(),
+            name: ann.name.clone(),
+        });
+        let ann = work.create_user(ann).await.unwrap();
+        work.audit(AuditEvent::new(AuthEvent::ProfileUpdated).with_user(&ann.id));
+        assert!(matches!(
+            work.finish(Ok(())).await,
+            Err(crate::error::AuthError::Audit(_))
+        ));
+        assert!(repos.users.find_by_id(&ann.id).await.unwrap().is_none());
+        assert!(outbox.due(i64::MAX, 10).await.unwrap().is_empty());
+
+        // A unit dropped half way, e.g. when the request is cancelled, is rolled back
+        let cancelled = tokio::time::timeout(std::time::Duration::from_millis(10), async {
+            let mut work = repos.unit_of_work(&session_store, &audit_sink);
+            work.create_user(User::new(
+                "ann@example.com".to_string(),
+                "01#hash".to_string(),
+                "Ann".to_string(),
+            ))
+            .await
+            .unwrap();
+            std::future::pending::<()>().await
+        })
+        .await;
+        assert!(cancelled.is_err());
+        let mut ann = repos.users.find_by_email("ann@example.com").await.unwrap();
+        for _ in 0..100 {
+            if ann.is_none() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            ann = repos.users.find_by_email("ann@example.com").await.unwrap();
+        }
+        assert!(ann.is_none());
+
+        // and a write that can't be undone, here a user saved again in between, fails the
+        // unit with an error of its own
+        let jane = repos.users.find_by_id(&jane.id).await.unwrap().unwrap();
+        let mut work = repos.unit_of_work(&session_store, &audit_sink);
+        let renamed = User {
+            name: "Jane Doe".to_string(),
+            ..jane.clone()
+        };
+        let saved = work.update_user(&jane, &renamed).await.unwrap();
+        repos
+            .users
+            .update_user(&User {
+                name: "Jane Roe".to_string(),
+                ..saved
+            })
+            .await
+            .unwrap();
+        let failed = work.finish::<()>(Err(AuthError::InvalidCredentials)).await;
+        let Err(AuthError::RollbackFailed(error, rollback)) = failed else {
+            panic!("expected the rollback to fail, got {failed:?}");
+        };
+        assert!(matches!(*error, AuthError::InvalidCredentials));
+        assert!(matches!(
+            *rollback,
+            AuthError::Repository(RepoError::Conflict)
+        ));
+    }
+
+    // takes messages but can't release them, like an outbox that went away mid-commit
+    struct UnreleasableOutbox(InMemoryOutbox);
+
+    #[async_trait]
+    impl OutboxStore for UnreleasableOutbox {
+        async fn enqueue(&self, messages: Vec<OutboxMessage>) -> OutboxResult<()> {
+            self.0.enqueue(messages).await
+        }
+        async fn release(&self, _: &[String]) -> OutboxResult<()> {
+            Err(OutboxError::Write("disk full".to_string()))
+        }
+        async fn discard(&self, ids: &[String]) -> OutboxResult<()> {
+            self.0.discard(ids).await
+        }
+        async fn due(&self, now: i64, limit: usize) -> OutboxResult<Vec<OutboxMessage>> {
+            self.0.due(now, limit).await
+        }
+        async fn delivered(&self, id: &str) -> OutboxResult<()> {
+            self.0.delivered(id).await
+        }
+        async fn failed(&self, message: &OutboxMessage) -> OutboxResult<()> {
+            self.0.failed(message).await
+        }
+        async fn dead_letters(&self) -> OutboxResult<Vec<OutboxMessage>> {
+            self.0.dead_letters().await
+        }
+        async fn retry(&self, id: &str, now: i64) -> OutboxResult<()> {
+            self.0.retry(id, now).await
+        }
+        async fn delete_by_user(&self, user_id: &str) -> OutboxResult<usize> {
+            self.0.delete_by_user(user_id).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unreleased_messages_fail_the_commit() {
+        let outbox = Arc::new(UnreleasableOutbox(InMemoryOutbox::new()));
+        let repos = Repos::new(Some(outbox.clone()));
+        let session_store: Arc<dyn SessionStore> = Arc::new(InMemorySessionStore::new());
+        let audit_sink: Arc<dyn AuditSink> = Arc::new(InMemoryAuditSink::new());
+
+        let mut work = repos.unit_of_work(&session_store, &audit_sink);
+        let ann = User::new(
+            "ann@example.com".to_string(),
+            "01#hash".to_string(),
+            "Ann".to_string(),
+        );
+        work.publish(DomainEvent::UserRegistered {
+            user_id: ann.id.clone(),
+            email: ann.email.clone(),
+            name: ann.name.clone(),
+        });
+        let ann = work.create_user(ann).await.unwrap();
+        assert!(matches!(
+            work.commit().await,
+            Err(AuthError::Outbox(OutboxError::Write(_)))
+        ));
+        // the change is undone with its messages, so it's neither kept without its events
+        // nor delivered later
+        assert!(repos.users.find_by_id(&ann.id).await.unwrap().is_none());
+        assert!(outbox.0.all().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unit_of_work() {
+        check_unit_of_work(
+            Arc::new(InMemorySessionStore::new()),
+            Arc::new(InMemoryAuditSink::new()),
+            None,
+        )
+        .await;
+        check_unit_of_work(
+            Arc::new(SqliteSessionStore::open_in_memory().unwrap()),
+            Arc::new(SqliteAuditSink::open_in_memory().unwrap()),
+            Some(Arc::new(SqliteOutbox::open_in_memory().unwrap())),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_erasure_is_undone() {
+        let repos = Repos::new(None);
+        let session_store: Arc<dyn SessionStore> = Arc::new(InMemorySessionStore::new());
+        let audit_sink: Arc<dyn AuditSink> = Arc::new(InMemoryAuditSink::new());
+        let jane = repos
+            .users
+            .create_user(User::new(
+                "jane@example.com".to_string(),
+                "01#hash".to_string(),
+                "Jane".to_string(),
+            ))
+            .await
+            .unwrap();
+        repos
+            .identities
+            .create_identity(identity(&jane.id))
+            .await
+            .unwrap();
+        repos
+            .api_keys
+            .create_api_key(ApiKey {
+                id: "k1".to_string(),
+                user_id: jane.id.clone(),
+                name: "laptop".to_string(),
+                prefix: "abcd1234".to_string(),
+                key_hash: "hash".to_string(),
+                scopes: vec![Scope::Read],
+                created_at: 100,
+                expires_at: None,
+                last_used_at: None,
+                revoked_at: None,
+            })
+            .await
+            .unwrap();
+        repos
+            .scim
+            .save_provisioned_user(provisioned("acme", &jane.id))
+            .await
+            .unwrap();
+        repos
+            .scim
+            .create_group(Group {
+                id: "g1".to_string(),
+                org: "acme".to_string(),
+                display_name: "Engineering".to_string(),
+                external_id: None,
+                members: vec![jane.id.clone()],
+                created_at: 100,
+                updated_at: 100,
+            })
+            .await
+            .unwrap();
+        let reset = |token_hash: &str| PasswordReset {
+            user_id: jane.id.clone(),
+            token_hash: token_hash.to_string(),
+            created_at: 100,
+            expires_at: i64::MAX,
+        };
+
+        // a reset that wasn't there is deleted again
+        let mut work = repos.unit_of_work(&session_store, &audit_sink);
+        work.save_password_reset(reset("first")).await.unwrap();
+        work.rollback().await.unwrap();
+        assert!(
+            repos
+                .password_resets
+                .find_by_user(&jane.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        repos
+            .password_resets
+            .save_password_reset(reset("first"))
+            .await
+            .unwrap();
+        let mut work = repos.unit_of_work(&session_store, &audit_sink);
+        work.save_password_reset(reset("second")).await.unwrap();
+        assert_eq!(work.delete_identities(&jane.id).await.unwrap(), 1);
+        assert_eq!(work.delete_api_keys(&jane.id).await.unwrap(), 1);
+        work.forget_scim_user(&jane.id).await.unwrap();
+        work.delete_user(&jane.id).await.unwrap();
+        assert!(repos.users.find_by_id(&jane.id).await.unwrap().is_none());
+        assert!(
+            repos
+                .api_keys
+                .list_by_user(&jane.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // the erasure failing part way gives everything back
+        work.rollback().await.unwrap();
+        assert_eq!(
+            repos
+                .users
+                .find_by_id(&jane.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .version,
+            jane.version
+        );
+        assert_eq!(
+            repos.identities.list_by_user(&jane.id).await.unwrap()[0].id,
+            identity(&jane.id).id
+        );
+        assert_eq!(
+            repos.api_keys.list_by_user(&jane.id).await.unwrap().len(),
+            1
+        );
+        assert!(
+            repos
+                .scim
+                .find_provisioned_user("acme", &jane.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        let group = repos.scim.find_group("acme", "g1").await.unwrap().unwrap();
+        assert_eq!(group.members, vec![jane.id.clone()]);
+        assert_eq!(
+            repos
+                .password_resets
+                .find_by_user(&jane.id)
+                .await
+                .unwrap()
+                .unwrap()
+                .token_hash,
+            "first"
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use rand::{Rng, distr::Alphanumeric};
+
+pub fn random_string(len: usize) -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+// Organizations are referred to by a slug, e.g. in urls
+pub fn is_valid_org(org: &str) -> bool {
+    !org.is_empty()
+        && org
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+// The form emails are compared in, addresses differing only in case or surrounding
+// whitespace belong to the same account
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
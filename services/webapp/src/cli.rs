@@ -0,0 +1,281 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader};
+
+use auth::{
+    InMemoryUserRepository, Migration, MigrationReport, PiiKeys, RedbUserRepository, UserFormat,
+    UserRepositoryTrait,
+};
+
+const USAGE: &str = "\
+Usage:
+  webapp users migrate --from <store> --to <store> [options]
+  webapp users import <file> --format <csv|jsonl> --to <store> [options]
+  webapp users export <file> --format <csv|jsonl> --from <store> [options]
+  webapp users reencrypt <store>
+
+A store is memory:<dir>, the directory USER_DATA_DIR points the app at, or
+redb:<file>. A file of - is stdin or stdout. With PII_KEYS and PII_INDEX_KEY set,
+stores keep the users' email and name encrypted, reencrypt seals the users still
+stored in the clear or with a previous key.
+
+Options:
+  --dry-run          validate and report, write nothing
+  --batch-size <n>   users read from a store at a time
+  --resume <token>   carry on from where an earlier run stopped";
+
+// `webapp users ...`, moves users between stores and files while the app isn't running.
+// Returns the exit code, 1 when anything failed or was rejected.
+pub async fn users(args: &[String]) -> i32 {
+    let args = match Args::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return 2;
+        }
+    };
+    if args.command == "reencrypt" {
+        return match reencrypt(&args).await {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+    }
+
+    let mut migration = Migration::new()
+        .with_dry_run(args.dry_run)
+        .with_resume_from(args.resume.clone());
+    if let Some(batch_size) = args.batch_size {
+        migration = migration.with_batch_size(batch_size);
+    }
+    let result = run(&args, &mut migration).await;
+
+    let report = migration.report();
+    print_report(report, args.dry_run);
+    match result {
+        Ok(()) if report.rejected.is_empty() => 0,
+        Ok(()) => 1,
+        Err(e) => {
+            eprintln!("{e}");
+            if let Some(resume) = &report.resume_from {
+                eprintln!("Run again with --resume {resume} to carry on");
+            }
+            1
+        }
+    }
+}
+
+async fn run(args: &Args, migration: &mut Migration) -> Result<(), String> {
+    match args.command.as_str() {
+        "migrate" => {
+            let from = Store::open(args.required("--from", &args.from)?)?;
+            let to = Store::open(args.required("--to", &args.to)?)?;
+            let copied = migration.copy_users(from.repo(), to.repo()).await;
+            to.close().await?;
+            copied.map_err(|e| e.to_string())
+        }
+        "import" => {
+            let format = args.format()?;
+            let to = Store::open(args.required("--to", &args.to)?)?;
+            let path = args.file()?;
+            let imported = match path {
+                "-" => {
+                    migration
+                        .import_users(io::stdin().lock(), format, to.repo())
+                        .await
+                }
+                path => {
+                    let file = File::open(path).map_err(|e| format!("Can't open {path}: {e}"))?;
+                    migration
+                        .import_users(BufReader::new(file), format, to.repo())
+                        .await
+                }
+            };
+            to.close().await?;
+            imported.map_err(|e| e.to_string())
+        }
+        "export" => {
+            let format = args.format()?;
+            let from = Store::open(args.required("--from", &args.from)?)?;
+            let exported = match args.file()? {
+                _ if args.dry_run => {
+                    migration
+                        .export_users(from.repo(), format, io::sink())
+                        .await
+                }
+                "-" => {
+                    migration
+                        .export_users(from.repo(), format, io::stdout().lock())
+                        .await
+                }
+                path => {
+                    // a resumed export adds to the file it stopped writing
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .append(args.resume.is_some())
+                        .truncate(args.resume.is_none())
+                        .open(path)
+                        .map_err(|e| format!("Can't open {path}: {e}"))?;
+                    migration.export_users(from.repo(), format, file).await
+                }
+            };
+            exported.map_err(|e| e.to_string())
+        }
+        _ => unreachable!("commands are checked when parsing"),
+    }
+}
+
+async fn reencrypt(args: &Args) -> Result<(), String> {
+    let store = args.required("<store>", &args.file)?;
+    if !PiiKeys::from_env().is_ok_and(|keys| keys.is_some()) {
+        return Err("PII_KEYS and PII_INDEX_KEY must be set to reencrypt".to_string());
+    }
+    let failed = |e: auth::RepoError| format!("Can't reencrypt {store}: {e}");
+    match Store::open(store)? {
+        // the in-memory store writes every user into a new snapshot
+        Store::Memory(repo) => {
+            if repo.reencrypt().await.map_err(failed)? {
+                eprintln!("Users written to a new snapshot");
+            }
+        }
+        Store::Redb(mut repo) => {
+            let count = repo.reencrypt().await.map_err(failed)?;
+            // drops the pages still holding the records as they were
+            repo.compact().map_err(failed)?;
+            eprintln!("{count} users reencrypted");
+        }
+    }
+    Ok(())
+}
+
+// The report goes to stderr, an export may be writing to stdout
+fn print_report(report: &MigrationReport, dry_run: bool) {
+    let written = match dry_run {
+        true => "would be written",
+        false => "written",
+    };
+    eprintln!(
+        "{} read, {} {written}, {} already there, {} rejected",
+        report.read,
+        report.written,
+        report.existing,
+        report.rejected.len()
+    );
+    for rejected in &report.rejected {
+        eprintln!("  {}: {}", rejected.record, rejected.reason);
+    }
+}
+
+enum Store {
+    Memory(Box<InMemoryUserRepository>),
+    Redb(RedbUserRepository),
+}
+
+impl Store {
+    fn open(store: &str) -> Result<Self, String> {
+        let keys = PiiKeys::from_env().map_err(|e| e.to_string())?;
+        let opened = match (store.split_once(':'), keys) {
+            (Some(("memory", dir)), Some(keys)) => {
+                InMemoryUserRepository::open_encrypted(dir, keys)
+                    .map(|repo| Store::Memory(Box::new(repo)))
+            }
+            (Some(("memory", dir)), None) => {
+                InMemoryUserRepository::open(dir).map(|repo| Store::Memory(Box::new(repo)))
+            }
+            (Some(("redb", file)), Some(keys)) => {
+                RedbUserRepository::open_encrypted(file, keys).map(Store::Redb)
+            }
+            (Some(("redb", file)), None) => RedbUserRepository::open(file).map(Store::Redb),
+            _ => return Err(format!("Unknown store: {store}")),
+        };
+        opened.map_err(|e| format!("Can't open {store}: {e}"))
+    }
+
+    fn repo(&self) -> &dyn UserRepositoryTrait {
+        match self {
+            Store::Memory(repo) => repo.as_ref(),
+            Store::Redb(repo) => repo,
+        }
+    }
+
+    // so the app starts from a snapshot rather than replaying everything written here
+    async fn close(&self) -> Result<(), String> {
+        match self {
+            Store::Memory(repo) => repo
+                .snapshot()
+                .await
+                .map(|_| ())
+                .map_err(|e| format!("Can't snapshot users: {e}")),
+            Store::Redb(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Args {
+    command: String,
+    file: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    format: Option<String>,
+    batch_size: Option<usize>,
+    resume: Option<String>,
+    dry_run: bool,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut args = args.iter();
+        let mut parsed = Args {
+            command: args.next().ok_or("Missing command")?.clone(),
+            ..Default::default()
+        };
+        if !["migrate", "import", "export", "reencrypt"].contains(&parsed.command.as_str()) {
+            return Err(format!("Unknown command: {}", parsed.command));
+        }
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--from" => parsed.from = Some(value()?),
+                "--to" => parsed.to = Some(value()?),
+                "--format" => parsed.format = Some(value()?),
+                "--resume" => parsed.resume = Some(value()?),
+                "--batch-size" => {
+                    let batch_size = value()?;
+                    parsed.batch_size = Some(
+                        batch_size
+                            .parse()
+                            .map_err(|_| format!("Invalid batch size: {batch_size}"))?,
+                    );
+                }
+                "--dry-run" => parsed.dry_run = true,
+                flag if flag.starts_with("--") => return Err(format!("Unknown option: {flag}")),
+                file if parsed.file.is_none() => parsed.file = Some(file.to_string()),
+                extra => return Err(format!("Unexpected argument: {extra}")),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn required<'a>(&self, flag: &str, value: &'a Option<String>) -> Result<&'a str, String> {
+        value.as_deref().ok_or_else(|| format!("Missing {flag}"))
+    }
+
+    fn file(&self) -> Result<&str, String> {
+        self.file
+            .as_deref()
+            .ok_or_else(|| "Missing file".to_string())
+    }
+
+    fn format(&self) -> Result<UserFormat, String> {
+        self.required("--format", &self.format)?
+            .parse()
+            .map_err(|e: auth::MigrationError| e.to_string())
+    }
+}
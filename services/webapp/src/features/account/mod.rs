@@ -0,0 +1,2 @@
+mod pages;
+pub mod routes;
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{ApiKey, AuthServiceTrait, NewApiKey, Scope, User};
+use axum::{
+    Extension,
+    extract::{Form, Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect},
+};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+
+use crate::features::auth::routes::SessionToken;
+
+pub async fn api_keys_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    api_keys_page(auth_service.as_ref(), &user, None, None)
+        .await
+        .into_response()
+}
+
+pub async fn create_api_key_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Extension(SessionToken(token)): Extension<SessionToken>,
+    Form(form): Form<CreateApiKeyForm>,
+) -> impl IntoResponse {
+    let mut scopes = Vec::new();
+    if form.scope_read.is_some() {
+        scopes.push(Scope::Read);
+    }
+    if form.scope_write.is_some() {
+        scopes.push(Scope::Write);
+    }
+
+    if scopes.is_empty() {
+        return api_keys_page(
+            auth_service.as_ref(),
+            &user,
+            None,
+            Some("Select at least one scope".to_string()),
+        )
+        .await
+        .into_response();
+    }
+
+    let expires_at = match form.expires_in_days.parse::<i64>() {
+        Ok(days) if days > 0 => {
+            Some((OffsetDateTime::now_utc() + Duration::days(days)).unix_timestamp())
+        }
+        _ => None,
+    };
+
+    let new_key = NewApiKey {
+        name: form.name,
+        scopes,
+        expires_at,
+    };
+
+    match auth_service.create_api_key(&token, new_key).await {
+        Ok((_, plain_text)) => api_keys_page(auth_service.as_ref(), &user, Some(plain_text), None)
+            .await
+            .into_response(),
+        Err(err) => {
+            let error_message = match err {
+                auth::AuthError::InvalidApiKey => "API key name is required",
+                _ => "Could not create API key. Please try again.",
+            };
+
+            api_keys_page(
+                auth_service.as_ref(),
+                &user,
+                None,
+                Some(error_message.to_string()),
+            )
+            .await
+            .into_response()
+        }
+    }
+}
+
+pub async fn revoke_api_key_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match auth_service.revoke_api_key(&user.id, &id).await {
+        Ok(_) => Redirect::to("/account/api-keys").into_response(),
+        Err(_) => api_keys_page(
+            auth_service.as_ref(),
+            &user,
+            None,
+            Some("Could not revoke API key".to_string()),
+        )
+        .await
+        .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyForm {
+    pub name: String,
+    pub scope_read: Option<String>,
+    pub scope_write: Option<String>,
+    pub expires_in_days: String,
+}
+
+struct ApiKeyRow {
+    id: String,
+    name: String,
+    prefix: String,
+    scopes: String,
+    created: String,
+    expires: String,
+    last_used: String,
+    status: &'static str,
+    active: bool,
+}
+
+impl ApiKeyRow {
+    fn from_api_key(api_key: &ApiKey, now: i64) -> Self {
+        let status = if api_key.is_revoked() {
+            "Revoked"
+        } else if api_key.is_expired(now) {
+            "Expired"
+        } else {
+            "Active"
+        };
+
+        Self {
+            id: api_key.id.clone(),
+            name: api_key.name.clone(),
+            prefix: format!("sk_{}", api_key.prefix),
+            scopes: api_key
+                .scopes
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            created: format_date(Some(api_key.created_at)),
+            expires: format_date(api_key.expires_at),
+            last_used: format_date(api_key.last_used_at),
+            status,
+            active: status == "Active",
+        }
+    }
+}
+
+fn format_date(timestamp: Option<i64>) -> String {
+    timestamp
+        .and_then(|ts| OffsetDateTime::from_unix_timestamp(ts).ok())
+        .map(|dt| dt.date().to_string())
+        .unwrap_or_else(|| "Never".to_string())
+}
+
+#[derive(Template)]
+#[template(path = "account/api_keys.html")]
+struct ApiKeysTemplate<'a> {
+    title: &'a str,
+    keys: Vec<ApiKeyRow>,
+    new_key: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+async fn api_keys_page(
+    auth_service: &dyn AuthServiceTrait,
+    user: &User,
+    new_key: Option<String>,
+    error: Option<String>,
+) -> Html<String> {
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    let keys = auth_service
+        .list_api_keys(&user.id)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(|k| ApiKeyRow::from_api_key(k, now))
+        .collect();
+
+    Html(
+        ApiKeysTemplate {
+            title: "API Keys",
+            keys,
+            new_key: new_key.as_deref(),
+            error: error.as_deref(),
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{AuthServiceTrait, ClientApp, NewClientApp, Scope, User};
+use axum::{
+    Extension,
+    extract::{Form, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use serde::Deserialize;
+use time::OffsetDateTime;
+
+pub async fn apps_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    apps_page(auth_service.as_ref(), &user, None, None).await
+}
+
+pub async fn create_app_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Form(form): Form<CreateAppForm>,
+) -> impl IntoResponse {
+    let mut scopes = Vec::new();
+    if form.scope_read.is_some() {
+        scopes.push(Scope::Read);
+    }
+    if form.scope_write.is_some() {
+        scopes.push(Scope::Write);
+    }
+
+    let new_client = NewClientApp {
+        name: form.name,
+        redirect_uris: form
+            .redirect_uris
+            .lines()
+            .map(str::trim)
+            .filter(|uri| !uri.is_empty())
+            .map(str::to_string)
+            .collect(),
+        scopes,
+    };
+
+    match auth_service.register_client_app(&user.id, new_client).await {
+        Ok((client_app, secret)) => {
+            let credentials = NewCredentials {
+                client_id: client_app.id,
+                client_secret: secret,
+            };
+            apps_page(auth_service.as_ref(), &user, Some(credentials), None).await
+        }
+        Err(err) => {
+            let error_message = match err {
+                auth::AuthError::Authz(_) => {
+                    "Name, at least one valid redirect URI and a scope are required"
+                }
+                _ => "Could not register the app. Please try again.",
+            };
+
+            apps_page(
+                auth_service.as_ref(),
+                &user,
+                None,
+                Some(error_message.to_string()),
+            )
+            .await
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateAppForm {
+    pub name: String,
+    // one per line
+    pub redirect_uris: String,
+    pub scope_read: Option<String>,
+    pub scope_write: Option<String>,
+}
+
+struct NewCredentials {
+    client_id: String,
+    client_secret: String,
+}
+
+struct AppRow {
+    client_id: String,
+    name: String,
+    redirect_uris: String,
+    scopes: String,
+    created: String,
+}
+
+impl AppRow {
+    fn from_client_app(client_app: &ClientApp) -> Self {
+        Self {
+            client_id: client_app.id.clone(),
+            name: client_app.name.clone(),
+            redirect_uris: client_app.redirect_uris.join(", "),
+            scopes: Scope::join(&client_app.scopes),
+            created: OffsetDateTime::from_unix_timestamp(client_app.created_at)
+                .map(|dt| dt.date().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "account/apps.html")]
+struct AppsTemplate<'a> {
+    title: &'a str,
+    apps: Vec<AppRow>,
+    credentials: Option<NewCredentials>,
+    error: Option<&'a str>,
+}
+
+async fn apps_page(
+    auth_service: &dyn AuthServiceTrait,
+    user: &User,
+    credentials: Option<NewCredentials>,
+    error: Option<String>,
+) -> Html<String> {
+    let apps = auth_service
+        .list_client_apps(&user.id)
+        .await
+        .unwrap_or_default()
+        .iter()
+        .map(AppRow::from_client_app)
+        .collect();
+
+    Html(
+        AppsTemplate {
+            title: "OAuth Apps",
+            apps,
+            credentials,
+            error: error.as_deref(),
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
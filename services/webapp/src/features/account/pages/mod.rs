@@ -0,0 +1,4 @@
+pub mod api_keys;
+pub mod apps;
+pub mod sessions;
+pub mod settings;
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{ActiveSession, AuthError, AuthServiceTrait};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::CookieJar;
+use time::OffsetDateTime;
+
+pub async fn sessions_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    sessions_page(auth_service.as_ref(), &cookie_jar, None).await
+}
+
+pub async fn revoke_session_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    cookie_jar: CookieJar,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    let token = session_token(&cookie_jar);
+    match auth_service.revoke_session(&token, &id).await {
+        Ok(_) => Redirect::to("/account/sessions").into_response(),
+        Err(_) => sessions_page(
+            auth_service.as_ref(),
+            &cookie_jar,
+            Some("Could not sign out that session".to_string()),
+        )
+        .await
+        .into_response(),
+    }
+}
+
+pub async fn revoke_other_sessions_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    let token = session_token(&cookie_jar);
+    match auth_service.revoke_other_sessions(&token).await {
+        Ok(_) => Redirect::to("/account/sessions").into_response(),
+        Err(_) => sessions_page(
+            auth_service.as_ref(),
+            &cookie_jar,
+            Some("Could not sign out the other sessions".to_string()),
+        )
+        .await
+        .into_response(),
+    }
+}
+
+// auth_middleware already checked the cookie
+fn session_token(cookie_jar: &CookieJar) -> String {
+    cookie_jar
+        .get("auth_token")
+        .map(|cookie| cookie.value().to_string())
+        .unwrap_or_default()
+}
+
+struct SessionRow {
+    id: String,
+    device: String,
+    ip: String,
+    signed_in: String,
+    last_seen: String,
+    current: bool,
+}
+
+impl SessionRow {
+    fn from_session(session: ActiveSession) -> Self {
+        Self {
+            device: session.device,
+            ip: session.ip.unwrap_or_else(|| "Unknown".to_string()),
+            signed_in: format_time(session.created_at),
+            last_seen: format_time(session.last_seen_at),
+            current: session.current,
+            id: session.id,
+        }
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .map(|dt| format!("{} {:02}:{:02} UTC", dt.date(), dt.hour(), dt.minute()))
+        .unwrap_or_default()
+}
+
+#[derive(Template)]
+#[template(path = "account/sessions.html")]
+struct SessionsTemplate<'a> {
+    title: &'a str,
+    sessions: Vec<SessionRow>,
+    // sessions are only listed when they're stored server side
+    tracked: bool,
+    error: Option<&'a str>,
+}
+
+async fn sessions_page(
+    auth_service: &dyn AuthServiceTrait,
+    cookie_jar: &CookieJar,
+    error: Option<String>,
+) -> Html<String> {
+    let (sessions, tracked) = match auth_service.list_sessions(&session_token(cookie_jar)).await {
+        Ok(sessions) => (sessions, true),
+        Err(AuthError::SessionStoreRequired) => (Vec::new(), false),
+        Err(_) => (Vec::new(), true),
+    };
+
+    Html(
+        SessionsTemplate {
+            title: "Sessions",
+            sessions: sessions.into_iter().map(SessionRow::from_session).collect(),
+            tracked,
+            error: error.as_deref(),
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{AuthError, AuthServiceTrait, ExportFormat, User};
+use axum::{
+    Extension,
+    extract::{Form, Path, State},
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use serde::Deserialize;
+use time::Duration;
+
+use crate::features::auth::{client::Client, routes::SessionToken};
+
+// the account was saved elsewhere at the same time, e.g. from another tab
+const CONFLICT_MESSAGE: &str = "Your account was changed at the same time, please try again";
+
+pub async fn settings_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+) -> impl IntoResponse {
+    settings_page(auth_service.as_ref(), &user, None, None).await
+}
+
+pub async fn update_profile_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Form(form): Form<ProfileForm>,
+) -> impl IntoResponse {
+    match auth_service.update_profile(&user.id, &form.name).await {
+        Ok(user) => {
+            settings_page(auth_service.as_ref(), &user, Some("Profile updated"), None).await
+        }
+        Err(AuthError::ProfileValidation(e)) => {
+            settings_page(auth_service.as_ref(), &user, None, Some(e)).await
+        }
+        Err(AuthError::Conflict) => {
+            settings_page(
+                auth_service.as_ref(),
+                &user,
+                None,
+                Some(CONFLICT_MESSAGE.to_string()),
+            )
+            .await
+        }
+        Err(_) => {
+            settings_page(
+                auth_service.as_ref(),
+                &user,
+                None,
+                Some("Could not update your profile".to_string()),
+            )
+            .await
+        }
+    }
+}
+
+pub async fn change_password_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Extension(SessionToken(token)): Extension<SessionToken>,
+    Client(client): Client,
+    Form(form): Form<PasswordForm>,
+) -> impl IntoResponse {
+    if form.new_password != form.confirm_password {
+        return settings_page(
+            auth_service.as_ref(),
+            &user,
+            None,
+            Some("The new passwords don't match".to_string()),
+        )
+        .await;
+    }
+
+    match auth_service
+        .change_password(&token, &form.current_password, &form.new_password, &client)
+        .await
+    {
+        Ok(_) => settings_page(auth_service.as_ref(), &user, Some("Password changed"), None).await,
+        Err(err) => {
+            let error_message = match err {
+                AuthError::Hook(reason) => reason.to_string(),
+                AuthError::InvalidCredentials => "Current password is incorrect".to_string(),
+                AuthError::PasswordValidation(e) => e,
+                AuthError::Conflict => CONFLICT_MESSAGE.to_string(),
+                _ => "Could not change your password".to_string(),
+            };
+
+            settings_page(auth_service.as_ref(), &user, None, Some(error_message)).await
+        }
+    }
+}
+
+pub async fn delete_account_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Extension(SessionToken(token)): Extension<SessionToken>,
+    Client(client): Client,
+    Form(form): Form<DeleteAccountForm>,
+) -> impl IntoResponse {
+    match auth_service
+        .delete_account(&token, &form.password, &client)
+        .await
+    {
+        Ok(_) => {
+            let cookie = Cookie::build(("auth_token", ""))
+                .path("/")
+                .max_age(Duration::seconds(0))
+                .same_site(SameSite::Strict)
+                .http_only(true)
+                .build();
+
+            (CookieJar::new().add(cookie), Redirect::to("/")).into_response()
+        }
+        Err(err) => {
+            let error_message = match err {
+                AuthError::Hook(reason) => reason.to_string(),
+                AuthError::InvalidCredentials => "Password is incorrect".to_string(),
+                _ => "Could not delete your account".to_string(),
+            };
+
+            settings_page(auth_service.as_ref(), &user, None, Some(error_message))
+                .await
+                .into_response()
+        }
+    }
+}
+
+// Downloads everything held about the user, as `json` or `zip`
+pub async fn export_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Extension(SessionToken(token)): Extension<SessionToken>,
+    Path(format): Path<String>,
+) -> impl IntoResponse {
+    let Ok(format) = format.parse::<ExportFormat>() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match auth_service.export_user_data(&token, format).await {
+        Ok(export) => (
+            [
+                (CONTENT_TYPE, format.content_type().to_string()),
+                (
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"my-data.{}\"", format.as_str()),
+                ),
+            ],
+            export,
+        )
+            .into_response(),
+        Err(_) => settings_page(
+            auth_service.as_ref(),
+            &user,
+            None,
+            Some("Could not export your data".to_string()),
+        )
+        .await
+        .into_response(),
+    }
+}
+
+pub async fn request_email_change_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Extension(SessionToken(token)): Extension<SessionToken>,
+    Client(client): Client,
+    Form(form): Form<EmailForm>,
+) -> impl IntoResponse {
+    match auth_service
+        .request_email_change(&token, &form.email, &form.password, &client)
+        .await
+    {
+        Ok(change) => {
+            let notice = format!(
+                "Follow the link sent to {} to finish the change",
+                change.new_email
+            );
+            settings_page(auth_service.as_ref(), &user, Some(&notice), None).await
+        }
+        Err(err) => {
+            let error_message = match err {
+                AuthError::InvalidCredentials => "Password is incorrect",
+                AuthError::EmailValidation => "Enter a valid email address you don't already use",
+                AuthError::UserExists => "Another account uses this email",
+                _ => "Could not change your email",
+            };
+
+            settings_page(
+                auth_service.as_ref(),
+                &user,
+                None,
+                Some(error_message.to_string()),
+            )
+            .await
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EmailForm {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ProfileForm {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordForm {
+    pub current_password: String,
+    pub new_password: String,
+    pub confirm_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteAccountForm {
+    pub password: String,
+}
+
+#[derive(Template)]
+#[template(path = "account/settings.html")]
+struct SettingsTemplate<'a> {
+    title: &'a str,
+    user: &'a User,
+    // waiting for the new address to confirm
+    pending_email: Option<String>,
+    // deleted accounts are purged after this many days
+    grace_days: i64,
+    notice: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+async fn settings_page(
+    auth_service: &dyn AuthServiceTrait,
+    user: &User,
+    notice: Option<&str>,
+    error: Option<String>,
+) -> Html<String> {
+    let pending_email = match auth_service.pending_email_change(&user.id).await {
+        Ok(change) => change.map(|c| c.new_email),
+        Err(_) => None,
+    };
+
+    Html(
+        SettingsTemplate {
+            title: "Account",
+            user,
+            pending_email,
+            grace_days: auth_service.erasure_grace_period().whole_days(),
+            notice,
+            error: error.as_deref(),
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
@@ -0,0 +1,49 @@
+use axum::{
+    Router, middleware,
+    routing::{get, post},
+};
+use std::sync::Arc;
+
+use super::pages::{
+    api_keys::{api_keys_handler, create_api_key_handler, revoke_api_key_handler},
+    apps::{apps_handler, create_app_handler},
+    sessions::{revoke_other_sessions_handler, revoke_session_handler, sessions_handler},
+    settings::{
+        change_password_handler, delete_account_handler, export_handler,
+        request_email_change_handler, settings_handler, update_profile_handler,
+    },
+};
+use crate::features::auth::routes::{
+    auth_middleware, no_impersonation_middleware, session_only_middleware,
+};
+use auth::AuthServiceTrait;
+
+pub fn account_routes(auth_service: Arc<dyn AuthServiceTrait>) -> Router {
+    Router::new()
+        .route("/profile", post(update_profile_handler))
+        .route("/password", post(change_password_handler))
+        .route("/email", post(request_email_change_handler))
+        .route("/export/{format}", get(export_handler))
+        .route("/delete", post(delete_account_handler))
+        .route("/api-keys", post(create_api_key_handler))
+        .route("/api-keys/{id}/revoke", post(revoke_api_key_handler))
+        .route("/apps", post(create_app_handler))
+        .route("/sessions/{id}/revoke", post(revoke_session_handler))
+        .route(
+            "/sessions/revoke-others",
+            post(revoke_other_sessions_handler),
+        )
+        // admins acting as the user can look around but not change anything here
+        .route_layer(middleware::from_fn(no_impersonation_middleware))
+        .route("/", get(settings_handler))
+        .route("/api-keys", get(api_keys_handler))
+        .route("/apps", get(apps_handler))
+        .route("/sessions", get(sessions_handler))
+        // api keys and client tokens can't be used to manage credentials
+        .route_layer(middleware::from_fn(session_only_middleware))
+        .route_layer(middleware::from_fn_with_state(
+            auth_service.clone(),
+            auth_middleware,
+        ))
+        .with_state(auth_service)
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{AuditEvent, AuditFilter, AuthEvent, AuthServiceTrait};
+use axum::{
+    extract::{Query, State},
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{Html, IntoResponse},
+};
+use serde::Deserialize;
+use time::{Date, Month, OffsetDateTime};
+
+// the page shows the most recent entries, the export has everything matching
+const PAGE_LIMIT: usize = 200;
+
+pub async fn audit_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    let filter = AuditFilter {
+        limit: Some(PAGE_LIMIT),
+        ..query.filter()
+    };
+    let (entries, error) = match auth_service.audit_log(&filter).await {
+        Ok(entries) => (entries, None),
+        Err(_) => (Vec::new(), Some("Could not read the audit log")),
+    };
+
+    Html(
+        AuditTemplate {
+            title: "Audit log",
+            kinds: AuthEvent::KINDS.to_vec(),
+            rows: entries.into_iter().map(AuditRow::from_entry).collect(),
+            query: &query,
+            error,
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
+
+pub async fn audit_csv_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Query(query): Query<AuditQuery>,
+) -> impl IntoResponse {
+    let entries = match auth_service.audit_log(&query.filter()).await {
+        Ok(entries) => entries,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let mut csv = String::from("id,time,type,actor,subject,ip,details\r\n");
+    for entry in entries {
+        let row = AuditRow::from_entry(entry);
+        let fields = [
+            row.id,
+            row.time,
+            row.kind,
+            row.actor,
+            row.subject,
+            row.ip,
+            row.details,
+        ];
+        let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+
+    (
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"audit.csv\""),
+        ],
+        csv,
+    )
+        .into_response()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuditQuery {
+    user: String,
+    kind: String,
+    // YYYY-MM-DD, as sent by date inputs
+    since: String,
+    until: String,
+}
+
+impl AuditQuery {
+    fn is_kind(&self, kind: &str) -> bool {
+        self.kind == kind
+    }
+
+    fn filter(&self) -> AuditFilter {
+        let not_empty = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+
+        AuditFilter {
+            user_id: not_empty(&self.user),
+            kind: not_empty(&self.kind),
+            since: parse_date(&self.since)
+                .map(|date| date.midnight().assume_utc().unix_timestamp()),
+            // the whole day is included
+            until: parse_date(&self.until)
+                .and_then(|date| date.next_day())
+                .map(|date| date.midnight().assume_utc().unix_timestamp() - 1),
+            limit: None,
+        }
+    }
+}
+
+fn parse_date(date: &str) -> Option<Date> {
+    let mut parts = date.trim().splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+pub(super) struct AuditRow {
+    pub(super) id: String,
+    pub(super) time: String,
+    pub(super) kind: String,
+    pub(super) actor: String,
+    pub(super) subject: String,
+    pub(super) ip: String,
+    pub(super) details: String,
+}
+
+impl AuditRow {
+    pub(super) fn from_entry(entry: AuditEvent) -> Self {
+        let details = entry
+            .event
+            .details()
+            .into_iter()
+            .map(|(name, value)| match value {
+                serde_json::Value::String(value) => format!("{name}={value}"),
+                value => format!("{name}={value}"),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Self {
+            id: entry.id,
+            time: format_time(entry.timestamp),
+            kind: entry.event.kind().to_string(),
+            actor: entry.actor.unwrap_or_default(),
+            subject: entry.subject.unwrap_or_default(),
+            ip: entry.ip.unwrap_or_default(),
+            details,
+        }
+    }
+}
+
+pub(super) fn format_time(timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .map(|dt| {
+            format!(
+                "{} {:02}:{:02}:{:02} UTC",
+                dt.date(),
+                dt.hour(),
+                dt.minute(),
+                dt.second()
+            )
+        })
+        .unwrap_or_default()
+}
+
+// Quotes fields when needed, and defuses ones a spreadsheet would run as a formula
+fn csv_field(field: &str) -> String {
+    let field = match field.chars().next() {
+        Some('=' | '+' | '-' | '@' | '\t' | '\r') => format!("'{field}"),
+        _ => field.to_string(),
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/audit.html")]
+struct AuditTemplate<'a> {
+    title: &'a str,
+    kinds: Vec<&'static str>,
+    rows: Vec<AuditRow>,
+    query: &'a AuditQuery,
+    error: Option<&'a str>,
+}
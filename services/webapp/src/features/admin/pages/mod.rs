@@ -0,0 +1,3 @@
+pub mod audit;
+pub mod outbox;
+pub mod users;
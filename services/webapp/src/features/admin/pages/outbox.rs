@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{AuthServiceTrait, OutboxMessage};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+
+use super::audit::format_time;
+
+pub async fn outbox_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+) -> impl IntoResponse {
+    outbox_page(auth_service.as_ref(), None, None).await
+}
+
+pub async fn retry_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match auth_service.retry_outbox_message(&id).await {
+        Ok(()) => outbox_page(auth_service.as_ref(), Some("Queued for delivery"), None).await,
+        Err(_) => {
+            outbox_page(
+                auth_service.as_ref(),
+                None,
+                Some("Could not retry the event"),
+            )
+            .await
+        }
+    }
+}
+
+async fn outbox_page(
+    auth_service: &dyn AuthServiceTrait,
+    notice: Option<&str>,
+    error: Option<&str>,
+) -> Html<String> {
+    let (rows, error) = match auth_service.outbox_dead_letters().await {
+        Ok(messages) => (
+            messages
+                .into_iter()
+                .map(DeadLetterRow::from_message)
+                .collect(),
+            error,
+        ),
+        Err(_) => (Vec::new(), Some("Could not read the outbox")),
+    };
+
+    Html(
+        OutboxTemplate {
+            title: "Undelivered events",
+            rows,
+            notice,
+            error,
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
+
+struct DeadLetterRow {
+    id: String,
+    time: String,
+    kind: String,
+    attempts: u32,
+    last_error: String,
+    event: String,
+}
+
+impl DeadLetterRow {
+    fn from_message(message: OutboxMessage) -> Self {
+        Self {
+            time: format_time(message.created_at),
+            kind: message.event.kind().to_string(),
+            attempts: message.attempts,
+            last_error: message.last_error.unwrap_or_default(),
+            event: serde_json::to_string(&message.event).unwrap_or_default(),
+            id: message.id,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/outbox.html")]
+struct OutboxTemplate<'a> {
+    title: &'a str,
+    rows: Vec<DeadLetterRow>,
+    notice: Option<&'a str>,
+    error: Option<&'a str>,
+}
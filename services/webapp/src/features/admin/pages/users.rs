@@ -0,0 +1,440 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{
+    AccountStatus, AuditFilter, AuthError, AuthServiceTrait, Identity, RepoError, Role, User,
+    UserFilter, UserQuery, UserSort,
+};
+use axum::{
+    Extension,
+    extract::{Form, Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+
+use super::audit::{AuditRow, format_time};
+use crate::features::auth::client::Client;
+
+const PAGE_SIZE: usize = 25;
+// the detail page shows the user's most recent events, the audit log has the rest
+const RECENT_EVENTS: usize = 20;
+
+pub async fn users_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Query(query): Query<UsersQuery>,
+) -> impl IntoResponse {
+    let sort = query.sort.parse::<UserSort>().unwrap_or_default();
+    let list = UserQuery {
+        filter: UserFilter {
+            search: Some(query.q.clone()).filter(|q| !q.trim().is_empty()),
+            status: status_filter(&query.status),
+            ..Default::default()
+        },
+        sort,
+        descending: query.order != "asc",
+        cursor: query.cursor.clone().filter(|c| !c.is_empty()),
+        limit: PAGE_SIZE,
+    };
+    let (rows, next_cursor, error) = match auth_service.list_users(&list).await {
+        Ok(found) => (
+            found.users.iter().map(UserRow::from_user).collect(),
+            found.next_cursor,
+            None,
+        ),
+        Err(AuthError::Repository(RepoError::InvalidCursor)) => (
+            Vec::new(),
+            None,
+            Some("This page is out of date, go back to the first page"),
+        ),
+        Err(_) => (Vec::new(), None, Some("Could not list users")),
+    };
+
+    Html(
+        UsersTemplate {
+            title: "Users",
+            q: query.q.trim(),
+            status: &query.status,
+            sort: sort.as_str(),
+            order: if list.descending { "desc" } else { "asc" },
+            rows,
+            first_page: list.cursor.is_none(),
+            next_cursor,
+            error,
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
+
+pub async fn user_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(admin): Extension<User>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    user_page(auth_service.as_ref(), &admin, &id, None, None).await
+}
+
+pub async fn user_action_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(admin): Extension<User>,
+    Path((id, action)): Path<(String, UserAction)>,
+) -> impl IntoResponse {
+    // admins can't lock themselves out
+    if id == admin.id && action.locks_out() {
+        return user_page(
+            auth_service.as_ref(),
+            &admin,
+            &id,
+            None,
+            Some("You can't do this to your own account"),
+        )
+        .await;
+    }
+
+    let outcome = match action {
+        UserAction::RevokeSessions => auth_service
+            .revoke_user_sessions(&admin.id, &id)
+            .await
+            .map(|count| format!("Signed out everywhere, {count} sessions ended")),
+        UserAction::VerifyEmail => auth_service
+            .verify_email(&admin.id, &id)
+            .await
+            .map(|_| "Email marked as verified".to_string()),
+        UserAction::ResetPassword => auth_service
+            .force_password_reset(&admin.id, &id)
+            .await
+            .map(|_| "Password reset, the user was emailed a link to choose a new one".to_string()),
+    };
+
+    match outcome {
+        Ok(notice) => user_page(auth_service.as_ref(), &admin, &id, Some(&notice), None).await,
+        Err(_) => {
+            user_page(
+                auth_service.as_ref(),
+                &admin,
+                &id,
+                None,
+                Some("Could not update the user"),
+            )
+            .await
+        }
+    }
+}
+
+pub async fn status_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(admin): Extension<User>,
+    Path(id): Path<String>,
+    Form(form): Form<StatusForm>,
+) -> Response {
+    if id == admin.id {
+        return user_page(
+            auth_service.as_ref(),
+            &admin,
+            &id,
+            None,
+            Some("You can't change the status of your own account"),
+        )
+        .await;
+    }
+
+    let (status, notice) = match form.status {
+        NewStatus::Active => (AccountStatus::Active, "Account enabled"),
+        NewStatus::Disabled => (
+            AccountStatus::Disabled,
+            "Account disabled, the user was signed out everywhere",
+        ),
+        NewStatus::Suspended => {
+            let until = OffsetDateTime::now_utc() + Duration::days(form.days.max(1));
+            (
+                AccountStatus::Suspended {
+                    until: until.unix_timestamp(),
+                },
+                "Account suspended, the user was signed out everywhere",
+            )
+        }
+        NewStatus::PendingVerification => (
+            AccountStatus::PendingVerification,
+            "The user can sign in again once their email is verified",
+        ),
+    };
+
+    match auth_service
+        .set_account_status(&admin.id, &id, status, Some(&form.reason))
+        .await
+    {
+        Ok(_) => user_page(auth_service.as_ref(), &admin, &id, Some(notice), None).await,
+        Err(AuthError::InvalidStatus(e)) => {
+            user_page(auth_service.as_ref(), &admin, &id, None, Some(&e)).await
+        }
+        Err(_) => {
+            user_page(
+                auth_service.as_ref(),
+                &admin,
+                &id,
+                None,
+                Some("Could not change the account status"),
+            )
+            .await
+        }
+    }
+}
+
+// Signs the admin in as the user. Their own session is set aside in another cookie until
+// they stop.
+pub async fn impersonate_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(admin): Extension<User>,
+    Client(client): Client,
+    cookie_jar: CookieJar,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(admin_token) = cookie_jar.get("auth_token") else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    match auth_service
+        .start_impersonation(&admin.id, &id, &client)
+        .await
+    {
+        Ok(token) => {
+            // the admin's session is set aside under a new id
+            let admin_token = match auth_service.rotate_session(admin_token.value()).await {
+                Ok(admin_token) => admin_token,
+                Err(_) => {
+                    let _ = auth_service.stop_impersonation(&token, &client).await;
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+            };
+            let admin_cookie = Cookie::build(("admin_token", admin_token))
+                .path("/")
+                .max_age(Duration::days(7))
+                .same_site(SameSite::Strict)
+                .http_only(true)
+                .build();
+            let auth_cookie = Cookie::build(("auth_token", token))
+                .path("/")
+                .max_age(Duration::hours(1))
+                .same_site(SameSite::Strict)
+                .http_only(true)
+                .build();
+
+            (
+                CookieJar::new().add(admin_cookie).add(auth_cookie),
+                Redirect::to("/account"),
+            )
+                .into_response()
+        }
+        Err(err) => {
+            let error_message = match err {
+                AuthError::ImpersonationNotAllowed => "Admins can't be impersonated",
+                AuthError::AccountDisabled
+                | AuthError::AccountSuspended(_)
+                | AuthError::AccountPendingVerification
+                | AuthError::AccountDeleted => "Only active accounts can be impersonated",
+                _ => "Could not impersonate the user",
+            };
+
+            user_page(
+                auth_service.as_ref(),
+                &admin,
+                &id,
+                None,
+                Some(error_message),
+            )
+            .await
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct UsersQuery {
+    q: String,
+    // empty for any status
+    status: String,
+    // created_at or updated_at
+    sort: String,
+    // asc or desc, newest first by default
+    order: String,
+    // where the previous page ended, unset on the first page
+    cursor: Option<String>,
+}
+
+impl Default for UsersQuery {
+    fn default() -> Self {
+        Self {
+            q: String::new(),
+            status: String::new(),
+            sort: UserSort::default().as_str().to_string(),
+            order: "desc".to_string(),
+            cursor: None,
+        }
+    }
+}
+
+// Statuses are filtered by kind, so any suspension matches
+fn status_filter(status: &str) -> Option<AccountStatus> {
+    match status {
+        "active" => Some(AccountStatus::Active),
+        "disabled" => Some(AccountStatus::Disabled),
+        "suspended" => Some(AccountStatus::Suspended { until: 0 }),
+        "pending_verification" => Some(AccountStatus::PendingVerification),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UserAction {
+    RevokeSessions,
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl UserAction {
+    fn locks_out(&self) -> bool {
+        matches!(self, UserAction::RevokeSessions | UserAction::ResetPassword)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewStatus {
+    Active,
+    Disabled,
+    Suspended,
+    PendingVerification,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatusForm {
+    status: NewStatus,
+    // how long a suspension lasts
+    days: i64,
+    reason: String,
+}
+
+struct UserRow {
+    id: String,
+    email: String,
+    name: String,
+    status: String,
+    created: String,
+}
+
+impl UserRow {
+    fn from_user(user: &User) -> Self {
+        Self {
+            id: user.id.clone(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+            status: status(user),
+            created: format_time(user.created_at),
+        }
+    }
+}
+
+fn status(user: &User) -> String {
+    if user.is_deleted() {
+        return "Pending deletion".to_string();
+    }
+
+    match user.status {
+        AccountStatus::Active => "Active".to_string(),
+        AccountStatus::Disabled => "Disabled".to_string(),
+        AccountStatus::Suspended { until }
+            if until > OffsetDateTime::now_utc().unix_timestamp() =>
+        {
+            format!("Suspended until {}", format_time(until))
+        }
+        AccountStatus::Suspended { .. } => "Active, suspension over".to_string(),
+        AccountStatus::PendingVerification => "Pending email verification".to_string(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/users.html")]
+struct UsersTemplate<'a> {
+    title: &'a str,
+    q: &'a str,
+    status: &'a str,
+    sort: &'a str,
+    order: &'a str,
+    rows: Vec<UserRow>,
+    first_page: bool,
+    next_cursor: Option<String>,
+    error: Option<&'a str>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/user.html")]
+struct UserTemplate<'a> {
+    title: &'a str,
+    user: &'a User,
+    status: String,
+    roles: String,
+    created: String,
+    // the admin looking at their own account
+    is_self: bool,
+    can_impersonate: bool,
+    identities: Vec<Identity>,
+    events: Vec<AuditRow>,
+    notice: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+async fn user_page(
+    auth_service: &dyn AuthServiceTrait,
+    admin: &User,
+    id: &str,
+    notice: Option<&str>,
+    error: Option<&str>,
+) -> Response {
+    let user = match auth_service.get_user(id).await {
+        Ok(user) => user,
+        Err(_) => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let identities = auth_service.list_identities(id).await.unwrap_or_default();
+    let filter = AuditFilter {
+        user_id: Some(user.id.clone()),
+        limit: Some(RECENT_EVENTS),
+        ..Default::default()
+    };
+    let events = auth_service.audit_log(&filter).await.unwrap_or_default();
+
+    Html(
+        UserTemplate {
+            title: "User",
+            status: status(&user),
+            roles: user
+                .roles
+                .iter()
+                .map(|r| r.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            created: format_time(user.created_at),
+            is_self: user.id == admin.id,
+            can_impersonate: user.id != admin.id
+                && user
+                    .status
+                    .is_active(OffsetDateTime::now_utc().unix_timestamp())
+                && !user.is_deleted()
+                && !user.has_role(Role::Admin),
+            identities,
+            events: events.into_iter().map(AuditRow::from_entry).collect(),
+            user: &user,
+            notice,
+            error,
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+    .into_response()
+}
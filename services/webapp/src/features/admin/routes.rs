@@ -0,0 +1,50 @@
+use axum::{
+    Router,
+    http::StatusCode,
+    middleware,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use std::sync::Arc;
+
+use super::pages::{
+    audit::{audit_csv_handler, audit_handler},
+    outbox::{outbox_handler, retry_handler},
+    users::{
+        impersonate_handler, status_handler, user_action_handler, user_handler, users_handler,
+    },
+};
+use crate::features::auth::routes::{auth_middleware, session_only_middleware};
+use auth::{AuthServiceTrait, Role, User};
+
+pub fn admin_routes(auth_service: Arc<dyn AuthServiceTrait>) -> Router {
+    Router::new()
+        .route("/", get(async || Redirect::to("/admin/users")))
+        .route("/users", get(users_handler))
+        .route("/users/{id}", get(user_handler))
+        .route("/users/{id}/impersonate", post(impersonate_handler))
+        .route("/users/{id}/status", post(status_handler))
+        .route("/users/{id}/{action}", post(user_action_handler))
+        .route("/audit", get(audit_handler))
+        .route("/audit.csv", get(audit_csv_handler))
+        .route("/outbox", get(outbox_handler))
+        .route("/outbox/{id}/retry", post(retry_handler))
+        .route_layer(middleware::from_fn(admin_middleware))
+        .route_layer(middleware::from_fn(session_only_middleware))
+        .route_layer(middleware::from_fn_with_state(
+            auth_service.clone(),
+            auth_middleware,
+        ))
+        .with_state(auth_service)
+}
+
+// Must run after auth_middleware, only lets admins through
+pub async fn admin_middleware(
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    match req.extensions().get::<User>() {
+        Some(user) if user.has_role(Role::Admin) => next.run(req).await,
+        _ => StatusCode::FORBIDDEN.into_response(),
+    }
+}
@@ -0,0 +1,44 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use auth::ClientInfo;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+
+// Where a request comes from, recorded with each signin
+pub struct Client(pub ClientInfo);
+
+impl<S: Send + Sync> FromRequestParts<S> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        // X-Forwarded-For can be forged, it's only trusted from a local reverse proxy
+        let forwarded = match peer {
+            Some(ip) if ip.is_loopback() => parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .map(|ip| ip.trim().to_string())
+                .filter(|ip| !ip.is_empty()),
+            _ => None,
+        };
+
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Ok(Client(ClientInfo {
+            ip: forwarded.or_else(|| peer.map(|ip| ip.to_string())),
+            user_agent,
+        }))
+    }
+}
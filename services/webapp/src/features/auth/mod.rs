@@ -1,2 +1,3 @@
+pub mod client;
 mod pages;
 pub mod routes;
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{AuthError, AuthServiceTrait};
+use axum::{
+    extract::{Form, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use serde::Deserialize;
+
+use crate::features::auth::client::Client;
+
+// The links in the emails open a page with a button, so link scanners following them
+// don't confirm or cancel anything
+pub async fn confirm_email_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Query(query): Query<TokenForm>,
+) -> impl IntoResponse {
+    email_change_page(auth_service.as_ref(), Action::Confirm, query.token).await
+}
+
+pub async fn confirm_email_submit_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Client(client): Client,
+    Form(form): Form<TokenForm>,
+) -> impl IntoResponse {
+    let outcome = match auth_service
+        .confirm_email_change(&form.token, &client)
+        .await
+    {
+        Ok(user) => Ok(format!("{} is now the email of your account.", user.email)),
+        Err(AuthError::UserExists) => {
+            Err("Another account uses this email now, the change was not made.")
+        }
+        Err(_) => Err("This link is invalid or has expired."),
+    };
+
+    done_page(outcome)
+}
+
+pub async fn cancel_email_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Query(query): Query<TokenForm>,
+) -> impl IntoResponse {
+    email_change_page(auth_service.as_ref(), Action::Cancel, query.token).await
+}
+
+pub async fn cancel_email_submit_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Client(client): Client,
+    Form(form): Form<TokenForm>,
+) -> impl IntoResponse {
+    let outcome = match auth_service.cancel_email_change(&form.token, &client).await {
+        Ok(_) => Ok(
+            "The change was cancelled. If you didn't ask for it, change your password.".to_string(),
+        ),
+        Err(_) => Err("This link is invalid or has expired."),
+    };
+
+    done_page(outcome)
+}
+
+#[derive(Deserialize)]
+pub struct TokenForm {
+    pub token: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Confirm,
+    Cancel,
+}
+
+#[derive(Template)]
+#[template(path = "auth/email_change.html")]
+struct EmailChangeTemplate<'a> {
+    title: &'a str,
+    // set before the button is pressed
+    confirm: bool,
+    new_email: Option<&'a str>,
+    token: &'a str,
+    notice: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+async fn email_change_page(
+    auth_service: &dyn AuthServiceTrait,
+    action: Action,
+    token: String,
+) -> Html<String> {
+    let change = auth_service.find_email_change(&token).await.ok();
+    let error = match change {
+        Some(_) => None,
+        None => Some("This link is invalid or has expired."),
+    };
+
+    render(EmailChangeTemplate {
+        title: "Email change",
+        confirm: action == Action::Confirm,
+        new_email: change.as_ref().map(|c| c.new_email.as_str()),
+        token: &token,
+        notice: None,
+        error,
+    })
+}
+
+fn done_page(outcome: Result<String, &str>) -> Html<String> {
+    let (notice, error) = match &outcome {
+        Ok(notice) => (Some(notice.as_str()), None),
+        Err(error) => (None, Some(*error)),
+    };
+
+    render(EmailChangeTemplate {
+        title: "Email change",
+        confirm: false,
+        new_email: None,
+        token: "",
+        notice,
+        error,
+    })
+}
+
+fn render(template: EmailChangeTemplate) -> Html<String> {
+    Html(
+        template
+            .render()
+            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
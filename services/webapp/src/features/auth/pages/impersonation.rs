@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::AuthServiceTrait;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use time::Duration;
+
+use crate::features::auth::client::Client;
+
+// The banner shown while an admin acts as a user, empty otherwise. Loaded by every page.
+pub async fn impersonation_banner_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    let Some(token) = cookie_jar.get("auth_token") else {
+        return Html(String::new());
+    };
+    let (Ok(user), Ok(Some(admin))) = (
+        auth_service.validate_token(token.value()).await,
+        auth_service.impersonator(token.value()).await,
+    ) else {
+        return Html(String::new());
+    };
+
+    Html(
+        ImpersonationBannerTemplate {
+            email: &user.email,
+            admin_email: &admin.email,
+        }
+        .render()
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
+
+// Ends the impersonation and puts the admin's own session back
+pub async fn stop_impersonation_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Client(client): Client,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    let Some(admin_token) = cookie_jar.get("admin_token") else {
+        return Redirect::to("/").into_response();
+    };
+
+    let mut back_to = "/admin/users".to_string();
+    if let Some(token) = cookie_jar.get("auth_token") {
+        if let Ok(user) = auth_service.validate_token(token.value()).await {
+            back_to = format!("/admin/users/{}", user.id);
+        }
+        let _ = auth_service
+            .stop_impersonation(token.value(), &client)
+            .await;
+    }
+
+    // the admin's session comes back under a new id, one that can't be rotated is no
+    // longer valid and the admin signs in again
+    let admin_token = auth_service
+        .rotate_session(admin_token.value())
+        .await
+        .unwrap_or_default();
+    let auth_cookie = Cookie::build(("auth_token", admin_token))
+        .path("/")
+        .max_age(Duration::days(7))
+        .same_site(SameSite::Strict)
+        .http_only(true)
+        .build();
+    let admin_cookie = Cookie::build(("admin_token", ""))
+        .path("/")
+        .max_age(Duration::seconds(0))
+        .same_site(SameSite::Strict)
+        .http_only(true)
+        .build();
+
+    (
+        CookieJar::new().add(auth_cookie).add(admin_cookie),
+        Redirect::to(&back_to),
+    )
+        .into_response()
+}
+
+#[derive(Template)]
+#[template(path = "auth/impersonation_banner.html")]
+struct ImpersonationBannerTemplate<'a> {
+    email: &'a str,
+    admin_email: &'a str,
+}
@@ -1,2 +1,7 @@
+pub mod email_change;
+pub mod impersonation;
+pub mod oauth;
+pub mod password_reset;
 pub mod register;
 pub mod signin;
+pub mod sso;
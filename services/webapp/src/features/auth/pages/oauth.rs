@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use auth::AuthServiceTrait;
+use axum::{
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use serde::Deserialize;
+use time::Duration;
+
+use super::signin::signin_page;
+use crate::features::auth::client::Client;
+
+const OAUTH_STATE_COOKIE: &str = "oauth_state";
+
+pub async fn oauth_authorize_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Path(provider): Path<String>,
+) -> impl IntoResponse {
+    match auth_service.oauth_authorize(&provider) {
+        Ok(request) => {
+            // binds the flow to this browser, checked again on callback
+            // lax so the cookie is sent on the redirect back from the provider
+            let cookie = Cookie::build((OAUTH_STATE_COOKIE, request.state))
+                .path("/auth/oauth")
+                .max_age(Duration::minutes(10))
+                .same_site(SameSite::Lax)
+                .http_only(true)
+                .build();
+
+            (CookieJar::new().add(cookie), Redirect::to(&request.url)).into_response()
+        }
+        Err(_) => signin_page(
+            auth_service.as_ref(),
+            Some("Sign in provider not available".to_string()),
+        )
+        .await
+        .into_response(),
+    }
+}
+
+pub async fn oauth_callback_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Path(provider): Path<String>,
+    Client(client): Client,
+    cookie_jar: CookieJar,
+    Query(query): Query<OAuthCallbackQuery>,
+) -> impl IntoResponse {
+    let expected_state = cookie_jar
+        .get(OAUTH_STATE_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    let cookie_jar = cookie_jar.remove(Cookie::build(OAUTH_STATE_COOKIE).path("/auth/oauth"));
+
+    let (code, state) = match (query.code, query.state) {
+        (Some(code), Some(state)) if expected_state.as_deref() == Some(state.as_str()) => {
+            (code, state)
+        }
+        _ => {
+            return (
+                cookie_jar,
+                signin_page(
+                    auth_service.as_ref(),
+                    Some("Sign in was cancelled".to_string()),
+                )
+                .await,
+            )
+                .into_response();
+        }
+    };
+
+    match auth_service
+        .oauth_signin(&provider, &code, &state, &client)
+        .await
+    {
+        Ok(token) => {
+            let cookie = Cookie::build(("auth_token", token))
+                .path("/")
+                .max_age(Duration::days(7))
+                .same_site(SameSite::Strict)
+                .http_only(true)
+                .build();
+
+            (cookie_jar.add(cookie), Redirect::to("/")).into_response()
+        }
+        Err(err) => {
+            let error_message = match err {
+                auth::AuthError::Hook(reason) => reason.to_string(),
+                auth::AuthError::UserExists => {
+                    "An account with this email already exists. Sign in with your password first."
+                        .to_string()
+                }
+                _ => "Sign in failed. Please try again.".to_string(),
+            };
+
+            (
+                cookie_jar,
+                signin_page(auth_service.as_ref(), Some(error_message)).await,
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OAuthCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+}
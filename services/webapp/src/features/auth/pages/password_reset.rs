@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{AuthError, AuthServiceTrait};
+use axum::{
+    extract::{Form, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use serde::Deserialize;
+
+use crate::features::auth::client::Client;
+
+pub async fn password_reset_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Query(query): Query<ResetQuery>,
+) -> impl IntoResponse {
+    let error = match auth_service.find_password_reset(&query.token).await {
+        Ok(_) => None,
+        Err(_) => Some("This link is invalid or has expired."),
+    };
+
+    render(PasswordResetTemplate {
+        title: "Reset password",
+        show_form: error.is_none(),
+        token: &query.token,
+        notice: None,
+        error,
+    })
+}
+
+pub async fn password_reset_submit_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Client(client): Client,
+    Form(form): Form<ResetForm>,
+) -> impl IntoResponse {
+    if form.new_password != form.confirm_password {
+        return render(PasswordResetTemplate {
+            title: "Reset password",
+            show_form: true,
+            token: &form.token,
+            notice: None,
+            error: Some("The passwords don't match"),
+        });
+    }
+
+    let error = match auth_service
+        .reset_password(&form.token, &form.new_password, &client)
+        .await
+    {
+        Ok(_) => {
+            return render(PasswordResetTemplate {
+                title: "Reset password",
+                show_form: false,
+                token: "",
+                notice: Some("Your password was changed, you can sign in with it now."),
+                error: None,
+            });
+        }
+        Err(AuthError::PasswordValidation(e)) => e,
+        Err(AuthError::Hook(reason)) => reason.to_string(),
+        Err(_) => {
+            return render(PasswordResetTemplate {
+                title: "Reset password",
+                show_form: false,
+                token: "",
+                notice: None,
+                error: Some("This link is invalid or has expired."),
+            });
+        }
+    };
+
+    render(PasswordResetTemplate {
+        title: "Reset password",
+        show_form: true,
+        token: &form.token,
+        notice: None,
+        error: Some(&error),
+    })
+}
+
+#[derive(Deserialize)]
+pub struct ResetQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetForm {
+    pub token: String,
+    pub new_password: String,
+    pub confirm_password: String,
+}
+
+#[derive(Template)]
+#[template(path = "auth/password_reset.html")]
+struct PasswordResetTemplate<'a> {
+    title: &'a str,
+    // hidden once the link is used up or turns out invalid
+    show_form: bool,
+    token: &'a str,
+    notice: Option<&'a str>,
+    error: Option<&'a str>,
+}
+
+fn render(template: PasswordResetTemplate) -> Html<String> {
+    Html(
+        template
+            .render()
+            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
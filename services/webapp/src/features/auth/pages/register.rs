@@ -9,12 +9,15 @@ use axum::{
 };
 use serde::Deserialize;
 
+use crate::features::auth::client::Client;
+
 pub async fn register_handler() -> Html<String> {
     register_page(None).await
 }
 
 pub async fn register_submit_handler(
     State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Client(client): Client,
     Form(form): Form<RegisterForm>,
 ) -> impl IntoResponse {
     let user_data = RegisterUser {
@@ -23,18 +26,18 @@ pub async fn register_submit_handler(
         name: form.name,
     };
 
-    match auth_service.register(user_data).await {
+    match auth_service.register(user_data, &client).await {
         Ok(_) => Redirect::to("/auth/signin").into_response(),
         Err(err) => {
             let error_message = match err {
-                auth::AuthError::UserExists => "Email already registered",
-                auth::AuthError::PasswordValidation(_) => "Password Validation",
-                _ => "Registration failed. Please try again.",
+                // an application hook turned the registration down, its reason is for the user
+                auth::AuthError::Hook(reason) => reason.to_string(),
+                auth::AuthError::UserExists => "Email already registered".to_string(),
+                auth::AuthError::PasswordValidation(_) => "Password Validation".to_string(),
+                _ => "Registration failed. Please try again.".to_string(),
             };
 
-            register_page(Some(error_message.to_string()))
-                .await
-                .into_response()
+            register_page(Some(error_message)).await.into_response()
         }
     }
 }
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use askama::Template;
-use auth::{AuthServiceTrait, Credentials};
+use auth::{AuthServiceTrait, Credentials, OAuthProvider};
 use axum::{
     extract::{Form, State},
     http::StatusCode,
@@ -12,14 +12,18 @@ use axum_extra::extract::{
     cookie::{Cookie, SameSite},
 };
 use serde::Deserialize;
-use time::Duration;
+use time::{Duration, OffsetDateTime};
 
-pub async fn signin_handler() -> Html<String> {
-    signin_page(None).await
+use crate::features::auth::client::Client;
+
+pub async fn signin_handler(State(auth_service): State<Arc<dyn AuthServiceTrait>>) -> Html<String> {
+    signin_page(auth_service.as_ref(), None).await
 }
 
 pub async fn signin_submit_handler(
     State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Client(client): Client,
+    cookie_jar: CookieJar,
     Form(form): Form<SignInForm>,
 ) -> impl IntoResponse {
     let creds = Credentials {
@@ -27,8 +31,12 @@ pub async fn signin_submit_handler(
         password: form.password,
     };
 
-    match auth_service.signin(creds).await {
+    match auth_service.signin(creds, &client).await {
         Ok(token) => {
+            // the session signed in over is ended, not left behind the new cookie
+            if let Some(previous) = cookie_jar.get("auth_token") {
+                let _ = auth_service.signout(previous.value()).await;
+            }
             let cookie = Cookie::build(("auth_token", token))
                 .path("/")
                 .max_age(Duration::days(7))
@@ -38,9 +46,27 @@ pub async fn signin_submit_handler(
 
             (CookieJar::new().add(cookie), Redirect::to("/")).into_response()
         }
-        Err(_) => signin_page(Some("Invalid email or password".to_string()))
-            .await
-            .into_response(),
+        Err(err) => {
+            // the status is only told to whoever got the password right
+            let error_message = match err {
+                auth::AuthError::Hook(reason) => reason.to_string(),
+                auth::AuthError::AccountDisabled => "Your account is disabled".to_string(),
+                auth::AuthError::AccountSuspended(until) => {
+                    match OffsetDateTime::from_unix_timestamp(until) {
+                        Ok(until) => format!("Your account is suspended until {}", until.date()),
+                        Err(_) => "Your account is suspended".to_string(),
+                    }
+                }
+                auth::AuthError::AccountPendingVerification => {
+                    "Your email address has to be verified first".to_string()
+                }
+                _ => "Invalid email or password".to_string(),
+            };
+
+            signin_page(auth_service.as_ref(), Some(error_message))
+                .await
+                .into_response()
+        }
     }
 }
 
@@ -55,13 +81,18 @@ pub struct SignInForm {
 struct SignInTemplate<'a> {
     title: &'a str,
     error: Option<&'a str>,
+    providers: Vec<OAuthProvider>,
 }
 
-pub async fn signin_page(error: Option<String>) -> Html<String> {
+pub async fn signin_page(
+    auth_service: &dyn AuthServiceTrait,
+    error: Option<String>,
+) -> Html<String> {
     Html(
         SignInTemplate {
             title: "Sign In",
             error: error.as_deref(),
+            providers: auth_service.oauth_providers(),
         }
         .render()
         .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use auth::{AuthError, AuthServiceTrait, SamlError};
+use axum::{
+    extract::{Form, Path, Query, State},
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use serde::Deserialize;
+use time::Duration;
+
+use super::signin::signin_page;
+use crate::features::auth::client::Client;
+
+// Form on the signin page, users pick their organization
+pub async fn sso_start_handler(Query(query): Query<SsoStartQuery>) -> impl IntoResponse {
+    let org = query.org.trim().to_lowercase();
+    if org.is_empty() || !org.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Redirect::to("/auth/signin");
+    }
+
+    Redirect::to(&format!("/auth/sso/{org}"))
+}
+
+pub async fn sso_authorize_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Path(org): Path<String>,
+) -> impl IntoResponse {
+    match auth_service.sso_authorize(&org).await {
+        Ok(url) => Redirect::to(&url).into_response(),
+        Err(_) => signin_page(
+            auth_service.as_ref(),
+            Some("Single sign-on is not set up for this organization".to_string()),
+        )
+        .await
+        .into_response(),
+    }
+}
+
+// Assertion consumer service, the IdP posts the signed response here
+pub async fn sso_acs_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Path(org): Path<String>,
+    Client(client): Client,
+    Form(form): Form<SsoResponseForm>,
+) -> impl IntoResponse {
+    match auth_service
+        .sso_signin(&org, &form.saml_response, &client)
+        .await
+    {
+        Ok(token) => {
+            let cookie = Cookie::build(("auth_token", token))
+                .path("/")
+                .max_age(Duration::days(7))
+                .same_site(SameSite::Strict)
+                .http_only(true)
+                .build();
+
+            (CookieJar::new().add(cookie), Redirect::to("/")).into_response()
+        }
+        Err(err) => {
+            let error_message = match err {
+                AuthError::Hook(reason) => reason.to_string(),
+                AuthError::UserExists => {
+                    "An account with this email already exists. Sign in with your password first."
+                        .to_string()
+                }
+                AuthError::Saml(SamlError::DomainNotAllowed) => {
+                    "Your email domain is not allowed for this organization.".to_string()
+                }
+                _ => "Sign in failed. Please try again.".to_string(),
+            };
+
+            signin_page(auth_service.as_ref(), Some(error_message))
+                .await
+                .into_response()
+        }
+    }
+}
+
+pub async fn sso_metadata_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Path(org): Path<String>,
+) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "application/samlmetadata+xml")],
+        auth_service.sso_metadata(&org),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct SsoStartQuery {
+    pub org: String,
+}
+
+#[derive(Deserialize)]
+pub struct SsoResponseForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+}
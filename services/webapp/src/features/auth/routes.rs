@@ -1,6 +1,7 @@
 use axum::{
     Router,
     extract::State,
+    http::{HeaderMap, Method, StatusCode, header::AUTHORIZATION},
     response::{IntoResponse, Redirect},
     routing::{get, post},
 };
@@ -12,10 +13,18 @@ use std::sync::Arc;
 use time::Duration;
 
 use super::pages::{
+    email_change::{
+        cancel_email_handler, cancel_email_submit_handler, confirm_email_handler,
+        confirm_email_submit_handler,
+    },
+    impersonation::{impersonation_banner_handler, stop_impersonation_handler},
+    oauth::{oauth_authorize_handler, oauth_callback_handler},
+    password_reset::{password_reset_handler, password_reset_submit_handler},
     register::{register_handler, register_submit_handler},
     signin::{signin_handler, signin_submit_handler},
+    sso::{sso_acs_handler, sso_authorize_handler, sso_metadata_handler, sso_start_handler},
 };
-use auth::{AuthService, AuthServiceTrait, InMemoryUserRepository};
+use auth::{AccessGrant, ApiKey, AuthServiceTrait, Scope, User, is_api_key};
 
 pub fn auth_routes<S: AuthServiceTrait>(auth_service: Arc<S>) -> Router {
     Router::new()
@@ -24,27 +33,106 @@ pub fn auth_routes<S: AuthServiceTrait>(auth_service: Arc<S>) -> Router {
         .route("/register", get(register_handler))
         .route("/register", post(register_submit_handler))
         .route("/logout", get(logout_handler))
+        .route("/email/confirm", get(confirm_email_handler))
+        .route("/email/confirm", post(confirm_email_submit_handler))
+        .route("/email/cancel", get(cancel_email_handler))
+        .route("/email/cancel", post(cancel_email_submit_handler))
+        .route("/password/reset", get(password_reset_handler))
+        .route("/password/reset", post(password_reset_submit_handler))
+        .route("/impersonation", get(impersonation_banner_handler))
+        .route("/impersonation/stop", post(stop_impersonation_handler))
+        .route("/oauth/{provider}", get(oauth_authorize_handler))
+        .route("/oauth/{provider}/callback", get(oauth_callback_handler))
+        .route("/sso", get(sso_start_handler))
+        .route("/sso/{org}", get(sso_authorize_handler))
+        .route("/sso/{org}/acs", post(sso_acs_handler))
+        .route("/sso/{org}/metadata", get(sso_metadata_handler))
         .with_state(auth_service)
 }
 
-async fn logout_handler() -> impl IntoResponse {
-    let cookie = Cookie::build(("auth_token", ""))
-        .path("/")
-        .max_age(Duration::seconds(0))
-        .same_site(SameSite::Strict)
-        .http_only(true)
-        .build();
+async fn logout_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    cookie_jar: CookieJar,
+) -> impl IntoResponse {
+    // server-side sessions end right away, JWTs just lose their cookie. An admin acting as
+    // a user is signed out of both sessions.
+    let mut cleared = CookieJar::new();
+    for name in ["auth_token", "admin_token"] {
+        if let Some(token) = cookie_jar.get(name) {
+            let _ = auth_service.signout(token.value()).await;
+        }
+
+        let cookie = Cookie::build((name, ""))
+            .path("/")
+            .max_age(Duration::seconds(0))
+            .same_site(SameSite::Strict)
+            .http_only(true)
+            .build();
+        cleared = cleared.add(cookie);
+    }
 
-    (CookieJar::new().add(cookie), Redirect::to("/auth/signin"))
+    (cleared, Redirect::to("/auth/signin"))
 }
 
+// The admin acting as the signed in user, set by auth_middleware on impersonation sessions
+#[derive(Clone)]
+pub struct Impersonator(pub User);
+
+// The token the signed in user's request was made with, set by auth_middleware for
+// sessions and first party tokens
+#[derive(Clone)]
+pub struct SessionToken(pub String);
+
 // Middleware that can be used to protect routes
 pub async fn auth_middleware(
-    State(auth_service): State<Arc<AuthService<InMemoryUserRepository>>>,
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
     cookie_jar: CookieJar,
     mut req: axum::extract::Request,
     next: axum::middleware::Next,
 ) -> impl IntoResponse {
+    // Programmatic access, either an api key or a jwt sent as a bearer token
+    if let Some(token) = bearer_token(req.headers()) {
+        if is_api_key(&token) {
+            return match auth_service.validate_api_key(&token).await {
+                Ok((user, api_key)) => {
+                    if !api_key.has_scope(required_scope(req.method())) {
+                        return StatusCode::FORBIDDEN.into_response();
+                    }
+
+                    req.extensions_mut().insert(user);
+                    req.extensions_mut().insert(api_key);
+                    next.run(req).await
+                }
+                Err(_) => StatusCode::UNAUTHORIZED.into_response(),
+            };
+        }
+
+        return match auth_service.validate_access_token(&token).await {
+            Ok((user, grant)) => {
+                // tokens issued to third party clients are limited to their granted scopes
+                if let Some(grant) = grant {
+                    if !grant.has_scope(required_scope(req.method())) {
+                        return StatusCode::FORBIDDEN.into_response();
+                    }
+                    req.extensions_mut().insert(grant);
+                } else {
+                    match auth_service.impersonator(&token).await {
+                        Ok(Some(admin)) => {
+                            req.extensions_mut().insert(Impersonator(admin));
+                        }
+                        Ok(None) => {}
+                        Err(_) => return StatusCode::FORBIDDEN.into_response(),
+                    }
+                    req.extensions_mut().insert(SessionToken(token));
+                }
+
+                req.extensions_mut().insert(user);
+                next.run(req).await
+            }
+            Err(_) => StatusCode::UNAUTHORIZED.into_response(),
+        };
+    }
+
     let token = cookie_jar
         .get("auth_token")
         .map(|cookie| cookie.value().to_string());
@@ -52,8 +140,17 @@ pub async fn auth_middleware(
         Some(token) => {
             match auth_service.validate_token(&token).await {
                 Ok(user) => {
+                    // whoever acts as the user has to be known, not taken to be the user
+                    match auth_service.impersonator(&token).await {
+                        Ok(Some(admin)) => {
+                            req.extensions_mut().insert(Impersonator(admin));
+                        }
+                        Ok(None) => {}
+                        Err(_) => return StatusCode::FORBIDDEN.into_response(),
+                    }
                     // Add user to request extensions for handlers to access
                     req.extensions_mut().insert(user);
+                    req.extensions_mut().insert(SessionToken(token));
                     next.run(req).await
                 }
                 Err(_) => Redirect::to("/").into_response(),
@@ -62,3 +159,45 @@ pub async fn auth_middleware(
         None => Redirect::to("/").into_response(),
     }
 }
+
+// Must run after auth_middleware, rejects requests made with an api key or a client token
+pub async fn session_only_middleware(
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> impl IntoResponse {
+    if req.extensions().get::<ApiKey>().is_some() || req.extensions().get::<AccessGrant>().is_some()
+    {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(req).await
+}
+
+// Must run after auth_middleware, keeps admins acting as a user away from what only the user
+// may do: credentials, data exports and granting apps access
+pub async fn no_impersonation_middleware(
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> impl IntoResponse {
+    if req.extensions().get::<Impersonator>().is_some() {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(req).await
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
+
+// Safe methods only need read access, anything else needs write
+fn required_scope(method: &Method) -> Scope {
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => Scope::Read,
+        _ => Scope::Write,
+    }
+}
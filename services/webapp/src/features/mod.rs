@@ -1,3 +1,7 @@
 pub mod about;
+pub mod account;
+pub mod admin;
 pub mod auth;
 pub mod contact;
+pub mod oauth;
+pub mod scim;
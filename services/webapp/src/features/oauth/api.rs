@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use auth::{AuthError, AuthServiceTrait, AuthzError, ClientCredentials, TokenRequest};
+use axum::{
+    Json,
+    extract::{Form, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{AUTHORIZATION, CACHE_CONTROL, WWW_AUTHENTICATE},
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+
+pub async fn token_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    headers: HeaderMap,
+    Form(request): Form<TokenRequest>,
+) -> Response {
+    let creds = match client_credentials(
+        &headers,
+        request.client_id.as_deref(),
+        request.client_secret.as_deref(),
+    ) {
+        Some(creds) => creds,
+        None => return error_response(AuthError::Authz(AuthzError::InvalidClient)),
+    };
+
+    match auth_service.exchange_token(&creds, request).await {
+        Ok(tokens) => ([(CACHE_CONTROL, "no-store")], Json(tokens)).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+// RFC 7662
+pub async fn introspect_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    headers: HeaderMap,
+    Form(form): Form<TokenForm>,
+) -> Response {
+    let creds = match client_credentials(
+        &headers,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    ) {
+        Some(creds) => creds,
+        None => return error_response(AuthError::Authz(AuthzError::InvalidClient)),
+    };
+
+    match auth_service.introspect_token(&creds, &form.token).await {
+        Ok(introspection) => Json(introspection).into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+// RFC 7009
+pub async fn revoke_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    headers: HeaderMap,
+    Form(form): Form<TokenForm>,
+) -> Response {
+    let creds = match client_credentials(
+        &headers,
+        form.client_id.as_deref(),
+        form.client_secret.as_deref(),
+    ) {
+        Some(creds) => creds,
+        None => return error_response(AuthError::Authz(AuthzError::InvalidClient)),
+    };
+
+    match auth_service.revoke_token(&creds, &form.token).await {
+        Ok(_) => StatusCode::OK.into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+// OpenID Connect discovery document
+pub async fn openid_configuration_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+) -> Response {
+    Json(auth_service.openid_configuration()).into_response()
+}
+
+pub async fn jwks_handler(State(auth_service): State<Arc<dyn AuthServiceTrait>>) -> Response {
+    Json(auth_service.jwks()).into_response()
+}
+
+pub async fn userinfo_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    headers: HeaderMap,
+) -> Response {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    let result = match token {
+        Some(token) => auth_service.userinfo(token).await,
+        None => Err(AuthError::Unauthorized),
+    };
+
+    // RFC 6750 errors are reported in the WWW-Authenticate header
+    match result {
+        Ok(user_info) => ([(CACHE_CONTROL, "no-store")], Json(user_info)).into_response(),
+        Err(AuthError::InsufficientScope) => (
+            StatusCode::FORBIDDEN,
+            [(WWW_AUTHENTICATE, r#"Bearer error="insufficient_scope""#)],
+        )
+            .into_response(),
+        Err(_) => (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#)],
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TokenForm {
+    pub token: String,
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+// Basic auth takes precedence over credentials in the body
+fn client_credentials(
+    headers: &HeaderMap,
+    client_id: Option<&str>,
+    client_secret: Option<&str>,
+) -> Option<ClientCredentials> {
+    if let Some(header) = headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+        return ClientCredentials::from_basic_auth(header);
+    }
+
+    Some(ClientCredentials {
+        client_id: client_id?.to_string(),
+        client_secret: client_secret?.to_string(),
+    })
+}
+
+fn error_response(err: AuthError) -> Response {
+    match err {
+        AuthError::Authz(e) => {
+            let status = match e {
+                AuthzError::InvalidClient => StatusCode::UNAUTHORIZED,
+                _ => StatusCode::BAD_REQUEST,
+            };
+
+            (status, Json(json!({ "error": e.code() }))).into_response()
+        }
+        _ => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": "server_error" })),
+        )
+            .into_response(),
+    }
+}
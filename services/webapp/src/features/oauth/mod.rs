@@ -0,0 +1,3 @@
+mod api;
+mod pages;
+pub mod routes;
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use askama::Template;
+use auth::{AuthServiceTrait, AuthorizeParams, Scope, User};
+use axum::{
+    Extension,
+    extract::{Form, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect},
+};
+use serde::Deserialize;
+
+pub async fn consent_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(_user): Extension<User>,
+    Query(params): Query<AuthorizeParams>,
+) -> impl IntoResponse {
+    match auth_service.validate_authorize_request(&params).await {
+        Ok((client_app, scopes)) => consent_page(ConsentTemplate {
+            title: "Authorize",
+            client_name: &client_app.name,
+            scopes: scopes.iter().map(scope_description).collect(),
+            params: &params,
+            error: None,
+        })
+        .into_response(),
+        // bad client or redirect uri, show the error instead of redirecting
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            consent_page(ConsentTemplate {
+                title: "Authorize",
+                client_name: "",
+                scopes: Vec::new(),
+                params: &params,
+                error: Some(&err.to_string()),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn consent_submit_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(user): Extension<User>,
+    Form(form): Form<ConsentForm>,
+) -> impl IntoResponse {
+    let result = match form.decision.as_str() {
+        "approve" => {
+            auth_service
+                .approve_authorize_request(&user.id, &form.params)
+                .await
+        }
+        _ => auth_service.deny_authorize_request(&form.params).await,
+    };
+
+    match result {
+        Ok(redirect) => Redirect::to(&redirect).into_response(),
+        Err(err) => (
+            StatusCode::BAD_REQUEST,
+            consent_page(ConsentTemplate {
+                title: "Authorize",
+                client_name: "",
+                scopes: Vec::new(),
+                params: &form.params,
+                error: Some(&err.to_string()),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+fn scope_description(scope: &Scope) -> &'static str {
+    match scope {
+        Scope::Read => "Read your data",
+        Scope::Write => "Create and change your data",
+        Scope::OpenId => "Sign you in with your account",
+        Scope::Email => "See your email address",
+        Scope::Profile => "See your name",
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConsentForm {
+    pub decision: String,
+    #[serde(flatten)]
+    pub params: AuthorizeParams,
+}
+
+#[derive(Template)]
+#[template(path = "oauth/consent.html")]
+struct ConsentTemplate<'a> {
+    title: &'a str,
+    client_name: &'a str,
+    scopes: Vec<&'a str>,
+    params: &'a AuthorizeParams,
+    error: Option<&'a str>,
+}
+
+fn consent_page(template: ConsentTemplate) -> Html<String> {
+    Html(
+        template
+            .render()
+            .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.to_string()),
+    )
+}
@@ -0,0 +1,49 @@
+use axum::{
+    Router, middleware,
+    routing::{get, post},
+};
+use std::sync::Arc;
+
+use super::api::{
+    introspect_handler, jwks_handler, openid_configuration_handler, revoke_handler, token_handler,
+    userinfo_handler,
+};
+use super::pages::{consent_handler, consent_submit_handler};
+use crate::features::auth::routes::{
+    auth_middleware, no_impersonation_middleware, session_only_middleware,
+};
+use auth::AuthServiceTrait;
+
+// Endpoints that let third party apps act on behalf of our users
+pub fn oauth_routes(auth_service: Arc<dyn AuthServiceTrait>) -> Router {
+    let consent = Router::new()
+        .route("/authorize", post(consent_submit_handler))
+        // only the user can grant an app access to their account
+        .route_layer(middleware::from_fn(no_impersonation_middleware))
+        .route("/authorize", get(consent_handler))
+        .route_layer(middleware::from_fn(session_only_middleware))
+        .route_layer(middleware::from_fn_with_state(
+            auth_service.clone(),
+            auth_middleware,
+        ));
+
+    // clients authenticate with their own credentials
+    let api = Router::new()
+        .route("/token", post(token_handler))
+        .route("/introspect", post(introspect_handler))
+        .route("/revoke", post(revoke_handler));
+
+    consent.merge(api).with_state(auth_service)
+}
+
+// OpenID Connect endpoints, served from the root so clients can discover them from the issuer
+pub fn oidc_routes(auth_service: Arc<dyn AuthServiceTrait>) -> Router {
+    Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(openid_configuration_handler),
+        )
+        .route("/.well-known/jwks.json", get(jwks_handler))
+        .route("/userinfo", get(userinfo_handler).post(userinfo_handler))
+        .with_state(auth_service)
+}
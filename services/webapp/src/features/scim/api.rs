@@ -0,0 +1,314 @@
+use std::sync::Arc;
+
+use auth::{
+    AuthError, AuthServiceTrait, ERROR_SCHEMA, ListQuery, Meta, PatchRequest, ScimError, ScimGroup,
+    ScimUser,
+};
+use axum::{
+    Extension, Json,
+    extract::{Path, Query, State, rejection::JsonRejection},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LOCATION},
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::json;
+
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+// The organization the SCIM token belongs to
+#[derive(Clone)]
+pub struct ScimOrg(pub String);
+
+pub async fn service_provider_config_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+) -> Response {
+    scim_response(StatusCode::OK, &auth_service.scim_service_provider_config())
+}
+
+pub async fn list_users_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    match auth_service.scim_list_users(&org, &query).await {
+        Ok(list) => scim_response(StatusCode::OK, &list),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn get_user_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    match auth_service.scim_get_user(&org, &id).await {
+        Ok(user) => resource_response(StatusCode::OK, &user, user.meta.as_ref(), &headers),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn create_user_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    body: Result<Json<ScimUser>, JsonRejection>,
+) -> Response {
+    let user = match body {
+        Ok(Json(user)) => user,
+        Err(rejection) => return syntax_error(rejection),
+    };
+
+    match auth_service.scim_create_user(&org, user).await {
+        Ok(user) => created_response(&user, user.meta.as_ref()),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn replace_user_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Result<Json<ScimUser>, JsonRejection>,
+) -> Response {
+    let user = match body {
+        Ok(Json(user)) => user,
+        Err(rejection) => return syntax_error(rejection),
+    };
+
+    match auth_service
+        .scim_replace_user(&org, &id, user, if_match(&headers))
+        .await
+    {
+        Ok(user) => resource_response(StatusCode::OK, &user, user.meta.as_ref(), &HeaderMap::new()),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn patch_user_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Result<Json<PatchRequest>, JsonRejection>,
+) -> Response {
+    let patch = match body {
+        Ok(Json(patch)) => patch,
+        Err(rejection) => return syntax_error(rejection),
+    };
+
+    match auth_service
+        .scim_patch_user(&org, &id, patch, if_match(&headers))
+        .await
+    {
+        Ok(user) => resource_response(StatusCode::OK, &user, user.meta.as_ref(), &HeaderMap::new()),
+        Err(err) => error_response(err),
+    }
+}
+
+// Deprovisioning, the account is disabled and its sessions revoked
+pub async fn delete_user_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    match auth_service
+        .scim_delete_user(&org, &id, if_match(&headers))
+        .await
+    {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn list_groups_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    match auth_service.scim_list_groups(&org, &query).await {
+        Ok(list) => scim_response(StatusCode::OK, &list),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn get_group_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    match auth_service.scim_get_group(&org, &id).await {
+        Ok(group) => resource_response(StatusCode::OK, &group, group.meta.as_ref(), &headers),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn create_group_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    body: Result<Json<ScimGroup>, JsonRejection>,
+) -> Response {
+    let group = match body {
+        Ok(Json(group)) => group,
+        Err(rejection) => return syntax_error(rejection),
+    };
+
+    match auth_service.scim_create_group(&org, group).await {
+        Ok(group) => created_response(&group, group.meta.as_ref()),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn replace_group_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Result<Json<ScimGroup>, JsonRejection>,
+) -> Response {
+    let group = match body {
+        Ok(Json(group)) => group,
+        Err(rejection) => return syntax_error(rejection),
+    };
+
+    match auth_service
+        .scim_replace_group(&org, &id, group, if_match(&headers))
+        .await
+    {
+        Ok(group) => resource_response(
+            StatusCode::OK,
+            &group,
+            group.meta.as_ref(),
+            &HeaderMap::new(),
+        ),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn patch_group_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Result<Json<PatchRequest>, JsonRejection>,
+) -> Response {
+    let patch = match body {
+        Ok(Json(patch)) => patch,
+        Err(rejection) => return syntax_error(rejection),
+    };
+
+    match auth_service
+        .scim_patch_group(&org, &id, patch, if_match(&headers))
+        .await
+    {
+        Ok(group) => resource_response(
+            StatusCode::OK,
+            &group,
+            group.meta.as_ref(),
+            &HeaderMap::new(),
+        ),
+        Err(err) => error_response(err),
+    }
+}
+
+pub async fn delete_group_handler(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    Extension(ScimOrg(org)): Extension<ScimOrg>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    match auth_service
+        .scim_delete_group(&org, &id, if_match(&headers))
+        .await
+    {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => error_response(err),
+    }
+}
+
+// Errors use the SCIM error schema, RFC 7644 section 3.12
+pub fn error_response(err: AuthError) -> Response {
+    let (status, scim_type) = match &err {
+        AuthError::Scim(e) => (
+            StatusCode::from_u16(e.status()).unwrap_or(StatusCode::BAD_REQUEST),
+            e.scim_type(),
+        ),
+        AuthError::UserExists => (StatusCode::CONFLICT, Some("uniqueness")),
+        AuthError::EmailValidation => (StatusCode::BAD_REQUEST, Some("invalidValue")),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, None),
+    };
+
+    let detail = match &err {
+        AuthError::Scim(e) => e.to_string(),
+        _ if status == StatusCode::INTERNAL_SERVER_ERROR => "Internal server error".to_string(),
+        _ => err.to_string(),
+    };
+
+    let mut body = json!({
+        "schemas": [ERROR_SCHEMA],
+        "status": status.as_u16().to_string(),
+        "detail": detail,
+    });
+    if let Some(scim_type) = scim_type {
+        body["scimType"] = json!(scim_type);
+    }
+
+    scim_response(status, &body)
+}
+
+fn syntax_error(rejection: JsonRejection) -> Response {
+    error_response(AuthError::Scim(ScimError::InvalidSyntax(
+        rejection.body_text(),
+    )))
+}
+
+fn scim_response<T: Serialize>(status: StatusCode, body: &T) -> Response {
+    let mut response = (status, Json(body)).into_response();
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(SCIM_CONTENT_TYPE));
+    response
+}
+
+// Sets the ETag, and answers 304 when the client already has this version
+fn resource_response<T: Serialize>(
+    status: StatusCode,
+    resource: &T,
+    meta: Option<&Meta>,
+    headers: &HeaderMap,
+) -> Response {
+    let Some(version) = meta.map(|meta| meta.version.as_str()) else {
+        return scim_response(status, resource);
+    };
+
+    let cached = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == version));
+    let mut response = match cached {
+        true => StatusCode::NOT_MODIFIED.into_response(),
+        false => scim_response(status, resource),
+    };
+
+    if let Ok(etag) = HeaderValue::from_str(version) {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    response
+}
+
+fn created_response<T: Serialize>(resource: &T, meta: Option<&Meta>) -> Response {
+    let mut response = resource_response(StatusCode::CREATED, resource, meta, &HeaderMap::new());
+    if let Some(location) = meta.and_then(|meta| HeaderValue::from_str(&meta.location).ok()) {
+        response.headers_mut().insert(LOCATION, location);
+    }
+    response
+}
+
+fn if_match(headers: &HeaderMap) -> Option<&str> {
+    headers.get(IF_MATCH).and_then(|value| value.to_str().ok())
+}
@@ -0,0 +1,77 @@
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, header::AUTHORIZATION},
+    middleware,
+    response::IntoResponse,
+    routing::get,
+};
+use std::sync::Arc;
+
+use super::api::{
+    ScimOrg, create_group_handler, create_user_handler, delete_group_handler, delete_user_handler,
+    error_response, get_group_handler, get_user_handler, list_groups_handler, list_users_handler,
+    patch_group_handler, patch_user_handler, replace_group_handler, replace_user_handler,
+    service_provider_config_handler,
+};
+use auth::{AuthError, AuthServiceTrait, ScimError};
+
+// SCIM 2.0 provisioning endpoints, used by the identity provider of an organization
+pub fn scim_routes(auth_service: Arc<dyn AuthServiceTrait>) -> Router {
+    Router::new()
+        .route(
+            "/ServiceProviderConfig",
+            get(service_provider_config_handler),
+        )
+        .route("/Users", get(list_users_handler).post(create_user_handler))
+        .route(
+            "/Users/{id}",
+            get(get_user_handler)
+                .put(replace_user_handler)
+                .patch(patch_user_handler)
+                .delete(delete_user_handler),
+        )
+        .route(
+            "/Groups",
+            get(list_groups_handler).post(create_group_handler),
+        )
+        .route(
+            "/Groups/{id}",
+            get(get_group_handler)
+                .put(replace_group_handler)
+                .patch(patch_group_handler)
+                .delete(delete_group_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            auth_service.clone(),
+            scim_middleware,
+        ))
+        .with_state(auth_service)
+}
+
+// Authenticates the organization's bearer token, handlers only see that organization
+async fn scim_middleware(
+    State(auth_service): State<Arc<dyn AuthServiceTrait>>,
+    mut req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> impl IntoResponse {
+    let Some(token) = bearer_token(req.headers()) else {
+        return error_response(AuthError::Scim(ScimError::InvalidToken));
+    };
+
+    match auth_service.validate_scim_token(&token).await {
+        Ok(org) => {
+            req.extensions_mut().insert(ScimOrg(org));
+            next.run(req).await
+        }
+        Err(err) => error_response(err),
+    }
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+}
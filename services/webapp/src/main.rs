@@ -1,6 +1,9 @@
+use std::net::SocketAddr;
+
 use state::AppState;
 use tokio::net::TcpListener;
 
+mod cli;
 mod error;
 mod features;
 mod router;
@@ -9,19 +12,57 @@ mod state;
 use error::Result;
 
 // for creating test user with in mem db
-use auth::{AuthServiceTrait, RegisterUser};
+use auth::{AuthServiceTrait, ClientInfo, RegisterUser, Role};
 
 #[tokio::main]
 async fn main() {
+    // `webapp users ...` works on the stored users instead of serving
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "users") {
+        std::process::exit(cli::users(&args[1..]).await);
+    }
+
     let app_state = AppState::new().await.unwrap();
 
     // for testing with in mem db
     create_test_user(&app_state).await;
 
+    let user_repository = app_state.user_repository().clone();
     let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, router::routes(app_state))
+    // peer addresses are recorded with each signin
+    let app = router::routes(app_state).into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+
+    // the next start loads the snapshot instead of replaying the log
+    if let Err(e) = user_repository.snapshot().await {
+        println!("Can't snapshot users: {e}");
+    }
+}
+
+// Ctrl-C, or SIGTERM from a process manager
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
 
 async fn create_test_user(state: &AppState) {
@@ -32,5 +73,14 @@ async fn create_test_user(state: &AppState) {
         name: "Matt".to_string(),
     };
 
-    state.auth_service().clone().register(user_data).await;
+    let auth_service = state.auth_service().clone();
+    // the test user can see the admin pages
+    if let Ok(user) = auth_service
+        .register(user_data, &ClientInfo::default())
+        .await
+    {
+        let _ = auth_service
+            .grant_role("system", &user.id, Role::Admin)
+            .await;
+    }
 }
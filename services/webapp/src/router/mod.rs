@@ -1,4 +1,7 @@
+use std::sync::Arc;
+
 use askama::Template;
+use auth::AuthServiceTrait;
 use axum::{Router, http::StatusCode, middleware, response::Html, routing::get};
 use tower_http::services::ServeDir;
 
@@ -6,19 +9,30 @@ use crate::features::auth::routes::auth_middleware;
 use crate::state::AppState;
 
 use super::features::about::routes::about_routes;
+use super::features::account::routes::account_routes;
+use super::features::admin::routes::admin_routes;
 use super::features::auth::routes::auth_routes;
 use super::features::contact::routes::contact_routes;
+use super::features::oauth::routes::{oauth_routes, oidc_routes};
+use super::features::scim::routes::scim_routes;
 
 pub fn routes(state: AppState) -> Router {
+    let auth_service: Arc<dyn AuthServiceTrait> = state.auth_service().clone();
+
     Router::new()
         .layer(middleware::from_fn_with_state(
-            state.auth_service().clone(),
+            auth_service.clone(),
             auth_middleware,
         ))
         .route("/", get(root))
         .merge(about_routes())
         .merge(contact_routes())
         .nest("/auth", auth_routes(state.auth_service().clone()))
+        .nest("/account", account_routes(auth_service.clone()))
+        .nest("/admin", admin_routes(auth_service.clone()))
+        .nest("/oauth", oauth_routes(auth_service.clone()))
+        .nest("/scim/v2", scim_routes(auth_service.clone()))
+        .merge(oidc_routes(auth_service))
         .nest_service("/assets", ServeDir::new("services/webapp/assets"))
     // .with_state(state)
 }
@@ -1,8 +1,16 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::Result;
 
-use auth::{AuthService, InMemoryUserRepository, JwtService};
+use auth::{
+    AuditSink, AuthService, AuthServiceTrait, AuthorizationServer, HttpTransport,
+    InMemorySessionStore, InMemoryUserRepository, JsonlAuditSink, JwtService, LogHandler,
+    OAuthClient, OAuthProviderConfig, OutboxDispatcher, OutboxStore, PiiKeys, ScimProvisioner,
+    ServiceProvider, SessionStore, SigningKey, SqliteAuditSink, SqliteOutbox, SqliteSessionStore,
+    WebhookHandler,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
 
 pub struct AppState {
     user_repository: Arc<InMemoryUserRepository>,
@@ -13,11 +21,42 @@ pub struct AppState {
 
 impl AppState {
     pub async fn new() -> Result<Self> {
-        let user_repository = Arc::new(InMemoryUserRepository::new());
+        let user_repository = Arc::new(user_repository_from_env());
+        tokio::spawn(snapshot_users(user_repository.clone()));
 
         let jwt_service = Arc::new(JwtService::new("jwt_secret".as_bytes(), 24));
+        let outbox = outbox_from_env();
+        let dispatched: Arc<dyn OutboxStore> = match &outbox {
+            Some(outbox) => outbox.clone(),
+            None => user_repository.clone(),
+        };
+        tokio::spawn(dispatch_outbox(outbox_dispatcher_from_env(dispatched)));
 
-        let auth_service = Arc::new(AuthService::new(user_repository.clone(), jwt_service));
+        let authz_server = AuthorizationServer::new(jwt_service.clone())
+            .with_issuer(&app_url())
+            .with_signing_key(Arc::new(signing_key_from_env()));
+
+        let mut auth_service = AuthService::new(user_repository.clone(), jwt_service)
+            .with_oauth_client(Arc::new(oauth_client_from_env()))
+            .with_authz_server(Arc::new(authz_server))
+            .with_service_provider(Arc::new(ServiceProvider::new(&app_url())))
+            .with_scim_provisioner(Arc::new(ScimProvisioner::new(&app_url())))
+            .with_base_url(&app_url())
+            .with_erasure_grace_period(erasure_grace_period_from_env());
+        auth_service = match outbox {
+            Some(outbox) => auth_service.with_outbox(outbox),
+            None => auth_service.with_user_outbox(),
+        };
+        if let Some(session_store) = session_store_from_env() {
+            auth_service = auth_service.with_session_store(session_store);
+        }
+        if let Some(audit_sink) = audit_sink_from_env() {
+            auth_service = auth_service.with_audit_sink(audit_sink);
+        }
+        let auth_service = Arc::new(auth_service);
+        import_sso_connections_from_env(auth_service.as_ref()).await;
+        create_scim_tokens_from_env(auth_service.as_ref()).await;
+        tokio::spawn(purge_deleted_accounts(auth_service.clone()));
 
         Ok(Self {
             user_repository,
@@ -28,4 +67,272 @@ impl AppState {
     pub fn auth_service(&self) -> &Arc<AuthService<InMemoryUserRepository>> {
         &self.auth_service
     }
+
+    pub fn user_repository(&self) -> &Arc<InMemoryUserRepository> {
+        &self.user_repository
+    }
+}
+
+// USER_DATA_DIR keeps users, and the events about them waiting for delivery, in a
+// snapshot and change log in the directory, so they survive restarts. Without it they only
+// live in memory. With PII_KEYS their email and name are encrypted in the files, see
+// PiiKeys::from_env.
+fn user_repository_from_env() -> InMemoryUserRepository {
+    let Ok(dir) = std::env::var("USER_DATA_DIR") else {
+        println!(
+            "WARNING - USER_DATA_DIR is not set, users and undelivered events only live in \
+             memory and are lost on restart"
+        );
+        return InMemoryUserRepository::new();
+    };
+    let opened = match PiiKeys::from_env() {
+        Ok(Some(keys)) => InMemoryUserRepository::open_encrypted(&dir, keys),
+        Ok(None) => InMemoryUserRepository::open(&dir),
+        Err(e) => panic!("FATAL - {e}"),
+    };
+    match opened {
+        Ok(repo) => repo,
+        Err(e) => panic!("FATAL - can't load users from {dir}: {e}"),
+    }
+}
+
+// Folds the change log into a new snapshot every USER_SNAPSHOT_SECS, 5 minutes by
+// default, so restarts have less to replay
+async fn snapshot_users(user_repository: Arc<InMemoryUserRepository>) {
+    let secs = match std::env::var("USER_SNAPSHOT_SECS") {
+        Ok(secs) => match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => secs,
+            _ => panic!("FATAL - USER_SNAPSHOT_SECS must be a number of seconds"),
+        },
+        Err(_) => 5 * 60,
+    };
+
+    let mut interval = tokio::time::interval(Duration::from_secs(secs));
+    loop {
+        interval.tick().await;
+        if let Err(e) = user_repository.snapshot().await {
+            println!("Can't snapshot users: {e}");
+        }
+    }
+}
+
+fn app_url() -> String {
+    std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string())
+}
+
+// ERASURE_GRACE_DAYS is how long deleted accounts are kept before they're purged, 30 by default
+fn erasure_grace_period_from_env() -> time::Duration {
+    match std::env::var("ERASURE_GRACE_DAYS") {
+        Ok(days) => match days.parse::<u32>() {
+            Ok(days) => time::Duration::days(days.into()),
+            Err(_) => panic!("FATAL - ERASURE_GRACE_DAYS must be a number of days"),
+        },
+        Err(_) => time::Duration::days(30),
+    }
+}
+
+// Purges deleted accounts once their grace period is over, checking every hour
+async fn purge_deleted_accounts(auth_service: Arc<AuthService<InMemoryUserRepository>>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match auth_service.purge_deleted_accounts().await {
+            Ok(0) => {}
+            Ok(count) => println!("Purged {count} deleted accounts"),
+            Err(e) => println!("Can't purge deleted accounts: {e}"),
+        }
+    }
+}
+
+// SESSION_STORE picks server-side sessions, `memory` or `sqlite:<path>`, without it
+// sessions are stateless JWTs
+fn session_store_from_env() -> Option<Arc<dyn SessionStore>> {
+    let store = std::env::var("SESSION_STORE").ok()?;
+
+    match store.split_once(':') {
+        None if store == "memory" => Some(Arc::new(InMemorySessionStore::new())),
+        Some(("sqlite", path)) => match SqliteSessionStore::open(path) {
+            Ok(store) => Some(Arc::new(store)),
+            Err(e) => panic!("FATAL - can't open session store {path}: {e}"),
+        },
+        _ => panic!("FATAL - unknown SESSION_STORE {store}"),
+    }
+}
+
+// AUDIT_SINK keeps the audit log in `jsonl:<path>` or `sqlite:<path>`, without it the log
+// only lives in memory
+fn audit_sink_from_env() -> Option<Arc<dyn AuditSink>> {
+    let sink = std::env::var("AUDIT_SINK").ok()?;
+
+    match sink.split_once(':') {
+        Some(("jsonl", path)) => match JsonlAuditSink::open(path) {
+            Ok(sink) => Some(Arc::new(sink)),
+            Err(e) => panic!("FATAL - can't open audit log {path}: {e}"),
+        },
+        Some(("sqlite", path)) => match SqliteAuditSink::open(path) {
+            Ok(sink) => Some(Arc::new(sink)),
+            Err(e) => panic!("FATAL - can't open audit log {path}: {e}"),
+        },
+        _ => panic!("FATAL - unknown AUDIT_SINK {sink}"),
+    }
+}
+
+// OUTBOX keeps events waiting for delivery in `sqlite:<path>`. Without it they're kept
+// with the users, saved in the same write as the change they're about.
+fn outbox_from_env() -> Option<Arc<dyn OutboxStore>> {
+    let Ok(outbox) = std::env::var("OUTBOX") else {
+        return None;
+    };
+
+    match outbox.split_once(':') {
+        Some(("sqlite", path)) => match SqliteOutbox::open(path) {
+            Ok(outbox) => Some(Arc::new(outbox)),
+            Err(e) => panic!("FATAL - can't open outbox {path}: {e}"),
+        },
+        _ => panic!("FATAL - unknown OUTBOX {outbox}"),
+    }
+}
+
+// OUTBOX_WEBHOOK_URL is where events are POSTed, without it they're printed
+fn outbox_dispatcher_from_env(outbox: Arc<dyn OutboxStore>) -> OutboxDispatcher {
+    let dispatcher = OutboxDispatcher::new(outbox);
+    match std::env::var("OUTBOX_WEBHOOK_URL") {
+        Ok(url) => match WebhookHandler::new(&url) {
+            Ok(webhook) => dispatcher.with_handler(Arc::new(webhook)),
+            Err(e) => panic!("FATAL - can't set up the outbox webhook: {e}"),
+        },
+        Err(_) => dispatcher.with_handler(Arc::new(LogHandler)),
+    }
+}
+
+// Delivers the events in the outbox, checking every 5 seconds
+async fn dispatch_outbox(dispatcher: OutboxDispatcher) {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        match dispatcher.dispatch(now).await {
+            Ok(report) if report.dead > 0 => {
+                println!(
+                    "Gave up delivering {} events, see /admin/outbox",
+                    report.dead
+                )
+            }
+            Ok(_) => {}
+            Err(e) => println!("Can't dispatch events: {e}"),
+        }
+    }
+}
+
+// OIDC_SIGNING_KEY is a base64 PKCS#8 P-256 key, without it id tokens don't survive a restart
+fn signing_key_from_env() -> SigningKey {
+    match std::env::var("OIDC_SIGNING_KEY") {
+        Ok(key) => STANDARD
+            .decode(key.trim())
+            .ok()
+            .and_then(|der| SigningKey::from_pkcs8(&der).ok())
+            .unwrap_or_else(|| panic!("FATAL - OIDC_SIGNING_KEY is not a valid PKCS#8 key")),
+        Err(_) => SigningKey::generate(),
+    }
+}
+
+// Each <org>.xml in SAML_METADATA_DIR is an IdP's metadata, SAML_<ORG>_EMAIL_DOMAINS
+// (comma separated) restricts the emails it can sign in and lets it link existing accounts
+async fn import_sso_connections_from_env(auth_service: &AuthService<InMemoryUserRepository>) {
+    let Ok(dir) = std::env::var("SAML_METADATA_DIR") else {
+        return;
+    };
+    let entries = std::fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("FATAL - can't read SAML_METADATA_DIR {dir}: {e}"));
+
+    for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        let (Some(org), Some("xml")) = (
+            path.file_stem().and_then(|s| s.to_str()),
+            path.extension().and_then(|s| s.to_str()),
+        ) else {
+            continue;
+        };
+
+        let env_org = org.to_uppercase().replace('-', "_");
+        let email_domains = std::env::var(format!("SAML_{env_org}_EMAIL_DOMAINS"))
+            .map(|domains| domains.split(',').map(str::to_string).collect())
+            .unwrap_or_default();
+
+        let imported = match std::fs::read_to_string(&path) {
+            Ok(xml) => auth_service
+                .import_sso_metadata(org, &xml, email_domains)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = imported {
+            panic!("FATAL - can't import SAML metadata {}: {e}", path.display());
+        }
+    }
+}
+
+// SCIM_ORGS (comma separated) lists the organizations provisioning users, their tokens
+// only live in memory so fresh ones are written to SCIM_TOKEN_FILE at startup, one
+// `<org> <token>` per line and readable only by the owner. They're never printed.
+async fn create_scim_tokens_from_env(auth_service: &AuthService<InMemoryUserRepository>) {
+    let Ok(orgs) = std::env::var("SCIM_ORGS") else {
+        return;
+    };
+    let Ok(path) = std::env::var("SCIM_TOKEN_FILE") else {
+        panic!("FATAL - SCIM_TOKEN_FILE must be set to hand out the SCIM_ORGS tokens");
+    };
+
+    let mut lines = String::new();
+    for org in orgs.split(',').map(str::trim).filter(|org| !org.is_empty()) {
+        match auth_service.create_scim_token(org).await {
+            Ok(token) => lines.push_str(&format!("{org} {token}\n")),
+            Err(e) => panic!("FATAL - can't create SCIM token for {org}: {e}"),
+        }
+    }
+    if let Err(e) = write_private(&path, &lines) {
+        panic!("FATAL - can't write SCIM tokens to {path}: {e}");
+    }
+    println!("SCIM tokens written to {path}");
+}
+
+// Replaces the file with `contents`, only the owner may read it
+fn write_private(path: &str, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // a file that already existed keeps its permissions
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+type ProviderPreset = fn(&str, &str, &str) -> OAuthProviderConfig;
+
+// Social login providers are enabled by setting <PROVIDER>_CLIENT_ID and <PROVIDER>_CLIENT_SECRET
+fn oauth_client_from_env() -> OAuthClient {
+    let base_url = app_url();
+    let mut client = OAuthClient::new(Arc::new(HttpTransport::new()));
+
+    let presets: [(&str, ProviderPreset); 3] = [
+        ("GOOGLE", OAuthProviderConfig::google),
+        ("GITHUB", OAuthProviderConfig::github),
+        ("MICROSOFT", OAuthProviderConfig::microsoft),
+    ];
+
+    for (env_prefix, preset) in presets {
+        let id = std::env::var(format!("{env_prefix}_CLIENT_ID"));
+        let secret = std::env::var(format!("{env_prefix}_CLIENT_SECRET"));
+
+        if let (Ok(id), Ok(secret)) = (id, secret) {
+            let name = env_prefix.to_lowercase();
+            let redirect_uri = format!("{base_url}/auth/oauth/{name}/callback");
+            client = client.with_provider(preset(&id, &secret, &redirect_uri));
+        }
+    }
+
+    client
 }